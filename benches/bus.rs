@@ -0,0 +1,57 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use nes::mapper::Mapper;
+
+#[path = "../tests/support/mod.rs"]
+mod support;
+
+/// A program that counts in X forever.
+#[rustfmt::skip]
+const PROGRAM: [u8; 4] = [
+    0xe8,             // INX
+    0x4c, 0x00, 0x80, // JMP $8000
+];
+
+fn mapper(c: &mut Criterion) {
+    let mut mapper = <dyn Mapper>::from_bytes(support::nrom(&PROGRAM)).unwrap();
+    let mut buffer = vec![0; 0x8000];
+
+    let mut group = c.benchmark_group("mapper prg rom");
+    group.bench_function("cpu_read", |b| {
+        b.iter(|| {
+            for (offset, data) in buffer.iter_mut().enumerate() {
+                *data = mapper.cpu_read(0x8000 + offset as u16).unwrap_or(0);
+            }
+            black_box(&buffer);
+        })
+    });
+    group.bench_function("cpu_read_into", |b| {
+        b.iter(|| {
+            mapper.cpu_read_into(0x8000, &mut buffer);
+            black_box(&buffer);
+        })
+    });
+    group.finish();
+}
+
+fn console(c: &mut Criterion) {
+    let mut console = support::run(&PROGRAM, 0);
+    let mut buffer = vec![0; 0x8000];
+
+    let mut group = c.benchmark_group("console");
+    group.bench_function("read_range wram", |b| {
+        b.iter(|| black_box(console.read_range(0x0000..=0x07ff)))
+    });
+    group.bench_function("read_range cartridge", |b| {
+        b.iter(|| black_box(console.read_range(0x6000..=0xffff)))
+    });
+    group.bench_function("read_into prg rom", |b| {
+        b.iter(|| {
+            console.read_into(0x8000, &mut buffer);
+            black_box(&buffer);
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, mapper, console);
+criterion_main!(benches);
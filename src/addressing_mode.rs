@@ -17,6 +17,7 @@ pub enum AddressingMode {
     Unimplemented,
 }
 
+#[allow(clippy::len_without_is_empty)]
 impl AddressingMode {
     /// The number of bytes an instruction with this addressing mode uses.
     ///
@@ -25,8 +26,8 @@ impl AddressingMode {
     pub fn len(&self) -> usize {
         match self {
             AddressingMode::Absolute => 3,
-            AddressingMode::AbsoluteX => 2,
-            AddressingMode::AbsoluteY => 2,
+            AddressingMode::AbsoluteX => 3,
+            AddressingMode::AbsoluteY => 3,
             AddressingMode::Accumulator => 1,
             AddressingMode::Immediate => 2,
             AddressingMode::Implied => 1,
@@ -51,8 +52,8 @@ const ADDRESSING_MODES: [AddressingMode; 256] = [
     AddressingMode::Implied,
     // 01 ORA
     AddressingMode::IndirectZeroPageX,
-    // 02 UNI
-    AddressingMode::Unimplemented,
+    // 02 JAM
+    AddressingMode::Implied,
     // 03 UNI
     AddressingMode::Unimplemented,
     // 04 UNI
@@ -83,8 +84,8 @@ const ADDRESSING_MODES: [AddressingMode; 256] = [
     AddressingMode::Relative,
     // 11 ORA
     AddressingMode::IndirectZeroPageY,
-    // 12 UNI
-    AddressingMode::Unimplemented,
+    // 12 JAM
+    AddressingMode::Implied,
     // 13 UNI
     AddressingMode::Unimplemented,
     // 14 UNI
@@ -99,8 +100,8 @@ const ADDRESSING_MODES: [AddressingMode; 256] = [
     AddressingMode::Implied,
     // 19 ORA
     AddressingMode::AbsoluteY,
-    // 1A UNI
-    AddressingMode::Unimplemented,
+    // 1A NOP
+    AddressingMode::Implied,
     // 1B UNI
     AddressingMode::Unimplemented,
     // 1C UNI
@@ -115,8 +116,8 @@ const ADDRESSING_MODES: [AddressingMode; 256] = [
     AddressingMode::Absolute,
     // 21 AND
     AddressingMode::IndirectZeroPageX,
-    // 22 UNI
-    AddressingMode::Unimplemented,
+    // 22 JAM
+    AddressingMode::Implied,
     // 23 UNI
     AddressingMode::Unimplemented,
     // 24 BIT
@@ -147,8 +148,8 @@ const ADDRESSING_MODES: [AddressingMode; 256] = [
     AddressingMode::Relative,
     // 31 AND
     AddressingMode::IndirectZeroPageY,
-    // 32 UNI
-    AddressingMode::Unimplemented,
+    // 32 JAM
+    AddressingMode::Implied,
     // 33 UNI
     AddressingMode::Unimplemented,
     // 34 UNI
@@ -163,8 +164,8 @@ const ADDRESSING_MODES: [AddressingMode; 256] = [
     AddressingMode::Implied,
     // 39 AND
     AddressingMode::AbsoluteY,
-    // 3A UNI
-    AddressingMode::Unimplemented,
+    // 3A NOP
+    AddressingMode::Implied,
     // 3B UNI
     AddressingMode::Unimplemented,
     // 3C UNI
@@ -179,8 +180,8 @@ const ADDRESSING_MODES: [AddressingMode; 256] = [
     AddressingMode::Implied,
     // 41 EOR
     AddressingMode::IndirectZeroPageX,
-    // 42 UNI
-    AddressingMode::Unimplemented,
+    // 42 JAM
+    AddressingMode::Implied,
     // 43 UNI
     AddressingMode::Unimplemented,
     // 44 UNI
@@ -211,8 +212,8 @@ const ADDRESSING_MODES: [AddressingMode; 256] = [
     AddressingMode::Relative,
     // 51 EOR
     AddressingMode::IndirectZeroPageY,
-    // 52 UNI
-    AddressingMode::Unimplemented,
+    // 52 JAM
+    AddressingMode::Implied,
     // 53 UNI
     AddressingMode::Unimplemented,
     // 54 UNI
@@ -227,8 +228,8 @@ const ADDRESSING_MODES: [AddressingMode; 256] = [
     AddressingMode::Implied,
     // 59 EOR
     AddressingMode::AbsoluteY,
-    // 5A UNI
-    AddressingMode::Unimplemented,
+    // 5A NOP
+    AddressingMode::Implied,
     // 5B UNI
     AddressingMode::Unimplemented,
     // 5C UNI
@@ -243,8 +244,8 @@ const ADDRESSING_MODES: [AddressingMode; 256] = [
     AddressingMode::Implied,
     // 61 ADC
     AddressingMode::IndirectZeroPageX,
-    // 62 UNI
-    AddressingMode::Unimplemented,
+    // 62 JAM
+    AddressingMode::Implied,
     // 63 UNI
     AddressingMode::Unimplemented,
     // 64 UNI
@@ -275,8 +276,8 @@ const ADDRESSING_MODES: [AddressingMode; 256] = [
     AddressingMode::Relative,
     // 71 ADC
     AddressingMode::IndirectZeroPageY,
-    // 72 UNI
-    AddressingMode::Unimplemented,
+    // 72 JAM
+    AddressingMode::Implied,
     // 73 UNI
     AddressingMode::Unimplemented,
     // 74 UNI
@@ -291,8 +292,8 @@ const ADDRESSING_MODES: [AddressingMode; 256] = [
     AddressingMode::Implied,
     // 79 ADC
     AddressingMode::AbsoluteY,
-    // 7A UNI
-    AddressingMode::Unimplemented,
+    // 7A NOP
+    AddressingMode::Implied,
     // 7B UNI
     AddressingMode::Unimplemented,
     // 7C UNI
@@ -339,8 +340,8 @@ const ADDRESSING_MODES: [AddressingMode; 256] = [
     AddressingMode::Relative,
     // 91 STA
     AddressingMode::IndirectZeroPageY,
-    // 92 UNI
-    AddressingMode::Unimplemented,
+    // 92 JAM
+    AddressingMode::Implied,
     // 93 UNI
     AddressingMode::Unimplemented,
     // 94 STY
@@ -353,8 +354,8 @@ const ADDRESSING_MODES: [AddressingMode; 256] = [
     AddressingMode::Unimplemented,
     // 98 TYA
     AddressingMode::Implied,
-    // 99 UNI
-    AddressingMode::Unimplemented,
+    // 99 STA
+    AddressingMode::AbsoluteY,
     // 9A TXS
     AddressingMode::Implied,
     // 9B UNI
@@ -403,8 +404,8 @@ const ADDRESSING_MODES: [AddressingMode; 256] = [
     AddressingMode::Relative,
     // B1 LDA
     AddressingMode::IndirectZeroPageY,
-    // B2 UNI
-    AddressingMode::Unimplemented,
+    // B2 JAM
+    AddressingMode::Implied,
     // B3 UNI
     AddressingMode::Unimplemented,
     // B4 LDY
@@ -467,8 +468,8 @@ const ADDRESSING_MODES: [AddressingMode; 256] = [
     AddressingMode::Relative,
     // D1 CMP
     AddressingMode::IndirectZeroPageY,
-    // D2 UNI
-    AddressingMode::Unimplemented,
+    // D2 JAM
+    AddressingMode::Implied,
     // D3 UNI
     AddressingMode::Unimplemented,
     // D4 UNI
@@ -483,8 +484,8 @@ const ADDRESSING_MODES: [AddressingMode; 256] = [
     AddressingMode::Implied,
     // D9 CMP
     AddressingMode::AbsoluteY,
-    // DA UNI
-    AddressingMode::Unimplemented,
+    // DA NOP
+    AddressingMode::Implied,
     // DB UNI
     AddressingMode::Unimplemented,
     // DC UNI
@@ -531,8 +532,8 @@ const ADDRESSING_MODES: [AddressingMode; 256] = [
     AddressingMode::Relative,
     // F1 SBC
     AddressingMode::IndirectZeroPageY,
-    // F2 UNI
-    AddressingMode::Unimplemented,
+    // F2 JAM
+    AddressingMode::Implied,
     // F3 UNI
     AddressingMode::Unimplemented,
     // F4 UNI
@@ -547,8 +548,8 @@ const ADDRESSING_MODES: [AddressingMode; 256] = [
     AddressingMode::Implied,
     // F9 SBC
     AddressingMode::AbsoluteY,
-    // FA UNI
-    AddressingMode::Unimplemented,
+    // FA NOP
+    AddressingMode::Implied,
     // FB UNI
     AddressingMode::Unimplemented,
     // FC UNI
@@ -0,0 +1,1230 @@
+use crate::capabilities::Capabilities;
+use crate::region::Region;
+#[cfg(feature = "serde")]
+use crate::state::serde_state;
+use crate::state::{State, StateReader, StateWriter};
+use crate::Result;
+
+/// Lengths loaded into the length counters, indexed by bits 3-7 of the
+/// fourth register of each channel.
+#[rustfmt::skip]
+const LENGTHS: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// Pulse waveforms, one per duty setting.
+const DUTIES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+#[rustfmt::skip]
+const TRIANGLE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+/// Noise periods in CPU cycles (NTSC).
+#[rustfmt::skip]
+const NOISE_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+/// Noise periods in CPU cycles (PAL).
+#[rustfmt::skip]
+const PAL_NOISE_PERIODS: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+/// [`Apu::output`] with one pulse channel at full volume and the rest
+/// silent, a reference for the level of cartridge expansion audio.
+pub const PULSE_MAX: f32 = 95.88 / (8128.0 / 15.0 + 100.0);
+
+bitflags! {
+    /// What the frame counter does at a step of its sequence.
+    struct FrameEvent: u8 {
+        /// Clock the envelopes and the linear counter
+        const QUARTER = 0b0001;
+        /// Clock the length counters and sweeps
+        const HALF = 0b0010;
+        const IRQ = 0b0100;
+        /// Start the sequence over
+        const RESTART = 0b1000;
+    }
+}
+
+/// The frame counter sequences in CPU cycles since the start of the
+/// sequence.
+const FOUR_STEP: [(u32, FrameEvent); 6] = [
+    (7457, FrameEvent::QUARTER),
+    (14913, FrameEvent::from_bits_truncate(0b0011)),
+    (22371, FrameEvent::QUARTER),
+    (29828, FrameEvent::IRQ),
+    (29829, FrameEvent::from_bits_truncate(0b0111)),
+    (29830, FrameEvent::from_bits_truncate(0b1100)),
+];
+const FIVE_STEP: [(u32, FrameEvent); 5] = [
+    (7457, FrameEvent::QUARTER),
+    (14913, FrameEvent::from_bits_truncate(0b0011)),
+    (22371, FrameEvent::QUARTER),
+    (37281, FrameEvent::from_bits_truncate(0b0011)),
+    (37282, FrameEvent::RESTART),
+];
+const PAL_FOUR_STEP: [(u32, FrameEvent); 6] = [
+    (8313, FrameEvent::QUARTER),
+    (16627, FrameEvent::from_bits_truncate(0b0011)),
+    (24939, FrameEvent::QUARTER),
+    (33252, FrameEvent::IRQ),
+    (33253, FrameEvent::from_bits_truncate(0b0111)),
+    (33254, FrameEvent::from_bits_truncate(0b1100)),
+];
+const PAL_FIVE_STEP: [(u32, FrameEvent); 5] = [
+    (8313, FrameEvent::QUARTER),
+    (16627, FrameEvent::from_bits_truncate(0b0011)),
+    (24939, FrameEvent::QUARTER),
+    (41565, FrameEvent::from_bits_truncate(0b0011)),
+    (41566, FrameEvent::RESTART),
+];
+
+/// Counts a note down to silence unless halted.
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct LengthCounter {
+    enabled: bool,
+    halt: bool,
+    count: u8,
+}
+
+impl LengthCounter {
+    fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.count = LENGTHS[index as usize >> 3];
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.count = 0;
+        }
+    }
+
+    fn clock(&mut self) {
+        if !self.halt && self.count > 0 {
+            self.count -= 1;
+        }
+    }
+
+    fn active(&self) -> bool {
+        self.count > 0
+    }
+}
+
+impl State for LengthCounter {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.enabled);
+        state.write(&self.halt);
+        state.write(&self.count);
+    }
+
+    fn load(state: &mut StateReader) -> Result<LengthCounter> {
+        Ok(LengthCounter {
+            enabled: state.read()?,
+            halt: state.read()?,
+            count: state.read()?,
+        })
+    }
+}
+
+/// Volume envelope shared by the pulse and noise channels.
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    /// Constant volume, or the envelope period
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    /// Handle bits 0-5 of the first register of a channel.
+    fn write(&mut self, data: u8) {
+        self.looping = data & 0x20 != 0;
+        self.constant = data & 0x10 != 0;
+        self.volume = data & 0x0f;
+    }
+
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+impl State for Envelope {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.start);
+        state.write(&self.looping);
+        state.write(&self.constant);
+        state.write(&self.volume);
+        state.write(&self.divider);
+        state.write(&self.decay);
+    }
+
+    fn load(state: &mut StateReader) -> Result<Envelope> {
+        Ok(Envelope {
+            start: state.read()?,
+            looping: state.read()?,
+            constant: state.read()?,
+            volume: state.read()?,
+            divider: state.read()?,
+            decay: state.read()?,
+        })
+    }
+}
+
+/// Bends the pitch of a pulse channel.
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Sweep {
+    enabled: bool,
+    period: u8,
+    negate: bool,
+    shift: u8,
+    reload: bool,
+    divider: u8,
+}
+
+impl State for Sweep {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.enabled);
+        state.write(&self.period);
+        state.write(&self.negate);
+        state.write(&self.shift);
+        state.write(&self.reload);
+        state.write(&self.divider);
+    }
+
+    fn load(state: &mut StateReader) -> Result<Sweep> {
+        Ok(Sweep {
+            enabled: state.read()?,
+            period: state.read()?,
+            negate: state.read()?,
+            shift: state.read()?,
+            reload: state.read()?,
+            divider: state.read()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Pulse {
+    /// Pulse 1 negates in ones' complement, pulse 2 in two's complement
+    ones_complement: bool,
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+    length: LengthCounter,
+    envelope: Envelope,
+    sweep: Sweep,
+}
+
+impl Pulse {
+    fn new(ones_complement: bool) -> Pulse {
+        Pulse {
+            ones_complement,
+            duty: 0,
+            step: 0,
+            period: 0,
+            timer: 0,
+            length: LengthCounter::default(),
+            envelope: Envelope::default(),
+            sweep: Sweep::default(),
+        }
+    }
+
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.duty = data >> 6;
+                self.length.halt = data & 0x20 != 0;
+                self.envelope.write(data);
+            }
+            1 => {
+                self.sweep.enabled = data & 0x80 != 0;
+                self.sweep.period = (data >> 4) & 0x07;
+                self.sweep.negate = data & 0x08 != 0;
+                self.sweep.shift = data & 0x07;
+                self.sweep.reload = true;
+            }
+            2 => self.period = (self.period & 0x0700) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00ff) | ((data as u16 & 0x07) << 8);
+                self.length.load(data);
+                self.step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    /// Clocked every other CPU cycle.
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) & 7;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// The period the sweep unit is heading for.
+    fn sweep_target(&self) -> u16 {
+        let change = self.period >> self.sweep.shift;
+        if !self.sweep.negate {
+            self.period + change
+        } else if self.ones_complement {
+            self.period.saturating_sub(change + 1)
+        } else {
+            self.period.saturating_sub(change)
+        }
+    }
+
+    /// Whether the sweep unit silences the channel, which it does even
+    /// while disabled.
+    fn muted(&self) -> bool {
+        self.period < 8 || self.sweep_target() > 0x7ff
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep.divider == 0 && self.sweep.enabled && self.sweep.shift > 0 && !self.muted() {
+            self.period = self.sweep_target();
+        }
+        if self.sweep.divider == 0 || self.sweep.reload {
+            self.sweep.divider = self.sweep.period;
+            self.sweep.reload = false;
+        } else {
+            self.sweep.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.length.active()
+            || self.muted()
+            || DUTIES[self.duty as usize][self.step as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+impl State for Pulse {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.ones_complement);
+        state.write(&self.duty);
+        state.write(&self.step);
+        state.write(&self.period);
+        state.write(&self.timer);
+        state.write(&self.length);
+        state.write(&self.envelope);
+        state.write(&self.sweep);
+    }
+
+    fn load(state: &mut StateReader) -> Result<Pulse> {
+        Ok(Pulse {
+            ones_complement: state.read()?,
+            duty: state.read::<u8>()? & 3,
+            step: state.read::<u8>()? & 7,
+            period: state.read()?,
+            timer: state.read()?,
+            length: state.read()?,
+            envelope: state.read()?,
+            sweep: state.read()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Triangle {
+    step: u8,
+    period: u16,
+    timer: u16,
+    length: LengthCounter,
+    /// Also halts the length counter
+    control: bool,
+    linear_reload_value: u8,
+    linear_reload: bool,
+    linear: u8,
+}
+
+impl Triangle {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.control = data & 0x80 != 0;
+                self.length.halt = self.control;
+                self.linear_reload_value = data & 0x7f;
+            }
+            1 => {}
+            2 => self.period = (self.period & 0x0700) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00ff) | ((data as u16 & 0x07) << 8);
+                self.length.load(data);
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle.
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.length.active() && self.linear > 0 {
+                self.step = (self.step + 1) & 31;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_linear(&mut self) {
+        if self.linear_reload {
+            self.linear = self.linear_reload_value;
+        } else if self.linear > 0 {
+            self.linear -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    /// A silenced triangle holds its last level rather than dropping to 0.
+    fn output(&self) -> u8 {
+        TRIANGLE[self.step as usize]
+    }
+}
+
+impl State for Triangle {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.step);
+        state.write(&self.period);
+        state.write(&self.timer);
+        state.write(&self.length);
+        state.write(&self.control);
+        state.write(&self.linear_reload_value);
+        state.write(&self.linear_reload);
+        state.write(&self.linear);
+    }
+
+    fn load(state: &mut StateReader) -> Result<Triangle> {
+        Ok(Triangle {
+            step: state.read::<u8>()? & 31,
+            period: state.read()?,
+            timer: state.read()?,
+            length: state.read()?,
+            control: state.read()?,
+            linear_reload_value: state.read()?,
+            linear_reload: state.read()?,
+            linear: state.read()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Noise {
+    /// Feed back from bit 6 instead of bit 1, for a short metallic loop
+    short_mode: bool,
+    period: u16,
+    timer: u16,
+    /// 15-bit linear feedback shift register
+    shift: u16,
+    /// The region's periods, selected by $400E
+    #[cfg_attr(feature = "serde", serde(skip, default = "Noise::ntsc_periods"))]
+    periods: &'static [u16; 16],
+    length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl Default for Noise {
+    fn default() -> Noise {
+        Noise {
+            short_mode: false,
+            period: NOISE_PERIODS[0],
+            timer: 0,
+            shift: 1,
+            periods: &NOISE_PERIODS,
+            length: LengthCounter::default(),
+            envelope: Envelope::default(),
+        }
+    }
+}
+
+impl Noise {
+    /// Until the APU sets its region's, after deserializing.
+    #[cfg(feature = "serde")]
+    fn ntsc_periods() -> &'static [u16; 16] {
+        &NOISE_PERIODS
+    }
+
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.length.halt = data & 0x20 != 0;
+                self.envelope.write(data);
+            }
+            1 => {}
+            2 => {
+                self.short_mode = data & 0x80 != 0;
+                self.period = self.periods[data as usize & 0x0f];
+            }
+            _ => {
+                self.length.load(data);
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle.
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 1;
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.length.active() || self.shift & 1 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+/// The periods table is left out: it follows the APU's region.
+impl State for Noise {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.short_mode);
+        state.write(&self.period);
+        state.write(&self.timer);
+        state.write(&self.shift);
+        state.write(&self.length);
+        state.write(&self.envelope);
+    }
+
+    fn load(state: &mut StateReader) -> Result<Noise> {
+        let noise = Noise {
+            short_mode: state.read()?,
+            period: state.read()?,
+            timer: state.read()?,
+            shift: state.read()?,
+            length: state.read()?,
+            envelope: state.read()?,
+            ..Noise::default()
+        };
+        if noise.period == 0 {
+            return Err("the noise period is 0".into());
+        }
+        Ok(noise)
+    }
+}
+
+/// A tone generator, for muting with [`Apu::set_muted`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 4] = [
+        Channel::Pulse1,
+        Channel::Pulse2,
+        Channel::Triangle,
+        Channel::Noise,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Channel::Pulse1 => "pulse 1",
+            Channel::Pulse2 => "pulse 2",
+            Channel::Triangle => "triangle",
+            Channel::Noise => "noise",
+        }
+    }
+}
+
+/// The audio processing unit's tone generators, registers $4000-$400F and
+/// $4015.
+#[derive(Debug, Clone)]
+pub struct Apu {
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    /// CPU cycles since power on
+    cycle: u64,
+    /// Last value written to $4017
+    frame_control: u8,
+    /// CPU cycles into the frame counter sequence
+    frame_cycle: u32,
+    /// CPU cycles until a write to $4017 restarts the sequence
+    frame_restart: Option<u8>,
+    frame_irq: bool,
+    region: Region,
+    /// Indexed by [`Channel`]
+    muted: [bool; 4],
+}
+
+impl Apu {
+    pub fn new() -> Apu {
+        Apu {
+            pulse_1: Pulse::new(true),
+            pulse_2: Pulse::new(false),
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            cycle: 0,
+            frame_control: 0,
+            frame_cycle: 0,
+            frame_restart: None,
+            frame_irq: false,
+            region: Region::Ntsc,
+            muted: [false; 4],
+        }
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Switch to another console's frame counter timing and noise periods.
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        let periods = if region.pal_apu() {
+            &PAL_NOISE_PERIODS
+        } else {
+            &NOISE_PERIODS
+        };
+        // Keep the period $400E selected, in the new region's cycles
+        let noise = &mut self.noise;
+        if let Some(index) = noise
+            .periods
+            .iter()
+            .position(|&period| period == noise.period)
+        {
+            noise.period = periods[index];
+        }
+        noise.periods = periods;
+    }
+
+    /// Write the channels and the frame counter, for savestates. The region
+    /// and muted channels are settings and are left out.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write(&self.pulse_1);
+        state.write(&self.pulse_2);
+        state.write(&self.triangle);
+        state.write(&self.noise);
+        state.write(&self.cycle);
+        state.write(&self.frame_control);
+        state.write(&self.frame_cycle);
+        state.write(&self.frame_restart);
+        state.write(&self.frame_irq);
+    }
+
+    /// Restore what `save_state` wrote, into an APU already set to the
+    /// region it was saved in.
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.pulse_1 = state.read()?;
+        self.pulse_2 = state.read()?;
+        self.triangle = state.read()?;
+        self.noise = state.read()?;
+        self.cycle = state.read()?;
+        self.frame_control = state.read()?;
+        self.frame_cycle = state.read()?;
+        self.frame_restart = state.read()?;
+        self.frame_irq = state.read()?;
+        self.set_region(self.region);
+        Ok(())
+    }
+
+    /// Return to the state at power on, keeping the region and the muted
+    /// channels.
+    pub fn power_on(&mut self) {
+        let mut apu = Apu::new();
+        apu.set_region(self.region);
+        apu.muted = self.muted;
+        *self = apu;
+    }
+
+    /// Silence the channels and restart the frame counter in the mode last
+    /// written to $4017.
+    pub fn reset(&mut self) {
+        self.write(0x4015, 0x00);
+        self.write(0x4017, self.frame_control);
+    }
+
+    /// Whether the frame counter is asserting IRQ.
+    pub fn irq(&self) -> bool {
+        self.frame_irq
+    }
+
+    fn five_step(&self) -> bool {
+        self.frame_control & 0x80 != 0
+    }
+
+    fn irq_inhibited(&self) -> bool {
+        self.frame_control & 0x40 != 0
+    }
+
+    /// The frame counter's steps for the mode and region.
+    fn sequence(&self) -> &'static [(u32, FrameEvent)] {
+        match (self.region.pal_apu(), self.five_step()) {
+            (false, false) => &FOUR_STEP,
+            (false, true) => &FIVE_STEP,
+            (true, false) => &PAL_FOUR_STEP,
+            (true, true) => &PAL_FIVE_STEP,
+        }
+    }
+
+    /// CPU cycles that can pass before the frame counter next raises IRQ,
+    /// at least. Only register writes change it otherwise.
+    pub fn cycles_until_irq(&self) -> u64 {
+        if self.irq_inhibited() {
+            return u64::MAX;
+        }
+        let next = self
+            .sequence()
+            .iter()
+            .find(|(cycle, event)| event.contains(FrameEvent::IRQ) && *cycle > self.frame_cycle)
+            .map_or(u64::MAX, |(cycle, _)| {
+                u64::from(cycle - self.frame_cycle - 1)
+            });
+        // The restart starts the sequence over, but only after a while
+        match self.frame_restart {
+            Some(delay) => next.min(u64::from(delay)),
+            None => next,
+        }
+    }
+
+    /// Advance the frame counter by one CPU cycle.
+    fn clock_frame_counter(&mut self) {
+        if let Some(delay) = self.frame_restart {
+            if delay > 1 {
+                self.frame_restart = Some(delay - 1);
+            } else {
+                self.frame_restart = None;
+                self.frame_cycle = 0;
+                // The five step sequence starts with a half frame
+                if self.five_step() {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+                return;
+            }
+        }
+        self.frame_cycle += 1;
+        let event = self
+            .sequence()
+            .iter()
+            .find(|(cycle, _)| *cycle == self.frame_cycle)
+            .map_or(FrameEvent::empty(), |(_, event)| *event);
+        if event.contains(FrameEvent::QUARTER) {
+            self.clock_quarter_frame();
+        }
+        if event.contains(FrameEvent::HALF) {
+            self.clock_half_frame();
+        }
+        if event.contains(FrameEvent::IRQ) && !self.irq_inhibited() {
+            self.frame_irq = true;
+        }
+        if event.contains(FrameEvent::RESTART) {
+            self.frame_cycle = 0;
+        }
+    }
+
+    /// Advance by one CPU cycle.
+    pub fn step(&mut self) {
+        self.clock_frame_counter();
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        if self.cycle % 2 == 1 {
+            self.pulse_1.clock_timer();
+            self.pulse_2.clock_timer();
+        }
+        self.cycle += 1;
+    }
+
+    /// Clock the envelopes and the triangle's linear counter.
+    fn clock_quarter_frame(&mut self) {
+        self.pulse_1.envelope.clock();
+        self.pulse_2.envelope.clock();
+        self.triangle.clock_linear();
+        self.noise.envelope.clock();
+    }
+
+    /// Clock the length counters and sweep units.
+    fn clock_half_frame(&mut self) {
+        self.pulse_1.length.clock();
+        self.pulse_2.length.clock();
+        self.triangle.length.clock();
+        self.noise.length.clock();
+        self.pulse_1.clock_sweep();
+        self.pulse_2.clock_sweep();
+    }
+
+    /// Read $4015: which channels are still sounding and whether the frame
+    /// IRQ is pending, which the read acknowledges.
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.frame_irq = false;
+        status
+    }
+
+    /// What `read_status` would return, leaving the frame IRQ pending.
+    pub fn peek_status(&self) -> u8 {
+        self.pulse_1.length.active() as u8
+            | (self.pulse_2.length.active() as u8) << 1
+            | (self.triangle.length.active() as u8) << 2
+            | (self.noise.length.active() as u8) << 3
+            | (self.frame_irq as u8) << 6
+    }
+
+    pub fn write(&mut self, address: u16, data: u8) {
+        let register = address & 0x03;
+        match address {
+            0x4000..=0x4003 => self.pulse_1.write(register, data),
+            0x4004..=0x4007 => self.pulse_2.write(register, data),
+            0x4008..=0x400b => self.triangle.write(register, data),
+            0x400c..=0x400f => self.noise.write(register, data),
+            0x4015 => {
+                self.pulse_1.length.set_enabled(data & 0x01 != 0);
+                self.pulse_2.length.set_enabled(data & 0x02 != 0);
+                self.triangle.length.set_enabled(data & 0x04 != 0);
+                self.noise.length.set_enabled(data & 0x08 != 0);
+            }
+            0x4017 => {
+                self.frame_control = data;
+                if self.irq_inhibited() {
+                    self.frame_irq = false;
+                }
+                // The sequence restarts 3 or 4 cycles later, depending on
+                // where the write falls in the APU's two-cycle period
+                self.frame_restart = Some(if self.cycle % 2 == 1 { 3 } else { 4 });
+            }
+            // DMC
+            _ => {}
+        }
+    }
+
+    /// The current level of each channel, 0-15: pulse 1, pulse 2, triangle
+    /// and noise. Muting does not affect these.
+    pub fn channels(&self) -> [u8; 4] {
+        [
+            self.pulse_1.output(),
+            self.pulse_2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+        ]
+    }
+
+    /// The mixed output, 0.0-1.0, using the approximation of the DACs'
+    /// non-linear response from the NESdev wiki. Muted channels count as
+    /// silent.
+    pub fn output(&self) -> f32 {
+        let mut levels = self.channels().map(f32::from);
+        for (level, &muted) in levels.iter_mut().zip(&self.muted) {
+            if muted {
+                *level = 0.0;
+            }
+        }
+        let [pulse_1, pulse_2, triangle, noise] = levels;
+        let pulse = if pulse_1 + pulse_2 == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / (pulse_1 + pulse_2) + 100.0)
+        };
+        let tnd = triangle / 8227.0 + noise / 12241.0;
+        let tnd = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+        pulse + tnd
+    }
+
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel as usize] = muted;
+    }
+
+    pub fn is_muted(&self, channel: Channel) -> bool {
+        self.muted[channel as usize]
+    }
+
+    /// Mute every channel but `channel`.
+    pub fn solo(&mut self, channel: Channel) {
+        for other in Channel::ALL {
+            self.set_muted(other, other != channel);
+        }
+    }
+
+    pub fn unmute_all(&mut self) {
+        self.muted = [false; 4];
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Apu {
+        Apu::new()
+    }
+}
+
+#[cfg(feature = "serde")]
+serde_state!(Apu {
+    pulse_1,
+    pulse_2,
+    triangle,
+    noise,
+    cycle,
+    frame_control,
+    frame_cycle,
+    frame_restart,
+    frame_irq,
+});
+
+/// Add the APU's tone generators.
+pub(crate) fn register_capabilities(capabilities: &mut Capabilities) {
+    capabilities
+        .audio_channels
+        .extend(Channel::ALL.iter().map(|channel| channel.name()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(apu: &mut Apu, cycles: u64) {
+        for _ in 0..cycles {
+            apu.step();
+        }
+    }
+
+    #[test]
+    fn length_counters_need_the_channel_enabled() {
+        let mut apu = Apu::new();
+        apu.write(0x4003, 0x08);
+        assert_eq!(apu.read_status(), 0x00);
+
+        apu.write(0x4015, 0x0f);
+        apu.write(0x4003, 0x08);
+        apu.write(0x4007, 0x08);
+        apu.write(0x400b, 0x08);
+        apu.write(0x400f, 0x08);
+        assert_eq!(apu.read_status(), 0x0f);
+        assert_eq!(apu.pulse_1.length.count, 254);
+
+        apu.write(0x4015, 0x0a);
+        assert_eq!(apu.read_status(), 0x0a);
+    }
+
+    #[test]
+    fn length_counter_halts() {
+        let mut apu = Apu::new();
+        apu.write(0x4015, 0x01);
+        apu.write(0x4000, 0x20);
+        apu.write(0x4003, 0x18);
+        apu.clock_half_frame();
+        assert_eq!(apu.pulse_1.length.count, 2);
+        apu.write(0x4000, 0x00);
+        apu.clock_half_frame();
+        apu.clock_half_frame();
+        assert_eq!(apu.read_status(), 0x00);
+    }
+
+    #[test]
+    fn pulse_plays_its_duty_cycle() {
+        let mut apu = Apu::new();
+        apu.write(0x4015, 0x01);
+        // 25% duty, constant volume 9, period 8
+        apu.write(0x4000, 0x59);
+        apu.write(0x4002, 0x08);
+        apu.write(0x4003, 0x08);
+        let mut levels = Vec::new();
+        for _ in 0..8 {
+            levels.push(apu.channels()[0]);
+            run(&mut apu, 18);
+        }
+        assert_eq!(levels, [0, 9, 9, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn sweep_mutes_and_bends() {
+        let mut apu = Apu::new();
+        apu.write(0x4015, 0x03);
+        apu.write(0x4000, 0x1f);
+        apu.write(0x4004, 0x1f);
+        // Periods below 8 are silent
+        apu.write(0x4002, 0x07);
+        assert!(apu.pulse_1.muted());
+        // So are targets above $7FF, even with the sweep disabled
+        apu.write(0x4002, 0xff);
+        apu.write(0x4003, 0x07);
+        assert!(apu.pulse_1.muted());
+
+        // Pulse 1 subtracts one more than pulse 2
+        for address in [0x4001, 0x4005] {
+            apu.write(address, 0x89);
+        }
+        for address in [0x4002, 0x4006] {
+            apu.write(address, 0x00);
+        }
+        for address in [0x4003, 0x4007] {
+            apu.write(address, 0x01);
+        }
+        apu.clock_half_frame();
+        assert_eq!(apu.pulse_1.period, 0x100 - 0x81);
+        assert_eq!(apu.pulse_2.period, 0x100 - 0x80);
+    }
+
+    /// CPU cycles until the frame IRQ is raised, counting from now.
+    fn cycles_to_irq(apu: &mut Apu, limit: u64) -> Option<u64> {
+        (1..=limit).find(|_| {
+            apu.step();
+            apu.irq()
+        })
+    }
+
+    #[test]
+    fn four_step_sequence_raises_irq() {
+        let mut apu = Apu::new();
+        apu.write(0x4017, 0x00);
+        assert_eq!(cycles_to_irq(&mut apu, 40_000), Some(4 + 29828));
+        // Reading the status acknowledges it, but the flag is set again on
+        // the next two cycles
+        assert_eq!(apu.read_status(), 0x40);
+        assert_eq!(apu.read_status(), 0x00);
+        assert_eq!(cycles_to_irq(&mut apu, 2), Some(1));
+        apu.read_status();
+        assert_eq!(cycles_to_irq(&mut apu, 2), Some(1));
+        apu.read_status();
+        assert_eq!(cycles_to_irq(&mut apu, 40_000), Some(29828));
+    }
+
+    #[test]
+    fn pal_frame_counter_and_noise() {
+        let mut apu = Apu::new();
+        apu.set_region(Region::Pal);
+        apu.write(0x4017, 0x00);
+        assert_eq!(cycles_to_irq(&mut apu, 40_000), Some(4 + 33252));
+
+        apu.write(0x400e, 0x0f);
+        assert_eq!(apu.noise.period, 3778);
+        apu.set_region(Region::Dendy);
+        assert_eq!(apu.noise.period, 4068);
+        apu.write(0x400e, 0x0e);
+        assert_eq!(apu.noise.period, 2034);
+    }
+
+    #[test]
+    fn predicts_the_frame_irq() {
+        let mut apu = Apu::new();
+        apu.write(0x4017, 0x00);
+        // The restart is 4 cycles away, then the sequence starts over
+        assert_eq!(apu.cycles_until_irq(), 4);
+        for _ in 0..4 {
+            apu.step();
+        }
+        assert_eq!(apu.cycles_until_irq(), 29827);
+        assert_eq!(cycles_to_irq(&mut apu, 40_000), Some(29828));
+
+        apu.write(0x4017, 0x40);
+        assert_eq!(apu.cycles_until_irq(), u64::MAX);
+    }
+
+    #[test]
+    fn write_delay_depends_on_the_cycle() {
+        let mut apu = Apu::new();
+        apu.step();
+        apu.write(0x4017, 0x00);
+        assert_eq!(cycles_to_irq(&mut apu, 40_000), Some(3 + 29828));
+    }
+
+    #[test]
+    fn irq_inhibit_and_five_step_mode() {
+        let mut apu = Apu::new();
+        apu.write(0x4017, 0x40);
+        assert_eq!(cycles_to_irq(&mut apu, 100_000), None);
+
+        apu.write(0x4017, 0x00);
+        cycles_to_irq(&mut apu, 40_000);
+        apu.write(0x4017, 0x40);
+        assert!(!apu.irq());
+
+        apu.write(0x4017, 0x80);
+        assert_eq!(cycles_to_irq(&mut apu, 100_000), None);
+    }
+
+    #[test]
+    fn five_step_mode_clocks_at_once() {
+        let mut apu = Apu::new();
+        apu.write(0x4015, 0x01);
+        apu.write(0x4003, 0x18);
+        apu.write(0x4017, 0x80);
+        assert_eq!(apu.pulse_1.length.count, 2);
+        run(&mut apu, 4);
+        assert_eq!(apu.pulse_1.length.count, 1);
+        // Then twice per 37282 cycles
+        run(&mut apu, 14913);
+        assert_eq!(apu.read_status() & 0x01, 0x00);
+    }
+
+    #[test]
+    fn mixer_is_non_linear() {
+        let mut apu = Apu::new();
+        // The triangle holds its level while silenced
+        assert!((apu.output() - 0.2464).abs() < 0.0001);
+        apu.set_muted(Channel::Triangle, true);
+        assert_eq!(apu.output(), 0.0);
+
+        apu.write(0x4015, 0x01);
+        apu.write(0x4000, 0x3f);
+        apu.write(0x4002, 0x10);
+        apu.write(0x4003, 0x08);
+        // Step into the high part of the duty cycle
+        while apu.channels()[0] == 0 {
+            apu.step();
+        }
+        let one = apu.output();
+        assert!((one - 0.1494).abs() < 0.0001);
+
+        apu.write(0x4015, 0x03);
+        apu.pulse_2 = apu.pulse_1;
+        apu.pulse_2.ones_complement = false;
+        let both = apu.output();
+        assert!((both - 0.2585).abs() < 0.0001);
+        assert!(both < one * 2.0);
+    }
+
+    #[test]
+    fn mute_and_solo() {
+        let mut apu = Apu::new();
+        apu.write(0x4015, 0x03);
+        apu.write(0x4000, 0x3f);
+        apu.write(0x4002, 0x10);
+        apu.write(0x4003, 0x08);
+        while apu.channels()[0] == 0 {
+            apu.step();
+        }
+        apu.pulse_2 = apu.pulse_1;
+        apu.pulse_2.ones_complement = false;
+        let both = apu.output();
+
+        apu.solo(Channel::Pulse1);
+        assert!(!apu.is_muted(Channel::Pulse1));
+        assert!(apu.is_muted(Channel::Noise));
+        let solo = apu.output();
+        assert!(solo > 0.0 && solo < both);
+        assert_eq!(apu.channels()[1], 15);
+
+        apu.set_muted(Channel::Pulse1, true);
+        assert_eq!(apu.output(), 0.0);
+
+        apu.unmute_all();
+        assert_eq!(apu.output(), both);
+    }
+
+    #[test]
+    fn envelope_decays_and_loops() {
+        let mut envelope = Envelope::default();
+        envelope.write(0x21);
+        envelope.start = true;
+        let mut levels = Vec::new();
+        for _ in 0..34 {
+            envelope.clock();
+            levels.push(envelope.output());
+        }
+        assert_eq!(levels[..4], [15, 15, 14, 14]);
+        assert_eq!(levels[30..], [0, 0, 15, 15]);
+    }
+
+    #[test]
+    fn triangle_needs_linear_and_length_counters() {
+        let mut apu = Apu::new();
+        apu.write(0x4015, 0x04);
+        apu.write(0x400a, 0x00);
+        apu.write(0x400b, 0x08);
+        run(&mut apu, 10);
+        assert_eq!(apu.triangle.step, 0);
+
+        apu.write(0x4008, 0x02);
+        apu.write(0x400b, 0x08);
+        apu.clock_quarter_frame();
+        run(&mut apu, 10);
+        assert_eq!(apu.triangle.step, 10);
+        apu.clock_quarter_frame();
+        apu.clock_quarter_frame();
+        run(&mut apu, 10);
+        assert_eq!(apu.triangle.step, 10);
+        assert_eq!(apu.channels()[2], 5);
+    }
+
+    #[test]
+    fn noise_shift_register() {
+        let mut noise = Noise::default();
+        let mut bits = Vec::new();
+        for _ in 0..20 {
+            noise.clock_timer();
+            noise.timer = 0;
+            bits.push(noise.shift & 1);
+        }
+        // Bit 0 of the shift register for the first twenty shifts from 1
+        assert_eq!(
+            bits,
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0]
+        );
+
+        let mut noise = Noise::default();
+        noise.write(2, 0x80);
+        let mut states = Vec::new();
+        for _ in 0..93 {
+            states.push(noise.shift);
+            noise.clock_timer();
+            noise.timer = 0;
+        }
+        assert_eq!(noise.shift, states[0]);
+    }
+}
@@ -0,0 +1,183 @@
+//! ROMs inside .zip and .7z archives, which is how most collections are
+//! stored.
+//!
+//! Reading an archive needs the `zip` or `sevenz` feature. Without it, an
+//! archive is reported as such rather than as a bad iNES header.
+
+use crate::Result;
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const SEVENZ_MAGIC: &[u8] = b"7z\xbc\xaf\x27\x1c";
+
+/// The largest ROM extracted, well past any cartridge. The sizes archives
+/// give for their files are not trusted.
+#[cfg(any(feature = "zip", feature = "sevenz"))]
+const MAX_ROM_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The single .nes file in `bytes` if it is an archive, or `bytes` as
+/// they are otherwise.
+pub fn extract_rom(bytes: Vec<u8>) -> Result<Vec<u8>> {
+    if bytes.starts_with(ZIP_MAGIC) {
+        extract_zip(&bytes)
+    } else if bytes.starts_with(SEVENZ_MAGIC) {
+        extract_sevenz(&bytes)
+    } else {
+        Ok(bytes)
+    }
+}
+
+#[cfg(any(feature = "zip", feature = "sevenz"))]
+fn is_rom(name: &str) -> bool {
+    name.to_ascii_lowercase().ends_with(".nes")
+}
+
+/// Read `name` from the archive, giving up past `MAX_ROM_SIZE`.
+#[cfg(any(feature = "zip", feature = "sevenz"))]
+fn read_rom(name: &str, reader: impl std::io::Read) -> std::io::Result<Vec<u8>> {
+    use std::io::{Error, ErrorKind, Read};
+
+    let mut rom = Vec::new();
+    reader.take(MAX_ROM_SIZE + 1).read_to_end(&mut rom)?;
+    if rom.len() as u64 > MAX_ROM_SIZE {
+        let message = format!("{} is larger than {} MiB", name, MAX_ROM_SIZE >> 20);
+        return Err(Error::new(ErrorKind::InvalidData, message));
+    }
+    Ok(rom)
+}
+
+/// The ROM among `roms`, as names and contents, if there is just one.
+#[cfg(any(feature = "zip", feature = "sevenz"))]
+fn single_rom(mut roms: Vec<(String, Vec<u8>)>) -> Result<Vec<u8>> {
+    match roms.len() {
+        0 => Err("the archive has no .nes file".into()),
+        1 => Ok(roms.remove(0).1),
+        _ => {
+            let names: Vec<_> = roms.into_iter().map(|(name, _)| name).collect();
+            Err(format!(
+                "the archive has more than one .nes file: {}",
+                names.join(", ")
+            )
+            .into())
+        }
+    }
+}
+
+#[cfg(feature = "zip")]
+fn extract_zip(bytes: &[u8]) -> Result<Vec<u8>> {
+    use std::io::Cursor;
+
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+    let mut roms = Vec::new();
+    for index in 0..archive.len() {
+        let file = archive.by_index(index)?;
+        if file.is_dir() || !is_rom(file.name()) {
+            continue;
+        }
+        let name = file.name().to_string();
+        let rom = read_rom(&name, file)?;
+        roms.push((name, rom));
+    }
+    single_rom(roms)
+}
+
+#[cfg(not(feature = "zip"))]
+fn extract_zip(_bytes: &[u8]) -> Result<Vec<u8>> {
+    Err("opening .zip files needs the `zip` feature".into())
+}
+
+#[cfg(feature = "sevenz")]
+fn extract_sevenz(bytes: &[u8]) -> Result<Vec<u8>> {
+    use sevenz_rust::{Password, SevenZReader};
+    use std::io::Cursor;
+
+    let mut archive = SevenZReader::new(Cursor::new(bytes), bytes.len() as u64, Password::empty())?;
+    let mut roms = Vec::new();
+    archive.for_each_entries(|entry, reader| {
+        if !entry.is_directory() && is_rom(entry.name()) {
+            let rom = read_rom(entry.name(), reader)?;
+            roms.push((entry.name().to_string(), rom));
+        }
+        Ok(true)
+    })?;
+    single_rom(roms)
+}
+
+#[cfg(not(feature = "sevenz"))]
+fn extract_sevenz(_bytes: &[u8]) -> Result<Vec<u8>> {
+    Err("opening .7z files needs the `sevenz` feature".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn other_files_pass_through() {
+        let bytes = b"NES\x1a".to_vec();
+        assert_eq!(extract_rom(bytes.clone()).unwrap(), bytes);
+    }
+
+    #[cfg(feature = "zip")]
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        use std::io::{Cursor, Write};
+        use zip::write::{FileOptions, ZipWriter};
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for (name, data) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn zip_with_one_rom() {
+        let archive = zip(&[("readme.txt", b"hi"), ("Game (USA).NES", b"NES\x1a")]);
+        assert_eq!(extract_rom(archive).unwrap(), b"NES\x1a");
+
+        let archive = zip(&[("a.nes", b"NES\x1a"), ("b.nes", b"NES\x1a")]);
+        assert!(extract_rom(archive).is_err());
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn oversized_roms_are_rejected() {
+        let rom = vec![0; MAX_ROM_SIZE as usize + 1];
+        let error = extract_rom(zip(&[("huge.nes", &rom)])).unwrap_err();
+        assert!(error.to_string().contains("larger than 16 MiB"));
+    }
+
+    #[cfg(feature = "sevenz")]
+    fn sevenz(files: &[(&str, &[u8])]) -> Vec<u8> {
+        use sevenz_rust::{SevenZArchiveEntry, SevenZWriter};
+        use std::io::Cursor;
+
+        let mut writer = SevenZWriter::new(Cursor::new(Vec::new())).unwrap();
+        for (name, data) in files {
+            let mut entry = SevenZArchiveEntry::new();
+            entry.name = name.to_string();
+            entry.has_stream = true;
+            writer.push_archive_entry(entry, Some(*data)).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[cfg(feature = "sevenz")]
+    #[test]
+    fn sevenz_with_one_rom() {
+        let archive = sevenz(&[("readme.txt", b"hi"), ("Game (USA).NES", b"NES\x1a")]);
+        assert_eq!(extract_rom(archive).unwrap(), b"NES\x1a");
+
+        let archive = sevenz(&[("a.nes", b"NES\x1a"), ("b.nes", b"NES\x1a")]);
+        assert!(extract_rom(archive).is_err());
+    }
+
+    #[cfg(not(feature = "zip"))]
+    #[test]
+    fn zip_needs_the_feature() {
+        let error = extract_rom(b"PK\x03\x04".to_vec()).unwrap_err();
+        assert!(error.to_string().contains("`zip` feature"));
+    }
+}
@@ -14,8 +14,7 @@ fn main() {
         let addressing_mode = cols.next().unwrap();
         ops[opcode as usize] = (String::from(mnemnoic), String::from(addressing_mode));
     }
-    for opcode in 0..256 {
-        let (mnemnoic, addressing_mode) = &ops[opcode];
+    for (opcode, (mnemnoic, addressing_mode)) in ops.iter().enumerate() {
         println!(
             "{:02X} {} {}",
             opcode,
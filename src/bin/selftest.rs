@@ -0,0 +1,196 @@
+//! Run a battery of tiny built-in CPU programs and print a pass/fail table.
+//!
+//! ```text
+//! cargo run --bin selftest
+//! ```
+//!
+//! Each program is loaded at $0600 with [`Console::load_raw_program`],
+//! stores its results from $0200 on and ends in a `JMP *` loop.
+
+use nes::console::Console;
+use std::process;
+
+const ORIGIN: u16 = 0x0600;
+const RESULTS: u16 = 0x0200;
+const MAX_STEPS: usize = 10_000;
+
+struct Check {
+    name: &'static str,
+    program: Vec<u8>,
+    expected: &'static [u8],
+}
+
+/// Append a `JMP *` so the program ends in a loop.
+fn halt(program: &[u8]) -> Vec<u8> {
+    let mut program = program.to_vec();
+    let [low, high] = (ORIGIN + program.len() as u16).to_le_bytes();
+    program.extend_from_slice(&[0x4c, low, high]);
+    program
+}
+
+#[rustfmt::skip]
+fn checks() -> Vec<Check> {
+    vec![
+        Check {
+            name: "ADC sets N and V on signed overflow",
+            program: halt(&[
+                0x18,             // CLC
+                0xa9, 0x7f,       // LDA #$7F
+                0x69, 0x01,       // ADC #$01
+                0x8d, 0x00, 0x02, // STA $0200
+                0x08,             // PHP
+                0x68,             // PLA
+                0x29, 0xc3,       // AND #$C3
+                0x8d, 0x01, 0x02, // STA $0201
+            ]),
+            expected: &[0x80, 0xc0],
+        },
+        Check {
+            name: "SBC borrows",
+            program: halt(&[
+                0x38,             // SEC
+                0xa9, 0x00,       // LDA #$00
+                0xe9, 0x01,       // SBC #$01
+                0x8d, 0x00, 0x02, // STA $0200
+                0x08,             // PHP
+                0x68,             // PLA
+                0x29, 0xc3,       // AND #$C3
+                0x8d, 0x01, 0x02, // STA $0201
+            ]),
+            expected: &[0xff, 0x80],
+        },
+        Check {
+            name: "CMP sets Z and C on equal",
+            program: halt(&[
+                0xa9, 0x40,       // LDA #$40
+                0xc9, 0x40,       // CMP #$40
+                0x08,             // PHP
+                0x68,             // PLA
+                0x29, 0xc3,       // AND #$C3
+                0x8d, 0x00, 0x02, // STA $0200
+            ]),
+            expected: &[0x03],
+        },
+        Check {
+            name: "BIT copies N and V from memory",
+            program: halt(&[
+                0xa9, 0xc0,       // LDA #$C0
+                0x85, 0x10,       // STA $10
+                0xa9, 0x00,       // LDA #$00
+                0x24, 0x10,       // BIT $10
+                0x08,             // PHP
+                0x68,             // PLA
+                0x29, 0xc3,       // AND #$C3
+                0x8d, 0x00, 0x02, // STA $0200
+            ]),
+            expected: &[0xc2],
+        },
+        Check {
+            name: "Shifts and rotates through carry",
+            program: halt(&[
+                0xa9, 0x81,       // LDA #$81
+                0x0a,             // ASL A
+                0x2a,             // ROL A
+                0x8d, 0x00, 0x02, // STA $0200
+                0x4a,             // LSR A
+                0x6a,             // ROR A
+                0x8d, 0x01, 0x02, // STA $0201
+            ]),
+            expected: &[0x05, 0x81],
+        },
+        Check {
+            name: "Stack pushes and pulls in LIFO order",
+            program: halt(&[
+                0xba,             // TSX
+                0x86, 0x10,       // STX $10
+                0xa9, 0x11,       // LDA #$11
+                0x48,             // PHA
+                0xa9, 0x22,       // LDA #$22
+                0x48,             // PHA
+                0x68,             // PLA
+                0x8d, 0x00, 0x02, // STA $0200
+                0x68,             // PLA
+                0x8d, 0x01, 0x02, // STA $0201
+                0xba,             // TSX
+                0x8a,             // TXA
+                0x38,             // SEC
+                0xe5, 0x10,       // SBC $10
+                0x8d, 0x02, 0x02, // STA $0202
+            ]),
+            expected: &[0x22, 0x11, 0x00],
+        },
+        Check {
+            name: "JSR and RTS",
+            program: vec![
+                0x20, 0x09, 0x06, // JSR $0609
+                0x8d, 0x01, 0x02, // STA $0201
+                0x4c, 0x06, 0x06, // JMP $0606
+                0xa9, 0x5a,       // LDA #$5A
+                0x8d, 0x00, 0x02, // STA $0200
+                0x60,             // RTS
+            ],
+            expected: &[0x5a, 0x5a],
+        },
+        Check {
+            name: "Counted loop with BNE",
+            program: halt(&[
+                0xa2, 0x03,       // LDX #$03
+                0xa9, 0x00,       // LDA #$00
+                0x18,             // CLC
+                0x69, 0x05,       // ADC #$05
+                0xca,             // DEX
+                0xd0, 0xfa,       // BNE $0604
+                0x8d, 0x00, 0x02, // STA $0200
+            ]),
+            expected: &[0x0f],
+        },
+    ]
+}
+
+/// Run `check` until it loops in place, returning its results.
+fn run(check: &Check) -> Result<Vec<u8>, String> {
+    let mut console =
+        Console::load_raw_program(&check.program, ORIGIN, ORIGIN).map_err(|err| err.to_string())?;
+    console.soft_reset();
+    for _ in 0..MAX_STEPS {
+        let pc = console.registers().pc;
+        console.step();
+        if console.registers().pc == pc {
+            let end = RESULTS + check.expected.len() as u16;
+            return Ok(console.read_range(RESULTS..end));
+        }
+    }
+    Err(format!("no JMP * loop after {} steps", MAX_STEPS))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn main() {
+    let checks = checks();
+    let mut failures = 0;
+    for check in &checks {
+        let (outcome, detail) = match run(check) {
+            Ok(results) if results == check.expected => ("pass", String::new()),
+            Ok(results) => (
+                "FAIL",
+                format!("expected {}, got {}", hex(check.expected), hex(&results)),
+            ),
+            Err(err) => ("FAIL", err),
+        };
+        if outcome != "pass" {
+            failures += 1;
+        }
+        let line = format!("{:4}  {:40}  {}", outcome, check.name, detail);
+        println!("{}", line.trim_end());
+    }
+    println!("{}/{} passed", checks.len() - failures, checks.len());
+    if failures > 0 {
+        process::exit(1);
+    }
+}
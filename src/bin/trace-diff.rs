@@ -0,0 +1,155 @@
+//! Find where two CPU trace logs first diverge.
+//!
+//! ```text
+//! cargo run --bin trace-diff -- [--context N] [--ignore-cycles] a.log b.log
+//! ```
+//!
+//! Either log may be written by this emulator, nestest or Mesen. Lines that
+//! are not trace lines are skipped, and the rest are compared instruction by
+//! instruction.
+
+use nes::debugger::TraceRecord;
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::process;
+
+const USAGE: &str = "usage: trace-diff [--context N] [--ignore-cycles] a.log b.log";
+
+struct Log {
+    path: String,
+    /// Line number and text of each trace line
+    lines: Vec<(usize, String)>,
+    records: Vec<TraceRecord>,
+}
+
+impl Log {
+    fn read(path: String) -> Log {
+        let text = fs::read_to_string(&path).unwrap_or_else(|err| {
+            eprintln!("cannot read {}: {}", path, err);
+            process::exit(2);
+        });
+        let mut lines = Vec::new();
+        let mut records = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            if let Some(record) = TraceRecord::parse(line) {
+                lines.push((index + 1, line.to_string()));
+                records.push(record);
+            }
+        }
+        Log {
+            path,
+            lines,
+            records,
+        }
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn field(record: &TraceRecord, name: &str) -> String {
+    match name {
+        "PC" => format!("{:04X}", record.pc),
+        "A" => format!("{:02X}", record.a),
+        "X" => format!("{:02X}", record.x),
+        "Y" => format!("{:02X}", record.y),
+        "P" => format!("{:02X}", record.ps),
+        "SP" => format!("{:02X}", record.sp),
+        "CYC" => record
+            .cycle
+            .map_or_else(String::new, |cycle| cycle.to_string()),
+        _ => unreachable!(),
+    }
+}
+
+fn main() {
+    let mut context = 5;
+    let mut compare_cycles = true;
+    let mut paths = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--context" => {
+                context = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or_else(|| usage())
+            }
+            "--ignore-cycles" => compare_cycles = false,
+            _ if arg.starts_with("--") => usage(),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.len() != 2 {
+        usage();
+    }
+    let b = Log::read(paths.pop().unwrap());
+    let a = Log::read(paths.pop().unwrap());
+
+    let (red, green, reset) = if io::stdout().is_terminal() {
+        ("\x1b[31m", "\x1b[32m", "\x1b[0m")
+    } else {
+        ("", "", "")
+    };
+
+    let len = a.records.len().min(b.records.len());
+    let divergence = (0..len).find(|&index| {
+        !a.records[index]
+            .differences(&b.records[index], compare_cycles)
+            .is_empty()
+    });
+    let index = match divergence {
+        Some(index) => index,
+        None if a.records.len() == b.records.len() => {
+            println!("no differences in {} instructions", len);
+            return;
+        }
+        None => {
+            let shorter = if a.records.len() < b.records.len() {
+                &a.path
+            } else {
+                &b.path
+            };
+            println!(
+                "no differences in {} instructions, then {} ends",
+                len, shorter
+            );
+            process::exit(1);
+        }
+    };
+
+    let fields = a.records[index].differences(&b.records[index], compare_cycles);
+    let (a_line, a_text) = &a.lines[index];
+    let (b_line, b_text) = &b.lines[index];
+    println!(
+        "first divergence at instruction {} ({}:{}, {}:{})",
+        index + 1,
+        a.path,
+        a_line,
+        b.path,
+        b_line
+    );
+    println!();
+    for (_, text) in &a.lines[index.saturating_sub(context)..index] {
+        println!("  {}", text);
+    }
+    println!("{}- {}{}", red, a_text, reset);
+    println!("{}+ {}{}", green, b_text, reset);
+    println!();
+    for name in fields {
+        println!(
+            "{:>4}: {}{}{} != {}{}{}",
+            name,
+            red,
+            field(&a.records[index], name),
+            reset,
+            green,
+            field(&b.records[index], name),
+            reset
+        );
+    }
+    process::exit(1);
+}
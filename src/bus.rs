@@ -1,28 +1,158 @@
+use crate::cpu::IrqSource;
 use std::ops;
 
 pub trait Bus {
     fn read(&mut self, address: u16) -> u8;
     fn write(&mut self, address: u16, data: u8);
 
+    /// What `read` would return, without the side effects reading some
+    /// registers has, for debuggers and disassemblers.
+    fn peek(&self, address: u16) -> u8;
+
+    /// Fill `buffer` with the bytes starting at `address`, wrapping around
+    /// after $FFFF.
+    ///
+    /// The default reads one byte at a time; buses that can copy whole
+    /// regions should override it.
+    fn read_into(&mut self, address: u16, buffer: &mut [u8]) {
+        for (offset, data) in buffer.iter_mut().enumerate() {
+            *data = self.read(address.wrapping_add(offset as u16));
+        }
+    }
+
+    /// The bytes at the addresses in `range`, which may run up to and
+    /// include $FFFF.
     fn read_range<R: ops::RangeBounds<u16>>(&mut self, range: R) -> Vec<u8> {
-        let start = match range.start_bound() {
-            ops::Bound::Included(address) => *address,
-            ops::Bound::Excluded(address) => *address - 1,
-            ops::Bound::Unbounded => u16::MIN,
-        };
-        let end = match range.end_bound() {
-            ops::Bound::Included(address) => *address,
-            ops::Bound::Excluded(address) => *address - 1,
-            ops::Bound::Unbounded => u16::MAX,
-        };
-        if start > end {
-            return vec![];
+        let addresses = addresses(&range);
+        let mut data = vec![0; addresses.len()];
+        self.read_into(addresses.start as u16, &mut data);
+        data
+    }
+
+    /// Write `data` to the bytes starting at `address`, wrapping around
+    /// after $FFFF, as when loading a program into RAM.
+    fn load_slice_at(&mut self, address: u16, data: &[u8]) {
+        for (offset, &data) in data.iter().enumerate() {
+            self.write(address.wrapping_add(offset as u16), data);
+        }
+    }
+
+    /// Write `data` to the addresses in `range`, which must be as many.
+    fn write_range<R: ops::RangeBounds<u16>>(&mut self, range: R, data: &[u8]) {
+        let addresses = addresses(&range);
+        assert_eq!(
+            addresses.len(),
+            data.len(),
+            "{} bytes for {} addresses",
+            data.len(),
+            addresses.len()
+        );
+        self.load_slice_at(addresses.start as u16, data);
+    }
+
+    /// The little-endian word at `address` and the byte after it, which
+    /// wraps around to $0000 after $FFFF.
+    fn read_u16_le(&mut self, address: u16) -> u16 {
+        let low = self.read(address);
+        let high = self.read(address.wrapping_add(1));
+        u16::from_le_bytes([low, high])
+    }
+
+    /// The interrupt inputs the bus drives, as they stand `cycles` CPU
+    /// cycles into the step in progress. The CPU polls them before acting
+    /// on an interrupt, so a bus that runs other chips can report edges
+    /// that arrive mid-instruction.
+    ///
+    /// The default is `None`, leaving the inputs to the CPU's owner.
+    fn interrupt_lines(&mut self, _cycles: u64) -> Option<InterruptLines> {
+        None
+    }
+}
+
+/// The interrupt inputs reported by [`Bus::interrupt_lines`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptLines {
+    /// Level of the NMI input
+    pub nmi: bool,
+    /// The IRQ sources the bus drives. Other sources keep what was set on
+    /// the CPU.
+    pub driven: IrqSource,
+    /// Those of `driven` asserting IRQ
+    pub irq: IrqSource,
+}
+
+/// The addresses in `range`, widened so that the end of a range ending at
+/// $FFFF fits.
+pub(crate) fn addresses<R: ops::RangeBounds<u16>>(range: &R) -> ops::Range<u32> {
+    let start = match range.start_bound() {
+        ops::Bound::Included(&address) => address as u32,
+        ops::Bound::Excluded(&address) => address as u32 + 1,
+        ops::Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        ops::Bound::Included(&address) => address as u32 + 1,
+        ops::Bound::Excluded(&address) => address as u32,
+        ops::Bound::Unbounded => 0x10000,
+    };
+    start..end.max(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Ram(Vec<u8>);
+
+    impl Bus for Ram {
+        fn read(&mut self, address: u16) -> u8 {
+            self.peek(address)
         }
-        let length = end as usize - start as usize;
-        let mut v = Vec::with_capacity(length);
-        for address in start..=end {
-            v.push(self.read(address));
+
+        fn write(&mut self, address: u16, data: u8) {
+            self.0[address as usize] = data;
+        }
+
+        fn peek(&self, address: u16) -> u8 {
+            self.0[address as usize]
         }
-        v
+    }
+
+    fn ram() -> Ram {
+        Ram((0..=0xffff).map(|address: u32| address as u8).collect())
+    }
+
+    #[test]
+    fn read_range_bounds() {
+        let mut ram = ram();
+        assert_eq!(ram.read_range(0x10..0x13), [0x10, 0x11, 0x12]);
+        assert_eq!(ram.read_range(0x10..=0x12), [0x10, 0x11, 0x12]);
+        assert_eq!(ram.read_range(0xfffe..), [0xfe, 0xff]);
+        assert_eq!(ram.read_range(..0x02), [0x00, 0x01]);
+        assert_eq!(ram.read_range(..).len(), 0x10000);
+        assert!(ram.read_range(0x00..0x00).is_empty());
+        let (start, end) = (0x12, 0x10);
+        assert!(ram.read_range(start..end).is_empty());
+
+        use std::ops::Bound::{Excluded, Included};
+        let range = (Excluded(0x10), Included(0x12));
+        assert_eq!(ram.read_range(range), [0x11, 0x12]);
+        let range = (Excluded(0xffff), Included(0xffff));
+        assert!(ram.read_range(range).is_empty());
+    }
+
+    #[test]
+    fn bulk_writes_and_words() {
+        let mut ram = ram();
+        ram.load_slice_at(0xffff, &[0xaa, 0xbb]);
+        assert_eq!(ram.read_u16_le(0xffff), 0xbbaa);
+        ram.write_range(0x0200..0x0203, &[1, 2, 3]);
+        assert_eq!(ram.read_range(0x01ff..=0x0203), [0xff, 1, 2, 3, 0x03]);
+        assert_eq!(ram.read_u16_le(0x0201), 0x0302);
+    }
+
+    #[test]
+    #[should_panic(expected = "2 bytes for 3 addresses")]
+    fn write_range_needs_a_byte_per_address() {
+        ram().write_range(0x0200..0x0203, &[1, 2]);
     }
 }
@@ -0,0 +1,67 @@
+use crate::{apu, console, mapper, ppu, region};
+
+/// What this build of the crate supports, for frontends and reports.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// iNES mapper numbers that load
+    pub mappers: Vec<u8>,
+    /// Consoles that can be emulated, see [`Region`](crate::region::Region)
+    pub regions: Vec<&'static str>,
+    /// Whether any of `audio_channels` are emulated
+    pub audio: bool,
+    /// Tone generators of the APU and of cartridge expansion audio
+    pub audio_channels: Vec<&'static str>,
+    /// Accuracy settings that can be turned on and off
+    pub accuracy: Vec<&'static str>,
+    /// Work RAM fills accepted by [`Console::power_on`](crate::console::Console::power_on)
+    pub ram_fills: Vec<&'static str>,
+    /// Cargo features compiled in
+    pub features: Vec<&'static str>,
+}
+
+/// Describe what this build supports, as each subsystem reports it.
+pub fn capabilities() -> Capabilities {
+    let mut capabilities = Capabilities::default();
+    region::register_capabilities(&mut capabilities);
+    console::register_capabilities(&mut capabilities);
+    apu::register_capabilities(&mut capabilities);
+    ppu::register_capabilities(&mut capabilities);
+    mapper::register_capabilities(&mut capabilities);
+    capabilities.audio = !capabilities.audio_channels.is_empty();
+
+    let features = &mut capabilities.features;
+    if cfg!(feature = "mmap") {
+        features.push("mmap");
+    }
+    if cfg!(feature = "png") {
+        features.push("png");
+    }
+    if cfg!(feature = "serde") {
+        features.push("serde");
+    }
+    if cfg!(feature = "sevenz") {
+        features.push("sevenz");
+    }
+    if cfg!(feature = "zip") {
+        features.push("zip");
+    }
+    capabilities
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subsystems_contribute() {
+        let capabilities = capabilities();
+        assert!(capabilities.mappers.contains(&0));
+        assert!(capabilities.regions.contains(&"NTSC"));
+        assert!(capabilities.audio);
+        assert!(capabilities.audio_channels.contains(&"triangle"));
+        assert!(capabilities.audio_channels.contains(&"VRC6"));
+        assert!(capabilities.accuracy.contains(&"bus conflicts"));
+        assert!(capabilities.accuracy.contains(&"latch decay"));
+        assert!(capabilities.ram_fills.contains(&"random"));
+    }
+}
@@ -0,0 +1,263 @@
+use crate::database::Database;
+use crate::ines::{self, Header};
+use crate::mapper::Chr;
+use crate::rom::Rom;
+use crate::Result;
+use std::fs;
+use std::path::Path;
+
+/// A game as stored in an iNES or NES 2.0 file: the header and the ROM
+/// sections, which [`Mapper::from_cartridge`](crate::mapper::Mapper::from_cartridge)
+/// wires up to the right board.
+///
+/// The sections are views into the file's bytes, see [`Rom`].
+#[derive(Debug, Clone)]
+pub struct Cartridge {
+    header: Header,
+    /// The header as stored, for boards that give its bits other meanings
+    header_bytes: [u8; 16],
+    trainer: Option<Rom>,
+    prg_rom: Rom,
+    chr_rom: Rom,
+}
+
+impl Cartridge {
+    /// PRG RAM on boards whose header gives no size, 8 kB.
+    pub const PRG_RAM_SIZE: usize = 8 * 1024;
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Cartridge> {
+        Self::from_bytes(fs::read(path)?)
+    }
+
+    /// Split an iNES or NES 2.0 file into its sections.
+    ///
+    /// A file too short for the sizes in its header is an error, as is one
+    /// without PRG ROM. Bytes past the end of CHR ROM, which padded and
+    /// overdumped files have, are ignored.
+    pub fn from_bytes(bytes: impl Into<Rom>) -> Result<Cartridge> {
+        let bytes = bytes.into();
+        let header = ines::parse_header(&bytes)?;
+        if header.prg_rom_size == 0 {
+            return Err("the header gives no PRG ROM".into());
+        }
+        let too_large = "the header's ROM sizes add up to more than fits in memory";
+        let prg_rom_start: usize = if header.has_trainer { 16 + 512 } else { 16 };
+        let chr_rom_start = prg_rom_start
+            .checked_add(header.prg_rom_size)
+            .ok_or(too_large)?;
+        let chr_rom_end = chr_rom_start
+            .checked_add(header.chr_rom_size)
+            .ok_or(too_large)?;
+        for &(section, end) in &[
+            ("trainer", prg_rom_start),
+            ("PRG ROM", chr_rom_start),
+            ("CHR ROM", chr_rom_end),
+        ] {
+            if bytes.len() < end {
+                return Err(format!(
+                    "file ends in {}: the header needs {} bytes but there are {}",
+                    section,
+                    end,
+                    bytes.len()
+                )
+                .into());
+            }
+        }
+        let trainer = if header.has_trainer {
+            Some(bytes.slice(16..prg_rom_start))
+        } else {
+            None
+        };
+        let mut header_bytes = [0; 16];
+        header_bytes.copy_from_slice(&bytes[..16]);
+        Ok(Cartridge {
+            header,
+            header_bytes,
+            trainer,
+            prg_rom: bytes.slice(prg_rom_start..chr_rom_start),
+            chr_rom: bytes.slice(chr_rom_start..chr_rom_end),
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Replace the header, for dumps whose header names the wrong board.
+    /// The ROM stays split as the file's header said.
+    pub fn set_header(&mut self, header: Header) {
+        self.header = Header {
+            has_trainer: self.header.has_trainer,
+            prg_rom_size: self.header.prg_rom_size,
+            chr_rom_size: self.header.chr_rom_size,
+            ..header
+        };
+    }
+
+    /// The 16 header bytes as stored in the file.
+    pub fn header_bytes(&self) -> &[u8; 16] {
+        &self.header_bytes
+    }
+
+    /// The 512 bytes some dumps carry for $7000-$71FF.
+    pub fn trainer(&self) -> Option<&Rom> {
+        self.trainer.as_ref()
+    }
+
+    pub fn prg_rom(&self) -> &Rom {
+        &self.prg_rom
+    }
+
+    /// Empty for boards with only CHR RAM.
+    pub fn chr_rom(&self) -> &Rom {
+        &self.chr_rom
+    }
+
+    /// What the board's pattern tables start as: CHR ROM, or CHR RAM of
+    /// the size in an NES 2.0 header, 8 kB if it gives none.
+    pub fn chr(&self) -> Chr {
+        Chr::new(self.chr_rom.clone(), self.chr_ram_size())
+    }
+
+    /// What the board's PRG RAM starts as: zeroed RAM of the size in the
+    /// header, [`Cartridge::PRG_RAM_SIZE`] if it gives none.
+    pub fn prg_ram(&self) -> Vec<u8> {
+        let size = match self.prg_ram_size() {
+            0 => Self::PRG_RAM_SIZE,
+            size => size,
+        };
+        vec![0; size]
+    }
+
+    /// Replace the header with the database's if the game is in it,
+    /// returning whether it was. Only the board is taken from the database:
+    /// if it splits PRG and CHR ROM differently, nothing changes.
+    pub fn apply_database(&mut self, database: &Database) -> bool {
+        let (crc32, sha1) = ines::checksums(&[&self.prg_rom, &self.chr_rom]);
+        match database.lookup(crc32, &sha1) {
+            Some(game)
+                if game.header.prg_rom_size == self.header.prg_rom_size
+                    && game.header.chr_rom_size == self.header.chr_rom_size =>
+            {
+                self.header = Header {
+                    has_trainer: self.header.has_trainer,
+                    ..game.header
+                };
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// PRG RAM in bytes, volatile or battery-backed.
+    pub fn prg_ram_size(&self) -> usize {
+        self.header.prg_ram_size + self.header.prg_nvram_size
+    }
+
+    /// CHR RAM in bytes, volatile or battery-backed.
+    pub fn chr_ram_size(&self) -> usize {
+        self.header.chr_ram_size + self.header.chr_nvram_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_the_sections() {
+        let mut bytes = vec![
+            b'N', b'E', b'S', 0x1a, 1, 1, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        bytes.extend(vec![0x77; 512]);
+        bytes.extend(vec![0x11; 16 * 1024]);
+        bytes.extend(vec![0x22; 8 * 1024]);
+        let cartridge = Cartridge::from_bytes(bytes).unwrap();
+
+        assert_eq!(&cartridge.trainer().unwrap()[..], &[0x77; 512][..]);
+        assert_eq!(cartridge.prg_rom().len(), 16 * 1024);
+        assert!(cartridge.prg_rom().iter().all(|&byte| byte == 0x11));
+        assert_eq!(cartridge.chr_rom().len(), 8 * 1024);
+        assert!(cartridge.chr_rom().iter().all(|&byte| byte == 0x22));
+        assert_eq!(cartridge.prg_ram_size(), 8 * 1024);
+        assert_eq!(cartridge.chr_ram_size(), 0);
+    }
+
+    #[test]
+    fn prg_ram_from_the_header() {
+        // NES 2.0 with 2 kB of PRG RAM and 8 kB of PRG NVRAM
+        let mut bytes = vec![
+            b'N', b'E', b'S', 0x1a, 1, 1, 0x00, 0x08, 0, 0, 0x75, 0, 0, 0, 0, 0,
+        ];
+        bytes.extend(vec![0; 16 * 1024 + 8 * 1024]);
+        let cartridge = Cartridge::from_bytes(bytes.clone()).unwrap();
+        assert_eq!(cartridge.prg_ram(), vec![0; 10 * 1024]);
+
+        // None given
+        bytes[10] = 0;
+        let cartridge = Cartridge::from_bytes(bytes).unwrap();
+        assert_eq!(cartridge.prg_ram().len(), Cartridge::PRG_RAM_SIZE);
+    }
+
+    #[test]
+    fn database_fixes_the_header() {
+        let mut bytes = vec![
+            b'N', b'E', b'S', 0x1a, 1, 1, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        bytes.extend(vec![0x11; 16 * 1024]);
+        bytes.extend(vec![0x22; 8 * 1024]);
+        let crc32 = crate::checksum::crc32(&bytes[16..]);
+        let xml = format!(
+            r#"<game>
+                <prgrom size="16384"/>
+                <chrrom size="8192"/>
+                <rom size="24576" crc32="{:08X}"/>
+                <pcb mapper="3" submapper="0" mirroring="V" battery="0"/>
+            </game>"#,
+            crc32
+        );
+        let database = Database::from_xml(&xml).unwrap();
+
+        let mut cartridge = Cartridge::from_bytes(bytes).unwrap();
+        assert!(cartridge.apply_database(&database));
+        assert_eq!(cartridge.header().mapper_id, 3);
+        assert_eq!(cartridge.header().mirroring, ines::Mirroring::Vertical);
+        assert!(!cartridge.apply_database(&Database::default()));
+    }
+
+    #[test]
+    fn checks_the_length() {
+        let mut bytes = vec![
+            b'N', b'E', b'S', 0x1a, 2, 1, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        assert!(Cartridge::from_bytes(&bytes[..10]).is_err());
+
+        bytes.extend(vec![0; 16 * 1024]);
+        let error = Cartridge::from_bytes(bytes.clone()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "file ends in PRG ROM: the header needs 32784 bytes but there are 16400"
+        );
+
+        // Overdumps are fine
+        bytes.extend(vec![0; 16 * 1024 + 8 * 1024 + 100]);
+        let cartridge = Cartridge::from_bytes(bytes).unwrap();
+        assert_eq!(cartridge.chr_rom().len(), 8 * 1024);
+    }
+
+    #[test]
+    fn rejects_impossible_sizes() {
+        let bytes = vec![
+            b'N', b'E', b'S', 0x1a, 0, 1, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let error = Cartridge::from_bytes(bytes).unwrap_err();
+        assert_eq!(error.to_string(), "the header gives no PRG ROM");
+
+        // NES 2.0, with 2^63 bytes each of PRG and CHR ROM
+        let bytes = vec![
+            b'N', b'E', b'S', 0x1a, 0xfc, 0xfc, 0x00, 0x08, 0, 0xff, 0, 0, 0, 0, 0, 0,
+        ];
+        let error = Cartridge::from_bytes(bytes).unwrap_err();
+        assert!(error.to_string().contains("more than fits"), "{}", error);
+    }
+}
@@ -0,0 +1,331 @@
+//! Cheat codes, which change what the CPU reads at an address.
+//!
+//! Game Genie codes patch cartridge ROM at $8000-$FFFF: six letters give
+//! an address and a value, eight add a compare byte, so the patch only
+//! applies while the bank holding the original byte is mapped in. Raw
+//! codes, written `AAAA:VV` or `AAAA:VV:CC` in hex, patch any address. Pro
+//! Action Replay codes, which hold a RAM address and value, are entered
+//! this way.
+
+use crate::Result;
+use std::fmt;
+use std::str::FromStr;
+
+/// The Game Genie's letters, by the four bits each stands for.
+const GAME_GENIE_LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+
+/// Read `value` at `address`, if what is there is `compare`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cheat {
+    pub address: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+impl Cheat {
+    /// Decode a six or eight letter Game Genie code.
+    pub fn from_game_genie(code: &str) -> Result<Cheat> {
+        let nibbles = code
+            .bytes()
+            .map(|letter| {
+                GAME_GENIE_LETTERS
+                    .iter()
+                    .position(|&digit| digit == letter.to_ascii_uppercase())
+                    .map(|nibble| nibble as u16)
+                    .ok_or_else(|| format!("{:?} is not a Game Genie letter", letter as char))
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let n = |index: usize| nibbles[index];
+        if nibbles.len() != 6 && nibbles.len() != 8 {
+            return Err(format!(
+                "Game Genie codes have 6 or 8 letters, not {}",
+                nibbles.len()
+            )
+            .into());
+        }
+
+        let address = 0x8000
+            | (n(3) & 7) << 12
+            | (n(5) & 7) << 8
+            | (n(4) & 8) << 8
+            | (n(2) & 7) << 4
+            | (n(1) & 8) << 4
+            | (n(4) & 7)
+            | (n(3) & 8);
+        // The last letter's high bit moves into the compare byte's place
+        let last = nibbles.len() - 1;
+        let value = (n(1) & 7) << 4 | (n(0) & 8) << 4 | (n(0) & 7) | (n(last) & 8);
+        let compare = if nibbles.len() == 8 {
+            Some((n(7) & 7) << 4 | (n(6) & 8) << 4 | (n(6) & 7) | (n(5) & 8))
+        } else {
+            None
+        };
+        Ok(Cheat {
+            address,
+            value: value as u8,
+            compare: compare.map(|compare| compare as u8),
+        })
+    }
+
+    /// The cheat as a Game Genie code, if its address is in cartridge ROM.
+    pub fn to_game_genie(&self) -> Option<String> {
+        if self.address < 0x8000 {
+            return None;
+        }
+        let address = self.address;
+        let value = self.value as u16;
+        let mut n = [0u16; 8];
+        n[0] = (value >> 4 & 8) | (value & 7);
+        n[1] = (address >> 4 & 8) | (value >> 4 & 7);
+        n[2] = address >> 4 & 7;
+        n[3] = (address & 8) | (address >> 12 & 7);
+        n[4] = (address >> 8 & 8) | (address & 7);
+        n[5] = address >> 8 & 7;
+        let len = match self.compare {
+            Some(compare) => {
+                let compare = compare as u16;
+                // Tells the Game Genie to read two more letters
+                n[2] |= 8;
+                n[5] |= compare & 8;
+                n[6] = (compare >> 4 & 8) | (compare & 7);
+                n[7] = (value & 8) | (compare >> 4 & 7);
+                8
+            }
+            None => {
+                n[5] |= value & 8;
+                6
+            }
+        };
+        Some(
+            n[..len]
+                .iter()
+                .map(|&nibble| GAME_GENIE_LETTERS[nibble as usize] as char)
+                .collect(),
+        )
+    }
+
+    /// Decode a raw code, `AAAA:VV` or `AAAA:VV:CC` in hex.
+    pub fn from_raw(code: &str) -> Result<Cheat> {
+        let mut fields = code.split(':');
+        let mut field = |name| {
+            fields
+                .next()
+                .ok_or_else(|| format!("no {} in {:?}", name, code))
+        };
+        let address = u16::from_str_radix(field("address")?, 16)?;
+        let value = u8::from_str_radix(field("value")?, 16)?;
+        let compare = match fields.next() {
+            Some(compare) => Some(u8::from_str_radix(compare, 16)?),
+            None => None,
+        };
+        if fields.next().is_some() {
+            return Err(format!("too many fields in {:?}", code).into());
+        }
+        Ok(Cheat {
+            address,
+            value,
+            compare,
+        })
+    }
+
+    /// Whether the cheat patches `data` read at `address`.
+    pub fn applies(&self, address: u16, data: u8) -> bool {
+        address == self.address && self.compare.is_none_or(|compare| compare == data)
+    }
+
+    /// What the CPU reads at `address`, where `data` is there.
+    pub fn apply(&self, address: u16, data: u8) -> u8 {
+        if self.applies(address, data) {
+            self.value
+        } else {
+            data
+        }
+    }
+}
+
+/// A Game Genie code, or a raw one if it has colons.
+impl FromStr for Cheat {
+    type Err = Box<dyn std::error::Error>;
+
+    fn from_str(code: &str) -> Result<Cheat> {
+        let code = code.trim();
+        if code.contains(':') {
+            Cheat::from_raw(code)
+        } else {
+            Cheat::from_game_genie(code)
+        }
+    }
+}
+
+/// The raw code.
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04X}:{:02X}", self.address, self.value)?;
+        if let Some(compare) = self.compare {
+            write!(f, ":{:02X}", compare)?;
+        }
+        Ok(())
+    }
+}
+
+/// The cheats a console applies, each of which can be switched off.
+#[derive(Debug, Clone, Default)]
+pub struct Cheats {
+    cheats: Vec<(Cheat, bool)>,
+    /// How many are switched on, to skip the search when none are
+    enabled: usize,
+}
+
+impl Cheats {
+    pub fn new() -> Cheats {
+        Cheats::default()
+    }
+
+    /// Add `cheat` switched on, or switch it on if it is already there.
+    pub fn add(&mut self, cheat: Cheat) {
+        if !self.set_enabled(&cheat, true) {
+            self.cheats.push((cheat, true));
+            self.enabled += 1;
+        }
+    }
+
+    /// Take `cheat` out. Returns whether it was there.
+    pub fn remove(&mut self, cheat: &Cheat) -> bool {
+        match self.cheats.iter().position(|(other, _)| other == cheat) {
+            Some(index) => {
+                let (_, enabled) = self.cheats.remove(index);
+                self.enabled -= enabled as usize;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Switch `cheat` on or off. Returns whether it was there.
+    pub fn set_enabled(&mut self, cheat: &Cheat, enabled: bool) -> bool {
+        match self.cheats.iter_mut().find(|(other, _)| other == cheat) {
+            Some((_, was_enabled)) => {
+                self.enabled = self.enabled + enabled as usize - *was_enabled as usize;
+                *was_enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_enabled(&self, cheat: &Cheat) -> bool {
+        self.cheats
+            .iter()
+            .any(|(other, enabled)| other == cheat && *enabled)
+    }
+
+    /// The cheats in the order they were added, and whether each is on.
+    pub fn iter(&self) -> impl Iterator<Item = (&Cheat, bool)> + '_ {
+        self.cheats.iter().map(|(cheat, enabled)| (cheat, *enabled))
+    }
+
+    pub fn len(&self) -> usize {
+        self.cheats.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.cheats.clear();
+        self.enabled = 0;
+    }
+
+    /// What the CPU reads at `address` with the cheats that are on, where
+    /// `data` is there. The first cheat added that applies wins.
+    pub fn apply(&self, address: u16, data: u8) -> u8 {
+        if self.enabled == 0 {
+            return data;
+        }
+        self.cheats
+            .iter()
+            .find(|(cheat, enabled)| *enabled && cheat.applies(address, data))
+            .map_or(data, |(cheat, _)| cheat.value)
+    }
+
+    /// Apply the cheats to `buffer`, read starting at `address`.
+    pub fn apply_range(&self, address: u16, buffer: &mut [u8]) {
+        if self.enabled == 0 {
+            return;
+        }
+        for (offset, data) in buffer.iter_mut().enumerate() {
+            *data = self.apply(address.wrapping_add(offset as u16), *data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn game_genie_codes_decode() {
+        assert_eq!(
+            "SXIOPO".parse::<Cheat>().unwrap(),
+            Cheat {
+                address: 0x91d9,
+                value: 0xad,
+                compare: None,
+            }
+        );
+        assert_eq!(
+            Cheat::from_game_genie("yeuzugaa").unwrap(),
+            Cheat {
+                address: 0xacb3,
+                value: 0x07,
+                compare: Some(0x00),
+            }
+        );
+        assert!(Cheat::from_game_genie("SXIOP").is_err());
+        assert!(Cheat::from_game_genie("SXIOPB").is_err());
+    }
+
+    #[test]
+    fn game_genie_codes_round_trip() {
+        for code in ["SXIOPO", "YEUZUGAA", "AAEAULPA", "NNLNNN", "NNNNNNNN"] {
+            let cheat = Cheat::from_game_genie(code).unwrap();
+            assert_eq!(cheat.to_game_genie().unwrap(), code);
+        }
+        assert_eq!(Cheat::from_raw("0075:09").unwrap().to_game_genie(), None);
+    }
+
+    #[test]
+    fn raw_codes_parse_and_display() {
+        for code in ["0075:09", "C123:FF:A9"] {
+            let cheat: Cheat = code.parse().unwrap();
+            assert_eq!(cheat.to_string(), code);
+        }
+        assert!(Cheat::from_raw("0075").is_err());
+        assert!(Cheat::from_raw("0075:09:00:00").is_err());
+        assert!(Cheat::from_raw("10000:00").is_err());
+    }
+
+    #[test]
+    fn compare_bytes_guard_patches() {
+        let mut cheats = Cheats::new();
+        let cheat = Cheat::from_raw("8000:EA:A9").unwrap();
+        cheats.add(cheat);
+        assert_eq!(cheats.apply(0x8000, 0xa9), 0xea);
+        assert_eq!(cheats.apply(0x8000, 0xa5), 0xa5);
+        assert_eq!(cheats.apply(0x8001, 0xa9), 0xa9);
+
+        let mut buffer = [0xa9; 4];
+        cheats.apply_range(0x7ffe, &mut buffer);
+        assert_eq!(buffer, [0xa9, 0xa9, 0xea, 0xa9]);
+
+        cheats.set_enabled(&cheat, false);
+        assert_eq!(cheats.apply(0x8000, 0xa9), 0xa9);
+        assert!(!cheats.is_enabled(&cheat));
+        cheats.add(cheat);
+        assert!(cheats.is_enabled(&cheat));
+        assert!(cheats.remove(&cheat));
+        assert!(!cheats.remove(&cheat));
+        assert!(cheats.is_empty());
+    }
+}
@@ -0,0 +1,92 @@
+/// Divides the master clock down to the CPU and PPU clocks.
+///
+/// Time is kept in master clock cycles, so the fractional PPU dots per CPU
+/// cycle on PAL (3.2) accumulate exactly instead of drifting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Clock {
+    cpu_divider: u64,
+    ppu_divider: u64,
+    master_cycle: u64,
+}
+
+impl Clock {
+    /// 21.477272 MHz master clock, CPU divided by 12 and PPU by 4.
+    pub const NTSC: Clock = Clock::new(12, 4);
+    /// 26.601712 MHz master clock, CPU divided by 16 and PPU by 5.
+    pub const PAL: Clock = Clock::new(16, 5);
+    /// PAL's master clock, CPU divided by 15 and PPU by 5.
+    pub const DENDY: Clock = Clock::new(15, 5);
+    /// The presets above, by region name.
+    pub const REGIONS: &'static [(&'static str, Clock)] = &[
+        ("NTSC", Clock::NTSC),
+        ("PAL", Clock::PAL),
+        ("Dendy", Clock::DENDY),
+    ];
+
+    /// A clock with custom dividers, e.g. a smaller CPU divider to
+    /// overclock the CPU relative to the PPU.
+    pub const fn new(cpu_divider: u64, ppu_divider: u64) -> Clock {
+        Clock {
+            cpu_divider,
+            ppu_divider,
+            master_cycle: 0,
+        }
+    }
+
+    pub fn master_cycle(&self) -> u64 {
+        self.master_cycle
+    }
+
+    /// Master cycles per CPU cycle.
+    pub fn cpu_divider(&self) -> u64 {
+        self.cpu_divider
+    }
+
+    /// Master cycles per PPU dot.
+    pub fn ppu_divider(&self) -> u64 {
+        self.ppu_divider
+    }
+
+    /// The same dividers, starting at `master_cycle`.
+    pub(crate) fn at(self, master_cycle: u64) -> Clock {
+        Clock {
+            master_cycle,
+            ..self
+        }
+    }
+
+    /// Advance by `cpu_cycles` CPU cycles and return how many PPU dots
+    /// elapsed.
+    pub fn advance_cpu(&mut self, cpu_cycles: u64) -> u64 {
+        let dots = self.master_cycle / self.ppu_divider;
+        self.master_cycle += cpu_cycles * self.cpu_divider;
+        self.master_cycle / self.ppu_divider - dots
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock::NTSC
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ntsc_runs_three_dots_per_cpu_cycle() {
+        let mut clock = Clock::NTSC;
+        assert_eq!(clock.advance_cpu(1), 3);
+        assert_eq!(clock.advance_cpu(7), 21);
+        assert_eq!(clock.master_cycle(), 8 * 12);
+    }
+
+    #[test]
+    fn pal_accumulates_fractional_dots() {
+        let mut clock = Clock::PAL;
+        let dots: Vec<_> = (0..5).map(|_| clock.advance_cpu(1)).collect();
+        assert_eq!(dots, [3, 3, 3, 3, 4]);
+    }
+}
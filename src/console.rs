@@ -1,41 +1,303 @@
-use crate::bus::Bus;
-use crate::cpu::Cpu;
-use crate::mapper::Mapper;
-use crate::ppu::Ppu;
+use crate::apu::Apu;
+use crate::archive;
+use crate::bus::{self, Bus};
+use crate::capabilities::Capabilities;
+use crate::cartridge::Cartridge;
+use crate::cheats::{Cheat, Cheats};
+use crate::checksum;
+use crate::clock::Clock;
+use crate::cpu::{Cpu, IrqSource, Registers, Step, Vector};
+use crate::debugger::{Access, AccessKind, Heatmap, TraceSink, WatchId, Watches};
+use crate::ines::Mirroring;
+use crate::input::{self, Button, Controller, FourScore, InputState, Joypad};
+use crate::mapper::{self, Mapper, PpuWindow};
+use crate::mappers::flat_ram::FlatRam;
+use crate::mappers::nsf_player::{self, NsfPlayer};
+use crate::nsf::{Nsf, NsfRegion};
+use crate::palette::Palette;
+use crate::ppu::{self, Ppu};
+use crate::region::Region;
+use crate::rom::Rom;
+use crate::scheduler::Scheduler;
+use crate::state::{self, StateReader, StateWriter};
 use crate::Result;
-use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::mem;
 use std::ops;
 use std::path::Path;
-use std::rc::Rc;
+use std::sync::Arc;
 
 #[derive(Debug, Clone)]
 struct CpuBus {
     wram: Vec<u8>,
-    mapper: Rc<RefCell<Box<dyn Mapper>>>,
-    ppu: Rc<RefCell<Ppu<PpuBus>>>,
+    /// The PPU, whose bus holds the cartridge, see [`CpuBus::mapper`]
+    ppu: Ppu<PpuBus>,
+    /// Page written to $4014, copied to OAM once the instruction ends
+    oam_dma: Option<u8>,
+    /// Devices in the ports read at $4016 and $4017
+    controllers: [Box<dyn Controller>; 2],
+    /// Buttons set through the console, see [`Console::set_input`]
+    input: InputState,
+    /// Whether `input` reaches the controllers only as a frame begins
+    input_latched: bool,
+    /// Codes patching what the CPU reads
+    cheats: Cheats,
+    /// The last value on the data bus, which reads of addresses nothing
+    /// drives return. Savestates leave it out, as the opcode fetch that
+    /// starts every instruction sets it.
+    open_bus: u8,
+    /// Callbacks for reads and writes, see [`Console::watch`]
+    watches: Watches,
+    /// Audio collected at the rate set by [`Console::set_sample_rate`]
+    sampler: Option<Sampler>,
+    apu: Apu,
+    /// Where the PPU, APU and mapper have been run up to
+    clock: Clock,
+    /// CPU cycles run since, which they have yet to catch up on
+    pending: u64,
+    /// When they must catch up by, because an interrupt line or the frame
+    /// count may change then, see [`CpuBus::update_lines`]
+    events: Scheduler<Event>,
+    /// CPU cycles of the step in progress already added to `pending` by
+    /// interrupt polls
+    ahead: u64,
+    /// The interrupt lines as of the last catch-up or register access
+    nmi: bool,
+    frame_irq: bool,
+    mapper_irq: bool,
+}
+
+/// What may happen without a register access, and so has the console
+/// catch up when due.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Event {
+    /// Vblank starts or ends, changing NMI and the frame count
+    VblankEdge,
+    /// The APU frame counter raises IRQ
+    FrameIrq,
+    /// The mapper's counter may raise IRQ
+    MapperIrq,
+}
+
+impl CpuBus {
+    /// The cartridge, which the CPU reaches through the PPU's bus so that
+    /// both buses can own it without sharing.
+    fn mapper(&self) -> &dyn Mapper {
+        &*self.ppu.bus().mapper
+    }
+
+    fn mapper_mut(&mut self) -> &mut dyn Mapper {
+        &mut *self.ppu.bus_mut().mapper
+    }
+
+    /// The master cycle the CPU has reached.
+    fn master_cycle(&self) -> u64 {
+        self.clock.master_cycle() + self.pending * self.clock.cpu_divider()
+    }
+
+    /// Run the PPU, APU and mapper for the CPU cycles they are behind.
+    fn catch_up(&mut self) {
+        if self.pending == 0 {
+            return;
+        }
+        let cycles = self.pending;
+        self.pending = 0;
+        let mapper = &mut self.ppu.bus_mut().mapper;
+        if let Some(sampler) = &mut self.sampler {
+            for _ in 0..cycles {
+                self.apu.step();
+                mapper.cpu_clock();
+                sampler.add(self.apu.output() + mapper.audio());
+            }
+        } else {
+            for _ in 0..cycles {
+                self.apu.step();
+            }
+            for _ in 0..cycles {
+                mapper.cpu_clock();
+            }
+        }
+        let dots = self.clock.advance_cpu(cycles);
+        let frames = self.ppu.frames();
+        for _ in 0..dots {
+            self.ppu.step();
+        }
+        let frames = self.ppu.frames() - frames;
+        for _ in 0..frames {
+            for controller in &mut self.controllers {
+                controller.advance_frame();
+            }
+        }
+        if frames > 0 && self.input_latched {
+            self.apply_input();
+        }
+        self.update_lines();
+    }
+
+    /// Pass the buttons in `input` on to the controllers.
+    fn apply_input(&mut self) {
+        for pad in 0..4 {
+            let controller = &mut self.controllers[pad % 2];
+            for &button in &Button::ALL {
+                controller.set_pad_button(pad / 2, button, self.input.is_pressed(pad, button));
+            }
+        }
+    }
+
+    /// Sample the interrupt lines and schedule the events that might next
+    /// change them.
+    fn update_lines(&mut self) {
+        let ppu = &self.ppu;
+        let mapper = &ppu.bus().mapper;
+        self.nmi = ppu.nmi();
+        self.frame_irq = self.apu.irq();
+        self.mapper_irq = mapper.irq_pending();
+
+        let now = self.clock.master_cycle();
+        let ppu_divider = self.clock.ppu_divider();
+        let after_cycles = |cycles: u64| {
+            let cycles = cycles.saturating_add(1);
+            now.saturating_add(cycles.saturating_mul(self.clock.cpu_divider()))
+        };
+        let vblank_edge = (now / ppu_divider)
+            .saturating_add(ppu.dots_until_vblank_edge() + 1)
+            .saturating_mul(ppu_divider);
+        let events = [
+            (vblank_edge, Event::VblankEdge),
+            (after_cycles(self.apu.cycles_until_irq()), Event::FrameIrq),
+            (after_cycles(mapper.cycles_until_irq()), Event::MapperIrq),
+        ];
+        self.events.clear();
+        for (due, event) in events {
+            self.events.schedule(due, event);
+        }
+    }
+
+    /// The master cycle the earliest event is due at.
+    fn next_event(&self) -> u64 {
+        self.events.next_due().unwrap_or(u64::MAX)
+    }
+
+    /// Catch up before a register access, and look at the lines again
+    /// after.
+    fn synced<T>(&mut self, access: impl FnOnce(&mut CpuBus) -> T) -> T {
+        self.catch_up();
+        let result = access(self);
+        self.update_lines();
+        result
+    }
+
+    /// Change work RAM or cartridge RAM, leaving registers alone. Returns
+    /// whether there was RAM at `address`.
+    fn poke(&mut self, address: u16, data: u8) -> bool {
+        match address {
+            0x0000..=0x1fff => {
+                let index = address as usize % self.wram.len();
+                self.wram[index] = data;
+                true
+            }
+            0x4020..=0xffff => self.mapper_mut().cpu_poke(address, data),
+            _ => false,
+        }
+    }
 }
 
 impl Bus for CpuBus {
     fn read(&mut self, address: u16) -> u8 {
-        match address {
+        let data = match address {
             // 2 kB work RAM
             0x0000..=0x1fff => {
                 let index = address as usize % self.wram.len();
                 self.wram[index]
             }
             // PPU
-            0x2000..=0x3fff => {
-                unimplemented!()
+            0x2000..=0x3fff => self.synced(|bus| bus.ppu.read(address)),
+            // Controllers
+            0x4016 | 0x4017 => self.synced(|bus| {
+                let port = (address - 0x4016) as usize;
+                let data = bus.controllers[port].read();
+                (bus.open_bus & !input::DRIVEN) | (data & input::DRIVEN)
+            }),
+            // APU status, all but bit 5
+            0x4015 => self.synced(|bus| bus.apu.read_status() | (bus.open_bus & 0x20)),
+            // Write-only APU registers and unused I/O
+            0x4000..=0x401f => self.open_bus,
+            // Cartridge registers and RAM, which may be counting
+            0x4020..=0x7fff => self
+                .synced(|bus| bus.mapper_mut().cpu_read(address))
+                .unwrap_or(self.open_bus),
+            // Cartridge ROM
+            0x8000..=0xffff => self.mapper_mut().cpu_read(address).unwrap_or(self.open_bus),
+        };
+        self.open_bus = self.cheats.apply(address, data);
+        if self.watches.watching(AccessKind::Read) {
+            self.watches.notify(Access {
+                kind: AccessKind::Read,
+                address,
+                data: self.open_bus,
+            });
+        }
+        self.open_bus
+    }
+
+    /// Registers are seen as of the last catch-up.
+    fn peek(&self, address: u16) -> u8 {
+        let data = match address {
+            0x0000..=0x1fff => self.wram[address as usize % self.wram.len()],
+            0x2000..=0x3fff => self.ppu.peek(address),
+            0x4016 | 0x4017 => {
+                let port = (address - 0x4016) as usize;
+                let data = self.controllers[port].peek();
+                (self.open_bus & !input::DRIVEN) | (data & input::DRIVEN)
             }
-            // APU and I/O
-            0x4000..=0x401f => {
-                unimplemented!()
+            0x4015 => self.apu.peek_status() | (self.open_bus & 0x20),
+            0x4000..=0x401f => self.open_bus,
+            0x4020..=0xffff => self.mapper().cpu_peek(address).unwrap_or(self.open_bus),
+        };
+        self.cheats.apply(address, data)
+    }
+
+    fn read_into(&mut self, address: u16, buffer: &mut [u8]) {
+        // Read watches see every byte
+        if self.watches.watching(AccessKind::Read) {
+            for (offset, data) in buffer.iter_mut().enumerate() {
+                *data = self.read(address.wrapping_add(offset as u16));
             }
-            // Cartridge
-            0x4020..=0xffff => self.mapper.borrow_mut().cpu_read(address),
+            return;
         }
+        mapper::read_chunks(address, buffer, |address, buffer| match address {
+            0x0000..=0x1fff => {
+                let index = address as usize % self.wram.len();
+                let len = mapper::copy_chunk(&self.wram[index..], buffer);
+                self.cheats.apply_range(address, &mut buffer[..len]);
+                len
+            }
+            0x8000..=0xffff => {
+                let len = buffer.len().min(0x10000 - address as usize);
+                buffer[..len].fill(self.open_bus);
+                self.mapper_mut().cpu_read_into(address, &mut buffer[..len]);
+                self.cheats.apply_range(address, &mut buffer[..len]);
+                len
+            }
+            // Cheats apply in `read`
+            _ => {
+                buffer[0] = self.read(address);
+                1
+            }
+        });
     }
+
     fn write(&mut self, address: u16, data: u8) {
+        self.open_bus = data;
+        if self.watches.watching(AccessKind::Write) {
+            self.watches.notify(Access {
+                kind: AccessKind::Write,
+                address,
+                data,
+            });
+        }
         match address {
             // 2 kB RAM
             0x0000..=0x1fff => {
@@ -43,73 +305,1451 @@ impl Bus for CpuBus {
                 self.wram[index] = data
             }
             // PPU
-            0x2000..=0x3fff => self.ppu.borrow_mut().write(address, data),
-            // APU and I/O
-            0x4000..=0x401f => unimplemented!(),
+            0x2000..=0x3fff => self.synced(|bus| bus.ppu.write(address, data)),
+            // OAM DMA
+            0x4014 => self.oam_dma = Some(data),
+            // Controller strobe
+            0x4016 => self.synced(|bus| {
+                for controller in &mut bus.controllers {
+                    controller.write(data);
+                }
+            }),
+            // APU, including the frame counter at $4017
+            0x4000..=0x4013 | 0x4015 | 0x4017 => self.synced(|bus| bus.apu.write(address, data)),
+            // CPU test mode
+            0x4018..=0x401f => {}
             // Cartridge
-            0x4020..=0xffff => self.mapper.borrow_mut().cpu_write(address, data),
+            0x4020..=0xffff => self.synced(|bus| bus.mapper_mut().cpu_write(address, data)),
         }
     }
+
+    /// Catches up into the step in progress only when a line may have
+    /// changed by then.
+    fn interrupt_lines(&mut self, cycles: u64) -> Option<bus::InterruptLines> {
+        let extra = cycles.saturating_sub(self.ahead);
+        if extra > 0 && self.master_cycle() + extra * self.clock.cpu_divider() >= self.next_event()
+        {
+            self.pending += extra;
+            self.ahead = cycles;
+            self.catch_up();
+        }
+        let mut irq = IrqSource::empty();
+        irq.set(IrqSource::APU_FRAME, self.frame_irq);
+        irq.set(IrqSource::MAPPER, self.mapper_irq);
+        Some(bus::InterruptLines {
+            nmi: self.nmi,
+            driven: IrqSource::APU_FRAME | IrqSource::MAPPER,
+            irq,
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
-pub struct PpuBus {
+pub(crate) struct PpuBus {
+    /// Nametable RAM, 2 kB in the console plus 2 kB more for cartridges
+    /// with four-screen mirroring
     vram: Vec<u8>,
-    mapper: Rc<RefCell<Box<dyn Mapper>>>,
+    mapper: Box<dyn Mapper>,
+    /// Mirroring from the header, used unless the mapper controls it
+    mirroring: Mirroring,
+    /// Address line A12 as of the last access
+    a12: bool,
+}
+
+impl PpuBus {
+    /// What serves `address`, asking the mapper before falling back on the
+    /// cartridge for pattern tables and mirrored nametable RAM.
+    fn window(&self, address: u16) -> PpuWindow {
+        let mapper = &self.mapper;
+        if let Some(window) = mapper.ppu_window(address) {
+            return window;
+        }
+        if address < 0x2000 {
+            return PpuWindow::Cartridge;
+        }
+        let mirroring = mapper.mirroring().unwrap_or(self.mirroring);
+        let table = (address as usize >> 10) & 3;
+        PpuWindow::Ciram(match mirroring {
+            Mirroring::Horizontal => table >> 1,
+            Mirroring::Vertical => table & 1,
+            Mirroring::FourScreen => table,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+        })
+    }
+
+    /// Tell the mapper about an access, and about A12 rising. Sprite
+    /// patterns are fetched back to back here, so the short pulses real
+    /// mappers filter out never happen.
+    fn accessed(&mut self, address: u16) {
+        let a12 = address & 0x1000 != 0;
+        if a12 && !self.a12 {
+            self.mapper.ppu_a12_clock();
+        }
+        self.a12 = a12;
+        self.mapper.ppu_accessed(address);
+    }
 }
 
 impl Bus for PpuBus {
     fn read(&mut self, address: u16) -> u8 {
-        todo!();
+        // Palette RAM is inside the PPU
+        let address = address & 0x3fff;
+        let data = match self.window(address) {
+            PpuWindow::Cartridge => self.mapper.ppu_read(address),
+            PpuWindow::Ciram(page) => self.vram[page * 0x400 + (address as usize & 0x3ff)],
+        };
+        self.accessed(address);
+        data
     }
     fn write(&mut self, address: u16, data: u8) {
-        todo!();
+        let address = address & 0x3fff;
+        match self.window(address) {
+            PpuWindow::Cartridge => self.mapper.ppu_write(address, data),
+            PpuWindow::Ciram(page) => self.vram[page * 0x400 + (address as usize & 0x3ff)] = data,
+        }
+        self.accessed(address);
+    }
+
+    /// Leaves A12 and the mapper's view of fetches alone.
+    fn peek(&self, address: u16) -> u8 {
+        let address = address & 0x3fff;
+        match self.window(address) {
+            PpuWindow::Cartridge => self.mapper.ppu_peek(address),
+            PpuWindow::Ciram(page) => self.vram[page * 0x400 + (address as usize & 0x3ff)],
+        }
+    }
+}
+
+/// What work RAM holds at power on, which varies between consoles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RamFill {
+    Zeros,
+    Ones,
+    /// Pseudo-random bytes, the same for the same seed
+    Random(u64),
+}
+
+impl RamFill {
+    /// The variants, by name.
+    pub const NAMES: &'static [&'static str] = &["zeros", "ones", "random"];
+
+    fn fill(self, ram: &mut [u8]) {
+        match self {
+            RamFill::Zeros => ram.iter_mut().for_each(|byte| *byte = 0x00),
+            RamFill::Ones => ram.iter_mut().for_each(|byte| *byte = 0xff),
+            RamFill::Random(seed) => {
+                // xorshift64, which must not start at zero
+                let mut state = seed | 1;
+                for byte in ram {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    *byte = (state >> 32) as u8;
+                }
+            }
+        }
+    }
+}
+
+/// Add the work RAM fills [`Console::power_on`] accepts.
+pub(crate) fn register_capabilities(capabilities: &mut Capabilities) {
+    capabilities.ram_fills.extend(RamFill::NAMES);
+}
+
+/// Averages the audio output over each sample's worth of CPU cycles.
+#[derive(Debug, Clone)]
+struct Sampler {
+    rate: u32,
+    /// CPU cycles per sample
+    period: f64,
+    /// CPU cycles into the current sample
+    elapsed: f64,
+    sum: f32,
+    count: u32,
+    samples: Vec<f32>,
+}
+
+impl Sampler {
+    fn add(&mut self, output: f32) {
+        self.sum += output;
+        self.count += 1;
+        self.elapsed += 1.0;
+        if self.elapsed >= self.period {
+            self.elapsed -= self.period;
+            self.samples.push(self.sum / self.count as f32);
+            self.sum = 0.0;
+            self.count = 0;
+        }
+    }
+}
+
+/// What a console does where real hardware is left to chance, see
+/// [`Console::power_on_deterministic`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deterministic {
+    /// What work RAM holds at power on
+    pub ram_fill: RamFill,
+    /// Whether the PPU's open bus bits fade, see [`Ppu::set_latch_decay`]
+    pub latch_decay: bool,
+}
+
+impl Default for Deterministic {
+    fn default() -> Deterministic {
+        Deterministic {
+            ram_fill: RamFill::Zeros,
+            latch_decay: false,
+        }
+    }
+}
+
+/// Settings for a new console, where [`Console::from_file`] and the like
+/// go with the cartridge's header and the defaults.
+///
+/// ```no_run
+/// use nes::prelude::*;
+///
+/// # fn main() -> Result<()> {
+/// let console = Console::builder()
+///     .region(Region::Pal)
+///     .ram_fill(RamFill::Random(1))
+///     .four_score()
+///     .sample_rate(48_000)
+///     .build_file("game.nes")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct ConsoleBuilder {
+    region: Option<Region>,
+    ram_fill: Option<RamFill>,
+    controllers: [Option<Box<dyn Controller>>; 2],
+    palette: Option<Palette>,
+    sample_rate: Option<u32>,
+    trace_sink: Option<BoxedSink>,
+    mapper: Option<(u16, u8)>,
+    mirroring: Option<Mirroring>,
+}
+
+impl ConsoleBuilder {
+    /// Run as `region` whatever the header says.
+    pub fn region(mut self, region: Region) -> ConsoleBuilder {
+        self.region = Some(region);
+        self
+    }
+
+    /// What work RAM holds at power on, zeros unless set.
+    pub fn ram_fill(mut self, fill: RamFill) -> ConsoleBuilder {
+        self.ram_fill = Some(fill);
+        self
+    }
+
+    /// Plug `controller` into `port` 0 or 1 instead of a [`Joypad`].
+    pub fn controller(
+        mut self,
+        port: usize,
+        controller: impl Controller + 'static,
+    ) -> ConsoleBuilder {
+        self.controllers[port] = Some(Box::new(controller));
+        self
+    }
+
+    /// Connect a Four Score to both ports, see [`Console::set_four_score`].
+    pub fn four_score(self) -> ConsoleBuilder {
+        self.controller(0, FourScore::new(0))
+            .controller(1, FourScore::new(1))
+    }
+
+    pub fn palette(mut self, palette: Palette) -> ConsoleBuilder {
+        self.palette = Some(palette);
+        self
+    }
+
+    /// Collect audio from the start, see [`Console::set_sample_rate`].
+    pub fn sample_rate(mut self, rate: u32) -> ConsoleBuilder {
+        self.sample_rate = Some(rate);
+        self
+    }
+
+    /// Trace from the first instruction, see [`Console::set_trace_sink`].
+    pub fn trace_sink(mut self, sink: impl TraceSink + Send + 'static) -> ConsoleBuilder {
+        self.trace_sink = Some(BoxedSink(Box::new(sink)));
+        self
+    }
+
+    /// Build the board `mapper_id`, variant `submapper_id`, whatever the
+    /// header says, for dumps whose header is wrong.
+    pub fn mapper(mut self, mapper_id: u16, submapper_id: u8) -> ConsoleBuilder {
+        self.mapper = Some((mapper_id, submapper_id));
+        self
+    }
+
+    /// Mirror nametables as `mirroring` whatever the header says.
+    pub fn mirroring(mut self, mirroring: Mirroring) -> ConsoleBuilder {
+        self.mirroring = Some(mirroring);
+        self
+    }
+
+    /// Load an iNES file, or an archive holding one, see
+    /// [`Console::from_bytes`].
+    pub fn build_file(self, path: impl AsRef<Path>) -> Result<Console> {
+        self.build_bytes(fs::read(path)?)
+    }
+
+    /// Load an iNES image, or a .zip or .7z archive holding one, see
+    /// [`Console::from_bytes`].
+    pub fn build_bytes(self, bytes: Vec<u8>) -> Result<Console> {
+        self.build(Cartridge::from_bytes(archive::extract_rom(bytes)?)?)
+    }
+
+    /// A console for `cartridge`, powered on and ready to run.
+    pub fn build(self, mut cartridge: Cartridge) -> Result<Console> {
+        let mut header = *cartridge.header();
+        if let Some((mapper_id, submapper_id)) = self.mapper {
+            header.mapper_id = mapper_id;
+            header.submapper_id = submapper_id;
+        }
+        if let Some(mirroring) = self.mirroring {
+            header.mirroring = mirroring;
+        }
+        cartridge.set_header(header);
+
+        let mut console = Console::from_cartridge(cartridge)?;
+        if let Some(region) = self.region {
+            console.set_region(region);
+        }
+        for (port, controller) in IntoIterator::into_iter(self.controllers).enumerate() {
+            if let Some(controller) = controller {
+                console.cpu.bus_mut().controllers[port] = controller;
+            }
+        }
+        if let Some(palette) = self.palette {
+            console.set_palette(palette);
+        }
+        if let Some(rate) = self.sample_rate {
+            console.set_sample_rate(Some(rate));
+        }
+        if let Some(sink) = self.trace_sink {
+            console.set_trace_sink(sink);
+        }
+        console.power_on(self.ram_fill.unwrap_or(RamFill::Zeros));
+        Ok(console)
+    }
+}
+
+/// A trace sink held by a [`ConsoleBuilder`] until the console is built.
+struct BoxedSink(Box<dyn TraceSink + Send>);
+
+impl TraceSink for BoxedSink {
+    fn trace(&mut self, line: &str) {
+        self.0.trace(line);
+    }
+}
+
+/// Run by [`Console::schedule_in`] once its time has passed.
+#[derive(Clone)]
+struct Callback(Arc<dyn Fn(&mut Console) + Send + Sync>);
+
+impl fmt::Debug for Callback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Callback")
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Console {
     cpu: Cpu<CpuBus>,
-    ppu: Rc<RefCell<Ppu<PpuBus>>>,
+    scheduler: Scheduler<Callback>,
+    /// Colors for [`Console::frame`]
+    palette: Palette,
+    /// What was loaded, unless the console runs a raw program
+    cartridge: Option<Cartridge>,
+    /// The tune loaded by [`Console::from_nsf`], and the song playing
+    nsf: Option<(Nsf, u8)>,
 }
 
 impl Console {
+    /// Load an iNES file, or an archive holding one, see
+    /// [`Console::from_bytes`].
     pub fn from_file(path: impl AsRef<Path> + 'static) -> Result<Console> {
-        let mapper = Mapper::from_file(path)?;
-        let mapper = Rc::new(RefCell::new(mapper));
+        Self::from_bytes(fs::read(path)?)
+    }
 
-        let ppu_bus = PpuBus {
-            vram: vec![0; 2 * 1024], // 2 kB
-            mapper: mapper.clone(),
+    /// Load an iNES image, or a .zip or .7z archive holding a single .nes
+    /// file, read to the end of `reader`.
+    pub fn from_reader(mut reader: impl Read) -> Result<Console> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Self::from_bytes(bytes)
+    }
+
+    /// Load an iNES image, or a .zip or .7z archive holding a single .nes
+    /// file. Archives need the `zip` or `sevenz` feature, see [`archive`].
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Console> {
+        Self::from_rom(archive::extract_rom(bytes)?)
+    }
+
+    /// Load an iNES image without copying its PRG and CHR data, see
+    /// [`Rom`].
+    pub fn from_rom(rom: impl Into<Rom>) -> Result<Console> {
+        Self::from_cartridge(Cartridge::from_bytes(rom)?)
+    }
+
+    /// Start configuring a console, see [`ConsoleBuilder`].
+    pub fn builder() -> ConsoleBuilder {
+        ConsoleBuilder::default()
+    }
+
+    pub fn from_cartridge(cartridge: Cartridge) -> Result<Console> {
+        let mapper = <dyn Mapper>::from_cartridge(&cartridge)?;
+        let mut console = Self::with_mapper(mapper, cartridge.header().mirroring);
+        console.set_region(cartridge.header().timing.region());
+        console.cartridge = Some(cartridge);
+        Ok(console)
+    }
+
+    /// Load an NSF file and start its first song, see
+    /// [`Console::from_nsf_bytes`].
+    pub fn from_nsf(path: impl AsRef<Path>) -> Result<Console> {
+        Self::from_nsf_bytes(fs::read(path)?)
+    }
+
+    /// Play an NSF tune. The console runs as an NSF player: a driver calls
+    /// the tune's INIT routine for the song picked with
+    /// [`Console::play_song`] and then its PLAY routine at the tune's
+    /// speed, and the sound comes out of [`Console::audio_output`].
+    ///
+    /// PAL-only tunes run on a PAL console, everything else on NTSC.
+    pub fn from_nsf_bytes(bytes: impl Into<Rom>) -> Result<Console> {
+        let nsf = Nsf::from_bytes(bytes)?;
+        let (region, cpu_hz, speed, default_speed) = match nsf.region {
+            NsfRegion::Pal => (Region::Pal, nsf_player::PAL_CPU_HZ, nsf.pal_speed, 19997),
+            _ => (Region::Ntsc, nsf_player::NTSC_CPU_HZ, nsf.ntsc_speed, 16639),
         };
+        let speed = if speed == 0 { default_speed } else { speed };
+        let mapper = NsfPlayer::new(&nsf, cpu_hz, speed)?;
+        let mut console = Self::with_mapper(Box::new(mapper), Mirroring::Horizontal);
+        console.set_region(region);
+        console.override_vector(Vector::Reset, Some(nsf_player::DRIVER));
+        let song = nsf.starting_song;
+        console.nsf = Some((nsf, song));
+        console.play_song(song)?;
+        Ok(console)
+    }
+
+    /// The tune loaded by [`Console::from_nsf`].
+    pub fn nsf(&self) -> Option<&Nsf> {
+        self.nsf.as_ref().map(|(nsf, _)| nsf)
+    }
 
-        let ppu = Ppu::new(ppu_bus);
-        let ppu = Rc::new(RefCell::new(ppu));
+    /// The song playing, numbered from 1.
+    pub fn song(&self) -> Option<u8> {
+        self.nsf.as_ref().map(|&(_, song)| song)
+    }
+
+    /// Start song `song` of the loaded NSF, numbered from 1 up to
+    /// [`Nsf::songs`].
+    pub fn play_song(&mut self, song: u8) -> Result<()> {
+        let (nsf, playing) = self.nsf.as_mut().ok_or("no NSF is loaded")?;
+        if song == 0 || song > nsf.songs {
+            return Err(format!("no song {}, there are {}", song, nsf.songs).into());
+        }
+        *playing = song;
+        let pal = nsf.region == NsfRegion::Pal;
+
+        self.soft_reset();
+        let bus = self.cpu.bus_mut();
+        bus.wram.iter_mut().for_each(|byte| *byte = 0);
+        bus.write(0x4015, 0x00);
+        for address in 0x4000..=0x4013 {
+            bus.write(address, 0x00);
+        }
+        bus.write(0x4015, 0x0f);
+        bus.write(0x4017, 0x40);
+        let registers = Registers {
+            a: song - 1,
+            x: pal as u8,
+            ..*self.cpu.registers()
+        };
+        self.cpu.set_registers(registers);
+        Ok(())
+    }
+
+    /// Load a headerless 6502 program at `address` in a cartridge that is
+    /// RAM from $4020 to $FFFF, with the reset vector pointing at `reset`.
+    ///
+    /// The program may also be placed in work RAM ($0000-$07FF), but not
+    /// over the PPU or APU registers or the reset vector at $FFFC-$FFFD.
+    /// Nametables are mirrored horizontally.
+    pub fn load_raw_program(program: &[u8], address: u16, reset: u16) -> Result<Console> {
+        let end = address as usize + program.len();
+        let in_wram = end <= 0x0800;
+        let in_cartridge = address >= 0x4020 && end <= 0x10000;
+        if !in_wram && !in_cartridge {
+            return Err(format!(
+                "program at ${:04X}-${:04X} is outside RAM",
+                address,
+                end.saturating_sub(1)
+            )
+            .into());
+        }
+        if (address as usize) < 0xfffe && end > 0xfffc {
+            return Err(format!(
+                "program at ${:04X}-${:04X} overlaps the reset vector",
+                address,
+                end - 1
+            )
+            .into());
+        }
+
+        let mut console = Self::with_mapper(Box::new(FlatRam::new()), Mirroring::Horizontal);
+        let bus = console.cpu.bus_mut();
+        bus.load_slice_at(0xfffc, &reset.to_le_bytes());
+        bus.load_slice_at(address, program);
+        Ok(console)
+    }
+
+    fn with_mapper(mapper: Box<dyn Mapper>, mirroring: Mirroring) -> Console {
+        let ppu_bus = PpuBus {
+            vram: vec![0; 4 * 1024], // 4 kB
+            mapper,
+            mirroring,
+            a12: false,
+        };
 
-        let cpu_bus = CpuBus {
+        let mut cpu_bus = CpuBus {
             wram: vec![0; 2 * 1024], // 2 kB
-            mapper: mapper.clone(),
-            ppu: ppu.clone(),
+            ppu: Ppu::new(ppu_bus),
+            oam_dma: None,
+            apu: Apu::new(),
+            controllers: [Box::new(Joypad::new()), Box::new(Joypad::new())],
+            input: InputState::default(),
+            input_latched: false,
+            cheats: Cheats::new(),
+            open_bus: 0,
+            watches: Watches::default(),
+            sampler: None,
+            clock: Clock::NTSC,
+            pending: 0,
+            events: Scheduler::new(),
+            ahead: 0,
+            nmi: false,
+            frame_irq: false,
+            mapper_irq: false,
         };
+        cpu_bus.update_lines();
 
         let cpu = Cpu::new(cpu_bus);
 
-        Ok(Console {
+        Console {
             cpu,
-            ppu: ppu.clone(),
-        })
+            scheduler: Scheduler::new(),
+            palette: Palette::ntsc(),
+            cartridge: None,
+            nsf: None,
+        }
+    }
+
+    fn ppu(&self) -> &Ppu<PpuBus> {
+        &self.cpu.bus().ppu
+    }
+
+    fn ppu_mut(&mut self) -> &mut Ppu<PpuBus> {
+        &mut self.cpu.bus_mut().ppu
+    }
+
+    /// The loaded cartridge, or `None` for [`Console::load_raw_program`].
+    pub fn cartridge(&self) -> Option<&Cartridge> {
+        self.cartridge.as_ref()
     }
 
     pub fn read_range<R: ops::RangeBounds<u16>>(&mut self, range: R) -> Vec<u8> {
-        self.cpu.bus.read_range(range)
+        self.cpu.bus_mut().read_range(range)
+    }
+
+    /// See [`Bus::read_u16_le`].
+    pub fn read_u16_le(&mut self, address: u16) -> u16 {
+        self.cpu.bus_mut().read_u16_le(address)
+    }
+
+    /// See [`Bus::load_slice_at`]. Writes go through the bus, so they reach
+    /// registers too; [`Console::poke`] leaves those alone.
+    pub fn load_slice_at(&mut self, address: u16, data: &[u8]) {
+        self.cpu.bus_mut().load_slice_at(address, data);
+    }
+
+    /// See [`Bus::write_range`].
+    pub fn write_range<R: ops::RangeBounds<u16>>(&mut self, range: R, data: &[u8]) {
+        self.cpu.bus_mut().write_range(range, data);
+    }
+
+    pub fn read_into(&mut self, address: u16, buffer: &mut [u8]) {
+        self.cpu.bus_mut().read_into(address, buffer)
+    }
+
+    /// What the CPU would read at `address`, without the side effects reads
+    /// of some registers have: PPUSTATUS keeps its vblank flag, controllers
+    /// do not shift, and so on. Cheats apply. This takes `&mut self` only
+    /// to catch the PPU, APU and mapper up to the CPU first.
+    pub fn peek(&mut self, address: u16) -> u8 {
+        let bus = self.cpu.bus_mut();
+        bus.catch_up();
+        bus.peek(address)
+    }
+
+    /// `peek` each address in `range`.
+    pub fn peek_range<R: ops::RangeBounds<u16>>(&mut self, range: R) -> Vec<u8> {
+        let addresses = bus::addresses(&range);
+        let bus = self.cpu.bus_mut();
+        bus.catch_up();
+        addresses.map(|address| bus.peek(address as u16)).collect()
+    }
+
+    /// Change work RAM or cartridge RAM at `address` to `data`, as a
+    /// debugger or cheat search would. Registers and ROM are left alone,
+    /// and nothing else sees the write. Returns whether there was RAM to
+    /// change.
+    pub fn poke(&mut self, address: u16, data: u8) -> bool {
+        let bus = self.cpu.bus_mut();
+        bus.catch_up();
+        bus.poke(address, data)
+    }
+
+    pub fn registers(&self) -> &Registers {
+        self.cpu.registers()
     }
 
-    pub fn reset(&mut self) {
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.cpu.set_registers(registers);
+    }
+
+    /// CPU cycles elapsed since power on.
+    pub fn cycles(&self) -> u64 {
+        self.cpu.cycles()
+    }
+
+    /// See [`Cpu::override_vector`].
+    pub fn override_vector(&mut self, vector: Vector, target: Option<u16>) {
+        self.cpu.override_vector(vector, target);
+    }
+
+    /// Whether the CPU has locked up on a JAM or unemulated opcode, see
+    /// [`Cpu::is_halted`].
+    pub fn is_halted(&self) -> bool {
+        self.cpu.is_halted()
+    }
+
+    /// Fill work RAM with `fill` and power on the CPU, see
+    /// [`Cpu::power_on`]. For a console that has run, see
+    /// [`Console::power_cycle`].
+    pub fn power_on(&mut self, fill: RamFill) {
+        fill.fill(&mut self.cpu.bus_mut().wram);
+        self.cpu.power_on();
+    }
+
+    /// Power on set up so that runs repeat exactly: with RAM and open bus
+    /// as `config` says, and input latched once a frame as by
+    /// [`Console::set_input_latched`]. Consoles set up alike with the same
+    /// game and given the same buttons each frame keep the same
+    /// [`Console::frame_hash`].
+    pub fn power_on_deterministic(&mut self, config: Deterministic) {
+        self.ppu_mut().set_latch_decay(config.latch_decay);
+        self.set_input_latched(true);
+        self.power_on(config.ram_fill);
+    }
+
+    /// Press the reset button. The CPU runs its reset sequence, the APU
+    /// falls silent and the mapper is told, see [`Mapper::reset`]. Memory
+    /// keeps what it holds, which is how games tell a reset from a power
+    /// cycle.
+    ///
+    /// The PPU is kept as it is, as on the Famicom, where the reset button
+    /// does not reach it.
+    pub fn soft_reset(&mut self) {
+        let bus = self.cpu.bus_mut();
+        bus.apu.reset();
+        bus.mapper_mut().reset();
+        bus.update_lines();
         self.cpu.reset();
     }
 
-    pub fn step(&mut self) {
-        self.cpu.step();
-        self.ppu.borrow_mut().step();
+    /// Switch the console off and on again. Everything starts over as at
+    /// power on, with work RAM and VRAM filled with `fill`.
+    ///
+    /// The cartridge starts over too, keeping only its battery-backed
+    /// memory, and an NSF starts its song again. The RAM cartridge of
+    /// [`Console::load_raw_program`] keeps the program. Callbacks waiting
+    /// from [`Console::schedule_in`] are dropped, since the cycles they
+    /// wait for count from the old power on.
+    pub fn power_cycle(&mut self, fill: RamFill) {
+        if let Some(cartridge) = &self.cartridge {
+            let mut mapper =
+                <dyn Mapper>::from_cartridge(cartridge).expect("the cartridge loaded before");
+            if let Some(ram) = self.battery_ram() {
+                mapper.load_battery_ram(&ram);
+            }
+            self.ppu_mut().bus_mut().mapper = mapper;
+        }
+
+        self.scheduler.clear();
+        let bus = self.cpu.bus_mut();
+        bus.clock = bus.clock.at(0);
+        bus.pending = 0;
+        bus.oam_dma = None;
+        bus.apu.power_on();
+        bus.ppu.power_on();
+        fill.fill(&mut bus.ppu.bus_mut().vram);
+        bus.ppu.bus_mut().a12 = false;
+        bus.update_lines();
+        self.power_on(fill);
+
+        if let Some((_, song)) = self.nsf {
+            self.play_song(song).expect("the song played before");
+        }
+    }
+
+    /// The machine's state after a versioned header: CPU, work RAM, PPU,
+    /// APU, controllers and cartridge. Load it with [`Console::load_state`]
+    /// into a console running the same game.
+    ///
+    /// Settings such as the palette, trace and undo history are left out,
+    /// as are scheduled callbacks and the pixels of the last frame.
+    pub fn save_state(&self) -> Vec<u8> {
+        let bus = self.cpu.bus();
+        let mut state = StateWriter::new();
+        state.write(state::MAGIC);
+        state.write(&state::VERSION);
+        state.write(&bus.mapper().id());
+        state.write(&self.region());
+        state.write(&bus.clock);
+        self.cpu.save_state(&mut state);
+        state.write(&bus.wram);
+        bus.ppu.save_state(&mut state);
+        state.write(&bus.ppu.bus().vram);
+        state.write(&bus.ppu.bus().a12);
+        bus.apu.save_state(&mut state);
+        for controller in &bus.controllers {
+            controller.save_state(&mut state);
+        }
+        bus.mapper().save_state(&mut state);
+        state.into_bytes()
+    }
+
+    /// Restore a state saved by [`Console::save_state`], from this version
+    /// of the crate or an earlier one. On error the console is left as it
+    /// was.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<()> {
+        let backup = self.save_state();
+        self.read_state(bytes).inspect_err(|_| {
+            self.read_state(&backup)
+                .expect("the state from before loading reloads");
+        })
+    }
+
+    fn read_state(&mut self, bytes: &[u8]) -> Result<()> {
+        let mut state = StateReader::new(bytes);
+        if !bytes.starts_with(state::MAGIC) {
+            return Err("not a savestate".into());
+        }
+        state.read::<[u8; 4]>()?;
+        let version = state.read::<u16>()?;
+        if version == 0 || version > state::VERSION {
+            return Err(format!(
+                "savestate version {} is not supported, only up to {}",
+                version,
+                state::VERSION
+            )
+            .into());
+        }
+        state.set_version(version);
+        let mapper_id = state.read::<u8>()?;
+        let expected = self.cpu.bus().mapper().id();
+        if mapper_id != expected {
+            return Err(format!(
+                "the savestate is for mapper {}, not {}",
+                mapper_id, expected
+            )
+            .into());
+        }
+
+        self.set_region(state.read()?);
+        let clock = state.read()?;
+        self.cpu.load_state(&mut state)?;
+        let bus = self.cpu.bus_mut();
+        bus.clock = clock;
+        state.read_into(&mut bus.wram)?;
+        bus.ppu.load_state(&mut state)?;
+        state.read_into(&mut bus.ppu.bus_mut().vram)?;
+        bus.ppu.bus_mut().a12 = state.read()?;
+        bus.apu.load_state(&mut state)?;
+        for controller in &mut bus.controllers {
+            controller.load_state(&mut state)?;
+        }
+        bus.mapper_mut().load_state(&mut state)?;
+        if state.remaining() > 0 {
+            return Err(format!("{} bytes left over in the savestate", state.remaining()).into());
+        }
+        bus.update_lines();
+        Ok(())
+    }
+
+    /// Print a CPU trace line to stdout before each instruction.
+    pub fn set_trace(&mut self, enabled: bool) {
+        self.cpu.set_trace(enabled);
+    }
+
+    /// See [`Cpu::set_trace_sink`].
+    pub fn set_trace_sink(&mut self, sink: impl TraceSink + Send + 'static) {
+        self.cpu.set_trace_sink(sink);
+    }
+
+    pub fn clear_trace_sink(&mut self) {
+        self.cpu.clear_trace_sink();
+    }
+
+    /// See [`Cpu::set_heatmap`].
+    pub fn set_heatmap(&mut self, enabled: bool) {
+        self.cpu.set_heatmap(enabled);
+    }
+
+    pub fn heatmap(&self) -> Option<&Heatmap> {
+        self.cpu.heatmap()
+    }
+
+    pub fn heatmap_mut(&mut self) -> Option<&mut Heatmap> {
+        self.cpu.heatmap_mut()
+    }
+
+    /// See [`Cpu::set_undo_depth`].
+    pub fn set_undo_depth(&mut self, depth: usize) {
+        self.cpu.set_undo_depth(depth);
+    }
+
+    /// Undo the most recent CPU instruction, see [`Cpu::step_back`].
+    pub fn step_back(&mut self) -> bool {
+        self.cpu.step_back()
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &self.cpu.bus().clock
+    }
+
+    /// Replace the clock, e.g. to overclock. The master cycle count
+    /// carries over.
+    pub fn set_clock(&mut self, clock: Clock) {
+        let bus = self.cpu.bus_mut();
+        bus.catch_up();
+        bus.clock = clock.at(bus.clock.master_cycle());
+        bus.update_lines();
+        self.set_sample_rate(self.sample_rate());
+    }
+
+    /// The console being emulated. Cartridges pick theirs from an NES 2.0
+    /// header, otherwise it is NTSC.
+    pub fn region(&self) -> Region {
+        self.ppu().region()
+    }
+
+    /// Switch console: the clock ratio, scanlines per frame and APU timing
+    /// all follow. The master cycle count carries over.
+    pub fn set_region(&mut self, region: Region) {
+        let bus = self.cpu.bus_mut();
+        bus.catch_up();
+        bus.ppu.set_region(region);
+        bus.apu.set_region(region);
+        // Last, as the sample rate follows the region's master clock
+        self.set_clock(region.clock());
+    }
+
+    /// Frames per second on the emulated console, for pacing playback.
+    pub fn frame_rate(&self) -> f64 {
+        self.region().frame_rate()
+    }
+
+    /// Run one CPU instruction and the PPU dots that elapse meanwhile.
+    pub fn step(&mut self) -> Step {
+        self.advance(true)
+    }
+
+    /// Run one CPU instruction. Unless `catch_up` is set, the PPU, APU and
+    /// mapper are left behind until their next event or register access.
+    /// Callbacks see them caught up.
+    fn advance(&mut self, catch_up: bool) -> Step {
+        let mut step = self.cpu.step();
+        if let Some(page) = self.cpu.bus_mut().oam_dma.take() {
+            step.cycles += self.oam_dma(page);
+        }
+        let bus = self.cpu.bus_mut();
+        bus.pending += step.cycles - mem::take(&mut bus.ahead);
+        let now = bus.master_cycle();
+        let next_event = bus
+            .next_event()
+            .min(self.scheduler.next_due().unwrap_or(u64::MAX));
+        if catch_up || now >= next_event {
+            bus.catch_up();
+        }
+
+        while let Some(Callback(callback)) = self.scheduler.pop_due(now) {
+            callback(self);
+        }
+        step
+    }
+
+    /// Copy a page of CPU memory to OAM, stalling the CPU for 513 cycles,
+    /// plus one to align to an even cycle. Returns the cycles taken.
+    fn oam_dma(&mut self, page: u8) -> u64 {
+        let cycles = 513 + self.cpu.cycles() % 2;
+        let bus = self.cpu.bus_mut();
+        bus.catch_up();
+        for low in 0..=0xff {
+            let data = bus.read(u16::from_be_bytes([page, low]));
+            bus.ppu.write_oam(data);
+        }
+        self.cpu.stall(cycles);
+        cycles
+    }
+
+    /// Plug `controller` into `port` 0 or 1. Both start with a [`Joypad`].
+    ///
+    /// The buttons of players `port` + 1 and `port` + 3 are released.
+    pub fn set_controller(&mut self, port: usize, controller: impl Controller + 'static) {
+        let bus = self.cpu.bus_mut();
+        bus.controllers[port] = Box::new(controller);
+        for &button in &Button::ALL {
+            bus.input.set_pressed(port, button, false);
+            bus.input.set_pressed(port + 2, button, false);
+        }
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.cpu.bus().apu
+    }
+
+    /// For muting channels, see [`Apu::set_muted`].
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        // Look at the lines again after the next instruction, in case the
+        // caller writes a register
+        let bus = self.cpu.bus_mut();
+        bus.events.schedule(0, Event::FrameIrq);
+        &mut bus.apu
+    }
+
+    /// The APU's output mixed with the cartridge's expansion audio, see
+    /// [`Apu::output`] and [`Mapper::audio`].
+    pub fn audio_output(&self) -> f32 {
+        self.apu().output() + self.cpu.bus().mapper().audio()
+    }
+
+    /// Collect [`Console::audio_output`] at `rate` samples per second, each
+    /// the average over its share of CPU cycles, or stop with `None`.
+    pub fn set_sample_rate(&mut self, rate: Option<u32>) {
+        let cpu_hz = self.region().master_clock_hz() / self.clock().cpu_divider() as f64;
+        let bus = self.cpu.bus_mut();
+        bus.catch_up();
+        bus.sampler = rate.map(|rate| {
+            assert!(rate > 0, "the sample rate must be above zero");
+            let samples = bus
+                .sampler
+                .take()
+                .map_or_else(Vec::new, |sampler| sampler.samples);
+            Sampler {
+                rate,
+                period: cpu_hz / rate as f64,
+                elapsed: 0.0,
+                sum: 0.0,
+                count: 0,
+                samples,
+            }
+        });
+    }
+
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.cpu.bus().sampler.as_ref().map(|sampler| sampler.rate)
+    }
+
+    /// The audio samples collected since the last call, see
+    /// [`Console::set_sample_rate`].
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.cpu
+            .bus_mut()
+            .sampler
+            .as_mut()
+            .map_or_else(Vec::new, |sampler| std::mem::take(&mut sampler.samples))
+    }
+
+    /// A copy of the cartridge's battery-backed memory for saving, see
+    /// [`Mapper::battery_ram`].
+    pub fn battery_ram(&self) -> Option<Vec<u8>> {
+        self.cpu.bus().mapper().battery_ram().map(<[u8]>::to_vec)
+    }
+
+    /// Restore battery-backed memory saved from [`Console::battery_ram`].
+    pub fn load_battery_ram(&mut self, data: &[u8]) -> Result<()> {
+        let bus = self.cpu.bus_mut();
+        match bus.mapper().battery_ram() {
+            Some(ram) if ram.len() == data.len() => {
+                bus.mapper_mut().load_battery_ram(data);
+                bus.update_lines();
+                Ok(())
+            }
+            Some(ram) => {
+                Err(format!("battery RAM is {} bytes, not {}", ram.len(), data.len()).into())
+            }
+            None => Err("the cartridge has no battery RAM".into()),
+        }
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Colors used by [`Console::frame`], NTSC by default.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Step until the PPU completes a frame.
+    ///
+    /// This and [`Console::run_cycles`] run the PPU, APU and mapper in
+    /// batches, between the points where they can interrupt the CPU or
+    /// the CPU touches their registers, which is faster than stepping.
+    pub fn run_frame(&mut self) {
+        // The frame count changes at an event, so it is never behind
+        let frames = self.frames();
+        while self.frames() == frames {
+            self.advance(false);
+        }
+        self.cpu.bus_mut().catch_up();
+    }
+
+    /// Step until at least `cycles` CPU cycles have passed. Instructions
+    /// run whole, so the last one can overshoot; returns the cycles run.
+    pub fn run_cycles(&mut self, cycles: u64) -> u64 {
+        let start = self.cycles();
+        while self.cycles() - start < cycles {
+            self.advance(false);
+        }
+        self.cpu.bus_mut().catch_up();
+        self.cycles() - start
+    }
+
+    /// Step until `done` holds, checking it after each instruction.
+    /// Returns the number of instructions run.
+    pub fn run_until(&mut self, mut done: impl FnMut(&mut Console) -> bool) -> usize {
+        let mut instructions = 0;
+        while !done(self) {
+            self.step();
+            instructions += 1;
+        }
+        instructions
+    }
+
+    /// A CRC-32 of the machine's state as [`Console::save_state`] has it,
+    /// to check once a frame that runs which should match still do, such
+    /// as a movie and its replay.
+    pub fn frame_hash(&mut self) -> u32 {
+        self.cpu.bus_mut().catch_up();
+        checksum::crc32(&self.save_state())
+    }
+
+    /// Frames the PPU has completed since power on.
+    pub fn frames(&self) -> u64 {
+        self.ppu().frames()
+    }
+
+    /// The last complete frame as RGBA, [`ppu::WIDTH`] by [`ppu::HEIGHT`]
+    /// pixels row by row.
+    pub fn frame(&self) -> Vec<u8> {
+        let ppu = self.ppu();
+        let mut rgba = Vec::with_capacity(ppu::WIDTH * ppu::HEIGHT * 4);
+        for &pixel in ppu.frame() {
+            rgba.extend_from_slice(&self.palette.rgb(pixel));
+            rgba.push(0xff);
+        }
+        rgba
+    }
+
+    /// The last complete frame as PPU pixel values, see [`Ppu::frame`].
+    pub fn frame_pixels(&self) -> Vec<u16> {
+        self.ppu().frame().to_vec()
+    }
+
+    /// Connect a Four Score to both ports, or plain joypads when `enabled`
+    /// is false. Either way every button starts released.
+    pub fn set_four_score(&mut self, enabled: bool) {
+        for port in 0..2 {
+            if enabled {
+                self.set_controller(port, FourScore::new(port));
+            } else {
+                self.set_controller(port, Joypad::new());
+            }
+        }
+    }
+
+    /// Press or release `button` for player `port` + 1.
+    ///
+    /// Ports 0 and 1 are the controller ports. With a Four Score connected,
+    /// ports 2 and 3 are its second pads on ports 0 and 1.
+    pub fn set_button_state(&mut self, port: usize, button: Button, pressed: bool) {
+        assert!(port < 4, "no controller port {}", port);
+        let bus = self.cpu.bus_mut();
+        bus.input.set_pressed(port, button, pressed);
+        if !bus.input_latched {
+            bus.controllers[port % 2].set_pad_button(port / 2, button, pressed);
+        }
+    }
+
+    /// The buttons held on every pad, as last set through the console.
+    pub fn input(&self) -> InputState {
+        self.cpu.bus().input
+    }
+
+    /// Set the buttons of all four players at once, see
+    /// [`Console::set_button_state`].
+    pub fn set_input(&mut self, input: InputState) {
+        let bus = self.cpu.bus_mut();
+        bus.input = input;
+        if !bus.input_latched {
+            bus.apply_input();
+        }
+    }
+
+    /// Hold button changes back until the next frame begins, so a game
+    /// sees the same buttons for a whole frame however the front end
+    /// times them. Movies rely on this to replay exactly.
+    ///
+    /// Turning it off passes on any changes still held back.
+    pub fn set_input_latched(&mut self, latched: bool) {
+        let bus = self.cpu.bus_mut();
+        bus.input_latched = latched;
+        if !latched {
+            bus.apply_input();
+        }
+    }
+
+    /// Pass on button changes held back by [`Console::set_input_latched`]
+    /// now, as if a frame had just begun.
+    pub fn latch_input(&mut self) {
+        self.cpu.bus_mut().apply_input();
+    }
+
+    /// Decode `code`, a Game Genie or raw code, and patch CPU reads with
+    /// it from now on, see [`Cheat`].
+    pub fn add_cheat(&mut self, code: &str) -> Result<Cheat> {
+        let cheat = code.parse()?;
+        self.cpu.bus_mut().cheats.add(cheat);
+        Ok(cheat)
+    }
+
+    /// Take out a cheat added with [`Console::add_cheat`]. Returns whether
+    /// it was there.
+    pub fn remove_cheat(&mut self, cheat: &Cheat) -> bool {
+        self.cpu.bus_mut().cheats.remove(cheat)
+    }
+
+    /// Switch a cheat on or off without taking it out. Returns whether it
+    /// was there.
+    pub fn set_cheat_enabled(&mut self, cheat: &Cheat, enabled: bool) -> bool {
+        self.cpu.bus_mut().cheats.set_enabled(cheat, enabled)
+    }
+
+    pub fn cheats(&self) -> &Cheats {
+        &self.cpu.bus().cheats
+    }
+
+    /// Sprite attribute memory, for debugging.
+    pub fn oam(&self) -> [u8; 256] {
+        *self.ppu().oam()
+    }
+
+    /// Call `callback` with each CPU read or write, as `kind` says, of an
+    /// address in `addresses`. Reads through [`Console::read_range`] count
+    /// and peeks do not. Returns an id for [`Console::unwatch`].
+    ///
+    /// Watches run in the middle of an instruction, so they cannot reach
+    /// the console. To stop at an access, have one set a flag and check it
+    /// between steps.
+    pub fn watch<R: ops::RangeBounds<u16>>(
+        &mut self,
+        kind: AccessKind,
+        addresses: R,
+        callback: impl FnMut(Access) + Send + 'static,
+    ) -> WatchId {
+        let addresses = bus::addresses(&addresses);
+        self.cpu.bus_mut().watches.add(kind, addresses, callback)
+    }
+
+    /// Take out a watch added with [`Console::watch`]. Returns whether it
+    /// was there.
+    pub fn unwatch(&mut self, id: WatchId) -> bool {
+        self.cpu.bus_mut().watches.remove(id)
+    }
+
+    pub fn clear_watches(&mut self) {
+        self.cpu.bus_mut().watches.clear();
+    }
+
+    /// Call `callback` once `cpu_cycles` CPU cycles have passed.
+    ///
+    /// Callbacks run between instructions, after the step that reaches
+    /// their cycle.
+    pub fn schedule_in(
+        &mut self,
+        cpu_cycles: u64,
+        callback: impl Fn(&mut Console) + Send + Sync + 'static,
+    ) {
+        let bus = self.cpu.bus();
+        let due = bus.master_cycle() + cpu_cycles * bus.clock.cpu_divider();
+        self.scheduler.schedule(due, Callback(Arc::new(callback)));
+    }
+}
+
+/// Fields of a [`Console`] serialized with serde, in order.
+#[cfg(feature = "serde")]
+const STATE_FIELDS: &[&str] = &[
+    "version",
+    "mapper",
+    "region",
+    "clock",
+    "cpu",
+    "wram",
+    "ppu",
+    "vram",
+    "a12",
+    "apu",
+    "controllers",
+    "cartridge",
+];
+
+/// The state [`Console::save_state`] covers, with field names, for any
+/// serde format: JSON to read or diff states, a compact binary one to send
+/// them over the network. Load it with the `DeserializeSeed` impl below.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Console {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let bus = self.cpu.bus();
+        let ppu = &bus.ppu;
+        let controllers = &bus.controllers;
+        let mapper = bus.mapper();
+        let mut fields = serializer.serialize_struct("Console", STATE_FIELDS.len())?;
+        fields.serialize_field("version", &state::VERSION)?;
+        fields.serialize_field("mapper", &mapper.id())?;
+        fields.serialize_field("region", &self.region())?;
+        fields.serialize_field("clock", &bus.clock)?;
+        fields.serialize_field("cpu", &self.cpu)?;
+        fields.serialize_field("wram", &state::Bytes(&bus.wram))?;
+        fields.serialize_field("ppu", ppu)?;
+        fields.serialize_field("vram", &state::Bytes(&ppu.bus().vram))?;
+        fields.serialize_field("a12", &ppu.bus().a12)?;
+        fields.serialize_field("apu", &bus.apu)?;
+        fields.serialize_field(
+            "controllers",
+            &[
+                controllers[0].serialize_state(),
+                controllers[1].serialize_state(),
+            ][..],
+        )?;
+        fields.serialize_field("cartridge", mapper.serialize_state())?;
+        fields.end()
+    }
+}
+
+/// Load a state serialized from a console running the same game, with the
+/// same checks as [`Console::load_state`]. On error the console is left as
+/// it was. With `serde::de::DeserializeSeed` in scope, that is
+/// `console.deserialize(deserializer)`.
+#[cfg(feature = "serde")]
+impl<'de> serde::de::DeserializeSeed<'de> for &mut Console {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<(), D::Error> {
+        let backup = self.save_state();
+        let visitor = StateVisitor {
+            console: &mut *self,
+            clock: None,
+        };
+        deserializer
+            .deserialize_struct("Console", STATE_FIELDS, visitor)
+            .inspect_err(|_| {
+                self.read_state(&backup)
+                    .expect("the state from before loading reloads");
+            })
+    }
+}
+
+#[cfg(feature = "serde")]
+struct StateVisitor<'a> {
+    console: &'a mut Console,
+    /// Applied once the region has been, which sets the clock too
+    clock: Option<Clock>,
+}
+
+#[cfg(feature = "serde")]
+impl StateVisitor<'_> {
+    /// Load the field at `index` in [`STATE_FIELDS`].
+    fn field<'de, D: serde::Deserializer<'de>>(
+        &mut self,
+        index: usize,
+        deserializer: D,
+    ) -> std::result::Result<(), D::Error> {
+        use serde::de::{DeserializeSeed, Error};
+        use serde::Deserialize;
+        let console = &mut *self.console;
+        match STATE_FIELDS[index] {
+            "version" => {
+                let version = u16::deserialize(deserializer)?;
+                if version == 0 || version > state::VERSION {
+                    return Err(D::Error::custom(format!(
+                        "savestate version {} is not supported, only up to {}",
+                        version,
+                        state::VERSION
+                    )));
+                }
+            }
+            "mapper" => {
+                let mapper_id = u8::deserialize(deserializer)?;
+                let expected = console.cpu.bus().mapper().id();
+                if mapper_id != expected {
+                    return Err(D::Error::custom(format!(
+                        "the savestate is for mapper {}, not {}",
+                        mapper_id, expected
+                    )));
+                }
+            }
+            "region" => console.set_region(Region::deserialize(deserializer)?),
+            "clock" => self.clock = Some(Clock::deserialize(deserializer)?),
+            "cpu" => (&mut console.cpu).deserialize(deserializer)?,
+            "wram" => {
+                state::BytesPlace(&mut console.cpu.bus_mut().wram).deserialize(deserializer)?
+            }
+            "ppu" => console.ppu_mut().deserialize(deserializer)?,
+            "vram" => state::BytesPlace(&mut console.ppu_mut().bus_mut().vram)
+                .deserialize(deserializer)?,
+            "a12" => console.ppu_mut().bus_mut().a12 = bool::deserialize(deserializer)?,
+            "apu" => (&mut console.cpu.bus_mut().apu).deserialize(deserializer)?,
+            "controllers" => {
+                let [first, second] = &mut console.cpu.bus_mut().controllers;
+                let mut ports = [ErasedPlace(&mut **first), ErasedPlace(&mut **second)];
+                state::Seeds(&mut ports[..]).deserialize(deserializer)?
+            }
+            "cartridge" => (&mut ErasedPlace(&mut *console.ppu_mut().bus_mut().mapper))
+                .deserialize(deserializer)?,
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Apply the clock and put the whole state through the savestate's
+    /// checks.
+    fn finish(self) -> Result<()> {
+        self.console.cpu.bus_mut().clock = self.clock.expect("the clock was loaded");
+        let bytes = self.console.save_state();
+        self.console.read_state(&bytes)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::Visitor<'de> for StateVisitor<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a console state")
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(
+        mut self,
+        mut seq: A,
+    ) -> std::result::Result<(), A::Error> {
+        use serde::de::Error;
+        for (index, name) in STATE_FIELDS.iter().enumerate() {
+            if seq
+                .next_element_seed(FieldSeed(&mut self, index))?
+                .is_none()
+            {
+                return Err(A::Error::missing_field(name));
+            }
+        }
+        self.finish().map_err(A::Error::custom)
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(
+        mut self,
+        mut map: A,
+    ) -> std::result::Result<(), A::Error> {
+        use serde::de::Error;
+        let mut seen = [false; STATE_FIELDS.len()];
+        while let Some(key) = map.next_key::<String>()? {
+            let index = STATE_FIELDS
+                .iter()
+                .position(|field| *field == key)
+                .ok_or_else(|| A::Error::unknown_field(&key, STATE_FIELDS))?;
+            if seen[index] {
+                return Err(A::Error::duplicate_field(STATE_FIELDS[index]));
+            }
+            seen[index] = true;
+            map.next_value_seed(FieldSeed(&mut self, index))?;
+        }
+        if let Some(index) = seen.iter().position(|seen| !seen) {
+            return Err(A::Error::missing_field(STATE_FIELDS[index]));
+        }
+        self.finish().map_err(A::Error::custom)
+    }
+}
+
+/// One field of a console state, see [`StateVisitor::field`].
+#[cfg(feature = "serde")]
+struct FieldSeed<'a, 'b>(&'a mut StateVisitor<'b>, usize);
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::DeserializeSeed<'de> for FieldSeed<'_, '_> {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<(), D::Error> {
+        self.0.field(self.1, deserializer)
+    }
+}
+
+/// Deserializes over a mapper or controller behind a trait object, through
+/// its `deserialize_state`.
+#[cfg(feature = "serde")]
+struct ErasedPlace<'a, T: ?Sized>(&'a mut T);
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::DeserializeSeed<'de> for &mut ErasedPlace<'_, dyn Mapper> {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<(), D::Error> {
+        let mut deserializer = <dyn erased_serde::Deserializer>::erase(deserializer);
+        self.0
+            .deserialize_state(&mut deserializer)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::DeserializeSeed<'de> for &mut ErasedPlace<'_, dyn Controller> {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<(), D::Error> {
+        let mut deserializer = <dyn erased_serde::Deserializer>::erase(deserializer);
+        self.0
+            .deserialize_state(&mut deserializer)
+            .map_err(serde::de::Error::custom)
     }
 }
@@ -1,10 +1,19 @@
-use crate::addressing_mode::AddressingMode;
 use crate::bus::Bus;
-use crate::instructions::Instruction;
+use crate::debugger::{self, Decoded, Heatmap, Stdout, TraceSink, Tracer, UndoLog};
+#[cfg(feature = "serde")]
+use crate::state::serde_state;
+use crate::state::{StateReader, StateWriter};
+use crate::Result;
 use std::fmt;
+use std::fmt::Write;
 
 bitflags! {
     #[derive(Default)]
+    #[cfg_attr(
+        feature = "serde",
+        derive(serde::Serialize, serde::Deserialize),
+        serde(transparent)
+    )]
     pub struct Status: u8 {
         const CARRY = 0x01;
         const ZERO_RESULT = 0x02;
@@ -17,6 +26,26 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Devices that can hold the shared IRQ line low.
+    ///
+    /// The CPU sees the line asserted while any source asserts it, and each
+    /// source releases only its own bit when it is acknowledged.
+    #[derive(Default)]
+    #[cfg_attr(
+        feature = "serde",
+        derive(serde::Serialize, serde::Deserialize),
+        serde(transparent)
+    )]
+    pub struct IrqSource: u8 {
+        const APU_FRAME = 0x01;
+        const DMC = 0x02;
+        const MAPPER = 0x04;
+        /// Anything else, such as a test harness or the expansion port
+        const EXTERNAL = 0x08;
+    }
+}
+
 impl fmt::Display for Status {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let n = if self.contains(Self::NEGATIVE_RESULT) {
@@ -60,19 +89,20 @@ impl fmt::Display for Status {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Registers {
     /// Program counter
-    pc: u16,
+    pub pc: u16,
     /// Stack pointer
-    sp: u8,
-    // Processor status
-    ps: Status,
-    // Accumulator
-    a: u8,
-    // X index
-    x: u8,
-    // Y index
-    y: u8,
+    pub sp: u8,
+    /// Processor status
+    pub ps: Status,
+    /// Accumulator
+    pub a: u8,
+    /// X index
+    pub x: u8,
+    /// Y index
+    pub y: u8,
 }
 
 impl Default for Registers {
@@ -88,11 +118,66 @@ impl Default for Registers {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// The interrupt vectors at the top of the address space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Vector {
+    Nmi,
+    Reset,
+    /// Also used by BRK
+    Irq,
+}
+
+impl Vector {
+    /// Address of the low byte of the vector.
+    pub fn address(self) -> u16 {
+        match self {
+            Vector::Nmi => 0xfffa,
+            Vector::Reset => 0xfffc,
+            Vector::Irq => 0xfffe,
+        }
+    }
+}
+
+/// What one call to [`Cpu::step`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Step {
+    /// CPU cycles taken
+    pub cycles: u64,
+    /// The instruction executed, or `None` if the step entered an
+    /// interrupt handler or the CPU is halted
+    pub opcode: Option<u8>,
+    /// The interrupt serviced instead of an instruction
+    pub interrupt: Option<Vector>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Cpu<B: Bus> {
-    pub(crate) bus: B,
+    bus: B,
     registers: Registers,
     cycle: u64,
+    /// Where trace lines go, `None` while tracing is off
+    tracer: Option<Tracer>,
+    /// Instruction bytes fetched in place of memory at the given PC, see
+    /// [`Cpu::execute`]
+    shadow: Option<([u8; 3], u16)>,
+    /// Targets used instead of the vectors in memory, indexed by `Vector`
+    vector_overrides: [Option<u16>; 3],
+    /// Journal for `step_back`, `None` while disabled
+    undo_log: Option<UndoLog>,
+    /// Level of the NMI input, `true` while asserted
+    nmi_line: bool,
+    /// Set on the NMI input's asserting edge until the interrupt is taken
+    nmi_pending: bool,
+    /// Sources currently asserting the IRQ input
+    irq_sources: IrqSource,
+    /// Whether the last instruction's interrupt poll saw an unmasked IRQ
+    irq_pending: bool,
+    /// Cycle the step in progress began on
+    step_start: u64,
+    /// Set by a JAM opcode, or one not emulated, until reset
+    halted: bool,
+    /// Access counts while enabled
+    heatmap: Option<Heatmap>,
 }
 
 impl<B: Bus> Cpu<B> {
@@ -103,16 +188,262 @@ impl<B: Bus> Cpu<B> {
             bus,
             registers: Default::default(),
             cycle: 0,
+            tracer: None,
+            shadow: None,
+            vector_overrides: [None; 3],
+            undo_log: None,
+            nmi_line: false,
+            nmi_pending: false,
+            irq_sources: IrqSource::empty(),
+            irq_pending: false,
+            step_start: 0,
+            halted: false,
+            heatmap: None,
         }
     }
 
-    pub fn reset(&mut self) {
-        self.registers.pc = {
-            let pcl = self.bus.read(0xfffc);
-            let pch = self.bus.read(0xfffd);
-            u16::from_be_bytes([pch, pcl])
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.registers.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.registers.pc = pc;
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.registers.sp
+    }
+
+    pub fn set_sp(&mut self, sp: u8) {
+        self.registers.sp = sp;
+    }
+
+    pub fn status(&self) -> Status {
+        self.registers.ps
+    }
+
+    pub fn set_status(&mut self, status: Status) {
+        self.registers.ps = status;
+    }
+
+    pub fn a(&self) -> u8 {
+        self.registers.a
+    }
+
+    pub fn set_a(&mut self, a: u8) {
+        self.registers.a = a;
+    }
+
+    pub fn x(&self) -> u8 {
+        self.registers.x
+    }
+
+    pub fn set_x(&mut self, x: u8) {
+        self.registers.x = x;
+    }
+
+    pub fn y(&self) -> u8 {
+        self.registers.y
+    }
+
+    pub fn set_y(&mut self, y: u8) {
+        self.registers.y = y;
+    }
+
+    /// CPU cycles elapsed since power on.
+    pub fn cycles(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Write the registers, cycle count and interrupt inputs, for
+    /// savestates. The bus is left to its owner.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        let registers = &self.registers;
+        state.write(&registers.pc);
+        state.write(&registers.sp);
+        state.write(&registers.ps.bits());
+        state.write(&registers.a);
+        state.write(&registers.x);
+        state.write(&registers.y);
+        state.write(&self.cycle);
+        state.write(&self.nmi_line);
+        state.write(&self.nmi_pending);
+        state.write(&self.irq_sources.bits());
+        state.write(&self.irq_pending);
+        state.write(&self.halted);
+    }
+
+    /// Restore what `save_state` wrote. The undo history no longer applies
+    /// and is dropped.
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.registers = Registers {
+            pc: state.read()?,
+            sp: state.read()?,
+            ps: Status::from_bits_truncate(state.read()?),
+            a: state.read()?,
+            x: state.read()?,
+            y: state.read()?,
+        };
+        self.cycle = state.read()?;
+        self.nmi_line = state.read()?;
+        self.nmi_pending = state.read()?;
+        self.irq_sources = IrqSource::from_bits_truncate(state.read()?);
+        self.irq_pending = state.read()?;
+        self.halted = state.read()?;
+        if let Some(undo_log) = &mut self.undo_log {
+            undo_log.clear();
+        }
+        Ok(())
+    }
+
+    /// Let `cycles` pass without running instructions, as while DMA halts
+    /// the CPU.
+    pub fn stall(&mut self, cycles: u64) {
+        self.cycle += cycles;
+    }
+
+    /// Overwrite the register file, e.g. to set up a test.
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.registers = registers;
+    }
+
+    /// Jump to `target` instead of the address stored in `vector`, or use
+    /// memory again for `None`.
+    ///
+    /// Lets test harnesses pick an entry point, such as nestest's $C000,
+    /// without patching the ROM.
+    pub fn override_vector(&mut self, vector: Vector, target: Option<u16>) {
+        self.vector_overrides[vector as usize] = target;
+    }
+
+    /// Whether a JAM opcode, or an unofficial opcode that is not emulated,
+    /// has locked up the CPU. Only reset recovers.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Put the registers in their power-up state and reset.
+    pub fn power_on(&mut self) {
+        self.registers = Registers {
+            pc: 0x0000,
+            sp: 0x00,
+            ps: Status::UNUSED | Status::INTERRUPT_DISABLE,
+            a: 0x00,
+            x: 0x00,
+            y: 0x00,
         };
-        self.cycle = 8;
+        self.cycle = 0;
+        self.reset();
+    }
+
+    /// The seven cycle reset sequence. It runs like an interrupt whose
+    /// pushes are reads, so SP drops by three without touching the stack.
+    ///
+    /// Interrupts pending from before are dropped. The NMI line keeps its
+    /// level, so one held asserted does not fire again until released.
+    pub fn reset(&mut self) {
+        self.halted = false;
+        self.nmi_pending = false;
+        self.irq_pending = false;
+        self.read(self.registers.pc);
+        self.read(self.registers.pc);
+        for _ in 0..3 {
+            self.read(self.stack_address());
+            self.registers.sp = self.registers.sp.wrapping_sub(1);
+        }
+        self.set_interrupt_disable_flag(true);
+        self.registers.pc = self.read_vector(Vector::Reset);
+    }
+
+    /// Drive the NMI input. NMI is edge-triggered, so the interrupt is
+    /// taken once each time the line goes from released to asserted.
+    pub fn set_nmi(&mut self, asserted: bool) {
+        if asserted && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = asserted;
+    }
+
+    /// Assert the level-sensitive IRQ input on behalf of `source`. The
+    /// interrupt is taken between instructions for as long as any source
+    /// asserts it and the interrupt disable flag is clear.
+    pub fn assert_irq(&mut self, source: IrqSource) {
+        self.irq_sources.insert(source);
+    }
+
+    pub fn release_irq(&mut self, source: IrqSource) {
+        self.irq_sources.remove(source);
+    }
+
+    /// Set or release `source`'s IRQ depending on `asserted`.
+    pub fn set_irq(&mut self, source: IrqSource, asserted: bool) {
+        self.irq_sources.set(source, asserted);
+    }
+
+    /// The sources asserting IRQ, for status register readback.
+    pub fn irq_sources(&self) -> IrqSource {
+        self.irq_sources
+    }
+
+    /// Sample the inputs the bus drives, as the 6502 does during the cycle
+    /// before the one that acts on them.
+    fn poll_lines(&mut self) {
+        let cycles = (self.cycle - self.step_start).saturating_sub(1);
+        if let Some(lines) = self.bus.interrupt_lines(cycles) {
+            self.set_nmi(lines.nmi);
+            self.irq_sources.remove(lines.driven);
+            self.irq_sources.insert(lines.irq & lines.driven);
+        }
+    }
+
+    /// Push PC and P and jump through `vector`, taking seven cycles.
+    fn interrupt(&mut self, vector: Vector) {
+        // The opcode fetch and the operand read are discarded
+        self.read(self.registers.pc);
+        self.read(self.registers.pc);
+        let [pch, pcl] = self.registers.pc.to_be_bytes();
+        self.push(pch);
+        self.push(pcl);
+        let p = (self.registers.ps - Status::BREAK_COMMAND) | Status::UNUSED;
+        self.push(p.bits());
+        self.set_interrupt_disable_flag(true);
+        let vector = self.hijack(vector);
+        self.registers.pc = self.read_vector(vector);
+    }
+
+    /// An NMI that arrives before the vector fetch of BRK or IRQ takes over
+    /// the sequence, which then jumps through the NMI vector instead. The
+    /// lines are polled again first, since the edge may come mid-sequence.
+    fn hijack(&mut self, vector: Vector) -> Vector {
+        if vector == Vector::Nmi {
+            return vector;
+        }
+        self.poll_lines();
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            Vector::Nmi
+        } else {
+            vector
+        }
+    }
+
+    fn read_vector(&mut self, vector: Vector) -> u16 {
+        let address = vector.address();
+        let adl = self.read(address);
+        let adh = self.read(address + 1);
+        self.vector_overrides[vector as usize].unwrap_or(u16::from_be_bytes([adh, adl]))
     }
 
     fn get_negative_result_flag(&self) -> bool {
@@ -132,10 +463,6 @@ impl<B: Bus> Cpu<B> {
         self.registers.ps.contains(Status::ZERO_RESULT)
     }
 
-    fn set_zero_result_flag(&mut self, value: bool) {
-        self.registers.ps.set(Status::ZERO_RESULT, value);
-    }
-
     fn set_zero_result_flag_for_value(&mut self, value: u8) {
         let is_zero = value == 0;
         self.registers.ps.set(Status::ZERO_RESULT, is_zero);
@@ -180,148 +507,249 @@ impl<B: Bus> Cpu<B> {
 
     fn push(&mut self, data: u8) {
         let address = self.stack_address();
-        self.registers.sp -= 1;
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
         self.write(address, data)
     }
 
     fn pull(&mut self) -> u8 {
+        self.registers.sp = self.registers.sp.wrapping_add(1);
         let address = self.stack_address();
-        self.registers.sp += 1;
         self.read(address)
     }
 
     fn fetch(&mut self) -> u8 {
-        let data = self.read(self.registers.pc);
+        let pc = self.registers.pc;
+        let data = match self.shadow {
+            Some((bytes, start)) if pc.wrapping_sub(start) < 3 => {
+                self.cycle += 1;
+                bytes[pc.wrapping_sub(start) as usize]
+            }
+            _ => self.read(pc),
+        };
         self.registers.pc = self.registers.pc.wrapping_add(1);
         data
     }
 
     fn read(&mut self, address: u16) -> u8 {
         self.cycle += 1;
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.record_read(address);
+        }
         self.bus.read(address)
     }
 
     fn write(&mut self, address: u16, data: u8) {
         self.cycle += 1;
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.record_write(address);
+        }
+        if let Some(undo_log) = &mut self.undo_log {
+            undo_log.record_write(address, self.bus.peek(address));
+        }
         self.bus.write(address, data)
     }
 
-    fn decode(&mut self) -> (String, String) {
-        let opcode = self.bus.read(self.registers.pc);
-        let mnemonic = Instruction::for_opcode(opcode);
-        let addressing_mode = AddressingMode::for_opcode(opcode);
-        match addressing_mode {
-            AddressingMode::Absolute => {
-                let adl = self.bus.read(self.registers.pc.wrapping_add(1));
-                let adh = self.bus.read(self.registers.pc.wrapping_add(2));
-                let address = u16::from_be_bytes([adh, adl]);
-                let byte_code = format!("{:02X} {:02X} {:02X}", opcode, adl, adh);
-                let disassembly = format!("{} ${:04X}", mnemonic, address);
-                (byte_code, disassembly)
-            }
-            AddressingMode::AbsoluteX => {
-                let adl = self.bus.read(self.registers.pc.wrapping_add(1));
-                let adh = self.bus.read(self.registers.pc.wrapping_add(2));
-                let address = u16::from_be_bytes([adh, adl]);
-                let byte_code = format!("{:02X} {:02X} {:02X}", opcode, adl, adh);
-                let disassembly = format!("{} ${:04X},X", mnemonic, address);
-                (byte_code, disassembly)
-            }
-            AddressingMode::AbsoluteY => {
-                let adl = self.bus.read(self.registers.pc.wrapping_add(1));
-                let adh = self.bus.read(self.registers.pc.wrapping_add(2));
-                let address = u16::from_be_bytes([adh, adl]);
-                let byte_code = format!("{:02X} {:02X} {:02X}", opcode, adl, adh);
-                let disassembly = format!("{} ${:04X},Y", mnemonic, address);
-                (byte_code, disassembly)
-            }
-            AddressingMode::Accumulator => {
-                let byte_code = format!("{:02X}", opcode);
-                let disassembly = format!("{} A", mnemonic);
-                (byte_code, disassembly)
-            }
-            AddressingMode::Immediate => {
-                let operand = self.bus.read(self.registers.pc.wrapping_add(1));
-                let byte_code = format!("{:02X} {:02X}", opcode, operand);
-                let disassembly = format!("{} #${:02X}", mnemonic, operand);
-                (byte_code, disassembly)
-            }
-            AddressingMode::Implied => {
-                let byte_code = format!("{:02X}", opcode);
-                let disassembly = format!("{}", mnemonic);
-                (byte_code, disassembly)
-            }
-            AddressingMode::IndirectAbsolute => {
-                let idl = self.bus.read(self.registers.pc.wrapping_add(1));
-                let idh = self.bus.read(self.registers.pc.wrapping_add(2));
-                let address = u16::from_be_bytes([idh, idl]);
-                let byte_code = format!("{:02X} {:02X} {:02X}", opcode, idl, idh);
-                let disassembly = format!("{} (${:04X})", mnemonic, address);
-                (byte_code, disassembly)
-            }
-            AddressingMode::IndirectZeroPageX => {
-                let bal = self.bus.read(self.registers.pc.wrapping_add(1));
-                let byte_code = format!("{:02X} {:02X}", opcode, bal);
-                let disassembly = format!("{} (${:02X},X)", mnemonic, bal);
-                (byte_code, disassembly)
-            }
-            AddressingMode::IndirectZeroPageY => {
-                let bal = self.bus.read(self.registers.pc.wrapping_add(1));
-                let byte_code = format!("{:02X} {:02X}", opcode, bal);
-                let disassembly = format!("{} (${:02X}),Y", mnemonic, bal);
-                (byte_code, disassembly)
-            }
-            AddressingMode::Relative => {
-                let offset = self.bus.read(self.registers.pc.wrapping_add(1));
-                let byte_code = format!("{:02X} {:02X}", opcode, offset);
-                let disassembly = format!("{} *{:+}", mnemonic, offset);
-                (byte_code, disassembly)
-            }
-            AddressingMode::ZeroPage => {
-                let bal = self.bus.read(self.registers.pc.wrapping_add(1));
-                let byte_code = format!("{:02X} {:02X}", opcode, bal);
-                let disassembly = format!("{} ${:02X}", mnemonic, bal);
-                (byte_code, disassembly)
-            }
-            AddressingMode::ZeroPageX => {
-                let bal = self.bus.read(self.registers.pc.wrapping_add(1));
-                let byte_code = format!("{:02X} {:02X}", opcode, bal);
-                let disassembly = format!("{} ${:02X},X", mnemonic, bal);
-                (byte_code, disassembly)
-            }
-            AddressingMode::ZeroPageY => {
-                let bal = self.bus.read(self.registers.pc.wrapping_add(1));
-                let byte_code = format!("{:02X} {:02X}", opcode, bal);
-                let disassembly = format!("{} ${:02X},Y", mnemonic, bal);
-                (byte_code, disassembly)
-            }
-            AddressingMode::Unimplemented => unimplemented!(
-                "Encountered opcode {:02X} at {:04X}",
-                opcode,
-                self.registers.pc
-            ),
+    /// The instruction at PC, peeked so that decoding it disturbs nothing.
+    pub fn decode(&self) -> Decoded {
+        let pc = self.registers.pc;
+        let mut bytes = [0; 3];
+        for (offset, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.bus.peek(pc.wrapping_add(offset as u16));
         }
-    }
-
-    pub fn step(&mut self) {
-        let (byte_code, disassembly) = self.decode();
-        println!(
-            "{:04X} {:8}   {:11}     A:{:02X} X:{:02X} Y:{:02X} S:{:02X} P:{} C:{} Stack: {:02X?}",
-            self.registers.pc,
-            byte_code,
-            disassembly,
+        Decoded::new(&bytes)
+    }
+
+    /// Write the trace line for the instruction at PC to `out`.
+    ///
+    /// Formats straight into the caller's buffer so that a reused buffer
+    /// costs no allocations.
+    pub fn trace(&self, out: &mut String) -> fmt::Result {
+        let pc = self.registers.pc;
+        let decoded = self.decode();
+
+        write!(out, "{:04X} ", pc)?;
+        debugger::write_byte_code(out, decoded.byte_code())?;
+        out.push_str("   ");
+        let disassembly_start = out.len();
+        write!(out, "{}", decoded)?;
+        let disassembly_len = out.len() - disassembly_start;
+        for _ in disassembly_len..11 {
+            out.push(' ');
+        }
+        write!(
+            out,
+            "     A:{:02X} X:{:02X} Y:{:02X} S:{:02X} P:{} C:{} Stack: [",
             self.registers.a,
             self.registers.x,
             self.registers.y,
             self.registers.sp,
             self.registers.ps,
             self.cycle,
-            self.bus.read_range(self.stack_address() + 1..=0x01FF),
-        );
+        )?;
+        for address in self.stack_address() + 1..=Self::STACK_BASE + 0xff {
+            if address != self.stack_address() + 1 {
+                out.push_str(", ");
+            }
+            write!(out, "{:02X}", self.bus.peek(address))?;
+        }
+        out.push(']');
+        Ok(())
+    }
+
+    /// Print a trace line to stdout before each instruction.
+    pub fn set_trace(&mut self, enabled: bool) {
+        if enabled {
+            self.set_trace_sink(Stdout);
+        } else {
+            self.clear_trace_sink();
+        }
+    }
+
+    /// Send a trace line to `sink` before each instruction. Tracing is off
+    /// by default and costs nothing then.
+    pub fn set_trace_sink(&mut self, sink: impl TraceSink + Send + 'static) {
+        self.tracer = Some(Tracer::new(sink));
+    }
+
+    pub fn clear_trace_sink(&mut self) {
+        self.tracer = None;
+    }
+
+    /// Count reads and writes per address while enabled. Disabling drops
+    /// the counts.
+    pub fn set_heatmap(&mut self, enabled: bool) {
+        self.heatmap = if enabled { Some(Heatmap::new()) } else { None };
+    }
+
+    pub fn heatmap(&self) -> Option<&Heatmap> {
+        self.heatmap.as_ref()
+    }
+
+    pub fn heatmap_mut(&mut self) -> Option<&mut Heatmap> {
+        self.heatmap.as_mut()
+    }
+
+    /// Keep enough history to undo the last `depth` instructions with
+    /// [`Cpu::step_back`], or none for zero.
+    ///
+    /// Undo restores registers and the bytes instructions wrote, so writes
+    /// with side effects, such as mapper bank switches or PPU registers,
+    /// are only undone as far as writing the old value back undoes them.
+    pub fn set_undo_depth(&mut self, depth: usize) {
+        self.undo_log = if depth > 0 {
+            Some(UndoLog::new(depth))
+        } else {
+            None
+        };
+    }
+
+    /// Undo the most recent instruction, returning `false` when there is no
+    /// more history.
+    pub fn step_back(&mut self) -> bool {
+        let entry = match self.undo_log.as_mut().and_then(UndoLog::pop) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        for &(address, old) in entry.writes.iter().rev() {
+            self.bus.write(address, old);
+        }
+        self.registers = entry.registers;
+        self.cycle = entry.cycle;
+        true
+    }
+
+    fn begin_instruction(&mut self) {
+        self.step_start = self.cycle;
+        if let Some(undo_log) = &mut self.undo_log {
+            undo_log.begin(self.registers, self.cycle);
+        }
+    }
+
+    /// Run one instruction, or enter the interrupt handler instead when an
+    /// interrupt is pending.
+    ///
+    /// A halted CPU only lets one cycle pass, so the rest of the console
+    /// keeps running.
+    pub fn step(&mut self) -> Step {
+        let cycle = self.cycle;
+        if self.halted {
+            self.cycle += 1;
+            return Step {
+                cycles: 1,
+                opcode: None,
+                interrupt: None,
+            };
+        }
+
+        if self.nmi_pending || self.irq_pending {
+            let vector = if self.nmi_pending {
+                Vector::Nmi
+            } else {
+                Vector::Irq
+            };
+            self.nmi_pending = false;
+            self.irq_pending = false;
+            self.begin_instruction();
+            self.interrupt(vector);
+            return Step {
+                cycles: self.cycle - cycle,
+                opcode: None,
+                interrupt: Some(vector),
+            };
+        }
 
+        if let Some(mut tracer) = self.tracer.take() {
+            tracer.buffer.clear();
+            self.trace(&mut tracer.buffer).unwrap();
+            tracer.sink.lock().unwrap().trace(&tracer.buffer);
+            self.tracer = Some(tracer);
+        }
+
+        self.begin_instruction();
+        let interrupt_disable = self.get_interrupt_disable_flag();
+        let opcode = self.fetch();
+        let instruction = Self::INSTRUCTIONS[opcode as usize];
+        instruction(self);
+
+        // IRQ is polled before the last cycle, which is when CLI, SEI and
+        // PLP change the flag, so their change only affects the next poll.
+        self.poll_lines();
+        let interrupt_disable = match opcode {
+            0x28 | 0x58 | 0x78 => interrupt_disable,
+            _ => self.get_interrupt_disable_flag(),
+        };
+        self.irq_pending = !self.irq_sources.is_empty() && !interrupt_disable;
+        Step {
+            cycles: self.cycle - cycle,
+            opcode: Some(opcode),
+            interrupt: None,
+        }
+    }
+
+    /// Run the instruction in `bytes` as if it were at PC, without reading
+    /// its opcode or operands from memory.
+    ///
+    /// Missing operand bytes read as zero. PC is left at the same address
+    /// afterwards unless the instruction jumps or takes a branch.
+    pub fn execute(&mut self, bytes: &[u8]) {
+        assert!(!bytes.is_empty(), "no instruction to execute");
+        let mut shadow = [0; 3];
+        let available = bytes.len().min(shadow.len());
+        shadow[..available].copy_from_slice(&bytes[..available]);
+        let decoded = Decoded::new(&shadow);
+        let pc = self.registers.pc;
+        self.shadow = Some((shadow, pc));
+
+        self.begin_instruction();
         let opcode = self.fetch();
         let instruction = Self::INSTRUCTIONS[opcode as usize];
         instruction(self);
+
+        self.shadow = None;
+        let next = pc.wrapping_add(decoded.byte_code().len() as u16);
+        if self.registers.pc == next {
+            self.registers.pc = pc;
+        }
     }
 
     fn fetch_implied(&mut self) {
@@ -508,12 +936,8 @@ impl<B: Bus> Cpu<B> {
         self.set_interrupt_disable_flag(true);
     }
 
-    fn sev_implied(&mut self) {
-        self.fetch_implied();
-        self.set_overflow_flag(true);
-    }
-
     fn inx_implied(&mut self) {
+        self.fetch_implied();
         let result = self.registers.x.wrapping_add(1);
         self.set_zero_result_flag_for_value(result);
         self.set_negative_result_flag_for_value(result);
@@ -521,6 +945,7 @@ impl<B: Bus> Cpu<B> {
     }
 
     fn iny_implied(&mut self) {
+        self.fetch_implied();
         let result = self.registers.y.wrapping_add(1);
         self.set_zero_result_flag_for_value(result);
         self.set_negative_result_flag_for_value(result);
@@ -528,6 +953,7 @@ impl<B: Bus> Cpu<B> {
     }
 
     fn dex_implied(&mut self) {
+        self.fetch_implied();
         let result = self.registers.x.wrapping_sub(1);
         self.set_zero_result_flag_for_value(result);
         self.set_negative_result_flag_for_value(result);
@@ -535,6 +961,7 @@ impl<B: Bus> Cpu<B> {
     }
 
     fn dey_implied(&mut self) {
+        self.fetch_implied();
         let result = self.registers.y.wrapping_sub(1);
         self.set_zero_result_flag_for_value(result);
         self.set_negative_result_flag_for_value(result);
@@ -543,27 +970,42 @@ impl<B: Bus> Cpu<B> {
 
     fn tax_implied(&mut self) {
         self.fetch_implied();
-        self.registers.x = self.registers.a;
+        let value = self.registers.a;
+        self.set_zero_result_flag_for_value(value);
+        self.set_negative_result_flag_for_value(value);
+        self.registers.x = value;
     }
 
     fn tay_implied(&mut self) {
         self.fetch_implied();
-        self.registers.y = self.registers.a;
+        let value = self.registers.a;
+        self.set_zero_result_flag_for_value(value);
+        self.set_negative_result_flag_for_value(value);
+        self.registers.y = value;
     }
 
     fn txa_implied(&mut self) {
         self.fetch_implied();
-        self.registers.a = self.registers.x;
+        let value = self.registers.x;
+        self.set_zero_result_flag_for_value(value);
+        self.set_negative_result_flag_for_value(value);
+        self.registers.a = value;
     }
 
     fn tya_implied(&mut self) {
         self.fetch_implied();
-        self.registers.a = self.registers.y;
+        let value = self.registers.y;
+        self.set_zero_result_flag_for_value(value);
+        self.set_negative_result_flag_for_value(value);
+        self.registers.a = value;
     }
 
     fn tsx_implied(&mut self) {
         self.fetch_implied();
-        self.registers.x = self.registers.sp;
+        let value = self.registers.sp;
+        self.set_zero_result_flag_for_value(value);
+        self.set_negative_result_flag_for_value(value);
+        self.registers.x = value;
     }
 
     fn txs_implied(&mut self) {
@@ -705,7 +1147,7 @@ impl<B: Bus> Cpu<B> {
     }
 
     fn lsr(&mut self, value: u8) -> u8 {
-        let carry_out = value & 0x80 == 0x80;
+        let carry_out = value & 0x01 == 0x01;
         let result = value >> 1;
         self.set_carry_flag(carry_out);
         self.set_zero_result_flag_for_value(result);
@@ -1032,16 +1474,8 @@ impl<B: Bus> Cpu<B> {
     }
 
     fn sbc(&mut self, value: u8) {
-        let borrow_in = !self.get_carry_flag();
-        let accumulator = self.registers.a;
-        let (result, borrow_out_1) = value.overflowing_sub(borrow_in as u8);
-        let (result, borrow_out_2) = accumulator.overflowing_sub(result);
-        let carry_out = !(borrow_out_1 | borrow_out_2);
-        self.set_overflow_flag_for_result(accumulator, value, result);
-        self.set_carry_flag(carry_out);
-        self.set_zero_result_flag_for_value(result);
-        self.set_negative_result_flag_for_value(result);
-        self.registers.a = result;
+        // A - M - (1 - C) is A + !M + C in two's complement
+        self.adc(!value);
     }
 
     fn and_immediate(&mut self) {
@@ -1327,11 +1761,10 @@ impl<B: Bus> Cpu<B> {
     }
 
     fn cmp(&mut self, register: u8, value: u8) {
-        let (result, carry_out) = register.overflowing_sub(value);
-        self.set_carry_flag(carry_out);
+        let (result, borrow_out) = register.overflowing_sub(value);
+        self.set_carry_flag(!borrow_out);
         self.set_zero_result_flag_for_value(result);
         self.set_negative_result_flag_for_value(result);
-        self.registers.a = result;
     }
 
     fn sta_zero_page(&mut self) {
@@ -1375,17 +1808,17 @@ impl<B: Bus> Cpu<B> {
 
     fn stx_zero_page(&mut self) {
         let address = self.fetch_zero_page();
-        self.sta(address);
+        self.stx(address);
     }
 
     fn stx_zero_page_y(&mut self) {
         let address = self.fetch_zero_page_y();
-        self.sta(address);
+        self.stx(address);
     }
 
     fn stx_absolute(&mut self) {
         let address = self.fetch_absolute();
-        self.sta(address);
+        self.stx(address);
     }
 
     fn stx(&mut self, address: u16) {
@@ -1394,17 +1827,17 @@ impl<B: Bus> Cpu<B> {
 
     fn sty_zero_page(&mut self) {
         let address = self.fetch_zero_page();
-        self.sta(address);
+        self.sty(address);
     }
 
     fn sty_zero_page_x(&mut self) {
         let address = self.fetch_zero_page_x();
-        self.sta(address);
+        self.sty(address);
     }
 
     fn sty_absolute(&mut self) {
         let address = self.fetch_absolute();
-        self.sta(address);
+        self.sty(address);
     }
 
     fn sty(&mut self, address: u16) {
@@ -1418,7 +1851,9 @@ impl<B: Bus> Cpu<B> {
 
     fn php_implied(&mut self) {
         self.fetch_implied();
-        self.push(self.registers.ps.bits());
+        // The pushed copy always has the B and unused bits set
+        let p = self.registers.ps | Status::BREAK_COMMAND | Status::UNUSED;
+        self.push(p.bits());
     }
 
     fn pla_implied(&mut self) {
@@ -1476,35 +1911,32 @@ impl<B: Bus> Cpu<B> {
     }
 
     fn branch(&mut self, condition: bool) {
-        let offset = self.fetch();
+        let offset = self.fetch() as i8;
         if !condition {
             return;
         }
-        let [pch, pcl] = self.registers.pc.to_be_bytes();
-        let (pcl_offset, carry) = (pcl as i16).overflowing_add(offset as i16);
-        let pcl_offset = pcl_offset as u8;
-        let same_page_address = u16::from_be_bytes([pch, pcl_offset]);
+        let target = self.registers.pc.wrapping_add(offset as u16);
+        // The offset is added to PCL first. Crossing a page, forwards or
+        // backwards, takes another cycle to fix up PCH.
+        let [pch, _] = self.registers.pc.to_be_bytes();
+        let same_page_address = u16::from_be_bytes([pch, target as u8]);
         self.read(same_page_address);
-        self.registers.pc = if carry {
-            let pch_c = pch.wrapping_add(1);
-            let next_page_address = u16::from_be_bytes([pch_c, pcl_offset]);
-            self.read(next_page_address);
-            next_page_address
-        } else {
-            same_page_address
+        if same_page_address != target {
+            self.read(target);
         }
+        self.registers.pc = target;
     }
 
     fn brk_implied(&mut self) {
         self.fetch();
         let [pch, pcl] = self.registers.pc.to_be_bytes();
-        let p = self.registers.ps.bits();
+        let p = self.registers.ps | Status::BREAK_COMMAND | Status::UNUSED;
         self.push(pch);
         self.push(pcl);
-        self.push(p);
-        let adl = self.read(0xfffe);
-        let adh = self.read(0xffff);
-        self.registers.pc = u16::from_be_bytes([adh, adl]);
+        self.push(p.bits());
+        self.set_interrupt_disable_flag(true);
+        let vector = self.hijack(Vector::Irq);
+        self.registers.pc = self.read_vector(vector);
     }
 
     fn jsr_absolute(&mut self) {
@@ -1552,14 +1984,22 @@ impl<B: Bus> Cpu<B> {
         self.registers.pc = u16::from_be_bytes([adh, adl]);
     }
 
+    /// Lock up until reset, like the unofficial KIL/JAM opcodes do.
+    fn jam_implied(&mut self) {
+        self.registers.pc = self.registers.pc.wrapping_sub(1);
+        self.halted = true;
+    }
+
+    /// Unofficial opcodes that are not emulated lock up like JAM, so a bad
+    /// ROM stops the CPU instead of the host.
     fn unimplemented(&mut self) {
-        unimplemented!();
+        self.jam_implied();
     }
 
     const INSTRUCTIONS: [fn(&mut Self); 256] = [
         Self::brk_implied,     // 00
         Self::ora_indirect_x,  // 01
-        Self::unimplemented,   // 02
+        Self::jam_implied,     // 02
         Self::unimplemented,   // 03
         Self::unimplemented,   // 04
         Self::ora_zero_page,   // 05
@@ -1575,7 +2015,7 @@ impl<B: Bus> Cpu<B> {
         Self::unimplemented,   // 0F
         Self::bpl_relative,    // 10
         Self::ora_indirect_y,  // 11
-        Self::unimplemented,   // 12
+        Self::jam_implied,     // 12
         Self::unimplemented,   // 13
         Self::unimplemented,   // 14
         Self::ora_zero_page_x, // 15
@@ -1583,7 +2023,7 @@ impl<B: Bus> Cpu<B> {
         Self::unimplemented,   // 17
         Self::clc_implied,     // 18
         Self::ora_absolute_y,  // 19
-        Self::unimplemented,   // 1A
+        Self::nop_implied,     // 1A
         Self::unimplemented,   // 1B
         Self::unimplemented,   // 1C
         Self::ora_absolute_x,  // 1D
@@ -1591,7 +2031,7 @@ impl<B: Bus> Cpu<B> {
         Self::unimplemented,   // 1F
         Self::jsr_absolute,    // 20
         Self::and_indirect_x,  // 21
-        Self::unimplemented,   // 22
+        Self::jam_implied,     // 22
         Self::unimplemented,   // 23
         Self::bit_zero_page,   // 24
         Self::and_zero_page,   // 25
@@ -1607,7 +2047,7 @@ impl<B: Bus> Cpu<B> {
         Self::unimplemented,   // 2F
         Self::bmi_relative,    // 30
         Self::and_indirect_y,  // 31
-        Self::unimplemented,   // 32
+        Self::jam_implied,     // 32
         Self::unimplemented,   // 33
         Self::unimplemented,   // 34
         Self::and_zero_page_x, // 35
@@ -1615,7 +2055,7 @@ impl<B: Bus> Cpu<B> {
         Self::unimplemented,   // 37
         Self::sec_implied,     // 38
         Self::and_absolute_y,  // 39
-        Self::unimplemented,   // 3A
+        Self::nop_implied,     // 3A
         Self::unimplemented,   // 3B
         Self::unimplemented,   // 3C
         Self::and_absolute_x,  // 3D
@@ -1623,7 +2063,7 @@ impl<B: Bus> Cpu<B> {
         Self::unimplemented,   // 3F
         Self::rti_implied,     // 40
         Self::eor_indirect_x,  // 41
-        Self::unimplemented,   // 42
+        Self::jam_implied,     // 42
         Self::unimplemented,   // 43
         Self::unimplemented,   // 44
         Self::eor_zero_page,   // 45
@@ -1639,7 +2079,7 @@ impl<B: Bus> Cpu<B> {
         Self::unimplemented,   // 4F
         Self::bvc_relative,    // 50
         Self::eor_indirect_y,  // 51
-        Self::unimplemented,   // 52
+        Self::jam_implied,     // 52
         Self::unimplemented,   // 53
         Self::unimplemented,   // 54
         Self::eor_zero_page_x, // 55
@@ -1647,7 +2087,7 @@ impl<B: Bus> Cpu<B> {
         Self::unimplemented,   // 57
         Self::cli_implied,     // 58
         Self::eor_absolute_y,  // 59
-        Self::unimplemented,   // 5A
+        Self::nop_implied,     // 5A
         Self::unimplemented,   // 5B
         Self::unimplemented,   // 5C
         Self::eor_absolute_x,  // 5D
@@ -1655,7 +2095,7 @@ impl<B: Bus> Cpu<B> {
         Self::unimplemented,   // 5F
         Self::rts_implied,     // 60
         Self::adc_indirect_x,  // 61
-        Self::unimplemented,   // 62
+        Self::jam_implied,     // 62
         Self::unimplemented,   // 63
         Self::unimplemented,   // 64
         Self::adc_zero_page,   // 65
@@ -1671,7 +2111,7 @@ impl<B: Bus> Cpu<B> {
         Self::unimplemented,   // 6F
         Self::bvs_relative,    // 70
         Self::adc_indirect_y,  // 71
-        Self::unimplemented,   // 72
+        Self::jam_implied,     // 72
         Self::unimplemented,   // 73
         Self::unimplemented,   // 74
         Self::adc_zero_page_x, // 75
@@ -1679,7 +2119,7 @@ impl<B: Bus> Cpu<B> {
         Self::unimplemented,   // 77
         Self::sei_implied,     // 78
         Self::adc_absolute_y,  // 79
-        Self::unimplemented,   // 7A
+        Self::nop_implied,     // 7A
         Self::unimplemented,   // 7B
         Self::unimplemented,   // 7C
         Self::adc_absolute_x,  // 7D
@@ -1703,14 +2143,14 @@ impl<B: Bus> Cpu<B> {
         Self::unimplemented,   // 8F
         Self::bcc_relative,    // 90
         Self::sta_indirect_y,  // 91
-        Self::unimplemented,   // 92
+        Self::jam_implied,     // 92
         Self::unimplemented,   // 93
         Self::sty_zero_page_x, // 94
         Self::sta_zero_page_x, // 95
         Self::stx_zero_page_y, // 96
         Self::unimplemented,   // 97
         Self::tya_implied,     // 98
-        Self::unimplemented,   // 99
+        Self::sta_absolute_y,  // 99
         Self::txs_implied,     // 9A
         Self::unimplemented,   // 9B
         Self::unimplemented,   // 9C
@@ -1735,7 +2175,7 @@ impl<B: Bus> Cpu<B> {
         Self::unimplemented,   // AF
         Self::bcs_relative,    // B0
         Self::lda_indirect_y,  // B1
-        Self::unimplemented,   // B2
+        Self::jam_implied,     // B2
         Self::unimplemented,   // B3
         Self::ldy_zero_page_x, // B4
         Self::lda_zero_page_x, // B5
@@ -1767,7 +2207,7 @@ impl<B: Bus> Cpu<B> {
         Self::unimplemented,   // CF
         Self::bne_relative,    // D0
         Self::cmp_indirect_y,  // D1
-        Self::unimplemented,   // D2
+        Self::jam_implied,     // D2
         Self::unimplemented,   // D3
         Self::unimplemented,   // D4
         Self::cmp_zero_page_x, // D5
@@ -1775,7 +2215,7 @@ impl<B: Bus> Cpu<B> {
         Self::unimplemented,   // D7
         Self::cld_implied,     // D8
         Self::cmp_absolute_y,  // D9
-        Self::unimplemented,   // DA
+        Self::nop_implied,     // DA
         Self::unimplemented,   // DB
         Self::unimplemented,   // DC
         Self::cmp_absolute_x,  // DD
@@ -1799,7 +2239,7 @@ impl<B: Bus> Cpu<B> {
         Self::unimplemented,   // EF
         Self::beq_relative,    // F0
         Self::sbc_indirect_y,  // F1
-        Self::unimplemented,   // F2
+        Self::jam_implied,     // F2
         Self::unimplemented,   // F3
         Self::unimplemented,   // F4
         Self::sbc_zero_page_x, // F5
@@ -1807,7 +2247,7 @@ impl<B: Bus> Cpu<B> {
         Self::unimplemented,   // F7
         Self::sed_implied,     // F8
         Self::sbc_absolute_y,  // F9
-        Self::unimplemented,   // FA
+        Self::nop_implied,     // FA
         Self::unimplemented,   // FB
         Self::unimplemented,   // FC
         Self::sbc_absolute_x,  // FD
@@ -1815,3 +2255,466 @@ impl<B: Bus> Cpu<B> {
         Self::unimplemented,   // FF
     ];
 }
+
+#[cfg(feature = "serde")]
+serde_state!(Cpu<B: Bus> {
+    registers,
+    cycle,
+    nmi_line,
+    nmi_pending,
+    irq_sources,
+    irq_pending,
+    halted,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const PROGRAM_START: u16 = 0x0200;
+
+    #[derive(Debug, Clone)]
+    struct Ram(Vec<u8>);
+
+    impl Bus for Ram {
+        fn read(&mut self, address: u16) -> u8 {
+            self.peek(address)
+        }
+
+        fn write(&mut self, address: u16, data: u8) {
+            self.0[address as usize] = data
+        }
+
+        fn peek(&self, address: u16) -> u8 {
+            self.0[address as usize]
+        }
+    }
+
+    /// Run `program` one instruction at a time starting with the given
+    /// accumulator and carry, returning the registers afterwards.
+    fn run(a: u8, carry: bool, program: &[u8], instructions: usize) -> Registers {
+        let mut ram = vec![0; 0x10000];
+        let start = PROGRAM_START as usize;
+        ram[start..start + program.len()].copy_from_slice(program);
+        let mut cpu = Cpu::new(Ram(ram));
+        cpu.registers.pc = PROGRAM_START;
+        cpu.registers.a = a;
+        cpu.set_carry_flag(carry);
+        for _ in 0..instructions {
+            cpu.step();
+        }
+        cpu.registers
+    }
+
+    #[test]
+    fn execute_does_not_fetch_from_memory() {
+        // Memory is all BRK
+        let mut cpu = Cpu::new(Ram(vec![0; 0x10000]));
+        cpu.registers.pc = PROGRAM_START;
+        cpu.execute(&[0xa9, 0x80]); // LDA #$80
+        cpu.execute(&[0x8d, 0x34, 0x12]); // STA $1234
+        assert_eq!(cpu.registers.a, 0x80);
+        assert_eq!(cpu.bus.0[0x1234], 0x80);
+        assert_eq!(cpu.registers.pc, PROGRAM_START);
+        assert_eq!(cpu.registers.sp, 0xff);
+
+        cpu.execute(&[0x4c, 0x00, 0x90]); // JMP $9000
+        assert_eq!(cpu.registers.pc, 0x9000);
+    }
+
+    #[test]
+    fn decode_leaves_the_cycle_count_alone() {
+        let mut ram = vec![0; 0x10000];
+        ram[PROGRAM_START as usize..][..3].copy_from_slice(&[0xad, 0x02, 0x20]);
+        let mut cpu = Cpu::new(Ram(ram));
+        cpu.registers.pc = PROGRAM_START;
+        assert_eq!(cpu.decode().to_string(), "LDA $2002");
+        assert_eq!(cpu.cycles(), 0);
+    }
+
+    #[test]
+    fn vector_overrides() {
+        let mut ram = vec![0; 0x10000];
+        ram[0xfffc..].copy_from_slice(&[0x00, 0x80, 0x00, 0x90]);
+        let mut cpu = Cpu::new(Ram(ram));
+        cpu.override_vector(Vector::Reset, Some(0xc000));
+        cpu.reset();
+        assert_eq!(cpu.registers.pc, 0xc000);
+
+        cpu.override_vector(Vector::Reset, None);
+        cpu.reset();
+        assert_eq!(cpu.registers.pc, 0x8000);
+
+        // BRK goes through the IRQ vector
+        cpu.override_vector(Vector::Irq, Some(0xa000));
+        cpu.step();
+        assert_eq!(cpu.registers.pc, 0xa000);
+    }
+
+    #[test]
+    fn branches_take_a_cycle_more_across_pages() {
+        let mut ram = vec![0; 0x10000];
+        ram[0x0200..0x0202].copy_from_slice(&[0xd0, 0x02]); // BNE *+2
+        ram[0x0280..0x0282].copy_from_slice(&[0xd0, 0x7f]); // BNE *+127
+        ram[0x0300..0x0302].copy_from_slice(&[0xd0, 0xf0]); // BNE *-16
+        let mut cpu = Cpu::new(Ram(ram));
+        for &(pc, target, cycles) in &[
+            (0x0200, 0x0204, 3),
+            (0x0280, 0x0301, 4),
+            (0x0300, 0x02f2, 4),
+        ] {
+            cpu.registers.pc = pc;
+            let step = cpu.step();
+            assert_eq!((cpu.registers.pc, step.cycles), (target, cycles));
+        }
+    }
+
+    #[test]
+    fn step_back_undoes_registers_and_writes() {
+        #[rustfmt::skip]
+        let program = [
+            0xa9, 0x01,       // LDA #$01
+            0x8d, 0x00, 0x03, // STA $0300
+            0x48,             // PHA
+            0xee, 0x00, 0x03, // INC $0300
+            0x20, 0x00, 0x90, // JSR $9000
+        ];
+        let mut ram = vec![0; 0x10000];
+        let start = PROGRAM_START as usize;
+        ram[start..start + program.len()].copy_from_slice(&program);
+        ram[0x0300] = 0xaa;
+        let mut cpu = Cpu::new(Ram(ram));
+        cpu.set_trace(false);
+        cpu.set_undo_depth(4);
+        cpu.registers.pc = PROGRAM_START;
+
+        let mut history = vec![cpu.clone()];
+        for _ in 0..5 {
+            cpu.step();
+            history.push(cpu.clone());
+        }
+        history.pop();
+        // Only the last four instructions can be undone
+        for expected in history.iter().rev().take(4) {
+            assert!(cpu.step_back());
+            assert_eq!(cpu.registers, expected.registers);
+            assert_eq!(cpu.cycle, expected.cycle);
+            assert_eq!(cpu.bus.0, expected.bus.0);
+        }
+        assert!(!cpu.step_back());
+        assert_eq!(cpu.registers.a, 0x01);
+        assert_eq!(cpu.bus.0[0x0300], 0xaa);
+    }
+
+    #[test]
+    fn reset_drops_pending_interrupts() {
+        let mut ram = vec![0xea; 0x10000]; // NOP
+        ram[0xfffa..].copy_from_slice(&[0x00, 0x90, 0x00, 0x80, 0x00, 0xa0]);
+        let mut cpu = Cpu::new(Ram(ram));
+        cpu.assert_irq(IrqSource::EXTERNAL);
+        cpu.registers.ps = Status::empty();
+        cpu.step();
+        cpu.set_nmi(true);
+        assert!(cpu.nmi_pending && cpu.irq_pending);
+
+        cpu.reset();
+        let step = cpu.step();
+        assert_eq!((step.interrupt, cpu.registers.pc), (None, 0x8001));
+    }
+
+    #[test]
+    fn nmi_is_edge_triggered() {
+        let mut ram = vec![0xea; 0x10000]; // NOP
+        ram[0xfffa..0xfffc].copy_from_slice(&[0x00, 0x90]);
+        let mut cpu = Cpu::new(Ram(ram));
+        cpu.set_trace(false);
+        cpu.registers.pc = PROGRAM_START;
+        cpu.registers.ps = Status::CARRY | Status::BREAK_COMMAND;
+
+        cpu.set_nmi(true);
+        let step = cpu.step();
+        assert_eq!(cpu.registers.pc, 0x9000);
+        assert_eq!(
+            step,
+            Step {
+                cycles: 7,
+                opcode: None,
+                interrupt: Some(Vector::Nmi)
+            }
+        );
+        assert_eq!(cpu.registers.sp, 0xfc);
+        assert_eq!(cpu.bus.0[0x01fd..=0x01ff], [0x21, 0x00, 0x02]);
+        assert!(cpu.registers.ps.contains(Status::INTERRUPT_DISABLE));
+
+        // Holding the line does not interrupt again
+        cpu.set_nmi(true);
+        let step = cpu.step();
+        assert_eq!(cpu.registers.pc, 0x9001);
+        assert_eq!((step.opcode, step.interrupt), (Some(0xea), None));
+
+        cpu.set_nmi(false);
+        cpu.set_nmi(true);
+        cpu.step();
+        assert_eq!(cpu.registers.pc, 0x9000);
+    }
+
+    #[test]
+    fn irq_is_level_triggered_and_masked() {
+        #[rustfmt::skip]
+        let program = [
+            0xea, // NOP
+            0x58, // CLI
+            0xea, // NOP
+            0xea, // NOP
+        ];
+        let mut ram = vec![0; 0x10000];
+        let start = PROGRAM_START as usize;
+        ram[start..start + program.len()].copy_from_slice(&program);
+        // The handler is an RTI
+        ram[0xfffe..].copy_from_slice(&[0x00, 0x90]);
+        ram[0x9000] = 0x40;
+        let mut cpu = Cpu::new(Ram(ram));
+        cpu.set_trace(false);
+        cpu.registers.pc = PROGRAM_START;
+        cpu.registers.ps = Status::INTERRUPT_DISABLE;
+
+        cpu.assert_irq(IrqSource::EXTERNAL);
+        cpu.step(); // NOP, masked
+        cpu.step(); // CLI, polled before I clears
+        assert_eq!(cpu.registers.pc, PROGRAM_START + 2);
+        cpu.step(); // NOP
+        cpu.step();
+        assert_eq!(cpu.registers.pc, 0x9000);
+        // B is clear in the pushed status
+        assert_eq!(cpu.bus.0[0x01fd], 0x20);
+
+        // Still asserted after RTI clears I again
+        cpu.step();
+        assert_eq!(cpu.registers.pc, PROGRAM_START + 3);
+        cpu.step();
+        assert_eq!(cpu.registers.pc, 0x9000);
+
+        cpu.release_irq(IrqSource::EXTERNAL);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.registers.pc, PROGRAM_START + 4);
+    }
+
+    #[test]
+    fn irq_sources_are_wired_or() {
+        let mut cpu = Cpu::new(Ram(vec![0xea; 0x10000]));
+        cpu.set_trace(false);
+        cpu.assert_irq(IrqSource::APU_FRAME);
+        cpu.assert_irq(IrqSource::MAPPER);
+        cpu.release_irq(IrqSource::APU_FRAME);
+        assert_eq!(cpu.irq_sources(), IrqSource::MAPPER);
+        cpu.step();
+        assert!(cpu.irq_pending);
+
+        cpu.set_irq(IrqSource::MAPPER, false);
+        assert!(cpu.irq_sources().is_empty());
+        cpu.step();
+        assert!(!cpu.irq_pending);
+    }
+
+    #[test]
+    fn brk_pushes_break_flag() {
+        let mut ram = vec![0; 0x10000];
+        ram[0xfffe..].copy_from_slice(&[0x00, 0x90]);
+        let mut cpu = Cpu::new(Ram(ram));
+        cpu.set_trace(false);
+        cpu.registers.pc = PROGRAM_START;
+        cpu.step();
+        assert_eq!(cpu.registers.pc, 0x9000);
+        assert_eq!(cpu.bus.0[0x01fd..=0x01ff], [0x30, 0x02, 0x02]);
+        assert!(cpu.registers.ps.contains(Status::INTERRUPT_DISABLE));
+    }
+
+    #[test]
+    fn jam_halts_until_reset() {
+        let mut ram = vec![0xea; 0x10000];
+        ram[PROGRAM_START as usize + 1] = 0x02;
+        ram[0xfffc..].copy_from_slice(&[0x00, 0x02, 0x00, 0x02]);
+        let mut cpu = Cpu::new(Ram(ram));
+        cpu.set_trace(false);
+        cpu.reset();
+        cpu.step();
+        cpu.step();
+        assert!(cpu.is_halted());
+        cpu.set_nmi(true);
+        for _ in 0..3 {
+            cpu.step();
+        }
+        assert_eq!(cpu.registers.pc, PROGRAM_START + 1);
+        assert!(cpu.is_halted());
+
+        cpu.reset();
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.registers.pc, PROGRAM_START);
+    }
+
+    #[test]
+    fn unemulated_opcodes_halt() {
+        let mut ram = vec![0xea; 0x10000];
+        ram[PROGRAM_START as usize] = 0x03; // SLO (zp,X)
+        ram[0xfffc..].copy_from_slice(&[0x00, 0x02, 0x00, 0x02]);
+        let mut cpu = Cpu::new(Ram(ram));
+        cpu.set_trace(false);
+        cpu.reset();
+        cpu.step();
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.registers.pc, PROGRAM_START);
+    }
+
+    #[test]
+    fn nmi_hijacks_brk() {
+        let mut ram = vec![0; 0x10000];
+        ram[0xfffa..].copy_from_slice(&[0x00, 0xa0, 0x00, 0x80, 0x00, 0x90]);
+        let mut cpu = Cpu::new(Ram(ram));
+        cpu.set_trace(false);
+        cpu.registers.pc = PROGRAM_START;
+
+        // execute does not service the pending NMI before running BRK
+        cpu.set_nmi(true);
+        cpu.execute(&[0x00]);
+        assert_eq!(cpu.registers.pc, 0xa000);
+        // The pushed status still has B set
+        assert_eq!(cpu.bus.0[0x01fd..=0x01ff], [0x30, 0x02, 0x02]);
+
+        // The NMI was consumed
+        cpu.bus.0[0xa000] = 0xea;
+        cpu.step();
+        assert_eq!(cpu.registers.pc, 0xa001);
+    }
+
+    #[test]
+    fn register_accessors() {
+        let mut cpu = Cpu::new(Ram(vec![0xea; 0x10000]));
+        cpu.set_pc(0x1234);
+        cpu.set_sp(0xfd);
+        cpu.set_status(Status::CARRY | Status::UNUSED);
+        cpu.set_a(1);
+        cpu.set_x(2);
+        cpu.set_y(3);
+        assert_eq!(
+            *cpu.registers(),
+            Registers {
+                pc: 0x1234,
+                sp: 0xfd,
+                ps: Status::CARRY | Status::UNUSED,
+                a: 1,
+                x: 2,
+                y: 3,
+            }
+        );
+        cpu.step();
+        assert_eq!(
+            (cpu.pc(), cpu.sp(), cpu.a(), cpu.x(), cpu.y()),
+            (0x1235, 0xfd, 1, 2, 3)
+        );
+        assert_eq!(cpu.status(), Status::CARRY | Status::UNUSED);
+        assert_eq!(cpu.cycles(), 2);
+    }
+
+    #[test]
+    fn reset_sequence() {
+        let mut ram = vec![0; 0x10000];
+        ram[0xfffc..0xfffe].copy_from_slice(&[0x00, 0x80]);
+        ram[0x0100..0x0200].iter_mut().for_each(|byte| *byte = 0x55);
+        let mut cpu = Cpu::new(Ram(ram.clone()));
+        cpu.power_on();
+        assert_eq!(cpu.registers.pc, 0x8000);
+        assert_eq!(cpu.registers.sp, 0xfd);
+        assert_eq!(cpu.registers.ps.bits(), 0x24);
+        assert_eq!(cpu.cycle, 7);
+
+        cpu.registers.ps = Status::UNUSED;
+        cpu.reset();
+        assert_eq!(cpu.registers.sp, 0xfa);
+        assert!(cpu.registers.ps.contains(Status::INTERRUPT_DISABLE));
+        assert_eq!(cpu.cycle, 14);
+        // Nothing was pushed
+        assert_eq!(cpu.bus.0, ram);
+    }
+
+    fn flags(registers: &Registers) -> Status {
+        registers.ps
+            & (Status::CARRY | Status::ZERO_RESULT | Status::OVERFLOW | Status::NEGATIVE_RESULT)
+    }
+
+    proptest! {
+        #[test]
+        fn adc_matches_wide_addition(a: u8, value: u8, carry: bool) {
+            let registers = run(a, carry, &[0x69, value], 1);
+            let sum = a as u16 + value as u16 + carry as u16;
+            let signed_sum = a as i8 as i16 + value as i8 as i16 + carry as i16;
+            prop_assert_eq!(registers.a, sum as u8);
+            prop_assert_eq!(registers.ps.contains(Status::CARRY), sum > 0xff);
+            prop_assert_eq!(
+                registers.ps.contains(Status::OVERFLOW),
+                !(-128..=127).contains(&signed_sum)
+            );
+            prop_assert_eq!(registers.ps.contains(Status::ZERO_RESULT), sum as u8 == 0);
+            prop_assert_eq!(registers.ps.contains(Status::NEGATIVE_RESULT), sum & 0x80 != 0);
+        }
+
+        #[test]
+        fn sbc_is_adc_of_complement(a: u8, value: u8, carry: bool) {
+            let sbc = run(a, carry, &[0xe9, value], 1);
+            let adc = run(a, carry, &[0x69, !value], 1);
+            prop_assert_eq!(sbc.a, adc.a);
+            prop_assert_eq!(flags(&sbc), flags(&adc));
+        }
+
+        #[test]
+        fn cmp_flags_match_sbc_without_store(a: u8, value: u8, carry: bool, overflow: bool) {
+            let mut ram = vec![0; 0x10000];
+            ram[PROGRAM_START as usize..][..2].copy_from_slice(&[0xc9, value]);
+            let mut cpu = Cpu::new(Ram(ram));
+            cpu.registers.pc = PROGRAM_START;
+            cpu.registers.a = a;
+            cpu.set_carry_flag(carry);
+            cpu.set_overflow_flag(overflow);
+            cpu.step();
+            let cmp = cpu.registers;
+            let sbc = run(a, true, &[0xe9, value], 1);
+            let nzc = Status::CARRY | Status::ZERO_RESULT | Status::NEGATIVE_RESULT;
+            prop_assert_eq!(cmp.a, a);
+            prop_assert_eq!(cmp.ps & nzc, sbc.ps & nzc);
+            prop_assert_eq!(cmp.ps.contains(Status::OVERFLOW), overflow);
+        }
+
+        #[test]
+        fn asl_doubles_and_carries_bit_7(a: u8, carry: bool) {
+            let registers = run(a, carry, &[0x0a], 1);
+            prop_assert_eq!(registers.a, a << 1);
+            prop_assert_eq!(registers.ps.contains(Status::CARRY), a & 0x80 != 0);
+        }
+
+        #[test]
+        fn rol_with_carry_clear_is_asl(a: u8) {
+            let rol = run(a, false, &[0x2a], 1);
+            let asl = run(a, false, &[0x0a], 1);
+            prop_assert_eq!(rol.a, asl.a);
+            prop_assert_eq!(flags(&rol), flags(&asl));
+        }
+
+        #[test]
+        fn ror_with_carry_clear_is_lsr(a: u8) {
+            let ror = run(a, false, &[0x6a], 1);
+            let lsr = run(a, false, &[0x4a], 1);
+            prop_assert_eq!(lsr.a, a >> 1);
+            prop_assert_eq!(lsr.ps.contains(Status::CARRY), a & 0x01 != 0);
+            prop_assert_eq!(ror.a, lsr.a);
+            prop_assert_eq!(flags(&ror), flags(&lsr));
+        }
+
+        #[test]
+        fn rol_then_ror_restores_value_and_carry(a: u8, carry: bool) {
+            let registers = run(a, carry, &[0x2a, 0x6a], 2);
+            prop_assert_eq!(registers.a, a);
+            prop_assert_eq!(registers.ps.contains(Status::CARRY), carry);
+        }
+    }
+}
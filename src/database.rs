@@ -0,0 +1,230 @@
+//! Offline lookup of known dumps, for games whose iNES header is wrong.
+//!
+//! Reads the NES 2.0 XML database (`nes20db.xml`) or any subset of it.
+//! Games are matched by the CRC-32 of their PRG and CHR ROM, and by SHA-1
+//! as well when the database gives one.
+
+use crate::ines::{FileFormat, Header, Mirroring, RomInfo, Timing};
+use crate::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// A known dump and the header it should have.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Game {
+    /// From the comment in the `<game>` element, if any
+    pub name: String,
+    pub rom_sha1: Option<[u8; 20]>,
+    pub header: Header,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Database {
+    /// By CRC-32 of PRG and CHR ROM
+    games: HashMap<u32, Game>,
+}
+
+impl Database {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Database> {
+        Self::from_xml(&fs::read_to_string(path)?)
+    }
+
+    /// Read the games in NES 2.0 XML. Elements the header does not need
+    /// are skipped, as are games without a `<rom>` checksum.
+    pub fn from_xml(xml: &str) -> Result<Database> {
+        let mut games = HashMap::new();
+        for block in xml.split("<game>").skip(1) {
+            let block = block.split("</game>").next().unwrap_or(block);
+            if let Some((crc32, game)) = parse_game(block)? {
+                games.insert(crc32, game);
+            }
+        }
+        Ok(Database { games })
+    }
+
+    pub fn len(&self) -> usize {
+        self.games.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.games.is_empty()
+    }
+
+    /// The game with PRG and CHR ROM matching `rom_crc32` and `rom_sha1`.
+    pub fn lookup(&self, rom_crc32: u32, rom_sha1: &[u8; 20]) -> Option<&Game> {
+        self.games
+            .get(&rom_crc32)
+            .filter(|game| game.rom_sha1.is_none_or(|sha1| sha1 == *rom_sha1))
+    }
+
+    /// The game `info` was dumped from.
+    pub fn identify(&self, info: &RomInfo) -> Option<&Game> {
+        self.lookup(info.rom_crc32, &info.rom_sha1)
+    }
+}
+
+/// The game in the contents of a `<game>` element, keyed by its ROM's
+/// CRC-32.
+fn parse_game(block: &str) -> Result<Option<(u32, Game)>> {
+    let name = block
+        .split("<!--")
+        .nth(1)
+        .and_then(|comment| comment.split("-->").next())
+        .unwrap_or("")
+        .trim()
+        .to_string();
+
+    let mut rom = None;
+    let mut header = Header {
+        format: FileFormat::Nes20,
+        prg_rom_size: 0,
+        chr_rom_size: 0,
+        mapper_id: 0,
+        submapper_id: 0,
+        mirroring: Mirroring::Horizontal,
+        has_trainer: false,
+        has_battery: false,
+        prg_ram_size: 0,
+        prg_nvram_size: 0,
+        chr_ram_size: 0,
+        chr_nvram_size: 0,
+        timing: Timing::Ntsc,
+    };
+    for (tag, attributes) in tags(block) {
+        let attribute = |name: &str| {
+            attributes
+                .iter()
+                .find(|&&(key, _)| key == name)
+                .map(|&(_, value)| value)
+        };
+        let number = |name: &str| -> Result<usize> {
+            match attribute(name) {
+                Some(value) => Ok(value
+                    .parse()
+                    .map_err(|_| format!("bad {} {:?} in {}", name, value, tag))?),
+                None => Ok(0),
+            }
+        };
+        match tag {
+            "rom" => {
+                let crc32 = attribute("crc32").ok_or("<rom> without a crc32")?;
+                let crc32 =
+                    u32::from_str_radix(crc32, 16).map_err(|_| format!("bad crc32 {:?}", crc32))?;
+                rom = Some((crc32, attribute("sha1").and_then(parse_sha1)));
+            }
+            "prgrom" => header.prg_rom_size = number("size")?,
+            "chrrom" => header.chr_rom_size = number("size")?,
+            "prgram" => header.prg_ram_size = number("size")?,
+            "prgnvram" => header.prg_nvram_size = number("size")?,
+            "chrram" => header.chr_ram_size = number("size")?,
+            "chrnvram" => header.chr_nvram_size = number("size")?,
+            "trainer" => header.has_trainer = number("size")? != 0,
+            "console" => {
+                header.timing = match number("region")? {
+                    1 => Timing::Pal,
+                    2 => Timing::MultiRegion,
+                    3 => Timing::Dendy,
+                    _ => Timing::Ntsc,
+                };
+            }
+            "pcb" => {
+                header.mapper_id = number("mapper")? as u16;
+                header.submapper_id = number("submapper")? as u8;
+                header.has_battery = number("battery")? != 0;
+                header.mirroring = match attribute("mirroring") {
+                    Some("V") => Mirroring::Vertical,
+                    Some("4") => Mirroring::FourScreen,
+                    _ => Mirroring::Horizontal,
+                };
+            }
+            _ => {}
+        }
+    }
+
+    Ok(rom.map(|(crc32, rom_sha1)| {
+        let game = Game {
+            name,
+            rom_sha1,
+            header,
+        };
+        (crc32, game)
+    }))
+}
+
+/// The empty elements in `xml`, as names and attributes.
+fn tags(xml: &str) -> impl Iterator<Item = (&str, Vec<(&str, &str)>)> {
+    xml.split('<').skip(1).filter_map(|element| {
+        if element.starts_with('!') || element.starts_with('/') {
+            return None;
+        }
+        let element = element.split('>').next()?.trim_end_matches('/');
+        let mut parts = element.splitn(2, char::is_whitespace);
+        let tag = parts.next()?;
+        let mut attributes = Vec::new();
+        let mut rest = parts.next().unwrap_or("");
+        while let Some(equals) = rest.find("=\"") {
+            let key = rest[..equals].trim();
+            let value_start = equals + 2;
+            let value_len = rest[value_start..].find('"')?;
+            attributes.push((key, &rest[value_start..value_start + value_len]));
+            rest = &rest[value_start + value_len + 1..];
+        }
+        Some((tag, attributes))
+    })
+}
+
+fn parse_sha1(hex: &str) -> Option<[u8; 20]> {
+    if hex.len() != 40 {
+        return None;
+    }
+    let mut sha1 = [0; 20];
+    for (index, byte) in sha1.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(index * 2..index * 2 + 2)?, 16).ok()?;
+    }
+    Some(sha1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<nes20db date="2024-01-01">
+<game>
+	<!-- Example (World) -->
+	<prgrom size="16384" crc32="00000000"/>
+	<chrrom size="8192" crc32="00000000"/>
+	<rom size="24576" crc32="1234ABCD" sha1="A9993E364706816ABA3E25717850C26C9CD0D89D"/>
+	<prgnvram size="8192"/>
+	<console type="0" region="1"/>
+	<pcb mapper="2" submapper="2" mirroring="V" battery="1"/>
+</game>
+<game>
+	<prgrom size="32768" crc32="00000000"/>
+</game>
+</nes20db>
+"#;
+
+    #[test]
+    fn reads_nes20db_games() {
+        let database = Database::from_xml(XML).unwrap();
+        assert_eq!(database.len(), 1);
+
+        let sha1 = crate::checksum::sha1(b"abc");
+        let game = database.lookup(0x1234_abcd, &sha1).unwrap();
+        assert_eq!(game.name, "Example (World)");
+        assert_eq!(game.header.prg_rom_size, 16 * 1024);
+        assert_eq!(game.header.chr_rom_size, 8 * 1024);
+        assert_eq!(game.header.mapper_id, 2);
+        assert_eq!(game.header.submapper_id, 2);
+        assert_eq!(game.header.mirroring, Mirroring::Vertical);
+        assert!(game.header.has_battery);
+        assert_eq!(game.header.prg_nvram_size, 8 * 1024);
+        assert_eq!(game.header.prg_ram_size, 0);
+        assert_eq!(game.header.timing, Timing::Pal);
+
+        // Same CRC, different SHA-1
+        assert_eq!(database.lookup(0x1234_abcd, &[0; 20]), None);
+    }
+}
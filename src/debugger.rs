@@ -1,15 +1,689 @@
 use crate::addressing_mode::AddressingMode;
+use crate::cpu::Registers;
+use crate::instructions::Instruction;
+use std::collections::VecDeque;
 use std::fmt;
+use std::ops;
+use std::sync::{Arc, Mutex};
 
+/// A single instruction decoded from memory.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Decoded {
-    byte_code: Vec<u8>,
+    byte_code: [u8; 3],
+    len: usize,
     opcode: u8,
     mnemonic: &'static str,
     addressing_mode: AddressingMode,
 }
 
+impl Decoded {
+    /// Decode the instruction at the start of `bytes`.
+    ///
+    /// An instruction cut off by the end of `bytes` decodes as a single
+    /// `.db` byte, since its operands are unknown.
+    pub fn new(bytes: &[u8]) -> Decoded {
+        let opcode = bytes.first().copied().unwrap_or(0);
+        let addressing_mode = match AddressingMode::for_opcode(opcode) {
+            AddressingMode::Unimplemented => AddressingMode::Unimplemented,
+            mode if mode.len() > bytes.len() => AddressingMode::Unimplemented,
+            mode => mode,
+        };
+        let (mnemonic, len) = match addressing_mode {
+            AddressingMode::Unimplemented => (".db", 1),
+            _ => (
+                Instruction::for_opcode(opcode).as_str(),
+                addressing_mode.len(),
+            ),
+        };
+        let mut byte_code = [0; 3];
+        let available = len.min(bytes.len());
+        byte_code[..available].copy_from_slice(&bytes[..available]);
+        Decoded {
+            byte_code,
+            len,
+            opcode,
+            mnemonic,
+            addressing_mode,
+        }
+    }
+
+    pub fn opcode(&self) -> u8 {
+        self.opcode
+    }
+
+    pub fn addressing_mode(&self) -> AddressingMode {
+        self.addressing_mode
+    }
+
+    /// The opcode followed by its operand bytes.
+    pub fn byte_code(&self) -> &[u8] {
+        &self.byte_code[..self.len]
+    }
+}
+
 impl fmt::Display for Decoded {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        todo!()
+        let operand = |index: usize| self.byte_code[index];
+        let address = u16::from_be_bytes([operand(2), operand(1)]);
+        match self.addressing_mode {
+            AddressingMode::Absolute => write!(f, "{} ${:04X}", self.mnemonic, address),
+            AddressingMode::AbsoluteX => write!(f, "{} ${:04X},X", self.mnemonic, address),
+            AddressingMode::AbsoluteY => write!(f, "{} ${:04X},Y", self.mnemonic, address),
+            AddressingMode::Accumulator => write!(f, "{} A", self.mnemonic),
+            AddressingMode::Immediate => write!(f, "{} #${:02X}", self.mnemonic, operand(1)),
+            AddressingMode::Implied => write!(f, "{}", self.mnemonic),
+            AddressingMode::IndirectAbsolute => write!(f, "{} (${:04X})", self.mnemonic, address),
+            AddressingMode::IndirectZeroPageX => {
+                write!(f, "{} (${:02X},X)", self.mnemonic, operand(1))
+            }
+            AddressingMode::IndirectZeroPageY => {
+                write!(f, "{} (${:02X}),Y", self.mnemonic, operand(1))
+            }
+            AddressingMode::Relative => write!(f, "{} *{:+}", self.mnemonic, operand(1) as i8),
+            AddressingMode::ZeroPage => write!(f, "{} ${:02X}", self.mnemonic, operand(1)),
+            AddressingMode::ZeroPageX => write!(f, "{} ${:02X},X", self.mnemonic, operand(1)),
+            AddressingMode::ZeroPageY => write!(f, "{} ${:02X},Y", self.mnemonic, operand(1)),
+            AddressingMode::Unimplemented => write!(f, "{} ${:02X}", self.mnemonic, self.opcode),
+        }
+    }
+}
+
+/// A decoded instruction and the address it was decoded from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub address: u16,
+    pub decoded: Decoded,
+}
+
+/// Hex bytes separated by spaces and padded to the width of three bytes.
+pub(crate) fn write_byte_code(f: &mut impl fmt::Write, byte_code: &[u8]) -> fmt::Result {
+    for (index, byte) in byte_code.iter().enumerate() {
+        if index > 0 {
+            f.write_char(' ')?;
+        }
+        write!(f, "{:02X}", byte)?;
+    }
+    for _ in byte_code.len()..3 {
+        f.write_str("   ")?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X}  ", self.address)?;
+        write_byte_code(f, self.decoded.byte_code())?;
+        write!(f, "  {}", self.decoded)
+    }
+}
+
+/// Linear sweep disassembly of `bytes`, which are mapped at `origin`.
+pub fn disassemble(bytes: &[u8], origin: u16) -> Disassembly<'_> {
+    Disassembly {
+        bytes,
+        origin,
+        offset: 0,
+    }
+}
+
+/// Iterator returned by [`disassemble`].
+#[derive(Debug, Clone)]
+pub struct Disassembly<'a> {
+    bytes: &'a [u8],
+    origin: u16,
+    offset: usize,
+}
+
+impl<'a> Iterator for Disassembly<'a> {
+    type Item = Line;
+
+    fn next(&mut self) -> Option<Line> {
+        let bytes = self
+            .bytes
+            .get(self.offset..)
+            .filter(|bytes| !bytes.is_empty())?;
+        let decoded = Decoded::new(bytes);
+        let address = self.origin.wrapping_add(self.offset as u16);
+        self.offset += decoded.byte_code().len();
+        Some(Line { address, decoded })
+    }
+}
+
+/// Receives a trace line before each instruction, see
+/// [`Cpu::set_trace_sink`](crate::cpu::Cpu::set_trace_sink).
+pub trait TraceSink {
+    fn trace(&mut self, line: &str);
+}
+
+impl<F: FnMut(&str)> TraceSink for F {
+    fn trace(&mut self, line: &str) {
+        self(line)
+    }
+}
+
+/// Prints trace lines to stdout.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stdout;
+
+impl TraceSink for Stdout {
+    fn trace(&mut self, line: &str) {
+        println!("{}", line);
+    }
+}
+
+/// An installed trace sink and the buffer reused for its lines.
+#[derive(Clone)]
+pub(crate) struct Tracer {
+    pub(crate) buffer: String,
+    pub(crate) sink: Arc<Mutex<dyn TraceSink + Send>>,
+}
+
+impl Tracer {
+    pub(crate) fn new(sink: impl TraceSink + Send + 'static) -> Tracer {
+        Tracer {
+            buffer: String::new(),
+            sink: Arc::new(Mutex::new(sink)),
+        }
+    }
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Tracer")
+    }
+}
+
+/// Tracers are equal when they feed the same sink.
+impl PartialEq for Tracer {
+    fn eq(&self, other: &Tracer) -> bool {
+        Arc::ptr_eq(&self.sink, &other.sink)
+    }
+}
+
+/// CPU state from one line of a trace log.
+///
+/// Understands the trace lines written by [`Cpu::trace`](crate::cpu::Cpu::trace)
+/// as well as nestest and Mesen logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub ps: u8,
+    pub sp: u8,
+    pub cycle: Option<u64>,
+}
+
+impl TraceRecord {
+    /// Parse a trace line, or `None` for lines that are not one, such as
+    /// headers and blank lines.
+    pub fn parse(line: &str) -> Option<TraceRecord> {
+        let mut tokens = line.split_whitespace();
+        let pc = tokens.next().filter(|pc| pc.len() == 4)?;
+        let pc = u16::from_str_radix(pc, 16).ok()?;
+        let (mut a, mut x, mut y, mut ps, mut sp, mut cycle) = (None, None, None, None, None, None);
+        for token in tokens {
+            let (key, value) = match token.split_once(':') {
+                Some(pair) => pair,
+                None => continue,
+            };
+            let hex = || u8::from_str_radix(value, 16).ok();
+            match key {
+                "A" => a = hex(),
+                "X" => x = hex(),
+                "Y" => y = hex(),
+                "S" | "SP" => sp = hex(),
+                "P" if value.len() == 8 => ps = parse_flags(value),
+                "P" => ps = hex(),
+                "C" | "CYC" => cycle = value.parse().ok(),
+                _ => {}
+            }
+        }
+        Some(TraceRecord {
+            pc,
+            a: a?,
+            x: x?,
+            y: y?,
+            ps: ps?,
+            sp: sp?,
+            cycle,
+        })
+    }
+
+    /// Names of the fields that differ from `other`.
+    ///
+    /// The break and unused flags are ignored since logs disagree on how
+    /// to show them, and cycles are only compared when both have them.
+    pub fn differences(&self, other: &TraceRecord, compare_cycles: bool) -> Vec<&'static str> {
+        const IGNORED_FLAGS: u8 = 0x30;
+        let mut fields = Vec::new();
+        if self.pc != other.pc {
+            fields.push("PC");
+        }
+        if self.a != other.a {
+            fields.push("A");
+        }
+        if self.x != other.x {
+            fields.push("X");
+        }
+        if self.y != other.y {
+            fields.push("Y");
+        }
+        if self.ps & !IGNORED_FLAGS != other.ps & !IGNORED_FLAGS {
+            fields.push("P");
+        }
+        if self.sp != other.sp {
+            fields.push("SP");
+        }
+        if let (true, Some(a), Some(b)) = (compare_cycles, self.cycle, other.cycle) {
+            if a != b {
+                fields.push("CYC");
+            }
+        }
+        fields
+    }
+}
+
+/// Flags written as `NVUBDIZC`, upper case for set.
+fn parse_flags(flags: &str) -> Option<u8> {
+    let mut ps = 0;
+    for (bit, flag) in flags.chars().enumerate() {
+        if !"nvubdizc".contains(flag.to_ascii_lowercase()) {
+            return None;
+        }
+        if flag.is_ascii_uppercase() {
+            ps |= 0x80 >> bit;
+        }
+    }
+    Some(ps)
+}
+
+/// Read and write counts for every CPU address.
+///
+/// Counts accumulate until [`Heatmap::clear`], so a window is whatever
+/// happened between two clears.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heatmap {
+    reads: Vec<u32>,
+    writes: Vec<u32>,
+}
+
+impl Heatmap {
+    /// Width and height of the image from [`Heatmap::to_rgba`].
+    pub const SIZE: usize = 256;
+
+    pub fn new() -> Heatmap {
+        Heatmap {
+            reads: vec![0; 0x10000],
+            writes: vec![0; 0x10000],
+        }
+    }
+
+    pub(crate) fn record_read(&mut self, address: u16) {
+        let count = &mut self.reads[address as usize];
+        *count = count.saturating_add(1);
+    }
+
+    pub(crate) fn record_write(&mut self, address: u16) {
+        let count = &mut self.writes[address as usize];
+        *count = count.saturating_add(1);
+    }
+
+    /// Reads indexed by address.
+    pub fn reads(&self) -> &[u32] {
+        &self.reads
+    }
+
+    /// Writes indexed by address.
+    pub fn writes(&self) -> &[u32] {
+        &self.writes
+    }
+
+    pub fn clear(&mut self) {
+        self.reads.iter_mut().for_each(|count| *count = 0);
+        self.writes.iter_mut().for_each(|count| *count = 0);
+    }
+
+    /// A 256x256 RGBA image with one pixel per address, $0000 at the top
+    /// left and one page per row.
+    ///
+    /// Writes are red and reads are green, each on a log scale relative to
+    /// the busiest address.
+    pub fn to_rgba(&self) -> Vec<u8> {
+        fn scale(counts: &[u32]) -> impl Fn(u32) -> u8 {
+            let max = counts.iter().copied().max().unwrap_or(0);
+            let max = (max as f64).ln_1p();
+            move |count| {
+                if count == 0 {
+                    0
+                } else {
+                    (255.0 * (count as f64).ln_1p() / max).round() as u8
+                }
+            }
+        }
+        let red = scale(&self.writes);
+        let green = scale(&self.reads);
+        let mut image = Vec::with_capacity(Self::SIZE * Self::SIZE * 4);
+        for (&reads, &writes) in self.reads.iter().zip(&self.writes) {
+            image.extend_from_slice(&[red(writes), green(reads), 0, 0xff]);
+        }
+        image
+    }
+
+    /// Save [`Heatmap::to_rgba`] as a PNG file.
+    #[cfg(feature = "png")]
+    pub fn write_png(&self, path: impl AsRef<std::path::Path>) -> crate::Result<()> {
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let size = Self::SIZE as u32;
+        let mut encoder = png::Encoder::new(file, size, size);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.to_rgba())?;
+        Ok(())
+    }
+}
+
+impl Default for Heatmap {
+    fn default() -> Heatmap {
+        Heatmap::new()
+    }
+}
+
+/// Whether a CPU bus access read or wrote.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// A CPU bus access seen by a watch, see
+/// [`Console::watch`](crate::console::Console::watch).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub kind: AccessKind,
+    pub address: u16,
+    /// The byte read, cheats applied, or the byte written
+    pub data: u8,
+}
+
+/// Names a watch to remove.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WatchId(u64);
+
+/// Callbacks for accesses to ranges of CPU addresses, shared by anything
+/// that wants to see them: breakpoints, code/data loggers, cheat searches.
+#[derive(Clone, Default)]
+pub(crate) struct Watches {
+    watches: Vec<Watch>,
+    next_id: u64,
+    /// How many watches there are of each kind, so that buses can skip
+    /// building an `Access` when none would see it
+    reads: usize,
+    writes: usize,
+}
+
+#[derive(Clone)]
+struct Watch {
+    id: WatchId,
+    kind: AccessKind,
+    addresses: ops::Range<u32>,
+    callback: Arc<Mutex<dyn FnMut(Access) + Send>>,
+}
+
+impl Watches {
+    pub(crate) fn add(
+        &mut self,
+        kind: AccessKind,
+        addresses: ops::Range<u32>,
+        callback: impl FnMut(Access) + Send + 'static,
+    ) -> WatchId {
+        let id = WatchId(self.next_id);
+        self.next_id += 1;
+        *self.count(kind) += 1;
+        self.watches.push(Watch {
+            id,
+            kind,
+            addresses,
+            callback: Arc::new(Mutex::new(callback)),
+        });
+        id
+    }
+
+    /// Take out the watch `id`. Returns whether it was there.
+    pub(crate) fn remove(&mut self, id: WatchId) -> bool {
+        match self.watches.iter().position(|watch| watch.id == id) {
+            Some(index) => {
+                let watch = self.watches.remove(index);
+                *self.count(watch.kind) -= 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.watches.clear();
+        self.reads = 0;
+        self.writes = 0;
+    }
+
+    /// Whether any watch is for accesses of `kind`.
+    #[inline]
+    pub(crate) fn watching(&self, kind: AccessKind) -> bool {
+        match kind {
+            AccessKind::Read => self.reads > 0,
+            AccessKind::Write => self.writes > 0,
+        }
+    }
+
+    /// Call the callbacks watching `access`, in the order they were added.
+    pub(crate) fn notify(&self, access: Access) {
+        let address = access.address as u32;
+        for watch in &self.watches {
+            if watch.kind == access.kind && watch.addresses.contains(&address) {
+                (watch.callback.lock().unwrap())(access);
+            }
+        }
+    }
+
+    fn count(&mut self, kind: AccessKind) -> &mut usize {
+        match kind {
+            AccessKind::Read => &mut self.reads,
+            AccessKind::Write => &mut self.writes,
+        }
+    }
+}
+
+impl fmt::Debug for Watches {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watches")
+            .field("reads", &self.reads)
+            .field("writes", &self.writes)
+            .finish()
+    }
+}
+
+/// State from before one instruction, enough to undo it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct UndoEntry {
+    pub(crate) registers: Registers,
+    pub(crate) cycle: u64,
+    /// Addresses written and the bytes they held before, in write order
+    pub(crate) writes: Vec<(u16, u8)>,
+}
+
+/// Bounded journal of the most recent instructions for reverse stepping.
+///
+/// The oldest entry is dropped once `depth` instructions are recorded, and
+/// its buffer is reused for the next one.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct UndoLog {
+    depth: usize,
+    entries: VecDeque<UndoEntry>,
+}
+
+impl UndoLog {
+    pub(crate) fn new(depth: usize) -> UndoLog {
+        UndoLog {
+            depth,
+            entries: VecDeque::with_capacity(depth),
+        }
+    }
+
+    /// Start recording the instruction about to run.
+    pub(crate) fn begin(&mut self, registers: Registers, cycle: u64) {
+        let mut writes = if self.entries.len() >= self.depth {
+            self.entries.pop_front().unwrap().writes
+        } else {
+            Vec::new()
+        };
+        writes.clear();
+        self.entries.push_back(UndoEntry {
+            registers,
+            cycle,
+            writes,
+        });
+    }
+
+    /// Record that `address` held `old` before the current instruction
+    /// wrote to it.
+    pub(crate) fn record_write(&mut self, address: u16, old: u8) {
+        if let Some(entry) = self.entries.back_mut() {
+            entry.writes.push((address, old));
+        }
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<UndoEntry> {
+        self.entries.pop_back()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Write;
+
+    fn listing(lines: impl Iterator<Item = Line>) -> String {
+        let mut listing = String::new();
+        for line in lines {
+            writeln!(listing, "{}", line).unwrap();
+        }
+        listing
+    }
+
+    #[test]
+    fn every_opcode() {
+        // Each opcode followed by the operand bytes $34 $12 it may consume
+        let mut bytes = Vec::new();
+        for opcode in 0..=0xff {
+            let decoded = Decoded::new(&[opcode, 0x34, 0x12]);
+            bytes.push(opcode);
+            bytes.extend_from_slice(&[0x34, 0x12][..decoded.byte_code().len() - 1]);
+        }
+        insta::assert_snapshot!(listing(disassemble(&bytes, 0x8000)));
+    }
+
+    #[test]
+    fn reset_routine() {
+        #[rustfmt::skip]
+        let program = [
+            0x78,             // SEI
+            0xd8,             // CLD
+            0xa2, 0xff,       // LDX #$FF
+            0x9a,             // TXS
+            0xe8,             // INX
+            0x8e, 0x00, 0x20, // STX $2000
+            0x8e, 0x01, 0x20, // STX $2001
+            0x2c, 0x02, 0x20, // BIT $2002
+            0x10, 0xfb,       // BPL $800C
+            0xa9, 0x00,       // LDA #$00
+            0x95, 0x00,       // STA $00,X
+            0x9d, 0x00, 0x03, // STA $0300,X
+            0xe8,             // INX
+            0xd0, 0xf8,       // BNE $8013
+            0x6c, 0xfc, 0xff, // JMP ($FFFC)
+        ];
+        insta::assert_snapshot!(listing(disassemble(&program, 0x8000)));
+    }
+
+    #[test]
+    fn trace_record_formats() {
+        let expected = TraceRecord {
+            pc: 0xc000,
+            a: 0x00,
+            x: 0x01,
+            y: 0x02,
+            ps: 0x24,
+            sp: 0xfd,
+            cycle: Some(7),
+        };
+        let own = "C000 4C F5 C5   JMP $C5F5        A:00 X:01 Y:02 S:FD P:nvUbdIzc C:7 Stack: []";
+        let nestest = "C000  4C F5 C5  JMP $C5F5                       \
+                       A:00 X:01 Y:02 P:24 SP:FD PPU:  0, 21 CYC:7";
+        let mesen = "C000  $4C $F5 $C5  JMP $C5F5  A:00 X:01 Y:02 P:24 SP:FD CYC:7";
+        for line in [own, nestest, mesen] {
+            assert_eq!(TraceRecord::parse(line), Some(expected), "{}", line);
+        }
+        assert_eq!(TraceRecord::parse(""), None);
+        assert_eq!(TraceRecord::parse("C000  4C F5 C5  JMP $C5F5"), None);
+    }
+
+    #[test]
+    fn trace_record_differences() {
+        let a = TraceRecord::parse("C000 A:00 X:00 Y:00 P:24 SP:FD CYC:7").unwrap();
+        let b = TraceRecord::parse("C000 A:01 X:00 Y:00 P:34 SP:FD CYC:9").unwrap();
+        assert_eq!(a.differences(&b, true), ["A", "CYC"]);
+        assert_eq!(a.differences(&b, false), ["A"]);
+    }
+
+    #[test]
+    fn heatmap_image() {
+        let mut heatmap = Heatmap::new();
+        for _ in 0..10 {
+            heatmap.record_read(0x0000);
+        }
+        heatmap.record_read(0x0101);
+        heatmap.record_write(0xffff);
+
+        let image = heatmap.to_rgba();
+        assert_eq!(image.len(), 256 * 256 * 4);
+        let pixel = |x: usize, y: usize| &image[(y * 256 + x) * 4..][..4];
+        assert_eq!(pixel(0, 0), [0, 255, 0, 255]);
+        assert_eq!(pixel(1, 1), [0, 74, 0, 255]);
+        assert_eq!(pixel(255, 255), [255, 0, 0, 255]);
+        assert_eq!(pixel(2, 0), [0, 0, 0, 255]);
+
+        heatmap.clear();
+        assert!(heatmap.reads().iter().all(|&count| count == 0));
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn heatmap_png() {
+        let path = std::env::temp_dir().join("nes-heatmap-test.png");
+        Heatmap::new().write_png(&path).unwrap();
+        let png = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    }
+
+    #[test]
+    fn truncated_instruction_at_end_of_bank() {
+        let lines: Vec<_> = disassemble(&[0xa9, 0x01, 0x8d, 0x00], 0xfffc)
+            .map(|line| line.to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "FFFC  A9 01     LDA #$01",
+                "FFFE  8D        .db $8D",
+                "FFFF  00        BRK",
+            ]
+        );
     }
 }
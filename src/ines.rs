@@ -1,6 +1,13 @@
-use std::error::Error;
+use crate::cartridge::Cartridge;
+use crate::checksum::{Crc32, Sha1};
+use crate::database::Database;
+use crate::region::Region;
+use crate::Result;
+use std::fs;
+use std::path::Path;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
 pub enum FileFormat {
     /// iNES
     INes,
@@ -9,21 +16,62 @@ pub enum FileFormat {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Mirroring {
     Horizontal,
     Vertical,
     FourScreen,
+    /// Every nametable address maps to the first 1 kB of VRAM
+    SingleScreenLower,
+    /// Every nametable address maps to the second 1 kB of VRAM
+    SingleScreenUpper,
 }
 
+/// The console a game was made for, from NES 2.0 byte 12.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    /// Runs on either NTSC or PAL consoles
+    MultiRegion,
+    Dendy,
+}
+
+impl Timing {
+    /// The region to emulate. Multi-region games run as NTSC.
+    pub fn region(self) -> Region {
+        match self {
+            Timing::Ntsc | Timing::MultiRegion => Region::Ntsc,
+            Timing::Pal => Region::Pal,
+            Timing::Dendy => Region::Dendy,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
 pub struct Header {
     pub format: FileFormat,
     pub prg_rom_size: usize,
     pub chr_rom_size: usize,
     pub mapper_id: u16,
+    /// NES 2.0 board variant of the mapper, 0 for iNES
+    pub submapper_id: u8,
     pub mirroring: Mirroring,
     pub has_trainer: bool,
     pub has_battery: bool,
+    /// Volatile PRG RAM in bytes. iNES does not say, so this is 8 kB.
+    pub prg_ram_size: usize,
+    /// Battery-backed PRG RAM in bytes, NES 2.0 only
+    pub prg_nvram_size: usize,
+    /// Volatile CHR RAM in bytes. For iNES this is 8 kB without CHR ROM.
+    pub chr_ram_size: usize,
+    /// Battery-backed CHR RAM in bytes, NES 2.0 only
+    pub chr_nvram_size: usize,
+    /// NES 2.0 only. iNES headers rarely say, so this is NTSC.
+    pub timing: Timing,
 }
 
 // Flags 6
@@ -32,7 +80,28 @@ const MIRRORING_FOUR_SCREEN_MASK: u8 = 0b0000_1000;
 const HAS_BATTERY_MASK: u8 = 0b0000_0010;
 const HAS_TRAINER_MASK: u8 = 0b0000_0100;
 
-pub fn parse_header(header: &[u8]) -> Result<Header, Box<dyn Error>> {
+/// A NES 2.0 ROM size given as 2^E * (M * 2 + 1), from a byte holding E
+/// in bits 2-7 and M in bits 0-1. Sizes too large for a `usize` are an
+/// error.
+fn exponent_multiplier_size(byte: usize, section: &str) -> Result<usize> {
+    let multiplier = (byte & 0b0000_0011) * 2 + 1;
+    let exponent = (byte & 0b1111_1100) >> 2;
+    1usize
+        .checked_shl(exponent as u32)
+        .and_then(|size| size.checked_mul(multiplier))
+        .ok_or_else(|| {
+            format!(
+                "{} of 2^{} * {} bytes is too large",
+                section, exponent, multiplier
+            )
+            .into()
+        })
+}
+
+pub fn parse_header(header: &[u8]) -> Result<Header> {
+    if header.len() < 16 {
+        return Err(format!("{} bytes is too short for a header", header.len()).into());
+    }
     let magic = &header[0..4];
     if magic != b"NES\x1a" {
         return Err("bad format".into());
@@ -53,9 +122,7 @@ pub fn parse_header(header: &[u8]) -> Result<Header, Box<dyn Error>> {
                 let size_lsb = header[4] as usize;
                 let size_msb = (header[9] as usize & 0b0000_1111) << 8;
                 if size_msb == 0b1111_0000_0000 {
-                    let multiplier = size_lsb & 0b0000_0011;
-                    let exponent = size_lsb & 0b1111_1100;
-                    2 ^ exponent * (multiplier * 2 + 1)
+                    exponent_multiplier_size(size_lsb, "PRG ROM")?
                 } else {
                     (size_msb | size_lsb) * multiplier
                 }
@@ -71,9 +138,7 @@ pub fn parse_header(header: &[u8]) -> Result<Header, Box<dyn Error>> {
                 let size_lsb = header[5] as usize;
                 let size_msb = (header[9] as usize & 0b1111_0000) << 4;
                 if size_msb == 0b1111_0000_0000 {
-                    let multiplier = size_lsb & 0b0000_0011;
-                    let exponent = size_lsb & 0b1111_1100;
-                    2 ^ exponent * (multiplier * 2 + 1)
+                    exponent_multiplier_size(size_lsb, "CHR ROM")?
                 } else {
                     (size_msb | size_lsb) * multiplier
                 }
@@ -92,7 +157,7 @@ pub fn parse_header(header: &[u8]) -> Result<Header, Box<dyn Error>> {
     let has_battery = header[6] & HAS_BATTERY_MASK != 0;
     let has_trainer = header[6] & HAS_TRAINER_MASK != 0;
 
-    let (mapper_id, _submapper_id) = match format {
+    let (mapper_id, submapper_id) = match format {
         FileFormat::INes => {
             let bits_0_3 = (header[6] & 0b1111_0000) as u16 >> 4;
             let bits_4_7 = (header[7] & 0b1111_0000) as u16;
@@ -103,22 +168,231 @@ pub fn parse_header(header: &[u8]) -> Result<Header, Box<dyn Error>> {
             let bits_4_7 = (header[7] & 0b1111_0000) as u16;
             let bits_8_11 = ((header[8] & 0b0000_1111) as u16) << 8;
             let mapper_id = bits_8_11 | bits_4_7 | bits_0_3;
-            let submapper_id = ((header[8] & 0b1111_0000) as u8) >> 4;
+            let submapper_id = (header[8] & 0b1111_0000) >> 4;
             (mapper_id, submapper_id)
         }
     };
 
+    // NES 2.0 gives RAM sizes as shift counts, 64 << shift bytes
+    let ram_size = |shift: u8| if shift == 0 { 0 } else { 64 << shift };
+    let (prg_ram_size, prg_nvram_size, chr_ram_size, chr_nvram_size) = match format {
+        FileFormat::INes => {
+            let chr_ram_size = if chr_rom_size == 0 { 8 * 1024 } else { 0 };
+            (8 * 1024, 0, chr_ram_size, 0)
+        }
+        FileFormat::Nes20 => (
+            ram_size(header[10] & 0x0f),
+            ram_size(header[10] >> 4),
+            ram_size(header[11] & 0x0f),
+            ram_size(header[11] >> 4),
+        ),
+    };
+
+    let timing = match format {
+        FileFormat::INes => Timing::Ntsc,
+        FileFormat::Nes20 => match header[12] & 0b0000_0011 {
+            0 => Timing::Ntsc,
+            1 => Timing::Pal,
+            2 => Timing::MultiRegion,
+            _ => Timing::Dendy,
+        },
+    };
+
     Ok(Header {
         format,
         prg_rom_size,
         chr_rom_size,
         mapper_id,
+        submapper_id,
         mirroring,
         has_trainer,
         has_battery,
+        prg_ram_size,
+        prg_nvram_size,
+        chr_ram_size,
+        chr_nvram_size,
+        timing,
     })
 }
 
+impl Header {
+    /// The 16 header bytes, in the header's format. Fails if a field does
+    /// not fit: a ROM size iNES can't express, say, or a mapper above 255.
+    ///
+    /// Single-screen mirroring is written as horizontal, since only the
+    /// mapper can select it.
+    pub fn to_bytes(&self) -> Result<[u8; 16]> {
+        let mut header = [0; 16];
+        header[..4].copy_from_slice(b"NES\x1a");
+        header[6] = match self.mirroring {
+            Mirroring::Vertical => MIRRORING_VERTICAL_MASK,
+            Mirroring::FourScreen => MIRRORING_FOUR_SCREEN_MASK,
+            _ => 0,
+        };
+        if self.has_battery {
+            header[6] |= HAS_BATTERY_MASK;
+        }
+        if self.has_trainer {
+            header[6] |= HAS_TRAINER_MASK;
+        }
+        header[6] |= (self.mapper_id as u8 & 0x0f) << 4;
+        header[7] = self.mapper_id as u8 & 0xf0;
+
+        match self.format {
+            FileFormat::INes => {
+                if self.mapper_id > 0xff || self.submapper_id != 0 {
+                    return Err(format!(
+                        "mapper {}.{} needs NES 2.0",
+                        self.mapper_id, self.submapper_id
+                    )
+                    .into());
+                }
+                header[4] = rom_size_units(self.prg_rom_size, 16 * 1024, 0xff)?.0;
+                header[5] = rom_size_units(self.chr_rom_size, 8 * 1024, 0xff)?.0;
+            }
+            FileFormat::Nes20 => {
+                if self.mapper_id > 0xfff || self.submapper_id > 0x0f {
+                    return Err(format!(
+                        "mapper {}.{} does not fit NES 2.0",
+                        self.mapper_id, self.submapper_id
+                    )
+                    .into());
+                }
+                header[7] |= 0b0000_1000;
+                header[8] = (self.mapper_id >> 8) as u8 | self.submapper_id << 4;
+                let (prg_lsb, prg_msb) = rom_size_units(self.prg_rom_size, 16 * 1024, 0xeff)?;
+                let (chr_lsb, chr_msb) = rom_size_units(self.chr_rom_size, 8 * 1024, 0xeff)?;
+                header[4] = prg_lsb;
+                header[5] = chr_lsb;
+                header[9] = prg_msb | chr_msb << 4;
+                header[10] = ram_shift(self.prg_ram_size)? | ram_shift(self.prg_nvram_size)? << 4;
+                header[11] = ram_shift(self.chr_ram_size)? | ram_shift(self.chr_nvram_size)? << 4;
+                header[12] = match self.timing {
+                    Timing::Ntsc => 0,
+                    Timing::Pal => 1,
+                    Timing::MultiRegion => 2,
+                    Timing::Dendy => 3,
+                };
+            }
+        }
+        Ok(header)
+    }
+}
+
+/// A ROM size as a count of `unit`s, split into its low byte and high
+/// nibble. NES 2.0 falls back on the exponent-multiplier form for sizes
+/// that are not a whole number of units.
+fn rom_size_units(size: usize, unit: usize, max_units: usize) -> Result<(u8, u8)> {
+    if size.is_multiple_of(unit) && size / unit <= max_units {
+        let units = size / unit;
+        return Ok((units as u8, (units >> 8) as u8));
+    }
+    if max_units > 0xff {
+        for multiplier in 0..4 {
+            let odd = multiplier * 2 + 1;
+            if size.is_multiple_of(odd) && (size / odd).is_power_of_two() {
+                let exponent = (size / odd).trailing_zeros() as usize;
+                if exponent < 64 {
+                    return Ok(((exponent << 2 | multiplier) as u8, 0x0f));
+                }
+            }
+        }
+    }
+    Err(format!("{} bytes of ROM does not fit the header", size).into())
+}
+
+/// A NES 2.0 RAM size, 64 << shift bytes or 0.
+fn ram_shift(size: usize) -> Result<u8> {
+    match size {
+        0 => Ok(0),
+        _ if size.is_power_of_two() && (128..=64 << 15).contains(&size) => {
+            Ok((size.trailing_zeros() - 6) as u8)
+        }
+        _ => Err(format!("{} bytes of RAM does not fit the header", size).into()),
+    }
+}
+
+/// Repair the header of an iNES file and upgrade it to NES 2.0, returning
+/// the new header.
+///
+/// The board comes from `database` when the game is in it. Otherwise the
+/// old header is kept, except that iNES headers with junk in bytes 12-15,
+/// such as "DiskDude!", lose the high nibble of the mapper that junk
+/// overwrote.
+pub fn fix_header(file: &mut [u8], database: Option<&Database>) -> Result<Header> {
+    let mut header = parse_header(file)?;
+    if header.format == FileFormat::INes && file[12..16].iter().any(|&byte| byte != 0) {
+        header.mapper_id &= 0x0f;
+    }
+    let mut cartridge = Cartridge::from_bytes(&file[..])?;
+    if database.is_some_and(|database| cartridge.apply_database(database)) {
+        header = *cartridge.header();
+    }
+    if header.format == FileFormat::INes && header.has_battery {
+        // iNES has no room for the split, and battery boards save theirs
+        header.prg_nvram_size = header.prg_ram_size;
+        header.prg_ram_size = 0;
+    }
+    header.format = FileFormat::Nes20;
+    file[..16].copy_from_slice(&header.to_bytes()?);
+    Ok(header)
+}
+
+/// Checksums that identify a dump, for looking it up in a
+/// [`Database`](crate::database::Database) or comparing it with others.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct RomInfo {
+    pub header: Header,
+    /// Of the whole file, header included
+    pub file_crc32: u32,
+    pub file_sha1: [u8; 20],
+    pub prg_crc32: u32,
+    pub prg_sha1: [u8; 20],
+    pub chr_crc32: u32,
+    pub chr_sha1: [u8; 20],
+    /// Of PRG ROM followed by CHR ROM, which databases go by since it does
+    /// not change when the header is fixed
+    pub rom_crc32: u32,
+    pub rom_sha1: [u8; 20],
+}
+
+impl RomInfo {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<RomInfo> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<RomInfo> {
+        let cartridge = Cartridge::from_bytes(bytes)?;
+        let (rom_crc32, rom_sha1) = checksums(&[cartridge.prg_rom(), cartridge.chr_rom()]);
+        let (prg_crc32, prg_sha1) = checksums(&[cartridge.prg_rom()]);
+        let (chr_crc32, chr_sha1) = checksums(&[cartridge.chr_rom()]);
+        let (file_crc32, file_sha1) = checksums(&[bytes]);
+        Ok(RomInfo {
+            header: *cartridge.header(),
+            file_crc32,
+            file_sha1,
+            prg_crc32,
+            prg_sha1,
+            chr_crc32,
+            chr_sha1,
+            rom_crc32,
+            rom_sha1,
+        })
+    }
+}
+
+/// CRC-32 and SHA-1 of `pieces` one after another.
+pub(crate) fn checksums(pieces: &[&[u8]]) -> (u32, [u8; 20]) {
+    let mut crc32 = Crc32::new();
+    let mut sha1 = Sha1::new();
+    for piece in pieces {
+        crc32.update(piece);
+        sha1.update(piece);
+    }
+    (crc32.finish(), sha1.finish())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -140,12 +414,136 @@ mod tests {
             Header {
                 format: FileFormat::INes,
                 mapper_id: 0,
+                submapper_id: 0,
                 prg_rom_size: 16 * 1024,
                 chr_rom_size: 8 * 1024,
                 mirroring: Mirroring::Horizontal,
                 has_trainer: false,
                 has_battery: false,
+                prg_ram_size: 8 * 1024,
+                prg_nvram_size: 0,
+                chr_ram_size: 0,
+                chr_nvram_size: 0,
+                timing: Timing::Ntsc,
             }
         )
     }
+
+    #[test]
+    fn nes20_exponent_multiplier_prg_size() {
+        let header = hex::decode("4E45531A1D000008000F000000000000").unwrap();
+        let header = parse_header(&header).unwrap();
+        assert_eq!(header.format, FileFormat::Nes20);
+        // 2^7 * (1 * 2 + 1)
+        assert_eq!(header.prg_rom_size, 384);
+    }
+
+    #[test]
+    fn nes20_exponent_multiplier_overflow() {
+        // 2^63 * 3
+        let header = hex::decode("4E45531AFD000008000F000000000000").unwrap();
+        let error = parse_header(&header).unwrap_err();
+        assert!(error.to_string().contains("too large"), "{}", error);
+    }
+
+    #[test]
+    fn nes20_submapper() {
+        let header = hex::decode("4E45531A020120082000000000000000").unwrap();
+        let header = parse_header(&header).unwrap();
+        assert_eq!(header.mapper_id, 2);
+        assert_eq!(header.submapper_id, 2);
+    }
+
+    #[test]
+    fn nes20_ram_sizes() {
+        let header = hex::decode("4E45531A020000080000700700000000").unwrap();
+        let header = parse_header(&header).unwrap();
+        assert_eq!(header.prg_ram_size, 0);
+        assert_eq!(header.prg_nvram_size, 8 * 1024);
+        assert_eq!(header.chr_ram_size, 8 * 1024);
+        assert_eq!(header.chr_nvram_size, 0);
+    }
+
+    #[test]
+    fn nes20_timing() {
+        let header = hex::decode("4E45531A020100080000070001000000").unwrap();
+        let header = parse_header(&header).unwrap();
+        assert_eq!(header.timing, Timing::Pal);
+        assert_eq!(header.timing.region(), Region::Pal);
+
+        // Ignored in iNES headers, where byte 12 is often junk
+        let header = hex::decode("4E45531A020100000000000003000000").unwrap();
+        let header = parse_header(&header).unwrap();
+        assert_eq!(header.timing, Timing::Ntsc);
+    }
+
+    #[test]
+    fn rom_info_checksums() {
+        let mut bytes = hex::decode("4E45531A010100000000000000000000").unwrap();
+        bytes.extend(vec![0x11; 16 * 1024]);
+        bytes.extend(vec![0x22; 8 * 1024]);
+        let info = RomInfo::from_bytes(&bytes).unwrap();
+
+        assert_eq!(info.header.prg_rom_size, 16 * 1024);
+        assert_eq!(info.file_crc32, crate::checksum::crc32(&bytes));
+        assert_eq!(
+            info.prg_crc32,
+            crate::checksum::crc32(&bytes[16..16 + 16 * 1024])
+        );
+        assert_eq!(
+            info.chr_sha1,
+            crate::checksum::sha1(&bytes[16 + 16 * 1024..])
+        );
+        assert_eq!(info.rom_crc32, crate::checksum::crc32(&bytes[16..]));
+    }
+
+    #[test]
+    fn headers_round_trip() {
+        for hex in &[
+            "4E45531A010100000000000000000000",
+            "4E45531A100007400000000000000000",
+            "4E45531A1D000008000F000000000000",
+            "4E45531A020120082000000000000000",
+            "4E45531A020000080000700700000000",
+            "4E45531A02014E183112070000000000",
+            "4E45531A020100080000070003000000",
+        ] {
+            let bytes = hex::decode(hex).unwrap();
+            let header = parse_header(&bytes).unwrap();
+            assert_eq!(&header.to_bytes().unwrap()[..], &bytes[..16], "{}", hex);
+        }
+    }
+
+    #[test]
+    fn to_bytes_rejects_what_ines_cannot_hold() {
+        let bytes = hex::decode("4E45531A020120082000000000000000").unwrap();
+        let mut header = parse_header(&bytes).unwrap();
+        header.format = FileFormat::INes;
+        assert!(header.to_bytes().is_err());
+    }
+
+    #[test]
+    fn fix_header_drops_diskdude() {
+        let mut file = b"NES\x1a\x01\x01\x11DiskDude!".to_vec();
+        file.extend(vec![0; 16 * 1024 + 8 * 1024]);
+        assert_eq!(parse_header(&file).unwrap().mapper_id, 0x41);
+
+        let header = fix_header(&mut file, None).unwrap();
+        assert_eq!(header.format, FileFormat::Nes20);
+        assert_eq!(header.mapper_id, 1);
+        assert_eq!(header.mirroring, Mirroring::Vertical);
+        assert_eq!(parse_header(&file).unwrap(), header);
+    }
+
+    #[test]
+    fn fix_header_keeps_battery_ram_battery_backed() {
+        let mut file = b"NES\x1a\x01\x01\x12\x00".to_vec();
+        file.extend(vec![0; 8 + 16 * 1024 + 8 * 1024]);
+
+        let header = fix_header(&mut file, None).unwrap();
+        assert!(header.has_battery);
+        assert_eq!(header.prg_ram_size, 0);
+        assert_eq!(header.prg_nvram_size, 8 * 1024);
+        assert_eq!(parse_header(&file).unwrap(), header);
+    }
 }
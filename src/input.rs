@@ -0,0 +1,486 @@
+#[cfg(feature = "serde")]
+use crate::state::{serde_state, serde_state_methods};
+use crate::state::{StateReader, StateWriter};
+use crate::Result;
+use std::fmt;
+
+/// Bits of the data bus the controller ports drive. The rest of a $4016 or
+/// $4017 read is open bus, usually the $40 left by the address high byte.
+pub(crate) const DRIVEN: u8 = 0x1f;
+
+/// Buttons on a standard controller, in the order it reports them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+    ];
+
+    fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// The buttons held on each of four pads, numbered like the ports of
+/// [`Console::set_button_state`](crate::console::Console::set_button_state).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct InputState {
+    pads: [u8; 4],
+}
+
+impl InputState {
+    pub fn is_pressed(&self, pad: usize, button: Button) -> bool {
+        self.pads[pad] & button.mask() != 0
+    }
+
+    pub fn set_pressed(&mut self, pad: usize, button: Button, pressed: bool) {
+        if pressed {
+            self.pads[pad] |= button.mask();
+        } else {
+            self.pads[pad] &= !button.mask();
+        }
+    }
+
+    /// Whether no button is held on any pad.
+    pub fn is_empty(&self) -> bool {
+        self.pads == [0; 4]
+    }
+}
+
+/// A device plugged into a controller port. Like mappers, devices are
+/// `Send` and clone with the console, see [`ControllerClone`].
+pub trait Controller: ControllerClone + Send {
+    /// Handle a write to $4016, whose bit 0 is the strobe line shared by
+    /// both ports.
+    fn write(&mut self, data: u8);
+
+    /// Handle a read of this port's register. Only bits 0-4 are driven.
+    fn read(&mut self) -> u8;
+
+    /// What `read` would return, without shifting to the next bit. The
+    /// default, 0, suits devices that cannot tell.
+    fn peek(&self) -> u8 {
+        0
+    }
+
+    /// Press or release `button`. Devices without buttons ignore this.
+    fn set_button(&mut self, _button: Button, _pressed: bool) {}
+
+    /// Press or release `button` on pad `pad` of a device with several,
+    /// such as the Four Score. Pad 0 is the one [`Controller::set_button`]
+    /// affects.
+    fn set_pad_button(&mut self, pad: usize, button: Button, pressed: bool) {
+        if pad == 0 {
+            self.set_button(button, pressed);
+        }
+    }
+
+    /// Called once per video frame, for devices that change on their own
+    /// such as turbo buttons.
+    fn advance_frame(&mut self) {}
+
+    /// Write the buttons held and the shift register, for savestates.
+    /// Settings such as turbo are left out.
+    fn save_state(&self, _state: &mut StateWriter) {}
+
+    /// Restore what `save_state` wrote, into the same kind of device.
+    fn load_state(&mut self, _state: &mut StateReader) -> Result<()> {
+        Ok(())
+    }
+
+    /// What `save_state` covers, for serde: see the `serde` feature.
+    #[cfg(feature = "serde")]
+    fn serialize_state(&self) -> &dyn erased_serde::Serialize {
+        &()
+    }
+
+    /// Load what `serialize_state` wrote, into the same kind of device.
+    #[cfg(feature = "serde")]
+    fn deserialize_state<'de>(
+        &mut self,
+        deserializer: &mut dyn erased_serde::Deserializer<'de>,
+    ) -> std::result::Result<(), erased_serde::Error> {
+        serde::Deserialize::deserialize(deserializer)
+    }
+}
+
+/// Cloning of boxed devices, implemented for every device that is `Clone`.
+pub trait ControllerClone {
+    fn clone_controller(&self) -> Box<dyn Controller>;
+}
+
+impl<T: Controller + Clone + 'static> ControllerClone for T {
+    fn clone_controller(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Controller> {
+    fn clone(&self) -> Box<dyn Controller> {
+        self.clone_controller()
+    }
+}
+
+impl fmt::Debug for dyn Controller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Controller")
+    }
+}
+
+/// The standard controller: a shift register loaded from the buttons while
+/// strobe is high and shifted out one bit per read.
+///
+/// Buttons set to turbo alternate between pressed and released while held,
+/// toggling every few frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Joypad {
+    buttons: u8,
+    shift: u8,
+    strobe: bool,
+    turbo: u8,
+    /// Frames between turbo toggles
+    turbo_rate: u8,
+    turbo_frames: u8,
+    /// Whether turbo buttons currently read as released
+    turbo_released: bool,
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            buttons: 0,
+            shift: 0,
+            strobe: false,
+            turbo: 0,
+            turbo_rate: 1,
+            turbo_frames: 0,
+            turbo_released: false,
+        }
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.buttons & button.mask() != 0
+    }
+
+    /// Make `button` toggle automatically while held.
+    pub fn set_turbo(&mut self, button: Button, enabled: bool) {
+        if enabled {
+            self.turbo |= button.mask();
+        } else {
+            self.turbo &= !button.mask();
+        }
+    }
+
+    /// Toggle turbo buttons every `frames` frames.
+    pub fn set_turbo_rate(&mut self, frames: u8) {
+        assert!(frames > 0, "turbo rate must be at least one frame");
+        self.turbo_rate = frames;
+        self.turbo_frames = self.turbo_frames.min(frames - 1);
+    }
+
+    /// The buttons as the console sees them this frame.
+    fn state(&self) -> u8 {
+        if self.turbo_released {
+            self.buttons & !self.turbo
+        } else {
+            self.buttons
+        }
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Joypad {
+        Joypad::new()
+    }
+}
+
+impl Controller for Joypad {
+    fn write(&mut self, data: u8) {
+        self.strobe = data & 1 != 0;
+        if self.strobe {
+            self.shift = self.state();
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        if self.strobe {
+            return self.state() & 1;
+        }
+        let bit = self.shift & 1;
+        // Official controllers report 1 after the eighth read
+        self.shift = (self.shift >> 1) | 0x80;
+        bit
+    }
+
+    fn peek(&self) -> u8 {
+        if self.strobe {
+            self.state() & 1
+        } else {
+            self.shift & 1
+        }
+    }
+
+    fn set_button(&mut self, button: Button, pressed: bool) {
+        if pressed {
+            self.buttons |= button.mask();
+        } else {
+            self.buttons &= !button.mask();
+        }
+        if self.strobe {
+            self.shift = self.state();
+        }
+    }
+
+    fn advance_frame(&mut self) {
+        self.turbo_frames += 1;
+        if self.turbo_frames >= self.turbo_rate {
+            self.turbo_frames = 0;
+            self.turbo_released = !self.turbo_released;
+            if self.strobe {
+                self.shift = self.state();
+            }
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write(&self.buttons);
+        state.write(&self.shift);
+        state.write(&self.strobe);
+        state.write(&self.turbo_frames);
+        state.write(&self.turbo_released);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.buttons = state.read()?;
+        self.shift = state.read()?;
+        self.strobe = state.read()?;
+        self.turbo_frames = state.read::<u8>()?.min(self.turbo_rate - 1);
+        self.turbo_released = state.read()?;
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    serde_state_methods!();
+}
+
+#[cfg(feature = "serde")]
+serde_state!(Joypad {
+    buttons,
+    shift,
+    strobe,
+    turbo_frames,
+    turbo_released,
+});
+
+/// One half of a Four Score adapter, which puts two joypads behind each
+/// port.
+///
+/// After strobe, a port reports its first pad, then its second, then an
+/// eight-bit signature that tells games the adapter is present.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FourScore {
+    pads: [Joypad; 2],
+    /// Reported from bit 0 after the two pads
+    signature: u8,
+    reads: u8,
+    strobe: bool,
+}
+
+impl FourScore {
+    /// The half plugged into `port` 0 (players 1 and 3) or 1 (players 2
+    /// and 4).
+    pub fn new(port: usize) -> FourScore {
+        let signature = match port {
+            0 => 0x08,
+            1 => 0x04,
+            _ => panic!("no controller port {}", port),
+        };
+        FourScore {
+            pads: [Joypad::new(); 2],
+            signature,
+            reads: 0,
+            strobe: false,
+        }
+    }
+}
+
+impl Controller for FourScore {
+    fn write(&mut self, data: u8) {
+        self.strobe = data & 1 != 0;
+        for pad in &mut self.pads {
+            pad.write(data);
+        }
+        self.reads = 0;
+    }
+
+    fn read(&mut self) -> u8 {
+        if self.strobe {
+            return self.pads[0].read();
+        }
+        let bit = match self.reads {
+            0..=7 => self.pads[0].read(),
+            8..=15 => self.pads[1].read(),
+            16..=23 => (self.signature >> (self.reads - 16)) & 1,
+            _ => 1,
+        };
+        self.reads = self.reads.saturating_add(1);
+        bit
+    }
+
+    fn peek(&self) -> u8 {
+        if self.strobe {
+            return self.pads[0].peek();
+        }
+        match self.reads {
+            0..=7 => self.pads[0].peek(),
+            8..=15 => self.pads[1].peek(),
+            16..=23 => (self.signature >> (self.reads - 16)) & 1,
+            _ => 1,
+        }
+    }
+
+    fn set_button(&mut self, button: Button, pressed: bool) {
+        self.pads[0].set_button(button, pressed);
+    }
+
+    fn set_pad_button(&mut self, pad: usize, button: Button, pressed: bool) {
+        if let Some(pad) = self.pads.get_mut(pad) {
+            pad.set_button(button, pressed);
+        }
+    }
+
+    fn advance_frame(&mut self) {
+        for pad in &mut self.pads {
+            pad.advance_frame();
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        for pad in &self.pads {
+            pad.save_state(state);
+        }
+        state.write(&self.reads);
+        state.write(&self.strobe);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        for pad in &mut self.pads {
+            pad.load_state(state)?;
+        }
+        self.reads = state.read()?;
+        self.strobe = state.read()?;
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    serde_state_methods!();
+}
+
+#[cfg(feature = "serde")]
+serde_state!(FourScore {
+    pads as seeds,
+    reads,
+    strobe,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(joypad: &mut Joypad, reads: usize) -> Vec<u8> {
+        (0..reads).map(|_| joypad.read()).collect()
+    }
+
+    #[test]
+    fn shifts_out_buttons_after_strobe() {
+        let mut joypad = Joypad::new();
+        joypad.set_button(Button::A, true);
+        joypad.set_button(Button::Start, true);
+        joypad.set_button(Button::Right, true);
+        joypad.write(1);
+        joypad.write(0);
+        assert_eq!(read_all(&mut joypad, 10), [1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn strobe_high_keeps_reporting_a() {
+        let mut joypad = Joypad::new();
+        joypad.write(1);
+        assert_eq!(read_all(&mut joypad, 3), [0, 0, 0]);
+        joypad.set_button(Button::A, true);
+        assert_eq!(read_all(&mut joypad, 3), [1, 1, 1]);
+    }
+
+    #[test]
+    fn turbo_buttons_toggle_every_few_frames() {
+        let mut joypad = Joypad::new();
+        joypad.set_turbo(Button::A, true);
+        joypad.set_turbo_rate(2);
+        joypad.set_button(Button::A, true);
+        joypad.set_button(Button::B, true);
+        let mut frames = Vec::new();
+        for _ in 0..6 {
+            joypad.write(1);
+            joypad.write(0);
+            frames.push(read_all(&mut joypad, 2));
+            joypad.advance_frame();
+        }
+        assert_eq!(frames, [[1, 1], [1, 1], [0, 1], [0, 1], [1, 1], [1, 1]]);
+        assert!(joypad.is_pressed(Button::A));
+    }
+
+    #[test]
+    fn four_score_reports_both_pads_and_signature() {
+        let mut port_1 = FourScore::new(0);
+        let mut port_2 = FourScore::new(1);
+        port_1.set_pad_button(0, Button::A, true);
+        port_1.set_pad_button(1, Button::B, true);
+        port_2.set_pad_button(1, Button::Right, true);
+        for port in [&mut port_1, &mut port_2] {
+            port.write(1);
+            port.write(0);
+        }
+        let read = |port: &mut FourScore| (0..26).map(|_| port.read()).collect::<Vec<_>>();
+        #[rustfmt::skip]
+        assert_eq!(read(&mut port_1), [
+            1, 0, 0, 0, 0, 0, 0, 0,
+            0, 1, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 1, 0, 0, 0, 0,
+            1, 1,
+        ]);
+        #[rustfmt::skip]
+        assert_eq!(read(&mut port_2), [
+            0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 1,
+            0, 0, 1, 0, 0, 0, 0, 0,
+            1, 1,
+        ]);
+    }
+
+    #[test]
+    fn buttons_are_latched_at_strobe() {
+        let mut joypad = Joypad::new();
+        joypad.write(1);
+        joypad.write(0);
+        joypad.set_button(Button::B, true);
+        assert_eq!(read_all(&mut joypad, 2), [0, 0]);
+        assert!(joypad.is_pressed(Button::B));
+    }
+}
@@ -30,6 +30,7 @@ pub enum Instruction {
     Inc,
     Inx,
     Iny,
+    Jam,
     Jmp,
     Jsr,
     Lda,
@@ -102,6 +103,7 @@ impl Instruction {
             Instruction::Inc => "INC",
             Instruction::Inx => "INX",
             Instruction::Iny => "INY",
+            Instruction::Jam => "JAM",
             Instruction::Jmp => "JMP",
             Instruction::Jsr => "JSR",
             Instruction::Lda => "LDA",
@@ -141,8 +143,8 @@ const INSTRUCTIONS: [Instruction; 256] = [
     Instruction::Brk,
     // 01 ORA IndirectX
     Instruction::Ora,
-    // 02
-    Instruction::Unimplemented,
+    // 02 JAM Implied, unofficial
+    Instruction::Jam,
     // 03
     Instruction::Unimplemented,
     // 04
@@ -173,8 +175,8 @@ const INSTRUCTIONS: [Instruction; 256] = [
     Instruction::Bpl,
     // 11 ORA IndirectY
     Instruction::Ora,
-    // 12
-    Instruction::Unimplemented,
+    // 12 JAM Implied, unofficial
+    Instruction::Jam,
     // 13
     Instruction::Unimplemented,
     // 14
@@ -189,8 +191,8 @@ const INSTRUCTIONS: [Instruction; 256] = [
     Instruction::Clc,
     // 19 ORA AbsoluteY
     Instruction::Ora,
-    // 1A
-    Instruction::Unimplemented,
+    // 1A NOP Implied, unofficial
+    Instruction::Nop,
     // 1B
     Instruction::Unimplemented,
     // 1C
@@ -205,8 +207,8 @@ const INSTRUCTIONS: [Instruction; 256] = [
     Instruction::Jsr,
     // 21 AND IndirectX
     Instruction::And,
-    // 22
-    Instruction::Unimplemented,
+    // 22 JAM Implied, unofficial
+    Instruction::Jam,
     // 23
     Instruction::Unimplemented,
     // 24 BIT ZeroPage
@@ -237,8 +239,8 @@ const INSTRUCTIONS: [Instruction; 256] = [
     Instruction::Bmi,
     // 31 AND IndirectY
     Instruction::And,
-    // 32
-    Instruction::Unimplemented,
+    // 32 JAM Implied, unofficial
+    Instruction::Jam,
     // 33
     Instruction::Unimplemented,
     // 34
@@ -253,8 +255,8 @@ const INSTRUCTIONS: [Instruction; 256] = [
     Instruction::Sec,
     // 39 AND AbsoluteY
     Instruction::And,
-    // 3A
-    Instruction::Unimplemented,
+    // 3A NOP Implied, unofficial
+    Instruction::Nop,
     // 3B
     Instruction::Unimplemented,
     // 3C
@@ -269,8 +271,8 @@ const INSTRUCTIONS: [Instruction; 256] = [
     Instruction::Rti,
     // 41 EOR IndirectX
     Instruction::Eor,
-    // 42
-    Instruction::Unimplemented,
+    // 42 JAM Implied, unofficial
+    Instruction::Jam,
     // 43
     Instruction::Unimplemented,
     // 44
@@ -301,8 +303,8 @@ const INSTRUCTIONS: [Instruction; 256] = [
     Instruction::Bvc,
     // 51 EOR IndirectY
     Instruction::Eor,
-    // 52
-    Instruction::Unimplemented,
+    // 52 JAM Implied, unofficial
+    Instruction::Jam,
     // 53
     Instruction::Unimplemented,
     // 54
@@ -317,8 +319,8 @@ const INSTRUCTIONS: [Instruction; 256] = [
     Instruction::Cli,
     // 59 EOR AbsoluteY
     Instruction::Eor,
-    // 5A
-    Instruction::Unimplemented,
+    // 5A NOP Implied, unofficial
+    Instruction::Nop,
     // 5B
     Instruction::Unimplemented,
     // 5C
@@ -333,8 +335,8 @@ const INSTRUCTIONS: [Instruction; 256] = [
     Instruction::Rts,
     // 61 ADC IndirectX
     Instruction::Adc,
-    // 62
-    Instruction::Unimplemented,
+    // 62 JAM Implied, unofficial
+    Instruction::Jam,
     // 63
     Instruction::Unimplemented,
     // 64
@@ -365,8 +367,8 @@ const INSTRUCTIONS: [Instruction; 256] = [
     Instruction::Bvs,
     // 71 ADC IndirectY
     Instruction::Adc,
-    // 72
-    Instruction::Unimplemented,
+    // 72 JAM Implied, unofficial
+    Instruction::Jam,
     // 73
     Instruction::Unimplemented,
     // 74
@@ -381,8 +383,8 @@ const INSTRUCTIONS: [Instruction; 256] = [
     Instruction::Sei,
     // 79 ADC AbsoluteY
     Instruction::Adc,
-    // 7A
-    Instruction::Unimplemented,
+    // 7A NOP Implied, unofficial
+    Instruction::Nop,
     // 7B
     Instruction::Unimplemented,
     // 7C
@@ -429,8 +431,8 @@ const INSTRUCTIONS: [Instruction; 256] = [
     Instruction::Bcc,
     // 91 STA IndirectY
     Instruction::Sta,
-    // 92
-    Instruction::Unimplemented,
+    // 92 JAM Implied, unofficial
+    Instruction::Jam,
     // 93
     Instruction::Unimplemented,
     // 94 STY ZeroPageX
@@ -443,8 +445,8 @@ const INSTRUCTIONS: [Instruction; 256] = [
     Instruction::Unimplemented,
     // 98 TYA Implied
     Instruction::Tya,
-    // 99
-    Instruction::Unimplemented,
+    // 99 STA AbsoluteY
+    Instruction::Sta,
     // 9A TXS Implied
     Instruction::Txs,
     // 9B
@@ -493,8 +495,8 @@ const INSTRUCTIONS: [Instruction; 256] = [
     Instruction::Bcs,
     // B1 LDA IndirectY
     Instruction::Lda,
-    // B2
-    Instruction::Unimplemented,
+    // B2 JAM Implied, unofficial
+    Instruction::Jam,
     // B3
     Instruction::Unimplemented,
     // B4 LDY ZeroPageX
@@ -557,8 +559,8 @@ const INSTRUCTIONS: [Instruction; 256] = [
     Instruction::Bne,
     // D1 CMP IndirectY
     Instruction::Cmp,
-    // D2
-    Instruction::Unimplemented,
+    // D2 JAM Implied, unofficial
+    Instruction::Jam,
     // D3
     Instruction::Unimplemented,
     // D4
@@ -573,8 +575,8 @@ const INSTRUCTIONS: [Instruction; 256] = [
     Instruction::Cld,
     // D9 CMP AbsoluteY
     Instruction::Cmp,
-    // DA
-    Instruction::Unimplemented,
+    // DA NOP Implied, unofficial
+    Instruction::Nop,
     // DB
     Instruction::Unimplemented,
     // DC
@@ -621,8 +623,8 @@ const INSTRUCTIONS: [Instruction; 256] = [
     Instruction::Beq,
     // F1 SBC IndirectY
     Instruction::Sbc,
-    // F2
-    Instruction::Unimplemented,
+    // F2 JAM Implied, unofficial
+    Instruction::Jam,
     // F3
     Instruction::Unimplemented,
     // F4
@@ -637,8 +639,8 @@ const INSTRUCTIONS: [Instruction; 256] = [
     Instruction::Sed,
     // F9 SBC AbsoluteY
     Instruction::Sbc,
-    // FA
-    Instruction::Unimplemented,
+    // FA NOP Implied, unofficial
+    Instruction::Nop,
     // FB
     Instruction::Unimplemented,
     // FC
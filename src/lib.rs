@@ -4,14 +4,34 @@ extern crate bitflags;
 extern crate derive_more;
 
 pub mod addressing_mode;
+pub mod apu;
+pub mod archive;
 pub mod bus;
+pub mod capabilities;
+pub mod cartridge;
+pub mod cheats;
+pub mod checksum;
+pub mod clock;
 pub mod console;
 pub mod cpu;
+pub mod database;
 pub mod debugger;
 pub mod ines;
+pub mod input;
 pub mod instructions;
+pub mod link;
 pub mod mapper;
 pub mod mappers;
+pub mod movie;
+pub mod nsf;
+pub mod palette;
 pub mod ppu;
+pub mod prelude;
+pub mod region;
+pub mod rom;
+pub mod scheduler;
+pub mod state;
 
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+pub use capabilities::capabilities;
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
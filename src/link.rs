@@ -0,0 +1,150 @@
+use crate::console::Console;
+use crate::cpu::Step;
+use crate::input::Controller;
+use std::sync::{Arc, Mutex};
+
+/// Two consoles run in lockstep on the master clock.
+///
+/// Whichever console is behind steps next, so neither gets more than one
+/// instruction ahead of the other and devices shared between them, such as a
+/// [`LinkCable`], see a consistent order of events.
+///
+/// A clone gets copies of both consoles, but a cable between them stays
+/// shared with the original, see [`LinkCable`].
+#[derive(Debug, Clone)]
+pub struct Link {
+    consoles: [Console; 2],
+}
+
+impl Link {
+    pub fn new(first: Console, second: Console) -> Link {
+        Link {
+            consoles: [first, second],
+        }
+    }
+
+    pub fn console(&self, index: usize) -> &Console {
+        &self.consoles[index]
+    }
+
+    pub fn console_mut(&mut self, index: usize) -> &mut Console {
+        &mut self.consoles[index]
+    }
+
+    pub fn into_consoles(self) -> [Console; 2] {
+        self.consoles
+    }
+
+    /// Step the console that is behind, returning its index and step.
+    /// Ties go to the first console.
+    pub fn step(&mut self) -> (usize, Step) {
+        let [first, second] = &self.consoles;
+        let index = if second.clock().master_cycle() < first.clock().master_cycle() {
+            1
+        } else {
+            0
+        };
+        (index, self.consoles[index].step())
+    }
+
+    /// Step until both consoles reach `master_cycle`.
+    pub fn run_until(&mut self, master_cycle: u64) {
+        while self
+            .consoles
+            .iter()
+            .any(|console| console.clock().master_cycle() < master_cycle)
+        {
+            self.step();
+        }
+    }
+}
+
+/// One end of a cable joining the controller ports of two consoles.
+///
+/// Each end latches the OUT0-OUT2 bits written to $4016 on its console, and
+/// reads of its port return the bits latched by the other end.
+///
+/// Clones stay on the same cable: a clone of either end, or of a console or
+/// [`Link`] holding one, reads what the other end latches and latches bits
+/// the other end reads, just as the original does. To run a copy on its
+/// own, give its consoles a new [`LinkCable::pair`].
+#[derive(Debug, Clone)]
+pub struct LinkCable {
+    lines: Arc<Mutex<[u8; 2]>>,
+    end: usize,
+}
+
+impl LinkCable {
+    /// Both ends of a new cable.
+    pub fn pair() -> (LinkCable, LinkCable) {
+        let lines = Arc::new(Mutex::new([0; 2]));
+        (
+            LinkCable {
+                lines: Arc::clone(&lines),
+                end: 0,
+            },
+            LinkCable { lines, end: 1 },
+        )
+    }
+}
+
+impl Controller for LinkCable {
+    fn write(&mut self, data: u8) {
+        self.lines.lock().unwrap()[self.end] = data & 0x07;
+    }
+
+    fn read(&mut self) -> u8 {
+        self.lines.lock().unwrap()[1 - self.end]
+    }
+
+    fn peek(&self) -> u8 {
+        self.lines.lock().unwrap()[1 - self.end]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cable_carries_writes_between_consoles() {
+        #[rustfmt::skip]
+        let sender = Console::load_raw_program(&[
+            0xa9, 0x05,       // LDA #$05
+            0x8d, 0x16, 0x40, // STA $4016
+            0x4c, 0x05, 0x06, // JMP $0605
+        ], 0x0600, 0x0600).unwrap();
+        #[rustfmt::skip]
+        let receiver = Console::load_raw_program(&[
+            0xad, 0x17, 0x40, // LDA $4017
+            0x8d, 0x00, 0x02, // STA $0200
+            0x4c, 0x00, 0x06, // JMP $0600
+        ], 0x0600, 0x0600).unwrap();
+        let mut link = Link::new(sender, receiver);
+        let (first, second) = LinkCable::pair();
+        link.console_mut(0).set_controller(0, first);
+        link.console_mut(1).set_controller(1, second);
+        for console in 0..2 {
+            link.console_mut(console).soft_reset();
+        }
+
+        link.run_until(1000);
+        assert!(link.console(0).clock().master_cycle() >= 1000);
+        assert!(link.console(1).clock().master_cycle() >= 1000);
+        let difference = link.console(0).clock().master_cycle() as i64
+            - link.console(1).clock().master_cycle() as i64;
+        assert!(difference.abs() < 7 * 12);
+        assert_eq!(link.console_mut(1).read_range(0x0200..=0x0200), [0x45]);
+    }
+
+    #[test]
+    fn clones_stay_linked() {
+        let (mut first, mut second) = LinkCable::pair();
+        let mut copy = first.clone();
+        second.write(0x03);
+        assert_eq!(first.read(), 0x03);
+        assert_eq!(copy.read(), 0x03);
+        copy.write(0x06);
+        assert_eq!(second.read(), 0x06);
+    }
+}
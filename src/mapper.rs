@@ -1,48 +1,516 @@
-use crate::ines;
+use crate::capabilities::Capabilities;
+use crate::cartridge::Cartridge;
+use crate::ines::Mirroring;
+use crate::mappers::axrom::Axrom;
+use crate::mappers::bnrom::Bnrom;
+use crate::mappers::cnrom::Cnrom;
+use crate::mappers::gxrom::Gxrom;
+use crate::mappers::mmc2::Mmc2;
+use crate::mappers::namco163::Namco163;
 use crate::mappers::nrom::Nrom;
+use crate::mappers::unrom512::Unrom512;
 use crate::mappers::uxrom::Uxrom;
+use crate::mappers::vrc6::Vrc6;
+use crate::rom::Rom;
+use crate::state::{StateReader, StateWriter};
 use crate::Result;
 use core::fmt;
-use std::fs;
+use std::ops;
 use std::path::Path;
 
-pub trait Mapper {
+/// What serves a 1 kB window of PPU memory, see [`Mapper::ppu_window`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PpuWindow {
+    /// A page of the console's 2 kB of nametable RAM, or of the extra 2 kB
+    /// for four-screen mirroring
+    Ciram(usize),
+    /// The cartridge, through `ppu_read` and `ppu_write`
+    Cartridge,
+}
+
+/// The memory behind the pattern tables: the cartridge's CHR ROM, or RAM
+/// on boards that have none.
+#[derive(Debug, Clone)]
+pub enum Chr {
+    Rom(Rom),
+    Ram(Vec<u8>),
+}
+
+impl Chr {
+    /// CHR RAM on boards whose header gives no size, 8 kB.
+    pub const RAM_SIZE: usize = 8 * 1024;
+
+    /// `rom`, or `ram_size` bytes of RAM if it is empty. A size of 0
+    /// means [`Chr::RAM_SIZE`].
+    pub fn new(rom: Rom, ram_size: usize) -> Chr {
+        if rom.is_empty() {
+            let ram_size = if ram_size == 0 {
+                Chr::RAM_SIZE
+            } else {
+                ram_size
+            };
+            Chr::Ram(vec![0; ram_size])
+        } else {
+            Chr::Rom(rom)
+        }
+    }
+
+    /// Store `data` at `index` if this is RAM. Writes to ROM are dropped.
+    pub fn write(&mut self, index: usize, data: u8) {
+        if let Chr::Ram(ram) = self {
+            ram[index] = data;
+        }
+    }
+
+    /// The RAM, or nothing for ROM, which savestates leave out.
+    pub fn ram(&self) -> &[u8] {
+        match self {
+            Chr::Rom(_) => &[],
+            Chr::Ram(ram) => ram,
+        }
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        match self {
+            Chr::Rom(_) => &mut [],
+            Chr::Ram(ram) => ram,
+        }
+    }
+
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        if let Chr::Ram(ram) = self {
+            state.write(ram);
+        }
+    }
+
+    /// Restore what `save_state` wrote. Version 1 states have no CHR RAM.
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        match self {
+            Chr::Ram(ram) if state.version() >= 2 => state.read_into(ram),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl ops::Deref for Chr {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Chr::Rom(rom) => rom,
+            Chr::Ram(ram) => ram,
+        }
+    }
+}
+
+/// ROM, or [`Chr::RAM_SIZE`] bytes of RAM if it is empty.
+impl From<Rom> for Chr {
+    fn from(rom: Rom) -> Chr {
+        Chr::new(rom, 0)
+    }
+}
+
+impl From<Vec<u8>> for Chr {
+    fn from(bytes: Vec<u8>) -> Chr {
+        Chr::from(Rom::from(bytes))
+    }
+}
+
+/// A cartridge board. Consoles own theirs outright, so boards must be
+/// `Send` and clone into an independent copy, see [`MapperClone`].
+pub trait Mapper: MapperClone + Send {
     fn id(&self) -> u8;
-    fn cpu_read(&mut self, address: u16) -> u8;
+
+    /// What the CPU would read at `address`, without the side effects a
+    /// read has on some registers. For debuggers, see `Console::peek`.
+    ///
+    /// `None` where the board leaves the data bus alone, so the CPU reads
+    /// open bus: whatever was last on it.
+    fn cpu_peek(&self, address: u16) -> Option<u8>;
+
+    /// By default `cpu_peek`. Boards with registers that change when read
+    /// override this.
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        self.cpu_peek(address)
+    }
+
+    /// Change the RAM `cpu_peek` reads at `address`, leaving any register
+    /// there alone, for debuggers. Returns whether there is RAM to change.
+    /// Boards without PRG RAM keep the default, which does nothing.
+    fn cpu_poke(&mut self, _address: u16, _data: u8) -> bool {
+        false
+    }
+
     fn cpu_write(&mut self, address: u16, _data: u8);
-    fn ppu_read(&mut self, address: u16) -> u8;
+
+    /// Bulk version of `cpu_read`, see `Bus::read_into`. Bytes the board
+    /// leaves alone keep what `buffer` held.
+    fn cpu_read_into(&mut self, address: u16, buffer: &mut [u8]) {
+        for (offset, data) in buffer.iter_mut().enumerate() {
+            if let Some(read) = self.cpu_read(address.wrapping_add(offset as u16)) {
+                *data = read;
+            }
+        }
+    }
+
+    /// What the PPU would read at `address`, for debuggers. No board here
+    /// changes when the PPU reads it; those that watch fetches do it in
+    /// `ppu_accessed`.
+    fn ppu_peek(&self, address: u16) -> u8;
+
+    /// By default `ppu_peek`.
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.ppu_peek(address)
+    }
+
     fn ppu_write(&mut self, address: u16, _data: u8);
+
+    /// Called after every PPU bus access, including nametable accesses the
+    /// mapper does not serve, so it can watch what the PPU fetches.
+    fn ppu_accessed(&mut self, _address: u16) {}
+
+    /// Nametable mirroring selected by the mapper, or `None` to use the
+    /// mirroring from the header.
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+
+    /// Select `mirroring` as a write to the board's mirroring register
+    /// would. Boards without one, or that cannot select `mirroring`, keep
+    /// what they have.
+    fn set_mirroring(&mut self, _mirroring: Mirroring) {}
+
+    /// What serves the 1 kB of PPU memory holding `address`, or `None` for
+    /// the usual: the cartridge for the pattern tables and nametable RAM
+    /// arranged by `mirroring` for the nametables.
+    fn ppu_window(&self, _address: u16) -> Option<PpuWindow> {
+        None
+    }
+
+    /// Whether the cartridge is asserting IRQ.
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    /// CPU cycles that can pass before `irq_pending` changes on its own,
+    /// at least. The console clocks the mapper in batches that stop short
+    /// of this, and catches it up before any access to $4020-$7FFF or
+    /// write to the cartridge.
+    ///
+    /// The default, 0, has it clocked after every instruction. Boards
+    /// without an IRQ return `u64::MAX`.
+    fn cycles_until_irq(&self) -> u64 {
+        0
+    }
+
+    /// Memory the cartridge keeps with the power off, such as battery-backed
+    /// RAM or flash, for saving between sessions.
+    fn battery_ram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Restore what `battery_ram` returned in an earlier session. Data of
+    /// the wrong size is ignored.
+    fn load_battery_ram(&mut self, _data: &[u8]) {}
+
+    /// Put a dump's 512-byte trainer in PRG RAM at $7000-$71FF, as the
+    /// copiers these dumps came from did. By default it is written through
+    /// `cpu_write`, which is enough for boards whose PRG RAM is always
+    /// writable. Boards without PRG RAM drop it.
+    fn load_trainer(&mut self, trainer: &[u8]) {
+        for (offset, &data) in trainer.iter().enumerate() {
+            self.cpu_write(0x7000 + offset as u16, data);
+        }
+    }
+
+    /// Write the registers and RAM that change as the game runs, for
+    /// savestates. ROM and settings fixed at load time are left out.
+    fn save_state(&self, _state: &mut StateWriter) {}
+
+    /// Restore what `save_state` wrote, into a mapper built from the same
+    /// cartridge.
+    fn load_state(&mut self, _state: &mut StateReader) -> Result<()> {
+        Ok(())
+    }
+
+    /// What `save_state` covers, for serde: see the `serde` feature.
+    #[cfg(feature = "serde")]
+    fn serialize_state(&self) -> &dyn erased_serde::Serialize {
+        &()
+    }
+
+    /// Load what `serialize_state` wrote, into a mapper built from the same
+    /// cartridge.
+    #[cfg(feature = "serde")]
+    fn deserialize_state<'de>(
+        &mut self,
+        deserializer: &mut dyn erased_serde::Deserializer<'de>,
+    ) -> std::result::Result<(), erased_serde::Error> {
+        serde::Deserialize::deserialize(deserializer)
+    }
+
+    /// Advance by one CPU cycle, for mappers with timers or sound.
+    fn cpu_clock(&mut self) {}
+
+    /// Called when PPU address line A12 rises, which happens once a
+    /// scanline when the background and sprites use different pattern
+    /// tables. Scanline counters like the MMC3's count these.
+    fn ppu_a12_clock(&mut self) {}
+
+    /// Called when the console's reset button is pressed. Most mappers keep
+    /// their registers.
+    fn reset(&mut self) {}
+
+    /// The cartridge's expansion audio on the scale of
+    /// [`Apu::output`](crate::apu::Apu::output), mixed in by
+    /// [`Console::audio_output`](crate::console::Console::audio_output).
+    fn audio(&self) -> f32 {
+        0.0
+    }
+}
+
+/// Builds a board from a cartridge whose header names it.
+type Constructor = fn(&Cartridge) -> Box<dyn Mapper>;
+
+/// The boards `Mapper::from_cartridge` can build, by iNES mapper number.
+const BOARDS: &[(u8, Constructor)] = &[
+    (0, nrom),
+    (2, uxrom),
+    (3, cnrom),
+    (7, axrom),
+    (9, mmc2),
+    (10, mmc2),
+    (11, gxrom),
+    (19, namco163),
+    (24, vrc6),
+    (26, vrc6),
+    (30, unrom512),
+    (34, bnrom),
+    (66, gxrom),
+    (94, uxrom),
+    (180, uxrom),
+];
+
+/// iNES mapper numbers `Mapper::from_cartridge` can load.
+pub fn supported() -> impl Iterator<Item = u8> {
+    BOARDS.iter().map(|&(id, _)| id)
+}
+
+/// Add the mappers, expansion audio and accuracy settings the boards here
+/// offer.
+pub(crate) fn register_capabilities(capabilities: &mut Capabilities) {
+    capabilities.mappers.extend(supported());
+    capabilities.audio_channels.extend(["VRC6", "Namco 163"]);
+    capabilities.accuracy.push("bus conflicts");
+}
+
+// Submapper 2 of the discrete logic boards has bus conflicts
+fn bus_conflicts(cartridge: &Cartridge) -> bool {
+    cartridge.header().submapper_id == 2
+}
+
+fn nrom(cartridge: &Cartridge) -> Box<dyn Mapper> {
+    Box::new(Nrom::new(
+        cartridge.prg_rom().clone(),
+        cartridge.chr(),
+        cartridge.prg_ram(),
+    ))
+}
+
+fn uxrom(cartridge: &Cartridge) -> Box<dyn Mapper> {
+    let mut mapper = Uxrom::new(cartridge.prg_rom().clone(), cartridge.chr());
+    mapper.set_bus_conflicts(bus_conflicts(cartridge));
+    Box::new(mapper)
+}
+
+fn cnrom(cartridge: &Cartridge) -> Box<dyn Mapper> {
+    let mut mapper = Cnrom::new(cartridge.prg_rom().clone(), cartridge.chr());
+    mapper.set_bus_conflicts(bus_conflicts(cartridge));
+    Box::new(mapper)
+}
+
+fn axrom(cartridge: &Cartridge) -> Box<dyn Mapper> {
+    let mut mapper = Axrom::new(cartridge.prg_rom().clone(), cartridge.chr_rom().clone());
+    mapper.set_bus_conflicts(bus_conflicts(cartridge));
+    Box::new(mapper)
+}
+
+fn mmc2(cartridge: &Cartridge) -> Box<dyn Mapper> {
+    Box::new(Mmc2::new(
+        cartridge.header().mapper_id as u8,
+        cartridge.prg_rom().clone(),
+        cartridge.chr(),
+        cartridge.prg_ram(),
+    ))
+}
+
+fn gxrom(cartridge: &Cartridge) -> Box<dyn Mapper> {
+    Box::new(Gxrom::new(
+        cartridge.header().mapper_id as u8,
+        cartridge.prg_rom().clone(),
+        cartridge.chr(),
+    ))
+}
+
+fn namco163(cartridge: &Cartridge) -> Box<dyn Mapper> {
+    Box::new(Namco163::new(
+        cartridge.prg_rom().clone(),
+        cartridge.chr(),
+        cartridge.prg_ram(),
+    ))
+}
+
+fn vrc6(cartridge: &Cartridge) -> Box<dyn Mapper> {
+    Box::new(Vrc6::new(
+        cartridge.header().mapper_id as u8,
+        cartridge.prg_rom().clone(),
+        cartridge.chr(),
+        cartridge.prg_ram(),
+    ))
+}
+
+fn unrom512(cartridge: &Cartridge) -> Box<dyn Mapper> {
+    // The four-screen bit without the vertical bit means the bank register
+    // picks the nametable
+    let one_screen = cartridge.header_bytes()[6] & 0x09 == 0x08;
+    Box::new(Unrom512::new(
+        cartridge.prg_rom().clone(),
+        one_screen,
+        cartridge.header().has_battery,
+    ))
+}
+
+fn bnrom(cartridge: &Cartridge) -> Box<dyn Mapper> {
+    Box::new(Bnrom::new(
+        cartridge.prg_rom().clone(),
+        cartridge.chr_rom().clone(),
+        cartridge.prg_ram(),
+    ))
 }
 
 impl dyn Mapper {
     pub fn from_file(path: impl AsRef<Path>) -> Result<Box<dyn Mapper>> {
-        let bytes = fs::read(path)?;
-        Self::from_bytes(bytes)
+        Self::from_cartridge(&Cartridge::from_file(path)?)
     }
 
-    pub fn from_bytes(bytes: impl Into<Vec<u8>>) -> Result<Box<dyn Mapper>> {
-        let bytes = bytes.into();
-        let (header, bytes) = bytes.split_at(16);
-        let header = ines::parse_header(header).unwrap();
-        let (_trainer, file) = if header.has_trainer {
-            bytes.split_at(512)
-        } else {
-            bytes.split_at(0)
-        };
-        let (prg_rom, file) = file.split_at(header.prg_rom_size);
-        let (chr_rom, _) = file.split_at(header.chr_rom_size);
-
-        let mapper: Box<dyn Mapper> = match header.mapper_id {
-            0 => Box::new(Nrom::new(prg_rom, chr_rom)),
-            2 | 94 | 180 => Box::new(Uxrom::new(prg_rom, chr_rom)),
-            _ => unimplemented!(),
-        };
+    /// Build the mapper for an iNES file, see [`Cartridge::from_bytes`].
+    pub fn from_bytes(bytes: impl Into<Rom>) -> Result<Box<dyn Mapper>> {
+        Self::from_cartridge(&Cartridge::from_bytes(bytes)?)
+    }
+
+    /// Build the board the cartridge's header names.
+    ///
+    /// The mapper shares the cartridge's PRG and CHR ROM rather than
+    /// copying them.
+    pub fn from_cartridge(cartridge: &Cartridge) -> Result<Box<dyn Mapper>> {
+        let id = cartridge.header().mapper_id;
+        let constructor = BOARDS
+            .iter()
+            .find(|&&(board, _)| board as u16 == id)
+            .map(|&(_, constructor)| constructor)
+            .ok_or_else(|| format!("mapper {} is not supported", id))?;
+        let mut mapper = constructor(cartridge);
+        if let Some(trainer) = cartridge.trainer() {
+            mapper.load_trainer(trainer);
+        }
         Ok(mapper)
     }
 }
 
+/// Drive a bulk read one chunk at a time.
+///
+/// `read_chunk` fills the start of the buffer it is given with the bytes at
+/// the address it is given and returns how many it wrote, which must be at
+/// least one.
+pub(crate) fn read_chunks<F>(address: u16, buffer: &mut [u8], mut read_chunk: F)
+where
+    F: FnMut(u16, &mut [u8]) -> usize,
+{
+    let mut address = address;
+    let mut offset = 0;
+    while offset < buffer.len() {
+        let len = read_chunk(address, &mut buffer[offset..]);
+        offset += len;
+        address = address.wrapping_add(len as u16);
+    }
+}
+
+/// Index into ROM of `rom_len` bytes for `offset` into bank `bank` of
+/// `bank_size` bytes, and the end of that bank.
+///
+/// Bank numbers wrap at the number of banks in the ROM, and ROM smaller than
+/// a bank is mirrored to fill it.
+pub(crate) fn bank_index(
+    rom_len: usize,
+    bank_size: usize,
+    bank: usize,
+    offset: usize,
+) -> (usize, usize) {
+    let bank_size = bank_size.min(rom_len);
+    let banks = rom_len / bank_size;
+    let start = bank % banks * bank_size;
+    (start + offset % bank_size, start + bank_size)
+}
+
+/// Index into PRG RAM of `ram_len` bytes for `address` in $6000-$7FFF, and
+/// the end of its mirror.
+///
+/// RAM smaller than 8 kB is mirrored. Past 8 kB, which none of the boards
+/// here bank, it goes unused.
+pub(crate) fn prg_ram_index(ram_len: usize, address: u16) -> (usize, usize) {
+    bank_index(ram_len, 0x2000, 0, (address - 0x6000) as usize)
+}
+
+/// The number of banks of `bank_size` bytes that [`bank_index`] wraps at in
+/// ROM of `rom_len` bytes.
+pub(crate) fn bank_count(rom_len: usize, bank_size: usize) -> usize {
+    rom_len / bank_size.min(rom_len)
+}
+
+/// Copy as much of `source` as fits into `buffer`, returning the length.
+pub(crate) fn copy_chunk(source: &[u8], buffer: &mut [u8]) -> usize {
+    let len = source.len().min(buffer.len());
+    buffer[..len].copy_from_slice(&source[..len]);
+    len
+}
+
+/// Cloning of boxed mappers, implemented for every mapper that is `Clone`.
+pub trait MapperClone {
+    fn clone_mapper(&self) -> Box<dyn Mapper>;
+}
+
+impl<T: Mapper + Clone + 'static> MapperClone for T {
+    fn clone_mapper(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Mapper> {
+    fn clone(&self) -> Box<dyn Mapper> {
+        self.clone_mapper()
+    }
+}
+
 impl fmt::Debug for dyn Mapper {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Mapper {}", self.id())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supported_mappers_construct() {
+        for id in supported() {
+            let mut bytes = vec![0; 16 + 0x8000 + 0x2000];
+            bytes[..8].copy_from_slice(&[b'N', b'E', b'S', 0x1a, 2, 1, id << 4, id & 0xf0]);
+            assert!(
+                <dyn Mapper>::from_bytes(Rom::from(bytes)).is_ok(),
+                "mapper {}",
+                id
+            );
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use crate::ines::Mirroring;
+use crate::mapper::{self, Mapper};
+use crate::rom::Rom;
+#[cfg(feature = "serde")]
+use crate::state::{serde_state, serde_state_methods};
+use crate::state::{StateReader, StateWriter};
+use crate::Result;
+
+/// Mapper 7: switchable 32 kB PRG banks, CHR RAM and a register that picks
+/// which nametable fills the screen.
+#[derive(Debug, Clone)]
+pub struct Axrom {
+    prg_rom: Rom,
+    chr_ram: Vec<u8>,
+    bank: usize,
+    /// Whether writes are ANDed with the ROM byte at the same address
+    bus_conflicts: bool,
+    mirroring: Mirroring,
+}
+
+impl Axrom {
+    const BANK_SIZE: usize = 32 * 1024; // 32 kB
+
+    /// CHR ROM, if any, is copied into CHR RAM. PRG ROM smaller than 32 kB
+    /// is mirrored to fill the bank.
+    pub fn new<V>(prg_rom: V, chr_rom: V) -> Axrom
+    where
+        V: Into<Rom>,
+    {
+        let chr_rom = chr_rom.into();
+        let mut chr_ram = vec![0; 8 * 1024];
+        let len = chr_rom.len().min(chr_ram.len());
+        chr_ram[..len].copy_from_slice(&chr_rom[..len]);
+        Axrom {
+            prg_rom: prg_rom.into(),
+            chr_ram,
+            bank: 0,
+            bus_conflicts: false,
+            mirroring: Mirroring::SingleScreenLower,
+        }
+    }
+
+    /// See [`Uxrom::set_bus_conflicts`](super::uxrom::Uxrom::set_bus_conflicts).
+    pub fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.bus_conflicts = enabled;
+    }
+
+    fn banks(&self) -> usize {
+        mapper::bank_count(self.prg_rom.len(), Self::BANK_SIZE)
+    }
+
+    /// Index into PRG ROM for $8000-$FFFF, and the end of its bank.
+    fn prg_index(&self, address: u16) -> (usize, usize) {
+        let offset = address as usize % Self::BANK_SIZE;
+        mapper::bank_index(self.prg_rom.len(), Self::BANK_SIZE, self.bank, offset)
+    }
+}
+
+impl Mapper for Axrom {
+    fn id(&self) -> u8 {
+        7
+    }
+
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x8000..=0xffff => Some(self.prg_rom[self.prg_index(address).0]),
+            _ => None,
+        }
+    }
+
+    fn cpu_read_into(&mut self, address: u16, buffer: &mut [u8]) {
+        mapper::read_chunks(address, buffer, |address, buffer| match address {
+            0x8000..=0xffff => {
+                let (index, end) = self.prg_index(address);
+                mapper::copy_chunk(&self.prg_rom[index..end], buffer)
+            }
+            _ => {
+                if let Some(data) = self.cpu_read(address) {
+                    buffer[0] = data;
+                }
+                1
+            }
+        });
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        if let 0x8000..=0xffff = address {
+            let data = if self.bus_conflicts {
+                self.cpu_peek(address).map_or(data, |rom| data & rom)
+            } else {
+                data
+            };
+            self.bank = (data as usize & 0x07) % self.banks();
+            self.mirroring = if data & 0x10 == 0 {
+                Mirroring::SingleScreenLower
+            } else {
+                Mirroring::SingleScreenUpper
+            };
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1fff => self.chr_ram[address as usize],
+            _ => 0,
+        }
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        if let 0x0000..=0x1fff = address {
+            self.chr_ram[address as usize] = data;
+        }
+    }
+
+    fn cycles_until_irq(&self) -> u64 {
+        u64::MAX
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn set_mirroring(&mut self, mirroring: Mirroring) {
+        if let Mirroring::SingleScreenLower | Mirroring::SingleScreenUpper = mirroring {
+            self.mirroring = mirroring;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write(&self.bank);
+        state.write(&self.mirroring);
+        state.write(&self.chr_ram);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        let bank = state.read()?;
+        if bank >= self.banks() {
+            return Err(format!("{} is not a PRG bank", bank).into());
+        }
+        self.bank = bank;
+        self.mirroring = state.read()?;
+        state.read_into(&mut self.chr_ram)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    serde_state_methods!();
+}
+
+#[cfg(feature = "serde")]
+serde_state!(Axrom {
+    bank,
+    mirroring,
+    chr_ram as bytes,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapper() -> Axrom {
+        let prg_rom: Vec<u8> = (0..4)
+            .flat_map(|bank| vec![bank; Axrom::BANK_SIZE])
+            .collect();
+        Axrom::new(prg_rom, Vec::new())
+    }
+
+    #[test]
+    fn prg_banks_and_mirroring() {
+        let mut mapper = mapper();
+        assert_eq!(mapper.cpu_read(0xffff), Some(0x00));
+        assert_eq!(mapper.mirroring(), Some(Mirroring::SingleScreenLower));
+
+        mapper.cpu_write(0x8000, 0x12);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x02));
+        assert_eq!(mapper.cpu_read(0xffff), Some(0x02));
+        assert_eq!(mapper.mirroring(), Some(Mirroring::SingleScreenUpper));
+
+        // Bank numbers wrap at the size of PRG ROM
+        mapper.cpu_write(0x8000, 0x07);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x03));
+        assert_eq!(mapper.mirroring(), Some(Mirroring::SingleScreenLower));
+
+        mapper.ppu_write(0x1234, 0x56);
+        assert_eq!(mapper.ppu_read(0x1234), 0x56);
+    }
+
+    #[test]
+    fn bulk_read_matches_single_reads() {
+        let mut mapper = mapper();
+        mapper.cpu_write(0x8000, 0x01);
+
+        let mut buffer = vec![0; 0x8010];
+        mapper.cpu_read_into(0x7ff8, &mut buffer);
+        for (offset, &data) in buffer.iter().enumerate() {
+            let address = 0x7ff8u16.wrapping_add(offset as u16);
+            let read = mapper.cpu_read(address).unwrap_or(0);
+            assert_eq!(data, read, "${:04X}", address);
+        }
+    }
+
+    #[test]
+    fn small_prg_rom_is_mirrored() {
+        let mut mapper = Axrom::new(
+            (0..=0xff).cycle().take(16 * 1024).collect::<Vec<u8>>(),
+            Vec::new(),
+        );
+        assert_eq!(mapper.cpu_read(0x8001), Some(0x01));
+        assert_eq!(mapper.cpu_read(0xfffc), Some(0xfc));
+        mapper.cpu_write(0x8000, 0x03);
+        assert_eq!(mapper.cpu_read(0xfffd), Some(0xfd));
+    }
+
+    #[test]
+    fn bank_past_prg_rom_in_state_is_rejected() {
+        let mut mapper = mapper();
+        let mut state = StateWriter::new();
+        state.write(&4usize);
+        state.write(&mapper.mirroring);
+        state.write(&mapper.chr_ram);
+        let state = state.into_bytes();
+        assert!(mapper.load_state(&mut StateReader::new(&state)).is_err());
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x00));
+    }
+
+    #[test]
+    fn bus_conflicts() {
+        let mut mapper = mapper();
+        mapper.cpu_write(0x8000, 0x03);
+        mapper.set_bus_conflicts(true);
+
+        // Bank 3 is all 3s, which masks out the mirroring bit
+        mapper.cpu_write(0x8000, 0x12);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x02));
+        assert_eq!(mapper.mirroring(), Some(Mirroring::SingleScreenLower));
+    }
+}
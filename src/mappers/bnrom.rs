@@ -0,0 +1,228 @@
+use crate::mapper::{self, Mapper};
+use crate::rom::Rom;
+#[cfg(feature = "serde")]
+use crate::state::{serde_state, serde_state_methods};
+use crate::state::{StateReader, StateWriter};
+use crate::Result;
+
+/// Mapper 34, which is two boards with switchable 32 kB PRG banks:
+///
+/// - BNROM has 8 kB of CHR RAM and a bank register anywhere in
+///   $8000-$FFFF.
+/// - NINA-001 has PRG RAM, two 4 kB CHR ROM banks and its registers at
+///   $7FFD-$7FFF.
+///
+/// Cartridges with more than 8 kB of CHR ROM are taken to be NINA-001.
+#[derive(Debug, Clone)]
+pub struct Bnrom {
+    prg_rom: Rom,
+    prg_ram: Vec<u8>,
+    /// CHR RAM for BNROM, a copy of CHR ROM for NINA-001
+    chr: Vec<u8>,
+    nina: bool,
+    prg_bank: usize,
+    /// The 4 kB banks at $0000 and $1000, NINA-001 only
+    chr_banks: [usize; 2],
+}
+
+impl Bnrom {
+    const PRG_BANK_SIZE: usize = 32 * 1024; // 32 kB
+    const CHR_BANK_SIZE: usize = 4 * 1024; // 4 kB
+
+    pub fn new<V>(prg_rom: V, chr_rom: V, prg_ram: Vec<u8>) -> Bnrom
+    where
+        V: Into<Rom>,
+    {
+        let chr_rom = chr_rom.into();
+        let nina = chr_rom.len() > 8 * 1024;
+        let mut chr = vec![0; chr_rom.len().max(8 * 1024)];
+        chr[..chr_rom.len()].copy_from_slice(&chr_rom[..]);
+        Bnrom {
+            prg_rom: prg_rom.into(),
+            prg_ram,
+            chr,
+            nina,
+            prg_bank: 0,
+            chr_banks: [0, 1],
+        }
+    }
+
+    /// Index into PRG ROM for $8000-$FFFF, and the end of its bank.
+    fn prg_index(&self, address: u16) -> (usize, usize) {
+        let offset = (address - 0x8000) as usize;
+        mapper::bank_index(
+            self.prg_rom.len(),
+            Self::PRG_BANK_SIZE,
+            self.prg_bank,
+            offset,
+        )
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        if !self.nina {
+            return address as usize;
+        }
+        let bank = self.chr_banks[address as usize / Self::CHR_BANK_SIZE];
+        let offset = address as usize % Self::CHR_BANK_SIZE;
+        mapper::bank_index(self.chr.len(), Self::CHR_BANK_SIZE, bank, offset).0
+    }
+}
+
+impl Mapper for Bnrom {
+    fn id(&self) -> u8 {
+        34
+    }
+
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if self.nina => {
+                Some(self.prg_ram[mapper::prg_ram_index(self.prg_ram.len(), address).0])
+            }
+            0x8000..=0xffff => Some(self.prg_rom[self.prg_index(address).0]),
+            _ => None,
+        }
+    }
+
+    fn cpu_read_into(&mut self, address: u16, buffer: &mut [u8]) {
+        mapper::read_chunks(address, buffer, |address, buffer| match address {
+            0x8000..=0xffff => {
+                let (index, end) = self.prg_index(address);
+                mapper::copy_chunk(&self.prg_rom[index..end], buffer)
+            }
+            _ => {
+                if let Some(data) = self.cpu_read(address) {
+                    buffer[0] = data;
+                }
+                1
+            }
+        });
+    }
+
+    fn cpu_poke(&mut self, address: u16, data: u8) -> bool {
+        match address {
+            0x6000..=0x7fff if self.nina => {
+                let index = mapper::prg_ram_index(self.prg_ram.len(), address).0;
+                self.prg_ram[index] = data;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        match address {
+            0x6000..=0x7fff if self.nina => {
+                let index = mapper::prg_ram_index(self.prg_ram.len(), address).0;
+                self.prg_ram[index] = data;
+                match address {
+                    0x7ffd => self.prg_bank = data as usize & 0x01,
+                    0x7ffe => self.chr_banks[0] = data as usize & 0x0f,
+                    0x7fff => self.chr_banks[1] = data as usize & 0x0f,
+                    _ => {}
+                }
+            }
+            0x8000..=0xffff if !self.nina => self.prg_bank = data as usize,
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1fff => self.chr[self.chr_index(address)],
+            _ => 0,
+        }
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        if let 0x0000..=0x1fff = address {
+            if !self.nina {
+                self.chr[address as usize] = data;
+            }
+        }
+    }
+
+    fn cycles_until_irq(&self) -> u64 {
+        u64::MAX
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write(&self.prg_bank);
+        state.write(&self.chr_banks);
+        state.write(&self.prg_ram);
+        // NINA-001's CHR is ROM
+        if !self.nina {
+            state.write(&self.chr);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.prg_bank = state.read()?;
+        self.chr_banks = state.read()?;
+        state.read_into(&mut self.prg_ram)?;
+        if !self.nina {
+            state.read_into(&mut self.chr)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    serde_state_methods!();
+}
+
+#[cfg(feature = "serde")]
+serde_state!(Bnrom {
+    prg_bank,
+    chr_banks,
+    prg_ram as bytes,
+    chr as bytes,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 128 kB of PRG ROM, each byte holding its bank number.
+    fn prg_rom() -> Vec<u8> {
+        (0..4)
+            .flat_map(|bank| vec![bank; Bnrom::PRG_BANK_SIZE])
+            .collect()
+    }
+
+    #[test]
+    fn bnrom_prg_banks_and_chr_ram() {
+        let mut mapper = Bnrom::new(prg_rom(), vec![], vec![0; 8 * 1024]);
+        mapper.cpu_write(0x8000, 0x03);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x03));
+        assert_eq!(mapper.cpu_read(0xffff), Some(0x03));
+
+        mapper.ppu_write(0x1234, 0xaa);
+        assert_eq!(mapper.ppu_read(0x1234), 0xaa);
+
+        // No PRG RAM, so NINA-001's registers do nothing
+        mapper.cpu_write(0x7ffd, 0x01);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x03));
+    }
+
+    #[test]
+    fn nina_001_registers() {
+        let chr_rom: Vec<u8> = (0..16)
+            .flat_map(|bank| vec![bank; Bnrom::CHR_BANK_SIZE])
+            .collect();
+        let mut mapper = Bnrom::new(prg_rom(), chr_rom, vec![0; 8 * 1024]);
+        mapper.cpu_write(0x7ffd, 0x01);
+        mapper.cpu_write(0x7ffe, 0x05);
+        mapper.cpu_write(0x7fff, 0x0c);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x01));
+        assert_eq!(mapper.ppu_read(0x0000), 0x05);
+        assert_eq!(mapper.ppu_read(0x1000), 0x0c);
+
+        // The registers are also PRG RAM, and $8000 is not a register
+        assert_eq!(mapper.cpu_read(0x7ffe), Some(0x05));
+        mapper.cpu_write(0x8000, 0x00);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x01));
+
+        // CHR is ROM
+        mapper.ppu_write(0x0000, 0xaa);
+        assert_eq!(mapper.ppu_read(0x0000), 0x05);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::mapper::{self, Chr, Mapper};
+use crate::rom::Rom;
+#[cfg(feature = "serde")]
+use crate::state::{serde_state, serde_state_methods};
+use crate::state::{StateReader, StateWriter};
+use crate::Result;
+
+/// Mapper 3: fixed PRG ROM and switchable 8 kB CHR banks.
+#[derive(Debug, Clone)]
+pub struct Cnrom {
+    prg_rom: Rom,
+    chr: Chr,
+    bank: usize,
+    /// Whether writes are ANDed with the ROM byte at the same address
+    bus_conflicts: bool,
+}
+
+impl Cnrom {
+    const BANK_SIZE: usize = 8 * 1024; // 8 kB
+
+    pub fn new<V, C>(prg_rom: V, chr: C) -> Cnrom
+    where
+        V: Into<Rom>,
+        C: Into<Chr>,
+    {
+        Cnrom {
+            prg_rom: prg_rom.into(),
+            chr: chr.into(),
+            bank: 0,
+            bus_conflicts: false,
+        }
+    }
+
+    /// See [`Uxrom::set_bus_conflicts`](super::uxrom::Uxrom::set_bus_conflicts).
+    pub fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.bus_conflicts = enabled;
+    }
+
+    /// Index into PRG ROM for $8000-$FFFF, and the end of its mirror.
+    fn prg_index(&self, address: u16) -> (usize, usize) {
+        let offset = (address - 0x8000) as usize;
+        mapper::bank_index(self.prg_rom.len(), 0x8000, 0, offset)
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        let offset = address as usize;
+        mapper::bank_index(self.chr.len(), Self::BANK_SIZE, self.bank, offset).0
+    }
+}
+
+impl Mapper for Cnrom {
+    fn id(&self) -> u8 {
+        3
+    }
+
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x8000..=0xffff => Some(self.prg_rom[self.prg_index(address).0]),
+            _ => None,
+        }
+    }
+
+    fn cpu_read_into(&mut self, address: u16, buffer: &mut [u8]) {
+        mapper::read_chunks(address, buffer, |address, buffer| match address {
+            0x8000..=0xffff => {
+                let (index, end) = self.prg_index(address);
+                mapper::copy_chunk(&self.prg_rom[index..end], buffer)
+            }
+            _ => {
+                if let Some(data) = self.cpu_read(address) {
+                    buffer[0] = data;
+                }
+                1
+            }
+        });
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        if let 0x8000..=0xffff = address {
+            let data = if self.bus_conflicts {
+                self.cpu_peek(address).map_or(data, |rom| data & rom)
+            } else {
+                data
+            };
+            self.bank = data as usize;
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1fff => self.chr[self.chr_index(address)],
+            _ => 0,
+        }
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        if let 0x0000..=0x1fff = address {
+            let index = self.chr_index(address);
+            self.chr.write(index, data);
+        }
+    }
+
+    fn cycles_until_irq(&self) -> u64 {
+        u64::MAX
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write(&self.bank);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.bank = state.read()?;
+        self.chr.load_state(state)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    serde_state_methods!();
+}
+
+#[cfg(feature = "serde")]
+serde_state!(Cnrom { bank, chr as chr });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chr_bank_switching() {
+        let prg_rom = vec![0xea; 16 * 1024];
+        let chr_rom: Vec<u8> = (0..4)
+            .flat_map(|bank| vec![bank; Cnrom::BANK_SIZE])
+            .collect();
+        let mut mapper = Cnrom::new(prg_rom, chr_rom);
+
+        assert_eq!(mapper.ppu_read(0x1fff), 0x00);
+        mapper.cpu_write(0x8000, 0x02);
+        assert_eq!(mapper.ppu_read(0x0000), 0x02);
+        assert_eq!(mapper.ppu_read(0x1fff), 0x02);
+
+        // Bank numbers wrap at the size of CHR ROM
+        mapper.cpu_write(0xffff, 0x07);
+        assert_eq!(mapper.ppu_read(0x1000), 0x03);
+
+        // PRG ROM is not switched and 16 kB is mirrored
+        assert_eq!(mapper.cpu_read(0xc000), Some(0xea));
+    }
+
+    #[test]
+    fn bus_conflicts() {
+        let prg_rom = vec![0x05; 16 * 1024];
+        let chr_rom: Vec<u8> = (0..8)
+            .flat_map(|bank| vec![bank; Cnrom::BANK_SIZE])
+            .collect();
+        let mut mapper = Cnrom::new(prg_rom, chr_rom);
+        mapper.set_bus_conflicts(true);
+        mapper.cpu_write(0x8000, 0x07);
+        assert_eq!(mapper.ppu_read(0x0000), 0x05);
+    }
+}
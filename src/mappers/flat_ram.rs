@@ -0,0 +1,114 @@
+use crate::mapper::{self, Mapper};
+#[cfg(feature = "serde")]
+use crate::state::{serde_state, serde_state_methods};
+use crate::state::{StateReader, StateWriter};
+use crate::Result;
+
+/// Pseudo-cartridge for bare-metal programs without an iNES header.
+///
+/// Everything the cartridge can see, $4020-$FFFF, is writable RAM, and the
+/// pattern tables are 8 kB of CHR RAM.
+#[derive(Debug, Clone)]
+pub struct FlatRam {
+    ram: Vec<u8>,
+    chr_ram: Vec<u8>,
+}
+
+impl FlatRam {
+    pub fn new() -> FlatRam {
+        FlatRam {
+            ram: vec![0; 0x10000],
+            chr_ram: vec![0; 8 * 1024],
+        }
+    }
+}
+
+impl Default for FlatRam {
+    fn default() -> FlatRam {
+        FlatRam::new()
+    }
+}
+
+impl Mapper for FlatRam {
+    /// Not an iNES mapper, so the otherwise unused number 255.
+    fn id(&self) -> u8 {
+        0xff
+    }
+
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        Some(self.ram[address as usize])
+    }
+
+    fn cpu_read_into(&mut self, address: u16, buffer: &mut [u8]) {
+        mapper::read_chunks(address, buffer, |address, buffer| {
+            mapper::copy_chunk(&self.ram[address as usize..], buffer)
+        });
+    }
+
+    fn cpu_poke(&mut self, address: u16, data: u8) -> bool {
+        self.ram[address as usize] = data;
+        true
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        self.ram[address as usize] = data;
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1fff => self.chr_ram[address as usize],
+            _ => 0,
+        }
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        if let 0x0000..=0x1fff = address {
+            self.chr_ram[address as usize] = data;
+        }
+    }
+
+    fn cycles_until_irq(&self) -> u64 {
+        u64::MAX
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write(&self.ram);
+        state.write(&self.chr_ram);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_into(&mut self.ram)?;
+        state.read_into(&mut self.chr_ram)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    serde_state_methods!();
+}
+
+#[cfg(feature = "serde")]
+serde_state!(FlatRam {
+    ram as bytes,
+    chr_ram as bytes,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bulk_read_matches_single_reads() {
+        let mut mapper = FlatRam::new();
+        for address in 0x4020..=0xffff {
+            mapper.cpu_write(address, (address as usize * 7) as u8);
+        }
+
+        // The end of the address space and the wrap to $0000
+        let mut buffer = vec![0; 0x20];
+        mapper.cpu_read_into(0xfff0, &mut buffer);
+        for (offset, &data) in buffer.iter().enumerate() {
+            let address = 0xfff0u16.wrapping_add(offset as u16);
+            assert_eq!(Some(data), mapper.cpu_read(address), "${:04X}", address);
+        }
+    }
+}
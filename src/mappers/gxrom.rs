@@ -0,0 +1,174 @@
+use crate::mapper::{self, Chr, Mapper};
+use crate::rom::Rom;
+#[cfg(feature = "serde")]
+use crate::state::{serde_state, serde_state_methods};
+use crate::state::{StateReader, StateWriter};
+use crate::Result;
+
+/// Mappers 66 (GxROM) and 11 (Color Dreams): one register anywhere in
+/// $8000-$FFFF that selects a 32 kB PRG bank and an 8 kB CHR bank. The two
+/// boards put the fields in opposite nibbles.
+#[derive(Debug, Clone)]
+pub struct Gxrom {
+    id: u8,
+    prg_rom: Rom,
+    chr: Chr,
+    prg_bank: usize,
+    chr_bank: usize,
+}
+
+impl Gxrom {
+    const PRG_BANK_SIZE: usize = 32 * 1024; // 32 kB
+    const CHR_BANK_SIZE: usize = 8 * 1024; // 8 kB
+
+    /// `id` is 66 for GxROM or 11 for Color Dreams.
+    pub fn new<V, C>(id: u8, prg_rom: V, chr: C) -> Gxrom
+    where
+        V: Into<Rom>,
+        C: Into<Chr>,
+    {
+        assert!(id == 11 || id == 66, "not GxROM or Color Dreams: {}", id);
+        Gxrom {
+            id,
+            prg_rom: prg_rom.into(),
+            chr: chr.into(),
+            prg_bank: 0,
+            chr_bank: 0,
+        }
+    }
+
+    /// Index into PRG ROM for $8000-$FFFF, and the end of its bank.
+    fn prg_index(&self, address: u16) -> (usize, usize) {
+        let offset = (address - 0x8000) as usize;
+        mapper::bank_index(
+            self.prg_rom.len(),
+            Self::PRG_BANK_SIZE,
+            self.prg_bank,
+            offset,
+        )
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        let offset = address as usize;
+        mapper::bank_index(self.chr.len(), Self::CHR_BANK_SIZE, self.chr_bank, offset).0
+    }
+}
+
+impl Mapper for Gxrom {
+    fn id(&self) -> u8 {
+        self.id
+    }
+
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x8000..=0xffff => Some(self.prg_rom[self.prg_index(address).0]),
+            _ => None,
+        }
+    }
+
+    fn cpu_read_into(&mut self, address: u16, buffer: &mut [u8]) {
+        mapper::read_chunks(address, buffer, |address, buffer| match address {
+            0x8000..=0xffff => {
+                let (index, end) = self.prg_index(address);
+                mapper::copy_chunk(&self.prg_rom[index..end], buffer)
+            }
+            _ => {
+                if let Some(data) = self.cpu_read(address) {
+                    buffer[0] = data;
+                }
+                1
+            }
+        });
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        if let 0x8000..=0xffff = address {
+            let (prg_bank, chr_bank) = if self.id == 66 {
+                ((data >> 4) & 0x03, data & 0x03)
+            } else {
+                (data & 0x03, data >> 4)
+            };
+            self.prg_bank = prg_bank as usize;
+            self.chr_bank = chr_bank as usize;
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1fff => self.chr[self.chr_index(address)],
+            _ => 0,
+        }
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        if let 0x0000..=0x1fff = address {
+            let index = self.chr_index(address);
+            self.chr.write(index, data);
+        }
+    }
+
+    fn cycles_until_irq(&self) -> u64 {
+        u64::MAX
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write(&self.prg_bank);
+        state.write(&self.chr_bank);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.prg_bank = state.read()?;
+        self.chr_bank = state.read()?;
+        self.chr.load_state(state)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    serde_state_methods!();
+}
+
+#[cfg(feature = "serde")]
+serde_state!(Gxrom {
+    prg_bank,
+    chr_bank,
+    chr as chr,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 128 kB of PRG ROM and CHR ROM, each byte holding its bank number.
+    fn mapper(id: u8) -> Gxrom {
+        let prg_rom: Vec<u8> = (0..4)
+            .flat_map(|bank| vec![bank; Gxrom::PRG_BANK_SIZE])
+            .collect();
+        let chr_rom: Vec<u8> = (0..16)
+            .flat_map(|bank| vec![bank; Gxrom::CHR_BANK_SIZE])
+            .collect();
+        Gxrom::new(id, prg_rom, chr_rom)
+    }
+
+    #[test]
+    fn gxrom_banks() {
+        let mut mapper = mapper(66);
+        mapper.cpu_write(0x8000, 0x21);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x02));
+        assert_eq!(mapper.cpu_read(0xffff), Some(0x02));
+        assert_eq!(mapper.ppu_read(0x0000), 0x01);
+        assert_eq!(mapper.ppu_read(0x1fff), 0x01);
+    }
+
+    #[test]
+    fn color_dreams_banks() {
+        let mut mapper = mapper(11);
+        mapper.cpu_write(0xc000, 0xa3);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x03));
+        assert_eq!(mapper.ppu_read(0x0000), 0x0a);
+
+        // Bank numbers wrap at the size of the ROM
+        mapper.cpu_write(0xc000, 0xf0);
+        assert_eq!(mapper.ppu_read(0x0000), 0x0f);
+    }
+}
@@ -0,0 +1,318 @@
+use crate::ines::Mirroring;
+use crate::mapper::{self, Chr, Mapper};
+use crate::rom::Rom;
+#[cfg(feature = "serde")]
+use crate::state::{serde_state, serde_state_methods};
+use crate::state::{StateReader, StateWriter};
+use crate::Result;
+
+/// Mappers 9 (MMC2) and 10 (MMC4): each half of the pattern tables has two
+/// 4 kB CHR banks, and the PPU fetching tile $FD or $FE from that half picks
+/// which one is used from then on.
+#[derive(Debug, Clone)]
+pub struct Mmc2 {
+    id: u8,
+    prg_rom: Rom,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    prg_bank: usize,
+    /// Banks for tile $FD and $FE, for each half of the pattern tables
+    chr_banks: [[usize; 2]; 2],
+    /// Which of `chr_banks` each half uses, 0 for $FD and 1 for $FE
+    latches: [usize; 2],
+    mirroring: Mirroring,
+}
+
+impl Mmc2 {
+    const CHR_BANK_SIZE: usize = 4 * 1024; // 4 kB
+
+    /// `id` is 9 for the MMC2 or 10 for the MMC4.
+    pub fn new<V, C>(id: u8, prg_rom: V, chr: C, prg_ram: Vec<u8>) -> Mmc2
+    where
+        V: Into<Rom>,
+        C: Into<Chr>,
+    {
+        assert!(id == 9 || id == 10, "not an MMC2 or MMC4: {}", id);
+        Mmc2 {
+            id,
+            prg_rom: prg_rom.into(),
+            prg_ram,
+            chr: chr.into(),
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            latches: [1; 2],
+            mirroring: Mirroring::Vertical,
+        }
+    }
+
+    /// The size of the switchable PRG bank at $8000. The rest of the
+    /// address space holds the last banks of PRG ROM.
+    fn prg_bank_size(&self) -> usize {
+        if self.id == 9 {
+            8 * 1024
+        } else {
+            16 * 1024
+        }
+    }
+
+    /// Index into PRG ROM for $8000-$FFFF, and the end of its bank.
+    fn prg_index(&self, address: u16) -> (usize, usize) {
+        let len = self.prg_rom.len();
+        let bank_size = self.prg_bank_size();
+        let offset = (address - 0x8000) as usize;
+        let bank = if offset < bank_size {
+            self.prg_bank
+        } else {
+            // The fixed banks are the last ones, however many fit. Adding
+            // a multiple of the bank count keeps this from going negative.
+            let banks = mapper::bank_count(len, bank_size);
+            let fixed = 0x8000 / bank_size - 1;
+            banks * fixed + (offset - bank_size) / bank_size - fixed
+        };
+        mapper::bank_index(len, bank_size, bank, offset % bank_size)
+    }
+
+    /// Index into CHR for `address`, through the bank its half's latch
+    /// picks.
+    fn chr_index(&self, address: u16) -> usize {
+        let half = address as usize >> 12;
+        let bank = self.chr_banks[half][self.latches[half]];
+        let offset = address as usize & 0x0fff;
+        mapper::bank_index(self.chr.len(), Self::CHR_BANK_SIZE, bank, offset).0
+    }
+
+    /// Switch the latch for pattern table `half` if `address` is where tile
+    /// $FD or $FE ends. The MMC2 watches a single address for the left
+    /// pattern table, the MMC4 a whole row of the tile.
+    fn update_latch(&mut self, address: u16) {
+        let half = (address >> 12) as usize & 1;
+        let row = address & 0x0ff8;
+        let exact = self.id == 10 || half == 1 || address & 0x0007 == 0;
+        match row {
+            0x0fd8 if exact => self.latches[half] = 0,
+            0x0fe8 if exact => self.latches[half] = 1,
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn id(&self) -> u8 {
+        self.id
+    }
+
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if self.id == 10 => {
+                Some(self.prg_ram[mapper::prg_ram_index(self.prg_ram.len(), address).0])
+            }
+            0x8000..=0xffff => Some(self.prg_rom[self.prg_index(address).0]),
+            _ => None,
+        }
+    }
+
+    fn cpu_read_into(&mut self, address: u16, buffer: &mut [u8]) {
+        mapper::read_chunks(address, buffer, |address, buffer| match address {
+            0x8000..=0xffff => {
+                let (index, end) = self.prg_index(address);
+                mapper::copy_chunk(&self.prg_rom[index..end], buffer)
+            }
+            _ => {
+                if let Some(data) = self.cpu_read(address) {
+                    buffer[0] = data;
+                }
+                1
+            }
+        });
+    }
+
+    fn cpu_poke(&mut self, address: u16, data: u8) -> bool {
+        match address {
+            0x6000..=0x7fff if self.id == 10 => {
+                let index = mapper::prg_ram_index(self.prg_ram.len(), address).0;
+                self.prg_ram[index] = data;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        let bank = data as usize & 0x1f;
+        match address {
+            0x6000..=0x7fff if self.id == 10 => {
+                let index = mapper::prg_ram_index(self.prg_ram.len(), address).0;
+                self.prg_ram[index] = data
+            }
+            0xa000..=0xafff => self.prg_bank = data as usize & 0x0f,
+            0xb000..=0xbfff => self.chr_banks[0][0] = bank,
+            0xc000..=0xcfff => self.chr_banks[0][1] = bank,
+            0xd000..=0xdfff => self.chr_banks[1][0] = bank,
+            0xe000..=0xefff => self.chr_banks[1][1] = bank,
+            0xf000..=0xffff => {
+                self.mirroring = if data & 1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1fff => self.chr[self.chr_index(address)],
+            _ => 0,
+        }
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        if let 0x0000..=0x1fff = address {
+            let index = self.chr_index(address);
+            self.chr.write(index, data);
+        }
+    }
+
+    fn cycles_until_irq(&self) -> u64 {
+        u64::MAX
+    }
+
+    fn ppu_accessed(&mut self, address: u16) {
+        if address < 0x2000 {
+            self.update_latch(address);
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn set_mirroring(&mut self, mirroring: Mirroring) {
+        if let Mirroring::Vertical | Mirroring::Horizontal = mirroring {
+            self.mirroring = mirroring;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write(&self.prg_bank);
+        state.write(&self.chr_banks);
+        state.write(&self.latches);
+        state.write(&self.mirroring);
+        state.write(&self.prg_ram);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.prg_bank = state.read()?;
+        self.chr_banks = state.read()?;
+        self.latches = state.read()?;
+        self.mirroring = state.read()?;
+        state.read_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    serde_state_methods!();
+}
+
+#[cfg(feature = "serde")]
+serde_state!(Mmc2 {
+    prg_bank,
+    chr_banks,
+    latches,
+    mirroring,
+    prg_ram as bytes,
+    chr as chr,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 128 kB of PRG ROM and CHR ROM, each byte holding its bank number.
+    fn mapper(id: u8) -> Mmc2 {
+        let prg_rom: Vec<u8> = (0..16).flat_map(|bank| vec![bank; 8 * 1024]).collect();
+        let chr_rom: Vec<u8> = (0..32)
+            .flat_map(|bank| vec![bank; Mmc2::CHR_BANK_SIZE])
+            .collect();
+        Mmc2::new(id, prg_rom, chr_rom, vec![0; 8 * 1024])
+    }
+
+    fn fetch(mapper: &mut Mmc2, address: u16) -> u8 {
+        let data = mapper.ppu_read(address);
+        mapper.ppu_accessed(address);
+        data
+    }
+
+    #[test]
+    fn mmc2_prg_banks() {
+        let mut mapper = mapper(9);
+        mapper.cpu_write(0xa000, 0x05);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x05));
+        assert_eq!(mapper.cpu_read(0x9fff), Some(0x05));
+        assert_eq!(mapper.cpu_read(0xa000), Some(0x0d));
+        assert_eq!(mapper.cpu_read(0xc000), Some(0x0e));
+        assert_eq!(mapper.cpu_read(0xffff), Some(0x0f));
+    }
+
+    #[test]
+    fn mmc4_prg_banks_and_ram() {
+        let mut mapper = mapper(10);
+        mapper.cpu_write(0xa000, 0x02);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x04));
+        assert_eq!(mapper.cpu_read(0xbfff), Some(0x05));
+        assert_eq!(mapper.cpu_read(0xc000), Some(0x0e));
+        assert_eq!(mapper.cpu_read(0xffff), Some(0x0f));
+
+        mapper.cpu_write(0x6000, 0xaa);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0xaa));
+    }
+
+    #[test]
+    fn latches_follow_fetches() {
+        let mut mapper = mapper(9);
+        for (address, bank) in [(0xb000, 1), (0xc000, 2), (0xd000, 3), (0xe000, 4)] {
+            mapper.cpu_write(address, bank);
+        }
+        assert_eq!(fetch(&mut mapper, 0x0000), 2);
+        assert_eq!(fetch(&mut mapper, 0x1000), 4);
+
+        // The fetch that trips the latch still reads the old bank
+        assert_eq!(fetch(&mut mapper, 0x0fd8), 2);
+        assert_eq!(fetch(&mut mapper, 0x0000), 1);
+        assert_eq!(fetch(&mut mapper, 0x1000), 4);
+        assert_eq!(fetch(&mut mapper, 0x1fdd), 4);
+        assert_eq!(fetch(&mut mapper, 0x1000), 3);
+
+        // The MMC2 only watches $0FE8 itself in the left pattern table
+        fetch(&mut mapper, 0x0fe9);
+        assert_eq!(fetch(&mut mapper, 0x0000), 1);
+        fetch(&mut mapper, 0x0fe8);
+        assert_eq!(fetch(&mut mapper, 0x0000), 2);
+    }
+
+    #[test]
+    fn mmc4_latches_watch_the_whole_row() {
+        let mut mapper = mapper(10);
+        mapper.cpu_write(0xb000, 0x01);
+        mapper.cpu_write(0xc000, 0x02);
+        fetch(&mut mapper, 0x0fdf);
+        assert_eq!(fetch(&mut mapper, 0x0000), 1);
+    }
+
+    #[test]
+    fn mirroring() {
+        let mut mapper = mapper(9);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::Vertical));
+        mapper.cpu_write(0xf000, 0x01);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::Horizontal));
+
+        mapper.set_mirroring(Mirroring::Vertical);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::Vertical));
+        // The register only has the two
+        mapper.set_mirroring(Mirroring::SingleScreenUpper);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::Vertical));
+    }
+}
@@ -1,2 +1,12 @@
+pub mod axrom;
+pub mod bnrom;
+pub mod cnrom;
+pub mod flat_ram;
+pub mod gxrom;
+pub mod mmc2;
+pub mod namco163;
 pub mod nrom;
+pub mod nsf_player;
+pub mod unrom512;
 pub mod uxrom;
+pub mod vrc6;
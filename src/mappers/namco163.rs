@@ -0,0 +1,550 @@
+use crate::apu;
+use crate::mapper::{self, Chr, Mapper, PpuWindow};
+use crate::rom::Rom;
+#[cfg(feature = "serde")]
+use crate::state::{serde_state, serde_state_methods};
+use crate::state::{State, StateReader, StateWriter};
+use crate::Result;
+
+/// The Namco 163's wavetable synthesizer: up to eight channels whose
+/// registers and 4-bit samples share 128 bytes of sound RAM.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Wavetable {
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::byte_array"))]
+    ram: [u8; 128],
+    /// Sound RAM address for $4800, set through $F800
+    address: u8,
+    auto_increment: bool,
+    /// CPU cycles until the next channel update
+    timer: u8,
+    /// The channel updated next, counting down from 7
+    channel: usize,
+    /// Last output of each channel, 0-225
+    outputs: [u8; 8],
+}
+
+impl Wavetable {
+    /// CPU cycles between channel updates.
+    const UPDATE_CYCLES: u8 = 15;
+
+    fn new() -> Wavetable {
+        Wavetable {
+            ram: [0; 128],
+            address: 0,
+            auto_increment: false,
+            timer: Self::UPDATE_CYCLES,
+            channel: 7,
+            outputs: [0; 8],
+        }
+    }
+
+    /// Number of channels enabled, 1-8. They are the highest numbered ones.
+    fn channels(&self) -> usize {
+        ((self.ram[0x7f] >> 4) & 0x07) as usize + 1
+    }
+
+    fn set_address(&mut self, data: u8) {
+        self.address = data & 0x7f;
+        self.auto_increment = data & 0x80 != 0;
+    }
+
+    /// Read or write the data port at $4800.
+    fn data(&mut self, write: Option<u8>) -> u8 {
+        let index = self.address as usize;
+        if let Some(data) = write {
+            self.ram[index] = data;
+        }
+        if self.auto_increment {
+            self.address = (self.address + 1) & 0x7f;
+        }
+        self.ram[index]
+    }
+
+    /// The 4-bit sample at `index` in sound RAM, low nibble first.
+    fn sample(&self, index: u8) -> u8 {
+        (self.ram[index as usize >> 1] >> ((index & 1) * 4)) & 0x0f
+    }
+
+    fn step(&mut self) {
+        self.timer -= 1;
+        if self.timer > 0 {
+            return;
+        }
+        self.timer = Self::UPDATE_CYCLES;
+        self.update(self.channel);
+        self.channel = if self.channel <= 8 - self.channels() {
+            7
+        } else {
+            self.channel - 1
+        };
+    }
+
+    /// Advance `channel`'s phase by its frequency and sample its waveform.
+    fn update(&mut self, channel: usize) {
+        let base = 0x40 + channel * 8;
+        let registers = &self.ram[base..base + 8];
+        let frequency =
+            registers[0] as u32 | (registers[2] as u32) << 8 | (registers[4] as u32 & 0x03) << 16;
+        let phase = registers[1] as u32 | (registers[3] as u32) << 8 | (registers[5] as u32) << 16;
+        let length = 256 - (registers[4] as u32 & 0xfc);
+        let offset = registers[6];
+        let volume = registers[7] & 0x0f;
+
+        let phase = (phase + frequency) % (length << 16);
+        let [phase_low, phase_mid, phase_high, _] = phase.to_le_bytes();
+        self.ram[base + 1] = phase_low;
+        self.ram[base + 3] = phase_mid;
+        self.ram[base + 5] = phase_high;
+
+        let sample = self.sample(phase_high.wrapping_add(offset));
+        self.outputs[channel] = sample * volume;
+    }
+
+    /// The enabled channels averaged, as the chip plays them one at a time,
+    /// 0-225.
+    fn level(&self) -> f32 {
+        let channels = self.channels();
+        let sum: u32 = self.outputs[8 - channels..].iter().map(|&x| x as u32).sum();
+        sum as f32 / channels as f32
+    }
+}
+
+impl State for Wavetable {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.ram);
+        state.write(&self.address);
+        state.write(&self.auto_increment);
+        state.write(&self.timer);
+        state.write(&self.channel);
+        state.write(&self.outputs);
+    }
+
+    fn load(state: &mut StateReader) -> Result<Wavetable> {
+        Ok(Wavetable {
+            ram: state.read()?,
+            address: state.read()?,
+            auto_increment: state.read()?,
+            timer: state.read()?,
+            channel: state.read()?,
+            outputs: state.read()?,
+        })
+    }
+}
+
+/// Mapper 19 (Namco 163): four 8 kB PRG banks, the last fixed, 1 kB CHR
+/// banks that can also point at nametable RAM, a CPU cycle IRQ counter and
+/// wavetable expansion audio.
+#[derive(Debug, Clone)]
+pub struct Namco163 {
+    prg_rom: Rom,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    /// 8 kB banks at $8000, $A000 and $C000
+    prg_banks: [usize; 3],
+    /// Banks for the pattern tables, then the nametables. Values $E0 and up
+    /// select a page of nametable RAM instead.
+    chr_banks: [u8; 12],
+    /// Whether values $E0 and up select CHR ROM for each pattern table
+    chr_ram_disabled: [bool; 2],
+    /// Last value written to $F800, which guards PRG RAM
+    write_protect: u8,
+    sound_disabled: bool,
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq: bool,
+    wavetable: Wavetable,
+}
+
+impl Namco163 {
+    const BANK_SIZE: usize = 8 * 1024; // 8 kB
+    const CHR_BANK_SIZE: usize = 1024; // 1 kB
+
+    pub fn new<V, C>(prg_rom: V, chr: C, prg_ram: Vec<u8>) -> Namco163
+    where
+        V: Into<Rom>,
+        C: Into<Chr>,
+    {
+        Namco163 {
+            prg_rom: prg_rom.into(),
+            prg_ram,
+            chr: chr.into(),
+            prg_banks: [0; 3],
+            chr_banks: [0; 12],
+            chr_ram_disabled: [false; 2],
+            write_protect: 0,
+            sound_disabled: false,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq: false,
+            wavetable: Wavetable::new(),
+        }
+    }
+
+    /// Index into PRG ROM for $8000-$FFFF, and the end of its bank.
+    fn prg_index(&self, address: u16) -> (usize, usize) {
+        let len = self.prg_rom.len();
+        let slot = (address as usize - 0x8000) / Self::BANK_SIZE;
+        let bank = match slot {
+            3 => mapper::bank_count(len, Self::BANK_SIZE) - 1,
+            _ => self.prg_banks[slot],
+        };
+        let offset = address as usize % Self::BANK_SIZE;
+        mapper::bank_index(len, Self::BANK_SIZE, bank, offset)
+    }
+
+    /// Whether $F800 allows writing PRG RAM at `address`: the high nibble
+    /// must be 4, and each low bit protects 2 kB.
+    fn prg_ram_writable(&self, address: u16) -> bool {
+        let region = (address - 0x6000) / 0x800;
+        self.write_protect & 0xf0 == 0x40 && self.write_protect & (1 << region) == 0
+    }
+
+    /// The CHR bank register for the 1 kB window holding `address`.
+    fn chr_bank(&self, address: u16) -> u8 {
+        self.chr_banks[(address as usize >> 10) % self.chr_banks.len()]
+    }
+
+    /// Index into CHR for `address`, through its 1 kB bank.
+    fn chr_index(&self, address: u16) -> usize {
+        let bank = self.chr_bank(address) as usize;
+        let offset = address as usize % Self::CHR_BANK_SIZE;
+        mapper::bank_index(self.chr.len(), Self::CHR_BANK_SIZE, bank, offset).0
+    }
+}
+
+impl Mapper for Namco163 {
+    fn id(&self) -> u8 {
+        19
+    }
+
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x4800..=0x4fff => Some(self.wavetable.ram[self.wavetable.address as usize]),
+            0x5000..=0x57ff => Some(self.irq_counter as u8),
+            0x5800..=0x5fff => Some((self.irq_counter >> 8) as u8 | (self.irq_enabled as u8) << 7),
+            0x6000..=0x7fff => {
+                Some(self.prg_ram[mapper::prg_ram_index(self.prg_ram.len(), address).0])
+            }
+            0x8000..=0xffff => Some(self.prg_rom[self.prg_index(address).0]),
+            _ => None,
+        }
+    }
+
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x4800..=0x4fff => Some(self.wavetable.data(None)),
+            _ => self.cpu_peek(address),
+        }
+    }
+
+    fn cpu_read_into(&mut self, address: u16, buffer: &mut [u8]) {
+        mapper::read_chunks(address, buffer, |address, buffer| match address {
+            0x8000..=0xffff => {
+                let (index, end) = self.prg_index(address);
+                mapper::copy_chunk(&self.prg_rom[index..end], buffer)
+            }
+            _ => {
+                if let Some(data) = self.cpu_read(address) {
+                    buffer[0] = data;
+                }
+                1
+            }
+        });
+    }
+
+    fn cpu_poke(&mut self, address: u16, data: u8) -> bool {
+        match address {
+            0x6000..=0x7fff => {
+                let index = mapper::prg_ram_index(self.prg_ram.len(), address).0;
+                self.prg_ram[index] = data;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        match address {
+            0x4800..=0x4fff => {
+                self.wavetable.data(Some(data));
+            }
+            0x5000..=0x57ff => {
+                self.irq_counter = self.irq_counter & 0x7f00 | data as u16;
+                self.irq = false;
+            }
+            0x5800..=0x5fff => {
+                self.irq_counter = self.irq_counter & 0x00ff | (data as u16 & 0x7f) << 8;
+                self.irq_enabled = data & 0x80 != 0;
+                self.irq = false;
+            }
+            0x6000..=0x7fff if self.prg_ram_writable(address) => {
+                let index = mapper::prg_ram_index(self.prg_ram.len(), address).0;
+                self.prg_ram[index] = data
+            }
+            0x8000..=0xdfff => self.chr_banks[(address as usize - 0x8000) / 0x800] = data,
+            0xe000..=0xe7ff => {
+                self.prg_banks[0] = data as usize & 0x3f;
+                self.sound_disabled = data & 0x40 != 0;
+            }
+            0xe800..=0xefff => {
+                self.prg_banks[1] = data as usize & 0x3f;
+                self.chr_ram_disabled = [data & 0x40 != 0, data & 0x80 != 0];
+            }
+            0xf000..=0xf7ff => self.prg_banks[2] = data as usize & 0x3f,
+            0xf800..=0xffff => {
+                self.write_protect = data;
+                self.wavetable.set_address(data);
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr[self.chr_index(address)]
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        let index = self.chr_index(address);
+        self.chr.write(index, data);
+    }
+
+    fn ppu_window(&self, address: u16) -> Option<PpuWindow> {
+        let bank = self.chr_bank(address);
+        let ciram = match address {
+            0x0000..=0x1fff => bank >= 0xe0 && !self.chr_ram_disabled[address as usize >> 12],
+            _ => bank >= 0xe0,
+        };
+        Some(if ciram {
+            PpuWindow::Ciram(bank as usize & 1)
+        } else {
+            PpuWindow::Cartridge
+        })
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq
+    }
+
+    fn cycles_until_irq(&self) -> u64 {
+        if self.irq_enabled && self.irq_counter < 0x7fff {
+            u64::from(0x7fff - self.irq_counter - 1)
+        } else {
+            u64::MAX
+        }
+    }
+
+    fn cpu_clock(&mut self) {
+        if self.irq_enabled && self.irq_counter < 0x7fff {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7fff {
+                self.irq = true;
+            }
+        }
+        if !self.sound_disabled {
+            self.wavetable.step();
+        }
+    }
+
+    /// One channel at full volume playing its loudest sample is about as
+    /// loud as an APU pulse at full volume.
+    fn audio(&self) -> f32 {
+        if self.sound_disabled {
+            return 0.0;
+        }
+        self.wavetable.level() * apu::PULSE_MAX / 225.0
+    }
+
+    /// PRG RAM starts out write-protected, so the trainer is copied in
+    /// directly.
+    fn load_trainer(&mut self, trainer: &[u8]) {
+        for (offset, &data) in trainer.iter().enumerate() {
+            let index = mapper::prg_ram_index(self.prg_ram.len(), 0x7000 + offset as u16).0;
+            self.prg_ram[index] = data;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write(&self.prg_banks);
+        state.write(&self.chr_banks);
+        state.write(&self.chr_ram_disabled);
+        state.write(&self.write_protect);
+        state.write(&self.sound_disabled);
+        state.write(&self.irq_counter);
+        state.write(&self.irq_enabled);
+        state.write(&self.irq);
+        state.write(&self.wavetable);
+        state.write(&self.prg_ram);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.prg_banks = state.read()?;
+        self.chr_banks = state.read()?;
+        self.chr_ram_disabled = state.read()?;
+        self.write_protect = state.read()?;
+        self.sound_disabled = state.read()?;
+        self.irq_counter = state.read()?;
+        self.irq_enabled = state.read()?;
+        self.irq = state.read()?;
+        self.wavetable = state.read()?;
+        state.read_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    serde_state_methods!();
+}
+
+#[cfg(feature = "serde")]
+serde_state!(Namco163 {
+    prg_banks,
+    chr_banks,
+    chr_ram_disabled,
+    write_protect,
+    sound_disabled,
+    irq_counter,
+    irq_enabled,
+    irq,
+    wavetable,
+    prg_ram as bytes,
+    chr as chr,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 128 kB of PRG ROM in 8 kB banks and 64 kB of CHR ROM in 1 kB banks,
+    /// each byte holding its bank number.
+    fn mapper() -> Namco163 {
+        let prg_rom: Vec<u8> = (0..16)
+            .flat_map(|bank| vec![bank; Namco163::BANK_SIZE])
+            .collect();
+        let chr_rom: Vec<u8> = (0..64)
+            .flat_map(|bank| vec![bank; Namco163::CHR_BANK_SIZE])
+            .collect();
+        Namco163::new(prg_rom, chr_rom, vec![0; 8 * 1024])
+    }
+
+    fn run(mapper: &mut Namco163, cycles: u32) {
+        for _ in 0..cycles {
+            mapper.cpu_clock();
+        }
+    }
+
+    #[test]
+    fn prg_banks() {
+        let mut mapper = mapper();
+        mapper.cpu_write(0xe000, 0x03);
+        mapper.cpu_write(0xe800, 0x05);
+        mapper.cpu_write(0xf000, 0x07);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x03));
+        assert_eq!(mapper.cpu_read(0xa000), Some(0x05));
+        assert_eq!(mapper.cpu_read(0xc000), Some(0x07));
+        assert_eq!(mapper.cpu_read(0xe000), Some(0x0f));
+    }
+
+    #[test]
+    fn prg_ram_write_protection() {
+        let mut mapper = mapper();
+        mapper.cpu_write(0x6000, 0xaa);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0x00));
+
+        // Enable writes except to $6800-$6FFF
+        mapper.cpu_write(0xf800, 0x42);
+        mapper.cpu_write(0x6000, 0xaa);
+        mapper.cpu_write(0x6800, 0xbb);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0xaa));
+        assert_eq!(mapper.cpu_read(0x6800), Some(0x00));
+    }
+
+    #[test]
+    fn chr_banks_and_nametable_ram() {
+        let mut mapper = mapper();
+        mapper.cpu_write(0x8800, 0x21);
+        mapper.cpu_write(0x9000, 0xe1);
+        mapper.cpu_write(0xc000, 0xe0);
+        mapper.cpu_write(0xc800, 0x22);
+
+        assert_eq!(mapper.ppu_window(0x0400), Some(PpuWindow::Cartridge));
+        assert_eq!(mapper.ppu_read(0x0400), 0x21);
+        assert_eq!(mapper.ppu_window(0x0800), Some(PpuWindow::Ciram(1)));
+        assert_eq!(mapper.ppu_window(0x2000), Some(PpuWindow::Ciram(0)));
+        assert_eq!(mapper.ppu_window(0x2400), Some(PpuWindow::Cartridge));
+        assert_eq!(mapper.ppu_read(0x2400), 0x22);
+
+        // $E800 bit 6 makes the low pattern table ignore nametable RAM
+        mapper.cpu_write(0xe800, 0x40);
+        assert_eq!(mapper.ppu_window(0x0800), Some(PpuWindow::Cartridge));
+        assert_eq!(mapper.ppu_read(0x0800), 0xe1 % 64);
+    }
+
+    #[test]
+    fn irq_counts_up_to_7fff() {
+        let mut mapper = mapper();
+        mapper.cpu_write(0x5000, 0xfd);
+        mapper.cpu_write(0x5800, 0xff);
+        assert_eq!(mapper.cycles_until_irq(), 1);
+        run(&mut mapper, 1);
+        assert!(!mapper.irq_pending());
+        assert_eq!(mapper.cpu_read(0x5000), Some(0xfe));
+        run(&mut mapper, 1);
+        assert!(mapper.irq_pending());
+        assert_eq!(mapper.cpu_read(0x5800), Some(0xff));
+
+        // The counter stops at $7FFF and writes acknowledge
+        run(&mut mapper, 10);
+        assert_eq!(mapper.cpu_read(0x5000), Some(0xff));
+        mapper.cpu_write(0x5000, 0x00);
+        assert!(!mapper.irq_pending());
+    }
+
+    #[test]
+    fn sound_ram_port_auto_increments() {
+        let mut mapper = mapper();
+        mapper.cpu_write(0xf800, 0x80 | 0x7e);
+        mapper.cpu_write(0x4800, 0x12);
+        mapper.cpu_write(0x4800, 0x34);
+        mapper.cpu_write(0x4800, 0x56);
+        mapper.cpu_write(0xf800, 0x80 | 0x7e);
+        assert_eq!(mapper.cpu_peek(0x4800), Some(0x12));
+        assert_eq!(mapper.cpu_read(0x4800), Some(0x12));
+        assert_eq!(mapper.cpu_read(0x4800), Some(0x34));
+        assert_eq!(mapper.cpu_read(0x4800), Some(0x56));
+
+        // Without auto-increment the address stays put
+        mapper.cpu_write(0xf800, 0x00);
+        mapper.cpu_write(0x4800, 0x9a);
+        assert_eq!(mapper.cpu_read(0x4800), Some(0x9a));
+        assert_eq!(mapper.cpu_read(0x4800), Some(0x9a));
+    }
+
+    #[test]
+    fn channel_plays_its_waveform() {
+        let mut mapper = mapper();
+        // A four-sample wave at address 0: 1, 2, 3, 4
+        mapper.cpu_write(0xf800, 0x80);
+        mapper.cpu_write(0x4800, 0x21);
+        mapper.cpu_write(0x4800, 0x43);
+
+        // Channel 7 alone: frequency $10000 to step one sample per update,
+        // length 4 and volume 15
+        mapper.cpu_write(0xf800, 0x80 | 0x78);
+        for data in [0x00, 0x00, 0x00, 0x00, 0xfd, 0x00, 0x00, 0x0f] {
+            mapper.cpu_write(0x4800, data);
+        }
+
+        let levels: Vec<f32> = (0..5)
+            .map(|_| {
+                run(&mut mapper, 15);
+                mapper.wavetable.level()
+            })
+            .collect();
+        assert_eq!(levels, [30.0, 45.0, 60.0, 15.0, 30.0]);
+
+        mapper.cpu_write(0xe000, 0x40);
+        assert_eq!(mapper.audio(), 0.0);
+    }
+}
@@ -1,23 +1,39 @@
-use crate::mapper::Mapper;
+use crate::mapper::{self, Chr, Mapper};
+use crate::rom::Rom;
+#[cfg(feature = "serde")]
+use crate::state::{serde_state, serde_state_methods};
+use crate::state::{StateReader, StateWriter};
+use crate::Result;
 
 #[derive(Debug, Clone)]
 pub struct Nrom {
-    prg_rom: Vec<u8>,
+    prg_rom: Rom,
     prg_ram: Vec<u8>,
-    chr_rom: Vec<u8>,
+    chr: Chr,
 }
 
 impl Nrom {
-    pub fn new<V>(prg_rom: V, chr_rom: V) -> Nrom
+    const BANK_SIZE: usize = 32 * 1024; // 32 kB
+
+    /// Empty CHR ROM gets 8 kB of CHR RAM, see [`Chr`]. PRG RAM at
+    /// $6000-$7FFF is mirrored if smaller than 8 kB.
+    pub fn new<V, C>(prg_rom: V, chr: C, prg_ram: Vec<u8>) -> Nrom
     where
-        V: Into<Vec<u8>>,
+        V: Into<Rom>,
+        C: Into<Chr>,
     {
         Nrom {
             prg_rom: prg_rom.into(),
-            chr_rom: chr_rom.into(),
-            prg_ram: vec![0; 8 * 1024],
+            chr: chr.into(),
+            prg_ram,
         }
     }
+
+    /// Index into PRG ROM for $8000-$FFFF, and the end of its mirror.
+    fn prg_index(&self, address: u16) -> (usize, usize) {
+        let offset = (address - 0x8000) as usize;
+        mapper::bank_index(self.prg_rom.len(), Self::BANK_SIZE, 0, offset)
+    }
 }
 
 impl Mapper for Nrom {
@@ -25,43 +41,136 @@ impl Mapper for Nrom {
         0
     }
 
-    fn cpu_read(&mut self, address: u16) -> u8 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
         match address {
             0x6000..=0x7fff => {
-                let address = address % self.prg_ram.len() as u16;
-                self.prg_ram[address as usize]
+                Some(self.prg_ram[mapper::prg_ram_index(self.prg_ram.len(), address).0])
             }
-            0x8000..=0xffff => {
-                let address = address % self.prg_rom.len() as u16;
-                self.prg_rom[address as usize]
-            }
-            _ => 0,
+            0x8000..=0xffff => Some(self.prg_rom[self.prg_index(address).0]),
+            _ => None,
         }
     }
 
-    fn cpu_write(&mut self, address: u16, data: u8) {
-        match address {
+    fn cpu_read_into(&mut self, address: u16, buffer: &mut [u8]) {
+        mapper::read_chunks(address, buffer, |address, buffer| match address {
             0x6000..=0x7fff => {
-                let address = address % self.prg_ram.len() as u16;
-                self.prg_ram[address as usize] = data
+                let (index, end) = mapper::prg_ram_index(self.prg_ram.len(), address);
+                mapper::copy_chunk(&self.prg_ram[index..end], buffer)
             }
             0x8000..=0xffff => {
-                let address = address % self.prg_rom.len() as u16;
-                self.prg_rom[address as usize] = data
+                let (index, end) = self.prg_index(address);
+                mapper::copy_chunk(&self.prg_rom[index..end], buffer)
             }
-            _ => (),
-        }
+            _ => {
+                if let Some(data) = self.cpu_read(address) {
+                    buffer[0] = data;
+                }
+                1
+            }
+        });
     }
 
-    fn ppu_read(&mut self, address: u16) -> u8 {
+    fn cpu_poke(&mut self, address: u16, data: u8) -> bool {
         match address {
-            0x0000..=0x1fff => {
-                let address = address % self.chr_rom.len() as u16;
-                self.chr_rom[address as usize]
+            0x6000..=0x7fff => {
+                let index = mapper::prg_ram_index(self.prg_ram.len(), address).0;
+                self.prg_ram[index] = data;
+                true
             }
+            _ => false,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        if let 0x6000..=0x7fff = address {
+            let index = mapper::prg_ram_index(self.prg_ram.len(), address).0;
+            self.prg_ram[index] = data
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1fff => self.chr[address as usize % self.chr.len()],
             _ => 0,
         }
     }
 
-    fn ppu_write(&mut self, _address: u16, _data: u8) {}
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        if let 0x0000..=0x1fff = address {
+            let index = address as usize % self.chr.len();
+            self.chr.write(index, data);
+        }
+    }
+
+    fn cycles_until_irq(&self) -> u64 {
+        u64::MAX
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write(&self.prg_ram);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    serde_state_methods!();
+}
+
+#[cfg(feature = "serde")]
+serde_state!(Nrom {
+    prg_ram as bytes,
+    chr as chr,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bulk_read_matches_single_reads() {
+        let prg_rom: Vec<u8> = (0..16 * 1024).map(|i| (i * 7) as u8).collect();
+        let chr_rom = vec![0; 8 * 1024];
+        let mut mapper = Nrom::new(prg_rom, chr_rom, vec![0; 8 * 1024]);
+        mapper.cpu_write(0x7ffe, 0xaa);
+
+        // RAM, both mirrors of a 16 kB PRG ROM and the wrap to $0000
+        let mut buffer = vec![0; 0x8010];
+        mapper.cpu_read_into(0x7ff0, &mut buffer);
+        for (offset, &data) in buffer.iter().enumerate() {
+            let address = 0x7ff0u16.wrapping_add(offset as u16);
+            assert_eq!(Some(data), mapper.cpu_read(address), "${:04X}", address);
+        }
+    }
+
+    #[test]
+    fn prg_ram_of_other_sizes() {
+        let prg_rom = vec![0xea; 16 * 1024];
+        let mut mapper = Nrom::new(prg_rom.clone(), Vec::new(), vec![0; 2 * 1024]);
+        mapper.cpu_write(0x6001, 0x12);
+        assert_eq!(mapper.cpu_read(0x6801), Some(0x12));
+        assert_eq!(mapper.cpu_read(0x7801), Some(0x12));
+
+        // Only the first 8 kB of larger RAM is mapped
+        let mut mapper = Nrom::new(prg_rom, Vec::new(), vec![0x55; 32 * 1024]);
+        let mut buffer = [0; 4];
+        mapper.cpu_read_into(0x7ffe, &mut buffer);
+        assert_eq!(buffer, [0x55, 0x55, 0xea, 0xea]);
+    }
+
+    #[test]
+    fn empty_chr_rom_is_chr_ram() {
+        let mut mapper = Nrom::new(vec![0; 16 * 1024], Vec::new(), vec![0; 8 * 1024]);
+        mapper.ppu_write(0x0abc, 0x56);
+        assert_eq!(mapper.ppu_read(0x0abc), 0x56);
+
+        // CHR ROM ignores writes
+        let mut mapper = Nrom::new(vec![0; 16 * 1024], vec![0x77; 8 * 1024], vec![0; 8 * 1024]);
+        mapper.ppu_write(0x0abc, 0x56);
+        assert_eq!(mapper.ppu_read(0x0abc), 0x77);
+    }
 }
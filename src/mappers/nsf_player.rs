@@ -0,0 +1,337 @@
+use crate::mapper::{self, Mapper};
+use crate::mappers::namco163::Namco163;
+use crate::mappers::vrc6::Vrc6;
+use crate::nsf::{ExpansionAudio, Nsf};
+use crate::rom::Rom;
+#[cfg(feature = "serde")]
+use crate::state::{serde_state, serde_state_methods};
+use crate::state::{StateReader, StateWriter};
+use crate::Result;
+
+/// Where the player's driver sits. Point the reset vector here, with the
+/// song in A and the region in X, to start a song.
+pub const DRIVER: u16 = 0x4100;
+
+/// Reads $80 once PLAY is due and clears, 0 otherwise.
+const PLAY_DUE: u16 = 0x4110;
+
+/// CPU cycles per second.
+pub const NTSC_CPU_HZ: u64 = 1_789_773;
+pub const PAL_CPU_HZ: u64 = 1_662_607;
+
+/// The board an NSF player provides: 8 kB of PRG RAM, 4 kB PRG banks
+/// selected at $5FF8-$5FFF, the tune's expansion sound and a driver that
+/// calls INIT once and PLAY every tick.
+///
+/// The driver polls a timer rather than waiting for NMI, so the tune's
+/// speed need not match the frame rate. iNES mapper 31 uses the same
+/// banking, hence the id.
+#[derive(Debug, Clone)]
+pub struct NsfPlayer {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    bankswitched: bool,
+    initial_banks: [usize; 8],
+    banks: [usize; 8],
+    driver: [u8; 14],
+    /// Time between PLAY calls and time since the last one, in units of
+    /// 1 / (1 000 000 × CPU Hz) seconds so they divide exactly
+    play_period: u64,
+    play_elapsed: u64,
+    play_due: bool,
+    vrc6: Option<Vrc6>,
+    namco163: Option<Namco163>,
+}
+
+impl NsfPlayer {
+    const BANK_SIZE: usize = 4 * 1024; // 4 kB
+
+    /// Load `nsf` for a CPU running at `cpu_hz`, calling PLAY every `speed`
+    /// microseconds.
+    ///
+    /// Fails for expansion chips other than the VRC6 and Namco 163.
+    pub fn new(nsf: &Nsf, cpu_hz: u64, speed: u16) -> Result<NsfPlayer> {
+        let supported = ExpansionAudio::VRC6 | ExpansionAudio::NAMCO_163;
+        if !supported.contains(nsf.expansion_audio) {
+            return Err(format!(
+                "unsupported expansion audio: {:?}",
+                nsf.expansion_audio - supported
+            )
+            .into());
+        }
+
+        // Without bankswitching the data is placed at its load address;
+        // with it, at that address's offset into the first bank
+        let (padding, initial_banks) = match nsf.bankswitch {
+            Some(banks) => {
+                let banks = [0, 1, 2, 3, 4, 5, 6, 7].map(|slot| banks[slot] as usize);
+                (nsf.load_address as usize % Self::BANK_SIZE, banks)
+            }
+            None => (nsf.load_address as usize - 0x8000, [0, 1, 2, 3, 4, 5, 6, 7]),
+        };
+        let len = padding + nsf.data.len();
+        let banks = len.div_ceil(Self::BANK_SIZE).max(1);
+        let mut prg_rom = vec![0; banks * Self::BANK_SIZE];
+        prg_rom[padding..len].copy_from_slice(&nsf.data);
+
+        let [init_low, init_high] = nsf.init_address.to_le_bytes();
+        let [play_low, play_high] = nsf.play_address.to_le_bytes();
+        let [due_low, due_high] = PLAY_DUE.to_le_bytes();
+        let [wait_low, wait_high] = (DRIVER + 3).to_le_bytes();
+        #[rustfmt::skip]
+        let driver = [
+            0x20, init_low, init_high, // JSR INIT
+            0x2c, due_low, due_high,   // BIT PLAY_DUE
+            0x10, 0xfb,                // BPL $-3
+            0x20, play_low, play_high, // JSR PLAY
+            0x4c, wait_low, wait_high, // JMP $-11
+        ];
+
+        let empty = || Rom::from(Vec::new());
+        Ok(NsfPlayer {
+            prg_rom,
+            prg_ram: vec![0; 8 * 1024],
+            bankswitched: nsf.bankswitch.is_some(),
+            initial_banks,
+            banks: initial_banks,
+            driver,
+            play_period: u64::from(speed.max(1)) * cpu_hz,
+            play_elapsed: 0,
+            play_due: false,
+            vrc6: if nsf.expansion_audio.contains(ExpansionAudio::VRC6) {
+                Some(Vrc6::new(24, empty(), empty(), vec![0; 8 * 1024]))
+            } else {
+                None
+            },
+            namco163: if nsf.expansion_audio.contains(ExpansionAudio::NAMCO_163) {
+                Some(Namco163::new(empty(), empty(), vec![0; 8 * 1024]))
+            } else {
+                None
+            },
+        })
+    }
+
+    /// Index into PRG ROM for $8000-$FFFF, and the end of its bank.
+    fn prg_index(&self, address: u16) -> (usize, usize) {
+        let offset = (address - 0x8000) as usize;
+        mapper::bank_index(
+            self.prg_rom.len(),
+            Self::BANK_SIZE,
+            self.banks[offset / Self::BANK_SIZE],
+            offset % Self::BANK_SIZE,
+        )
+    }
+}
+
+impl Mapper for NsfPlayer {
+    fn id(&self) -> u8 {
+        31
+    }
+
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            PLAY_DUE if self.play_due => Some(0x80),
+            PLAY_DUE => Some(0x00),
+            0x4100..=0x410d => Some(self.driver[(address - DRIVER) as usize]),
+            0x4800..=0x4fff => self
+                .namco163
+                .as_ref()
+                .and_then(|namco163| namco163.cpu_peek(address)),
+            0x6000..=0x7fff => Some(self.prg_ram[(address - 0x6000) as usize]),
+            0x8000..=0xffff => Some(self.prg_rom[self.prg_index(address).0]),
+            _ => None,
+        }
+    }
+
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            PLAY_DUE => {
+                let data = self.cpu_peek(address);
+                self.play_due = false;
+                data
+            }
+            0x4800..=0x4fff => self
+                .namco163
+                .as_mut()
+                .and_then(|namco163| namco163.cpu_read(address)),
+            _ => self.cpu_peek(address),
+        }
+    }
+
+    fn cpu_read_into(&mut self, address: u16, buffer: &mut [u8]) {
+        mapper::read_chunks(address, buffer, |address, buffer| match address {
+            0x8000..=0xffff => {
+                let (index, end) = self.prg_index(address);
+                mapper::copy_chunk(&self.prg_rom[index..end], buffer)
+            }
+            _ => {
+                if let Some(data) = self.cpu_read(address) {
+                    buffer[0] = data;
+                }
+                1
+            }
+        });
+    }
+
+    fn cpu_poke(&mut self, address: u16, data: u8) -> bool {
+        match address {
+            0x6000..=0x7fff => {
+                self.prg_ram[(address - 0x6000) as usize] = data;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        match address {
+            0x4800..=0x4fff | 0xf800..=0xffff => {
+                if let Some(namco163) = &mut self.namco163 {
+                    namco163.cpu_write(address, data);
+                }
+            }
+            0x5ff8..=0x5fff if self.bankswitched => {
+                self.banks[(address - 0x5ff8) as usize] = data as usize;
+            }
+            0x6000..=0x7fff => self.prg_ram[(address - 0x6000) as usize] = data,
+            0x9000..=0xb002 => {
+                if let Some(vrc6) = &mut self.vrc6 {
+                    vrc6.cpu_write(address, data);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, _address: u16) -> u8 {
+        0
+    }
+
+    fn ppu_write(&mut self, _address: u16, _data: u8) {}
+
+    fn cycles_until_irq(&self) -> u64 {
+        u64::MAX
+    }
+
+    fn cpu_clock(&mut self) {
+        self.play_elapsed += 1_000_000;
+        if self.play_elapsed >= self.play_period {
+            self.play_elapsed -= self.play_period;
+            self.play_due = true;
+        }
+        if let Some(vrc6) = &mut self.vrc6 {
+            vrc6.cpu_clock();
+        }
+        if let Some(namco163) = &mut self.namco163 {
+            namco163.cpu_clock();
+        }
+    }
+
+    /// Get ready for the next song: the banks go back to how the file
+    /// sets them and PRG RAM is cleared.
+    fn reset(&mut self) {
+        self.banks = self.initial_banks;
+        self.prg_ram.iter_mut().for_each(|byte| *byte = 0);
+        self.play_elapsed = 0;
+        self.play_due = false;
+    }
+
+    fn audio(&self) -> f32 {
+        let vrc6 = self.vrc6.as_ref().map_or(0.0, Vrc6::audio);
+        let namco163 = self.namco163.as_ref().map_or(0.0, Namco163::audio);
+        vrc6 + namco163
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write(&self.banks);
+        state.write(&self.prg_ram);
+        state.write(&self.play_elapsed);
+        state.write(&self.play_due);
+        if let Some(vrc6) = &self.vrc6 {
+            vrc6.save_state(state);
+        }
+        if let Some(namco163) = &self.namco163 {
+            namco163.save_state(state);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.banks = state.read()?;
+        state.read_into(&mut self.prg_ram)?;
+        self.play_elapsed = state.read()?;
+        self.play_due = state.read()?;
+        if let Some(vrc6) = &mut self.vrc6 {
+            vrc6.load_state(state)?;
+        }
+        if let Some(namco163) = &mut self.namco163 {
+            namco163.load_state(state)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    serde_state_methods!();
+}
+
+#[cfg(feature = "serde")]
+serde_state!(NsfPlayer {
+    banks,
+    prg_ram as bytes,
+    play_elapsed,
+    play_due,
+    vrc6 as seeds,
+    namco163 as seeds,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An NSF of `data` loaded at `load_address`.
+    fn nsf(load_address: u16, bankswitch: Option<[u8; 8]>, data: Vec<u8>) -> Nsf {
+        let mut bytes = vec![0; 0x80];
+        bytes[..8].copy_from_slice(b"NESM\x1a\x01\x01\x01");
+        bytes[0x08..0x0a].copy_from_slice(&load_address.to_le_bytes());
+        if let Some(banks) = bankswitch {
+            bytes[0x70..0x78].copy_from_slice(&banks);
+        }
+        bytes.extend(data);
+        Nsf::from_bytes(bytes).unwrap()
+    }
+
+    #[test]
+    fn loads_at_the_load_address() {
+        let mut mapper =
+            NsfPlayer::new(&nsf(0xc000, None, vec![1, 2]), NTSC_CPU_HZ, 16639).unwrap();
+        assert_eq!(mapper.cpu_read(0xc000), Some(1));
+        assert_eq!(mapper.cpu_read(0xc001), Some(2));
+        assert_eq!(mapper.cpu_read(0xbfff), Some(0));
+    }
+
+    #[test]
+    fn switches_4k_banks() {
+        let data: Vec<u8> = (0..4).flat_map(|bank| vec![bank; 0x1000]).collect();
+        let banks = Some([3, 2, 1, 0, 0, 0, 0, 0]);
+        let mut mapper = NsfPlayer::new(&nsf(0x8000, banks, data), NTSC_CPU_HZ, 16639).unwrap();
+        assert_eq!(mapper.cpu_read(0x8000), Some(3));
+        assert_eq!(mapper.cpu_read(0xa000), Some(1));
+
+        mapper.cpu_write(0x5ff8, 2);
+        assert_eq!(mapper.cpu_read(0x8fff), Some(2));
+        mapper.reset();
+        assert_eq!(mapper.cpu_read(0x8fff), Some(3));
+    }
+
+    #[test]
+    fn play_comes_due_at_the_speed() {
+        // 1000 µs at 1 MHz is 1000 cycles
+        let mut mapper = NsfPlayer::new(&nsf(0x8000, None, vec![]), 1_000_000, 1000).unwrap();
+        for _ in 0..999 {
+            mapper.cpu_clock();
+        }
+        assert_eq!(mapper.cpu_read(PLAY_DUE), Some(0x00));
+        mapper.cpu_clock();
+        assert_eq!(mapper.cpu_read(PLAY_DUE), Some(0x80));
+        assert_eq!(mapper.cpu_read(PLAY_DUE), Some(0x00));
+    }
+}
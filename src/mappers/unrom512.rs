@@ -0,0 +1,407 @@
+use crate::ines::Mirroring;
+use crate::mapper::{self, Mapper};
+use crate::rom::Rom;
+#[cfg(feature = "serde")]
+use crate::state::{serde_state, serde_state_methods};
+use crate::state::{State, StateReader, StateWriter};
+use crate::Result;
+
+/// Where a flashable board is in the flash chip's command sequences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum Flash {
+    Ready,
+    /// $AA written to $5555
+    Unlocking,
+    /// $55 written to $2AAA after that
+    Unlocked,
+    /// The next write programs a byte
+    Program,
+    /// Erase requested, waiting for the unlock sequence again
+    Erase,
+    EraseUnlocking,
+    EraseUnlocked,
+    /// Reads return the manufacturer and device IDs
+    SoftwareId,
+}
+
+impl State for Flash {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&(*self as u8));
+    }
+
+    fn load(state: &mut StateReader) -> Result<Flash> {
+        const STATES: [Flash; 8] = [
+            Flash::Ready,
+            Flash::Unlocking,
+            Flash::Unlocked,
+            Flash::Program,
+            Flash::Erase,
+            Flash::EraseUnlocking,
+            Flash::EraseUnlocked,
+            Flash::SoftwareId,
+        ];
+        let index = state.read::<u8>()? as usize;
+        STATES
+            .get(index)
+            .copied()
+            .ok_or_else(|| format!("{} is not a flash state", index).into())
+    }
+}
+
+/// Mapper 30 (UNROM 512): 16 kB PRG banks with the last fixed, four 8 kB
+/// CHR RAM banks and, depending on the header, one-screen mirroring
+/// selected by the bank register.
+///
+/// Boards with the battery flag set have an SST39SF0x0 flash chip for PRG
+/// ROM that games rewrite to save, which [`Mapper::battery_ram`] exposes.
+/// On those the bank register is at $C000-$FFFF and writes to $8000-$BFFF
+/// go to the flash chip.
+#[derive(Debug, Clone)]
+pub struct Unrom512 {
+    prg_rom: Rom,
+    /// PRG ROM once the game has written to flash or a save is loaded
+    flash: Option<Vec<u8>>,
+    flashable: bool,
+    flash_state: Flash,
+    chr_ram: Vec<u8>,
+    /// Whether bit 7 of the bank register selects the nametable
+    one_screen: bool,
+    /// Last value written to the bank register
+    bank: u8,
+}
+
+impl Unrom512 {
+    const PRG_BANK_SIZE: usize = 16 * 1024; // 16 kB
+    const CHR_BANK_SIZE: usize = 8 * 1024; // 8 kB
+    const SECTOR_SIZE: usize = 4 * 1024; // 4 kB
+
+    pub fn new<V>(prg_rom: V, one_screen: bool, flashable: bool) -> Unrom512
+    where
+        V: Into<Rom>,
+    {
+        Unrom512 {
+            prg_rom: prg_rom.into(),
+            flash: None,
+            flashable,
+            flash_state: Flash::Ready,
+            chr_ram: vec![0; 32 * 1024],
+            one_screen,
+            bank: 0,
+        }
+    }
+
+    fn prg(&self) -> &[u8] {
+        self.flash.as_deref().unwrap_or(&self.prg_rom)
+    }
+
+    fn prg_mut(&mut self) -> &mut [u8] {
+        let prg_rom = &self.prg_rom;
+        self.flash.get_or_insert_with(|| prg_rom.to_vec())
+    }
+
+    /// Index into PRG for $8000-$FFFF, and the end of its bank.
+    fn prg_index(&self, address: u16) -> (usize, usize) {
+        let bank = match address {
+            0x8000..=0xbfff => self.bank as usize & 0x1f,
+            _ => mapper::bank_count(self.prg().len(), Self::PRG_BANK_SIZE) - 1,
+        };
+        let offset = address as usize % Self::PRG_BANK_SIZE;
+        mapper::bank_index(self.prg().len(), Self::PRG_BANK_SIZE, bank, offset)
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        let bank = (self.bank as usize >> 5) & 0x03;
+        bank * Self::CHR_BANK_SIZE + address as usize
+    }
+
+    /// Reads in software ID mode: the manufacturer, then the device, which
+    /// depends on the size of the chip.
+    fn software_id(&self, address: u16) -> u8 {
+        if address & 1 == 0 {
+            return 0xbf;
+        }
+        match self.prg().len() {
+            0..=0x20000 => 0xb5,
+            0x20001..=0x40000 => 0xb6,
+            _ => 0xb7,
+        }
+    }
+
+    /// Feed a write at $8000-$BFFF to the flash chip.
+    fn write_flash(&mut self, address: u16, data: u8) {
+        let index = self.prg_index(address).0;
+        // The chip decodes commands from A0-A14
+        let command_address = index & 0x7fff;
+        self.flash_state = match (self.flash_state, command_address, data) {
+            (Flash::Program, _, _) => {
+                // Programming can only clear bits
+                self.prg_mut()[index] &= data;
+                Flash::Ready
+            }
+            (_, _, 0xf0) => Flash::Ready,
+            (Flash::Ready, 0x5555, 0xaa) => Flash::Unlocking,
+            (Flash::Unlocking, 0x2aaa, 0x55) => Flash::Unlocked,
+            (Flash::Unlocked, 0x5555, 0xa0) => Flash::Program,
+            (Flash::Unlocked, 0x5555, 0x80) => Flash::Erase,
+            (Flash::Unlocked, 0x5555, 0x90) => Flash::SoftwareId,
+            (Flash::Erase, 0x5555, 0xaa) => Flash::EraseUnlocking,
+            (Flash::EraseUnlocking, 0x2aaa, 0x55) => Flash::EraseUnlocked,
+            (Flash::EraseUnlocked, _, 0x30) => {
+                let sector = index / Self::SECTOR_SIZE * Self::SECTOR_SIZE;
+                self.prg_mut()[sector..sector + Self::SECTOR_SIZE].fill(0xff);
+                Flash::Ready
+            }
+            (Flash::EraseUnlocked, 0x5555, 0x10) => {
+                self.prg_mut().fill(0xff);
+                Flash::Ready
+            }
+            (Flash::SoftwareId, _, _) => Flash::SoftwareId,
+            _ => Flash::Ready,
+        };
+    }
+}
+
+impl Mapper for Unrom512 {
+    fn id(&self) -> u8 {
+        30
+    }
+
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x8000..=0xffff if self.flash_state == Flash::SoftwareId => {
+                Some(self.software_id(address))
+            }
+            0x8000..=0xffff => Some(self.prg()[self.prg_index(address).0]),
+            _ => None,
+        }
+    }
+
+    fn cpu_read_into(&mut self, address: u16, buffer: &mut [u8]) {
+        mapper::read_chunks(address, buffer, |address, buffer| match address {
+            0x8000..=0xffff if self.flash_state != Flash::SoftwareId => {
+                let (index, end) = self.prg_index(address);
+                mapper::copy_chunk(&self.prg()[index..end], buffer)
+            }
+            _ => {
+                if let Some(data) = self.cpu_read(address) {
+                    buffer[0] = data;
+                }
+                1
+            }
+        });
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        match address {
+            0x8000..=0xbfff if self.flashable => self.write_flash(address, data),
+            0x8000..=0xffff => self.bank = data,
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1fff => self.chr_ram[self.chr_index(address)],
+            _ => 0,
+        }
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        if let 0x0000..=0x1fff = address {
+            let index = self.chr_index(address);
+            self.chr_ram[index] = data;
+        }
+    }
+
+    fn cycles_until_irq(&self) -> u64 {
+        u64::MAX
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        if !self.one_screen {
+            None
+        } else if self.bank & 0x80 == 0 {
+            Some(Mirroring::SingleScreenLower)
+        } else {
+            Some(Mirroring::SingleScreenUpper)
+        }
+    }
+
+    fn set_mirroring(&mut self, mirroring: Mirroring) {
+        match mirroring {
+            _ if !self.one_screen => {}
+            Mirroring::SingleScreenLower => self.bank &= !0x80,
+            Mirroring::SingleScreenUpper => self.bank |= 0x80,
+            _ => {}
+        }
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        if self.flashable {
+            Some(self.prg())
+        } else {
+            None
+        }
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        if self.flashable && data.len() == self.prg_rom.len() {
+            self.flash = Some(data.to_vec());
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write(&self.bank);
+        state.write(&self.flash_state);
+        state.write(&self.flash);
+        state.write(&self.chr_ram);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.bank = state.read()?;
+        self.flash_state = state.read()?;
+        let flash: Option<Vec<u8>> = state.read()?;
+        if let Some(flash) = &flash {
+            if flash.len() != self.prg_rom.len() {
+                return Err(format!(
+                    "saved {} bytes of flash where {} were expected",
+                    flash.len(),
+                    self.prg_rom.len()
+                )
+                .into());
+            }
+        }
+        self.flash = flash;
+        state.read_into(&mut self.chr_ram)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    serde_state_methods!();
+}
+
+#[cfg(feature = "serde")]
+serde_state!(Unrom512 {
+    bank,
+    flash_state,
+    flash,
+    chr_ram as bytes,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 128 kB of PRG ROM, each byte holding its bank number.
+    fn mapper(one_screen: bool, flashable: bool) -> Unrom512 {
+        let prg_rom: Vec<u8> = (0..8)
+            .flat_map(|bank| vec![bank; Unrom512::PRG_BANK_SIZE])
+            .collect();
+        Unrom512::new(prg_rom, one_screen, flashable)
+    }
+
+    /// Write the flash chip's unlock sequence, with the bank register set
+    /// as the chip's address lines need.
+    fn unlock(mapper: &mut Unrom512) {
+        for (bank, address, data) in [(1, 0x9555, 0xaa), (0, 0xaaaa, 0x55)] {
+            mapper.cpu_write(0xc000, bank);
+            mapper.cpu_write(address, data);
+        }
+    }
+
+    fn flash_command(mapper: &mut Unrom512, command: u8) {
+        unlock(mapper);
+        mapper.cpu_write(0xc000, 0x01);
+        mapper.cpu_write(0x9555, command);
+    }
+
+    #[test]
+    fn prg_and_chr_banks() {
+        let mut mapper = mapper(false, false);
+        mapper.cpu_write(0x8000, 0x43);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x03));
+        assert_eq!(mapper.cpu_read(0xc000), Some(0x07));
+
+        mapper.ppu_write(0x0000, 0xaa);
+        mapper.cpu_write(0x8000, 0x03);
+        assert_eq!(mapper.ppu_read(0x0000), 0x00);
+        mapper.cpu_write(0x8000, 0x43);
+        assert_eq!(mapper.ppu_read(0x0000), 0xaa);
+    }
+
+    #[test]
+    fn one_screen_mirroring() {
+        assert_eq!(mapper(false, false).mirroring(), None);
+
+        let mut mapper = mapper(true, false);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::SingleScreenLower));
+        mapper.cpu_write(0x8000, 0x80);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::SingleScreenUpper));
+    }
+
+    #[test]
+    fn flash_program_and_sector_erase() {
+        let mut mapper = mapper(false, true);
+        flash_command(&mut mapper, 0xa0);
+        mapper.cpu_write(0xc000, 0x02);
+        mapper.cpu_write(0x8123, 0x2a);
+        assert_eq!(mapper.cpu_read(0x8123), Some(0x02 & 0x2a));
+        assert_eq!(mapper.battery_ram().unwrap()[0x8123], 0x02);
+
+        // Writes outside a command sequence change nothing
+        mapper.cpu_write(0x8000, 0x00);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x02));
+
+        flash_command(&mut mapper, 0x80);
+        unlock(&mut mapper);
+        mapper.cpu_write(0xc000, 0x02);
+        mapper.cpu_write(0x8000, 0x30);
+        assert_eq!(mapper.cpu_read(0x8123), Some(0xff));
+        assert_eq!(mapper.cpu_read(0x8fff), Some(0xff));
+        assert_eq!(mapper.cpu_read(0x9000), Some(0x02));
+    }
+
+    #[test]
+    fn software_id() {
+        let mut mapper = mapper(false, true);
+        flash_command(&mut mapper, 0x90);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0xbf));
+        assert_eq!(mapper.cpu_read(0x8001), Some(0xb5));
+        mapper.cpu_write(0x8000, 0xf0);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x01));
+    }
+
+    #[test]
+    fn battery_ram_round_trip() {
+        assert_eq!(mapper(false, false).battery_ram(), None);
+
+        let mut mapper = mapper(false, true);
+        let mut save = mapper.battery_ram().unwrap().to_vec();
+        save[0] = 0x55;
+        mapper.load_battery_ram(&save);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x55));
+
+        // Saves for another size of chip are ignored
+        mapper.load_battery_ram(&[0; 16]);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x55));
+    }
+
+    #[test]
+    fn bad_flash_states_are_rejected() {
+        let mut mapper = mapper(false, true);
+        for flash in [Vec::new(), vec![0; Unrom512::PRG_BANK_SIZE]] {
+            let mut state = StateWriter::new();
+            state.write(&mapper.bank);
+            state.write(&mapper.flash_state);
+            state.write(&Some(flash));
+            state.write(&mapper.chr_ram);
+            let state = state.into_bytes();
+            let error = mapper
+                .load_state(&mut StateReader::new(&state))
+                .unwrap_err();
+            assert!(error.to_string().contains("flash"), "{}", error);
+        }
+        assert_eq!(mapper.cpu_read(0xc000), Some(7));
+    }
+}
@@ -1,25 +1,53 @@
-use crate::mapper::Mapper;
+use crate::mapper::{self, Chr, Mapper};
+use crate::rom::Rom;
+#[cfg(feature = "serde")]
+use crate::state::{serde_state, serde_state_methods};
+use crate::state::{StateReader, StateWriter};
+use crate::Result;
 
 #[derive(Debug, Clone)]
 pub struct Uxrom {
-    prg_rom: Vec<u8>,
-    chr_rom: Vec<u8>,
+    prg_rom: Rom,
+    chr: Chr,
     bank: usize,
+    /// Whether writes are ANDed with the ROM byte at the same address
+    bus_conflicts: bool,
 }
 
 impl Uxrom {
     const BANK_SIZE: usize = 16 * 1024; // 16 kB
 
-    pub fn new<V>(prg_rom: V, chr_rom: V) -> Uxrom
+    /// Empty CHR ROM gets 8 kB of CHR RAM, as most of these boards have.
+    pub fn new<V, C>(prg_rom: V, chr: C) -> Uxrom
     where
-        V: Into<Vec<u8>>,
+        V: Into<Rom>,
+        C: Into<Chr>,
     {
         Uxrom {
             prg_rom: prg_rom.into(),
-            chr_rom: chr_rom.into(),
+            chr: chr.into(),
             bank: 0,
+            bus_conflicts: false,
         }
     }
+
+    /// Emulate boards without bus-conflict prevention, where the ROM drives
+    /// the data bus during register writes so only bits set in both the
+    /// written value and the ROM byte reach the register.
+    pub fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.bus_conflicts = enabled;
+    }
+
+    /// Index into PRG ROM for $8000-$FFFF, and the end of its bank. The
+    /// last bank is fixed at $C000.
+    fn prg_index(&self, address: u16) -> (usize, usize) {
+        let bank = match address {
+            0x8000..=0xbfff => self.bank,
+            _ => mapper::bank_count(self.prg_rom.len(), Self::BANK_SIZE) - 1,
+        };
+        let offset = address as usize % Self::BANK_SIZE;
+        mapper::bank_index(self.prg_rom.len(), Self::BANK_SIZE, bank, offset)
+    }
 }
 
 impl Mapper for Uxrom {
@@ -27,43 +55,75 @@ impl Mapper for Uxrom {
         2
     }
 
-    fn cpu_read(&mut self, address: u16) -> u8 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
         match address {
-            0x8000..=0xbfff => {
-                let index = (address - 0x8000) as usize + Self::BANK_SIZE * self.bank;
-                self.prg_rom[index]
-            }
-            0xc000..=0xffff => {
-                let last_bank = self.prg_rom.len() - Self::BANK_SIZE;
-                let index = last_bank + (address % 0xc000) as usize;
-                self.prg_rom[index]
-            }
-            _ => 0,
+            0x8000..=0xffff => Some(self.prg_rom[self.prg_index(address).0]),
+            _ => None,
         }
     }
 
-    fn cpu_write(&mut self, address: u16, data: u8) {
-        match address {
+    fn cpu_read_into(&mut self, address: u16, buffer: &mut [u8]) {
+        mapper::read_chunks(address, buffer, |address, buffer| match address {
             0x8000..=0xffff => {
-                self.bank = data as usize;
+                let (index, end) = self.prg_index(address);
+                mapper::copy_chunk(&self.prg_rom[index..end], buffer)
             }
-            _ => (),
+            _ => {
+                if let Some(data) = self.cpu_read(address) {
+                    buffer[0] = data;
+                }
+                1
+            }
+        });
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        if let 0x8000..=0xffff = address {
+            let data = if self.bus_conflicts {
+                self.cpu_peek(address).map_or(data, |rom| data & rom)
+            } else {
+                data
+            };
+            self.bank = data as usize;
         }
     }
 
-    fn ppu_read(&mut self, address: u16) -> u8 {
+    fn ppu_peek(&self, address: u16) -> u8 {
         match address {
-            0x0000..=0x1fff => {
-                let address = address % self.chr_rom.len() as u16;
-                self.chr_rom[address as usize]
-            }
+            0x0000..=0x1fff => self.chr[address as usize % self.chr.len()],
             _ => 0,
         }
     }
 
-    fn ppu_write(&mut self, _address: u16, _data: u8) {}
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        if let 0x0000..=0x1fff = address {
+            let index = address as usize % self.chr.len();
+            self.chr.write(index, data);
+        }
+    }
+
+    fn cycles_until_irq(&self) -> u64 {
+        u64::MAX
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write(&self.bank);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.bank = state.read()?;
+        self.chr.load_state(state)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    serde_state_methods!();
 }
 
+#[cfg(feature = "serde")]
+serde_state!(Uxrom { bank, chr as chr });
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -82,19 +142,77 @@ mod tests {
         let mut mapper = Uxrom::new(prg_rom, chr_rom);
 
         // read from fixed bank
-        assert_eq!(mapper.cpu_read(0xc000), 0x0f);
+        assert_eq!(mapper.cpu_read(0xc000), Some(0x0f));
 
         // switch to bank 0
         mapper.cpu_write(0x8000, 0x00);
-        assert_eq!(mapper.cpu_read(0x8000), 0x00);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x00));
 
         // switch to bank 1
         mapper.cpu_write(0x8000, 0x01);
 
         // fixed bank should not have changed
-        assert_eq!(mapper.cpu_read(0xc000), 0x0f);
+        assert_eq!(mapper.cpu_read(0xc000), Some(0x0f));
 
         // should be reading from bank 1
-        assert_eq!(mapper.cpu_read(0x8000), 0x01);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x01));
+    }
+
+    #[test]
+    fn bulk_read_matches_single_reads() {
+        let prg_rom: Vec<u8> = (0..64 * 1024).map(|i| (i / 3) as u8).collect();
+        let chr_rom = vec![0; 8 * 1024];
+        let mut mapper = Uxrom::new(prg_rom, chr_rom);
+        mapper.cpu_write(0x8000, 0x02);
+
+        let mut buffer = vec![0; 0x8010];
+        mapper.cpu_read_into(0x7ff8, &mut buffer);
+        for (offset, &data) in buffer.iter().enumerate() {
+            let address = 0x7ff8u16.wrapping_add(offset as u16);
+            let read = mapper.cpu_read(address).unwrap_or(0);
+            assert_eq!(data, read, "${:04X}", address);
+        }
+    }
+
+    #[test]
+    fn bus_conflicts() {
+        let mut prg_rom: Vec<u8> = (0..4)
+            .flat_map(|bank| vec![bank; Uxrom::BANK_SIZE])
+            .collect();
+        prg_rom[0x0000] = 0x01;
+        let mut mapper = Uxrom::new(prg_rom, vec![0; 8 * 1024]);
+
+        mapper.set_bus_conflicts(true);
+        mapper.cpu_write(0xc000, 0x02);
+        assert_eq!(mapper.cpu_read(0x8001), Some(0x02));
+        // The ROM at $8000 in bank 2 holds 2, which masks out bit 0
+        mapper.cpu_write(0x8000, 0x03);
+        assert_eq!(mapper.cpu_read(0x8001), Some(0x02));
+
+        mapper.set_bus_conflicts(false);
+        mapper.cpu_write(0x8000, 0x03);
+        assert_eq!(mapper.cpu_read(0x8001), Some(0x03));
+    }
+
+    #[test]
+    fn empty_chr_rom_is_chr_ram() {
+        let mut mapper = Uxrom::new(vec![0; 32 * 1024], Vec::new());
+        mapper.ppu_write(0x0000, 0x12);
+        mapper.ppu_write(0x1fff, 0x34);
+        assert_eq!(mapper.ppu_read(0x0000), 0x12);
+        assert_eq!(mapper.ppu_read(0x1fff), 0x34);
+
+        let mut state = StateWriter::new();
+        mapper.save_state(&mut state);
+        let state = state.into_bytes();
+        let mut loaded = Uxrom::new(vec![0; 32 * 1024], Vec::new());
+        loaded.load_state(&mut StateReader::new(&state)).unwrap();
+        assert_eq!(loaded.ppu_read(0x1fff), 0x34);
+
+        // Version 1 states end after the bank
+        let mut old = StateReader::new(&state[..8]);
+        old.set_version(1);
+        loaded.load_state(&mut old).unwrap();
+        assert_eq!(old.remaining(), 0);
     }
 }
@@ -0,0 +1,715 @@
+use crate::apu;
+use crate::ines::Mirroring;
+use crate::mapper::{self, Chr, Mapper};
+use crate::rom::Rom;
+#[cfg(feature = "serde")]
+use crate::state::{serde_state, serde_state_methods};
+use crate::state::{State, StateReader, StateWriter};
+use crate::Result;
+
+/// A VRC6 pulse channel: sixteen steps, `duty` + 1 of them high.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Pulse {
+    volume: u8,
+    duty: u8,
+    /// Ignore the duty and output the volume constantly
+    digitized: bool,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    /// Counts down from 15
+    step: u8,
+}
+
+impl Pulse {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.volume = data & 0x0f;
+                self.duty = (data >> 4) & 0x07;
+                self.digitized = data & 0x80 != 0;
+            }
+            1 => self.period = self.period & 0x0f00 | data as u16,
+            _ => {
+                self.period = self.period & 0x00ff | (data as u16 & 0x0f) << 8;
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = self.step.wrapping_sub(1) & 0x0f;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.digitized || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+impl State for Pulse {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.volume);
+        state.write(&self.duty);
+        state.write(&self.digitized);
+        state.write(&self.period);
+        state.write(&self.enabled);
+        state.write(&self.timer);
+        state.write(&self.step);
+    }
+
+    fn load(state: &mut StateReader) -> Result<Pulse> {
+        Ok(Pulse {
+            volume: state.read()?,
+            duty: state.read()?,
+            digitized: state.read()?,
+            period: state.read()?,
+            enabled: state.read()?,
+            timer: state.read()?,
+            step: state.read()?,
+        })
+    }
+}
+
+/// The VRC6 sawtooth: an accumulator that adds `rate` on even clocks of a
+/// fourteen clock cycle and is cleared on the fourteenth, so six additions
+/// make up each ramp.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Sawtooth {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    /// 0-13
+    step: u8,
+    accumulator: u8,
+}
+
+impl Sawtooth {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => self.rate = data & 0x3f,
+            1 => self.period = self.period & 0x0f00 | data as u16,
+            _ => {
+                self.period = self.period & 0x00ff | (data as u16 & 0x0f) << 8;
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step += 1;
+            if self.step == 14 {
+                self.step = 0;
+                self.accumulator = 0;
+            } else if self.step.is_multiple_of(2) {
+                self.accumulator = self.accumulator.wrapping_add(self.rate);
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// The top five bits of the accumulator.
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+impl State for Sawtooth {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.rate);
+        state.write(&self.period);
+        state.write(&self.enabled);
+        state.write(&self.timer);
+        state.write(&self.step);
+        state.write(&self.accumulator);
+    }
+
+    fn load(state: &mut StateReader) -> Result<Sawtooth> {
+        Ok(Sawtooth {
+            rate: state.read()?,
+            period: state.read()?,
+            enabled: state.read()?,
+            timer: state.read()?,
+            step: state.read()?,
+            accumulator: state.read()?,
+        })
+    }
+}
+
+/// Mappers 24 (VRC6a) and 26 (VRC6b): a 16 kB and an 8 kB switchable PRG
+/// bank, eight 1 kB CHR banks, a scanline/cycle IRQ counter and three extra
+/// sound channels. The VRC6b has address lines A0 and A1 swapped.
+///
+/// Only the CHR banking mode used by released games (mode 0) is supported.
+#[derive(Debug, Clone)]
+pub struct Vrc6 {
+    id: u8,
+    prg_rom: Rom,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    /// The 16 kB bank at $8000 and 8 kB bank at $C000
+    prg_banks: [usize; 2],
+    chr_banks: [usize; 8],
+    /// Last value written to $B003
+    control: u8,
+    irq_latch: u8,
+    irq_counter: u8,
+    /// CPU cycles times three left until the next scanline clock
+    irq_prescaler: i16,
+    irq_enabled: bool,
+    /// Whether acknowledging the IRQ enables it again
+    irq_enabled_after_ack: bool,
+    /// Clock the counter every CPU cycle instead of every scanline
+    irq_cycle_mode: bool,
+    irq: bool,
+    /// Last value written to $9003
+    frequency_control: u8,
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    sawtooth: Sawtooth,
+}
+
+impl Vrc6 {
+    const CHR_BANK_SIZE: usize = 1024; // 1 kB
+
+    /// `id` is 24 for the VRC6a or 26 for the VRC6b.
+    pub fn new<V, C>(id: u8, prg_rom: V, chr: C, prg_ram: Vec<u8>) -> Vrc6
+    where
+        V: Into<Rom>,
+        C: Into<Chr>,
+    {
+        assert!(id == 24 || id == 26, "not a VRC6: {}", id);
+        Vrc6 {
+            id,
+            prg_rom: prg_rom.into(),
+            prg_ram,
+            chr: chr.into(),
+            prg_banks: [0; 2],
+            chr_banks: [0; 8],
+            control: 0,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_prescaler: 341,
+            irq_enabled: false,
+            irq_enabled_after_ack: false,
+            irq_cycle_mode: false,
+            irq: false,
+            frequency_control: 0,
+            pulse_1: Pulse {
+                step: 15,
+                ..Pulse::default()
+            },
+            pulse_2: Pulse {
+                step: 15,
+                ..Pulse::default()
+            },
+            sawtooth: Sawtooth::default(),
+        }
+    }
+
+    /// Index into PRG ROM for $8000-$FFFF, and the end of its bank.
+    fn prg_index(&self, address: u16) -> (usize, usize) {
+        let len = self.prg_rom.len();
+        let (bank, bank_size) = match address {
+            0x8000..=0xbfff => (self.prg_banks[0], 16 * 1024),
+            0xc000..=0xdfff => (self.prg_banks[1], 8 * 1024),
+            _ => (mapper::bank_count(len, 8 * 1024) - 1, 8 * 1024),
+        };
+        let offset = address as usize % bank_size;
+        mapper::bank_index(len, bank_size, bank, offset)
+    }
+
+    /// Index into CHR for $0000-$1FFF, through its 1 kB bank.
+    fn chr_index(&self, address: u16) -> usize {
+        let bank = self.chr_banks[address as usize / Self::CHR_BANK_SIZE];
+        let offset = address as usize % Self::CHR_BANK_SIZE;
+        mapper::bank_index(self.chr.len(), Self::CHR_BANK_SIZE, bank, offset).0
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.control & 0x80 != 0
+    }
+
+    /// The VRC6b swaps A0 and A1, so both see the same register layout.
+    fn register(&self, address: u16) -> u16 {
+        let address = address & 0xf003;
+        if self.id == 26 {
+            address & 0xf000 | (address & 0x01) << 1 | (address & 0x02) >> 1
+        } else {
+            address
+        }
+    }
+
+    /// Right shift applied to the sound channels' periods by $9003.
+    fn frequency_shift(&self) -> u8 {
+        if self.frequency_control & 0x04 != 0 {
+            8
+        } else if self.frequency_control & 0x02 != 0 {
+            4
+        } else {
+            0
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0xff {
+            self.irq_counter = self.irq_latch;
+            self.irq = true;
+        } else {
+            self.irq_counter += 1;
+        }
+    }
+
+    /// The sum of the three channels, 0-61.
+    fn level(&self) -> u8 {
+        self.pulse_1.output() + self.pulse_2.output() + self.sawtooth.output()
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn id(&self) -> u8 {
+        self.id
+    }
+
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if self.prg_ram_enabled() => {
+                Some(self.prg_ram[mapper::prg_ram_index(self.prg_ram.len(), address).0])
+            }
+            0x8000..=0xffff => Some(self.prg_rom[self.prg_index(address).0]),
+            _ => None,
+        }
+    }
+
+    fn cpu_read_into(&mut self, address: u16, buffer: &mut [u8]) {
+        mapper::read_chunks(address, buffer, |address, buffer| match address {
+            0x8000..=0xffff => {
+                let (index, end) = self.prg_index(address);
+                mapper::copy_chunk(&self.prg_rom[index..end], buffer)
+            }
+            _ => {
+                if let Some(data) = self.cpu_read(address) {
+                    buffer[0] = data;
+                }
+                1
+            }
+        });
+    }
+
+    fn cpu_poke(&mut self, address: u16, data: u8) -> bool {
+        match address {
+            0x6000..=0x7fff => {
+                let index = mapper::prg_ram_index(self.prg_ram.len(), address).0;
+                self.prg_ram[index] = data;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        if let 0x6000..=0x7fff = address {
+            if self.prg_ram_enabled() {
+                let index = mapper::prg_ram_index(self.prg_ram.len(), address).0;
+                self.prg_ram[index] = data;
+            }
+            return;
+        }
+        let register = self.register(address);
+        match register {
+            0x8000..=0x8003 => self.prg_banks[0] = data as usize & 0x0f,
+            0x9000..=0x9002 => self.pulse_1.write(register & 0x03, data),
+            0x9003 => self.frequency_control = data,
+            0xa000..=0xa002 => self.pulse_2.write(register & 0x03, data),
+            0xb000..=0xb002 => self.sawtooth.write(register & 0x03, data),
+            0xb003 => self.control = data,
+            0xc000..=0xc003 => self.prg_banks[1] = data as usize & 0x1f,
+            0xd000..=0xd003 => self.chr_banks[(register & 0x03) as usize] = data as usize,
+            0xe000..=0xe003 => self.chr_banks[4 + (register & 0x03) as usize] = data as usize,
+            0xf000 => self.irq_latch = data,
+            0xf001 => {
+                self.irq_enabled_after_ack = data & 0x01 != 0;
+                self.irq_enabled = data & 0x02 != 0;
+                self.irq_cycle_mode = data & 0x04 != 0;
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_latch;
+                    self.irq_prescaler = 341;
+                }
+                self.irq = false;
+            }
+            0xf002 => {
+                self.irq_enabled = self.irq_enabled_after_ack;
+                self.irq = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1fff => self.chr[self.chr_index(address)],
+            _ => 0,
+        }
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        if let 0x0000..=0x1fff = address {
+            let index = self.chr_index(address);
+            self.chr.write(index, data);
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(match (self.control >> 2) & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        })
+    }
+
+    fn set_mirroring(&mut self, mirroring: Mirroring) {
+        let bits = match mirroring {
+            Mirroring::Vertical => 0,
+            Mirroring::Horizontal => 1,
+            Mirroring::SingleScreenLower => 2,
+            Mirroring::SingleScreenUpper => 3,
+            Mirroring::FourScreen => return,
+        };
+        self.control = (self.control & !0x0c) | bits << 2;
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq
+    }
+
+    /// The counter reaches $FF after one clock per count, and in scanline
+    /// mode a clock takes 113 or 114 cycles once the prescaler runs out.
+    fn cycles_until_irq(&self) -> u64 {
+        if !self.irq_enabled {
+            return u64::MAX;
+        }
+        let counts = u64::from(0xff - self.irq_counter);
+        if self.irq_cycle_mode {
+            counts
+        } else {
+            let first = (self.irq_prescaler.max(1) as u64 - 1) / 3;
+            first + counts * 113
+        }
+    }
+
+    fn cpu_clock(&mut self) {
+        if self.irq_enabled {
+            if self.irq_cycle_mode {
+                self.clock_irq_counter();
+            } else {
+                // 341 PPU dots to a scanline, three to a CPU cycle
+                self.irq_prescaler -= 3;
+                if self.irq_prescaler <= 0 {
+                    self.irq_prescaler += 341;
+                    self.clock_irq_counter();
+                }
+            }
+        }
+
+        // $9003 bit 0 halts all three channels
+        if self.frequency_control & 0x01 == 0 {
+            let shift = self.frequency_shift();
+            self.pulse_1.clock(shift);
+            self.pulse_2.clock(shift);
+            self.sawtooth.clock(shift);
+        }
+    }
+
+    /// A VRC6 pulse at full volume is about as loud as an APU pulse at full
+    /// volume, and the channels mix linearly.
+    fn audio(&self) -> f32 {
+        self.level() as f32 * apu::PULSE_MAX / 15.0
+    }
+
+    /// PRG RAM starts out disabled, so the trainer is copied in directly.
+    fn load_trainer(&mut self, trainer: &[u8]) {
+        for (offset, &data) in trainer.iter().enumerate() {
+            let index = mapper::prg_ram_index(self.prg_ram.len(), 0x7000 + offset as u16).0;
+            self.prg_ram[index] = data;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write(&self.prg_banks);
+        state.write(&self.chr_banks);
+        state.write(&self.control);
+        state.write(&self.irq_latch);
+        state.write(&self.irq_counter);
+        state.write(&self.irq_prescaler);
+        state.write(&self.irq_enabled);
+        state.write(&self.irq_enabled_after_ack);
+        state.write(&self.irq_cycle_mode);
+        state.write(&self.irq);
+        state.write(&self.frequency_control);
+        state.write(&self.pulse_1);
+        state.write(&self.pulse_2);
+        state.write(&self.sawtooth);
+        state.write(&self.prg_ram);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.prg_banks = state.read()?;
+        self.chr_banks = state.read()?;
+        self.control = state.read()?;
+        self.irq_latch = state.read()?;
+        self.irq_counter = state.read()?;
+        self.irq_prescaler = state.read()?;
+        self.irq_enabled = state.read()?;
+        self.irq_enabled_after_ack = state.read()?;
+        self.irq_cycle_mode = state.read()?;
+        self.irq = state.read()?;
+        self.frequency_control = state.read()?;
+        self.pulse_1 = state.read()?;
+        self.pulse_2 = state.read()?;
+        self.sawtooth = state.read()?;
+        state.read_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    serde_state_methods!();
+}
+
+#[cfg(feature = "serde")]
+serde_state!(Vrc6 {
+    prg_banks,
+    chr_banks,
+    control,
+    irq_latch,
+    irq_counter,
+    irq_prescaler,
+    irq_enabled,
+    irq_enabled_after_ack,
+    irq_cycle_mode,
+    irq,
+    frequency_control,
+    pulse_1,
+    pulse_2,
+    sawtooth,
+    prg_ram as bytes,
+    chr as chr,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 128 kB of PRG ROM in 8 kB banks and 32 kB of CHR ROM in 1 kB banks,
+    /// each byte holding its bank number.
+    fn mapper(id: u8) -> Vrc6 {
+        let prg_rom: Vec<u8> = (0..16).flat_map(|bank| vec![bank; 8 * 1024]).collect();
+        let chr_rom: Vec<u8> = (0..32)
+            .flat_map(|bank| vec![bank; Vrc6::CHR_BANK_SIZE])
+            .collect();
+        Vrc6::new(id, prg_rom, chr_rom, vec![0; 8 * 1024])
+    }
+
+    fn run(mapper: &mut Vrc6, cycles: u32) {
+        for _ in 0..cycles {
+            mapper.cpu_clock();
+        }
+    }
+
+    #[test]
+    fn prg_banks() {
+        let mut mapper = mapper(24);
+        mapper.cpu_write(0x8000, 0x02);
+        mapper.cpu_write(0xc000, 0x07);
+        assert_eq!(mapper.cpu_read(0x8000), Some(0x04));
+        assert_eq!(mapper.cpu_read(0xbfff), Some(0x05));
+        assert_eq!(mapper.cpu_read(0xc000), Some(0x07));
+        assert_eq!(mapper.cpu_read(0xe000), Some(0x0f));
+        assert_eq!(mapper.cpu_read(0xffff), Some(0x0f));
+    }
+
+    #[test]
+    fn small_prg_rom_is_mirrored() {
+        let prg_rom: Vec<u8> = (0..=0xff).cycle().take(8 * 1024).collect();
+        let mut mapper = Vrc6::new(24, prg_rom, vec![0; 8 * 1024], vec![0; 8 * 1024]);
+        mapper.cpu_write(0x8000, 0x03);
+        mapper.cpu_write(0xc000, 0x05);
+        for address in [0x8001, 0xa001, 0xc001, 0xe001] {
+            assert_eq!(mapper.cpu_read(address), Some(0x01), "${:04X}", address);
+        }
+    }
+
+    #[test]
+    fn prg_ram_needs_enabling() {
+        let mut mapper = mapper(24);
+        mapper.cpu_write(0x6000, 0xaa);
+        assert_eq!(mapper.cpu_read(0x6000), None);
+        mapper.cpu_write(0xb003, 0x80);
+        mapper.cpu_write(0x6000, 0xaa);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0xaa));
+    }
+
+    #[test]
+    fn set_mirroring_keeps_prg_ram_enabled() {
+        let mut mapper = mapper(24);
+        mapper.cpu_write(0xb003, 0x80);
+        mapper.set_mirroring(Mirroring::SingleScreenUpper);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::SingleScreenUpper));
+        mapper.cpu_write(0x6000, 0xaa);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0xaa));
+    }
+
+    #[test]
+    fn chr_banks_and_mirroring() {
+        for id in [24, 26] {
+            let mut mapper = mapper(id);
+            for (bank, address) in [
+                0xd000, 0xd001, 0xd002, 0xd003, 0xe000, 0xe001, 0xe002, 0xe003,
+            ]
+            .iter()
+            .enumerate()
+            {
+                mapper.cpu_write(*address, 10 + bank as u8);
+            }
+            mapper.cpu_write(0xb003, 0x04);
+
+            // The VRC6b sees $D001 as $D002 and the other way around
+            let swapped = if id == 26 { [0, 2, 1, 3] } else { [0, 1, 2, 3] };
+            for bank in 0..8 {
+                let expected = 10 + bank / 4 * 4 + swapped[bank % 4];
+                assert_eq!(mapper.ppu_read(bank as u16 * 0x400), expected as u8);
+            }
+            assert_eq!(mapper.mirroring(), Some(Mirroring::Horizontal));
+        }
+    }
+
+    #[test]
+    fn irq_cycle_mode() {
+        let mut mapper = mapper(24);
+        mapper.cpu_write(0xf000, 0xfe);
+        mapper.cpu_write(0xf001, 0x07);
+        assert_eq!(mapper.cycles_until_irq(), 1);
+        run(&mut mapper, 1);
+        assert!(!mapper.irq_pending());
+        run(&mut mapper, 1);
+        assert!(mapper.irq_pending());
+
+        // Acknowledging keeps the IRQ enabled because of bit 0
+        mapper.cpu_write(0xf002, 0x00);
+        assert!(!mapper.irq_pending());
+        run(&mut mapper, 2);
+        assert!(mapper.irq_pending());
+    }
+
+    #[test]
+    fn irq_scanline_mode() {
+        let mut mapper = mapper(24);
+        mapper.cpu_write(0xf000, 0xff);
+        mapper.cpu_write(0xf001, 0x02);
+        assert_eq!(mapper.cycles_until_irq(), 113);
+        run(&mut mapper, 113);
+        assert!(!mapper.irq_pending());
+        run(&mut mapper, 1);
+        assert!(mapper.irq_pending());
+
+        // Without bit 0 acknowledging disables the counter
+        mapper.cpu_write(0xf002, 0x00);
+        assert_eq!(mapper.cycles_until_irq(), u64::MAX);
+        run(&mut mapper, 1000);
+        assert!(!mapper.irq_pending());
+
+        // Two scanlines, the second a cycle longer than the estimate
+        mapper.cpu_write(0xf000, 0xfe);
+        mapper.cpu_write(0xf001, 0x02);
+        assert_eq!(mapper.cycles_until_irq(), 113 + 113);
+        run(&mut mapper, 227);
+        assert!(!mapper.irq_pending());
+        run(&mut mapper, 1);
+        assert!(mapper.irq_pending());
+    }
+
+    #[test]
+    fn pulse_duty_cycle() {
+        let mut mapper = mapper(24);
+        mapper.cpu_write(0x9000, 0x3f); // duty 3, volume 15
+        mapper.cpu_write(0x9001, 0x00);
+        mapper.cpu_write(0x9002, 0x80);
+        let mut high = 0;
+        for _ in 0..16 {
+            run(&mut mapper, 1);
+            if mapper.level() > 0 {
+                high += 1;
+            }
+        }
+        assert_eq!(high, 4);
+
+        mapper.cpu_write(0x9002, 0x00);
+        assert_eq!(mapper.level(), 0);
+    }
+
+    #[test]
+    fn sawtooth_ramps_and_resets() {
+        let mut mapper = mapper(24);
+        mapper.cpu_write(0xb000, 0x2a);
+        mapper.cpu_write(0xb001, 0x00);
+        mapper.cpu_write(0xb002, 0x80);
+        let levels: Vec<u8> = (0..14)
+            .map(|_| {
+                run(&mut mapper, 1);
+                mapper.level()
+            })
+            .collect();
+        let expected = [0, 5, 5, 10, 10, 15, 15, 21, 21, 26, 26, 31, 31, 0];
+        assert_eq!(levels, expected);
+    }
+
+    #[test]
+    fn halt_stops_the_channels() {
+        let mut mapper = mapper(24);
+        mapper.cpu_write(0xb000, 0x2a);
+        mapper.cpu_write(0xb002, 0x80);
+        mapper.cpu_write(0x9003, 0x01);
+        run(&mut mapper, 100);
+        assert_eq!(mapper.level(), 0);
+        assert_eq!(mapper.audio(), 0.0);
+    }
+
+    #[test]
+    fn trainer_goes_in_prg_ram() {
+        let mut mapper = mapper(24);
+        mapper.load_trainer(&[0x77; 512]);
+        mapper.cpu_write(0xb003, 0x80);
+        assert_eq!(mapper.cpu_read(0x6fff), Some(0x00));
+        assert_eq!(mapper.cpu_read(0x7000), Some(0x77));
+        assert_eq!(mapper.cpu_read(0x71ff), Some(0x77));
+    }
+}
@@ -0,0 +1,686 @@
+//! Input movies: the buttons held on each frame from a known start, which
+//! replay exactly on the same game.
+//!
+//! Movies convert to and from FCEUX's .fm2 text and, with the `zip`
+//! feature, Mesen's .mmo archives. Both only carry standard controllers,
+//! and both emulators time things differently enough that a movie made
+//! in one rarely stays in sync for long in another.
+//!
+//! ```no_run
+//! use nes::movie::{Game, Movie};
+//! use nes::prelude::*;
+//!
+//! # fn main() -> Result<()> {
+//! let mut console = Console::from_file("game.nes")?;
+//! let mut movie = Movie::new(console.region(), false);
+//! movie.game = console.cartridge().map(|cartridge| Game::new("game", cartridge));
+//! movie.start(&mut console)?;
+//! for _ in 0..600 {
+//!     console.set_button_state(0, Button::Right, true);
+//!     movie.record_frame(&mut console, false);
+//! }
+//! std::fs::write("game.fm2", movie.to_fm2()?)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::cartridge::Cartridge;
+use crate::checksum::Md5;
+use crate::console::Console;
+use crate::input::{Button, InputState};
+use crate::region::Region;
+use crate::Result;
+use std::collections::hash_map::RandomState;
+use std::convert::TryInto;
+use std::fmt::Write;
+use std::hash::{BuildHasher, Hasher};
+
+/// The FCEUX version .fm2 files say they are from, 2.2.2.
+const FM2_EMU_VERSION: u32 = 22020;
+
+/// FCEUX's letters for a gamepad's buttons, in the order it writes them.
+const FM2_BUTTONS: [(Button, char); 8] = [
+    (Button::Right, 'R'),
+    (Button::Left, 'L'),
+    (Button::Down, 'D'),
+    (Button::Up, 'U'),
+    (Button::Start, 'T'),
+    (Button::Select, 'S'),
+    (Button::B, 'B'),
+    (Button::A, 'A'),
+];
+
+/// Mesen's letters for a gamepad's buttons, in the order it writes them.
+#[cfg(feature = "zip")]
+const MMO_BUTTONS: [(Button, char); 8] = [
+    (Button::Up, 'U'),
+    (Button::Down, 'D'),
+    (Button::Left, 'L'),
+    (Button::Right, 'R'),
+    (Button::Start, 'S'),
+    (Button::Select, 's'),
+    (Button::B, 'B'),
+    (Button::A, 'A'),
+];
+
+/// What happens on one frame of a movie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Frame {
+    /// The buttons held throughout the frame
+    pub input: InputState,
+    /// Whether the reset button is pressed as the frame begins
+    pub reset: bool,
+}
+
+/// The game a movie is for, as FCEUX tells games apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Game {
+    /// The ROM's file name without its extension, which is only shown
+    pub name: String,
+    /// MD5 of PRG ROM followed by CHR ROM
+    pub md5: [u8; 16],
+}
+
+impl Game {
+    pub fn new(name: impl Into<String>, cartridge: &Cartridge) -> Game {
+        let mut md5 = Md5::new();
+        md5.update(cartridge.prg_rom());
+        md5.update(cartridge.chr_rom());
+        Game {
+            name: name.into(),
+            md5: md5.finish(),
+        }
+    }
+}
+
+/// A recording of every player's input, frame by frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    /// The game recorded, which .fm2 files must name
+    pub game: Option<Game>,
+    /// Tells movies apart, so that FCEUX can match savestates to them
+    pub guid: [u8; 16],
+    /// Where the movie starts, from [`Console::save_state`], or `None` to
+    /// start from power on
+    pub savestate: Option<Vec<u8>>,
+    pub region: Region,
+    /// Whether the players are on a Four Score rather than two joypads
+    pub four_score: bool,
+    pub frames: Vec<Frame>,
+    /// How many times recording went back to an earlier frame
+    pub rerecords: u32,
+}
+
+impl Movie {
+    /// An empty movie starting from power on, with a new random GUID.
+    pub fn new(region: Region, four_score: bool) -> Movie {
+        let mut guid = [0; 16];
+        for half in guid.chunks_mut(8) {
+            half.copy_from_slice(&RandomState::new().build_hasher().finish().to_le_bytes());
+        }
+        Movie {
+            game: None,
+            guid,
+            savestate: None,
+            region,
+            four_score,
+            frames: Vec::new(),
+            rerecords: 0,
+        }
+    }
+
+    /// An empty movie starting from where `console` is now. `four_score`
+    /// must match the controllers connected. The game is recorded without
+    /// a name.
+    pub fn from_console(console: &Console, four_score: bool) -> Movie {
+        Movie {
+            game: console
+                .cartridge()
+                .map(|cartridge| Game::new("", cartridge)),
+            savestate: Some(console.save_state()),
+            ..Movie::new(console.region(), four_score)
+        }
+    }
+
+    /// Put `console` at the start of the movie, with input latched once a
+    /// frame, ready to record or play frames.
+    ///
+    /// A movie from power on needs a console that has not run a frame
+    /// since it was loaded, filled with the same [`RamFill`] as the one it
+    /// was recorded on.
+    ///
+    /// [`RamFill`]: crate::console::RamFill
+    pub fn start(&self, console: &mut Console) -> Result<()> {
+        console.set_four_score(self.four_score);
+        match &self.savestate {
+            Some(state) => console.load_state(state)?,
+            None if console.frames() == 0 => console.set_region(self.region),
+            None => return Err("a movie from power on needs a console that has not run".into()),
+        }
+        if console.region() != self.region {
+            return Err(format!(
+                "the movie is for {:?} but its savestate is for {:?}",
+                self.region,
+                console.region()
+            )
+            .into());
+        }
+        console.set_input(InputState::default());
+        console.set_input_latched(true);
+        Ok(())
+    }
+
+    /// Run a frame of `console` with the buttons set on it since the last
+    /// frame, pressing reset first if asked, and add it to the movie.
+    pub fn record_frame(&mut self, console: &mut Console, reset: bool) {
+        let frame = Frame {
+            input: console.input(),
+            reset,
+        };
+        run(console, frame);
+        self.frames.push(frame);
+    }
+
+    /// Run frame `index` of the movie on `console`, or return false if the
+    /// movie has ended.
+    pub fn play_frame(&self, console: &mut Console, index: usize) -> bool {
+        match self.frames.get(index) {
+            Some(&frame) => {
+                run(console, frame);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Start `console` and play the whole movie on it.
+    pub fn play(&self, console: &mut Console) -> Result<()> {
+        self.start(console)?;
+        for &frame in &self.frames {
+            run(console, frame);
+        }
+        Ok(())
+    }
+
+    /// Go back to frame `index` to record from there: drop the frames from
+    /// it on and replay the ones before it on `console`, which must be one
+    /// [`Movie::start`] accepts.
+    pub fn rerecord_from(&mut self, console: &mut Console, index: usize) -> Result<()> {
+        self.frames.truncate(index);
+        self.rerecords += 1;
+        self.play(console)
+    }
+
+    /// The movie as an FCEUX .fm2 file, which needs [`Movie::game`]. Its
+    /// savestate, if any, is this emulator's, which only this emulator can
+    /// load.
+    pub fn to_fm2(&self) -> Result<String> {
+        let pal = match self.region {
+            Region::Ntsc => 0,
+            Region::Pal => 1,
+            Region::Dendy => return Err("FCEUX movies cannot be for a Dendy".into()),
+        };
+        let game = self
+            .game
+            .as_ref()
+            .ok_or("FCEUX movies must say which game they are for")?;
+        let mut fm2 = String::new();
+        writeln!(fm2, "version 3")?;
+        writeln!(fm2, "emuVersion {}", FM2_EMU_VERSION)?;
+        writeln!(fm2, "rerecordCount {}", self.rerecords)?;
+        writeln!(fm2, "palFlag {}", pal)?;
+        writeln!(fm2, "romFilename {}", game.name)?;
+        writeln!(fm2, "romChecksum base64:{}", encode_base64(&game.md5))?;
+        writeln!(fm2, "guid {}", format_guid(&self.guid))?;
+        writeln!(fm2, "fourscore {}", self.four_score as u8)?;
+        writeln!(fm2, "port0 1")?;
+        writeln!(fm2, "port1 1")?;
+        writeln!(fm2, "port2 0")?;
+        if let Some(state) = &self.savestate {
+            writeln!(fm2, "savestate base64:{}", encode_base64(state))?;
+        }
+        for frame in &self.frames {
+            write!(fm2, "|{}|", frame.reset as u8)?;
+            for pad in 0..self.pads() {
+                for &(button, letter) in &FM2_BUTTONS {
+                    fm2.push(if frame.input.is_pressed(pad, button) {
+                        letter
+                    } else {
+                        '.'
+                    });
+                }
+                fm2.push('|');
+            }
+            fm2.push_str("|\n");
+        }
+        Ok(fm2)
+    }
+
+    /// Read an FCEUX .fm2 file of gamepad input. Binary input, power
+    /// cycles and the Famicom Disk System's commands are not supported.
+    pub fn from_fm2(fm2: &str) -> Result<Movie> {
+        let mut movie = Movie::new(Region::Ntsc, false);
+        for (number, line) in fm2.lines().enumerate() {
+            let parsed = if line.starts_with('|') {
+                movie.parse_fm2_frame(line)
+            } else {
+                movie.parse_fm2_header(line)
+            };
+            parsed.map_err(|error| format!("line {}: {}", number + 1, error))?;
+        }
+        Ok(movie)
+    }
+
+    fn parse_fm2_header(&mut self, line: &str) -> Result<()> {
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        let value = value.trim();
+        match key {
+            "version" if value != "3" => {
+                return Err(format!("unsupported version {}", value).into());
+            }
+            "binary" if value != "0" => return Err("binary input is not supported".into()),
+            "rerecordCount" => self.rerecords = value.parse()?,
+            "palFlag" => {
+                self.region = if value == "1" {
+                    Region::Pal
+                } else {
+                    Region::Ntsc
+                };
+            }
+            "romFilename" => self.game_mut().name = value.to_string(),
+            "romChecksum" => {
+                let md5 = value
+                    .strip_prefix("base64:")
+                    .ok_or("only base64 checksums are supported")?;
+                self.game_mut().md5 = decode_base64(md5)?
+                    .try_into()
+                    .map_err(|_| "the checksum is not an MD5")?;
+            }
+            "guid" => self.guid = parse_guid(value)?,
+            "fourscore" => self.four_score = value == "1",
+            "port0" | "port1" if value != "0" && value != "1" => {
+                return Err(format!("{} is not a gamepad", key).into());
+            }
+            "port2" if value != "0" => {
+                return Err("expansion port devices are not supported".into());
+            }
+            "savestate" => {
+                let state = value
+                    .strip_prefix("base64:")
+                    .ok_or("only base64 savestates are supported")?;
+                self.savestate = Some(decode_base64(state)?);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn parse_fm2_frame(&mut self, line: &str) -> Result<()> {
+        let mut fields = line[1..].split('|');
+        let commands = fields.next().unwrap_or("").trim();
+        let commands: u8 = if commands.is_empty() {
+            0
+        } else {
+            commands.parse()?
+        };
+        match commands {
+            0 | 1 => {}
+            2 | 3 => return Err("power cycles are not supported".into()),
+            _ => return Err(format!("unsupported commands {}", commands).into()),
+        }
+
+        let mut frame = Frame {
+            input: InputState::default(),
+            reset: commands == 1,
+        };
+        for pad in 0..self.pads() {
+            let buttons = fields.next().ok_or("too few gamepads")?;
+            if buttons.is_empty() {
+                continue;
+            }
+            if buttons.chars().count() != FM2_BUTTONS.len() {
+                return Err(format!("bad gamepad {:?}", buttons).into());
+            }
+            for (letter, &(button, _)) in buttons.chars().zip(&FM2_BUTTONS) {
+                frame
+                    .input
+                    .set_pressed(pad, button, letter != '.' && letter != ' ');
+            }
+        }
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    /// The movie as a Mesen .mmo archive, which only holds movies from
+    /// power on.
+    #[cfg(feature = "zip")]
+    pub fn to_mmo(&self) -> Result<Vec<u8>> {
+        use std::io::{Cursor, Write as _};
+        use zip::write::{FileOptions, ZipWriter};
+
+        if self.savestate.is_some() {
+            return Err("Mesen movies cannot start from a savestate of ours".into());
+        }
+        let region = Region::NAMES
+            .iter()
+            .find(|&&(_, region)| region == self.region)
+            .map(|&(name, _)| name)
+            .unwrap_or("NTSC");
+        let mut settings = String::new();
+        writeln!(settings, "MesenVersion 0.9.9")?;
+        writeln!(settings, "MovieFormatVersion 1")?;
+        writeln!(settings, "Region {}", region)?;
+        writeln!(settings, "ConsoleType Nes")?;
+        for pad in 0..self.pads() {
+            writeln!(settings, "Controller{} StandardController", pad + 1)?;
+        }
+
+        let mut input = String::new();
+        for frame in &self.frames {
+            input.push_str(if frame.reset { "|R." } else { "|.." });
+            for pad in 0..self.pads() {
+                input.push('|');
+                for &(button, letter) in &MMO_BUTTONS {
+                    input.push(if frame.input.is_pressed(pad, button) {
+                        letter
+                    } else {
+                        '.'
+                    });
+                }
+            }
+            input.push('\n');
+        }
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default();
+        writer.start_file("GameSettings.txt", options)?;
+        writer.write_all(settings.as_bytes())?;
+        writer.start_file("Input.txt", options)?;
+        writer.write_all(input.as_bytes())?;
+        Ok(writer.finish()?.into_inner())
+    }
+
+    /// Read a Mesen .mmo archive of standard controller input from power
+    /// on. Each line of its input has the reset and power buttons, then
+    /// each controller's.
+    #[cfg(feature = "zip")]
+    pub fn from_mmo(bytes: &[u8]) -> Result<Movie> {
+        use std::io::{Cursor, Read};
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+        if archive.by_name("SaveState.mst").is_ok() {
+            return Err("movies from a Mesen savestate are not supported".into());
+        }
+        let mut read = |name: &str| -> Result<String> {
+            let mut text = String::new();
+            archive.by_name(name)?.read_to_string(&mut text)?;
+            Ok(text)
+        };
+        let settings = read("GameSettings.txt")?;
+        let input = read("Input.txt")?;
+
+        let mut movie = Movie::new(Region::Ntsc, false);
+        for line in settings.lines() {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let value = value.trim();
+            match key {
+                "Region" => {
+                    movie.region = Region::NAMES
+                        .iter()
+                        .find(|&&(name, _)| name.eq_ignore_ascii_case(value))
+                        .map(|&(_, region)| region)
+                        .ok_or_else(|| format!("unknown region {}", value))?;
+                }
+                "Controller1" | "Controller2" | "Controller3" | "Controller4" => {
+                    if value != "StandardController" && value != "None" {
+                        return Err(format!("{} is not supported", value).into());
+                    }
+                    if (key == "Controller3" || key == "Controller4") && value != "None" {
+                        movie.four_score = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+        for (number, line) in input.lines().enumerate() {
+            movie
+                .parse_mmo_frame(line)
+                .map_err(|error| format!("Input.txt line {}: {}", number + 1, error))?;
+        }
+        Ok(movie)
+    }
+
+    #[cfg(feature = "zip")]
+    fn parse_mmo_frame(&mut self, line: &str) -> Result<()> {
+        let mut fields = line.strip_prefix('|').ok_or("no input")?.split('|');
+        let system: Vec<char> = fields.next().unwrap_or("").chars().collect();
+        if system.get(1).is_some_and(|&power| power != '.') {
+            return Err("power cycles are not supported".into());
+        }
+        let mut frame = Frame {
+            input: InputState::default(),
+            reset: system.first().is_some_and(|&reset| reset != '.'),
+        };
+        for pad in 0..self.pads() {
+            let buttons = fields.next().unwrap_or("");
+            for (letter, &(button, _)) in buttons.chars().zip(&MMO_BUTTONS) {
+                frame.input.set_pressed(pad, button, letter != '.');
+            }
+        }
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    /// The game, named and checksummed as nothing until the header says.
+    fn game_mut(&mut self) -> &mut Game {
+        self.game.get_or_insert_with(|| Game {
+            name: String::new(),
+            md5: [0; 16],
+        })
+    }
+
+    fn pads(&self) -> usize {
+        if self.four_score {
+            4
+        } else {
+            2
+        }
+    }
+}
+
+/// Press reset if the frame asks, then run it with its buttons.
+fn run(console: &mut Console, frame: Frame) {
+    if frame.reset {
+        console.soft_reset();
+    }
+    console.set_input(frame.input);
+    console.latch_input();
+    console.run_frame();
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let mut group = [0; 3];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let bits = u32::from_be_bytes([0, group[0], group[1], group[2]]);
+        for index in 0..4 {
+            if index <= chunk.len() {
+                text.push(BASE64[(bits >> (18 - 6 * index)) as usize & 0x3f] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for letter in text.bytes() {
+        let value = BASE64
+            .iter()
+            .position(|&digit| digit == letter)
+            .ok_or_else(|| format!("bad base64 character {:?}", letter as char))?;
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+/// A GUID in FCEUX's form, XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX.
+fn format_guid(guid: &[u8; 16]) -> String {
+    let mut text = String::with_capacity(36);
+    for (index, byte) in guid.iter().enumerate() {
+        if let 4 | 6 | 8 | 10 = index {
+            text.push('-');
+        }
+        write!(text, "{:02X}", byte).unwrap();
+    }
+    text
+}
+
+fn parse_guid(text: &str) -> Result<[u8; 16]> {
+    let digits: String = text.chars().filter(|&letter| letter != '-').collect();
+    let bad = || format!("bad GUID {:?}", text);
+    if digits.len() != 32 || text.len() != 36 {
+        return Err(bad().into());
+    }
+    let mut guid = [0; 16];
+    for (byte, pair) in guid.iter_mut().zip(digits.as_bytes().chunks(2)) {
+        let pair = std::str::from_utf8(pair).map_err(|_| bad())?;
+        *byte = u8::from_str_radix(pair, 16).map_err(|_| bad())?;
+    }
+    Ok(guid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(pads: &[(usize, Button)], reset: bool) -> Frame {
+        let mut input = InputState::default();
+        for &(pad, button) in pads {
+            input.set_pressed(pad, button, true);
+        }
+        Frame { input, reset }
+    }
+
+    #[test]
+    fn base64_round_trips() {
+        for length in 0..8 {
+            let bytes: Vec<u8> = (0..length).map(|byte| (byte * 37) ^ 0xa5).collect();
+            assert_eq!(decode_base64(&encode_base64(&bytes)).unwrap(), bytes);
+        }
+        assert_eq!(encode_base64(b"NESS"), "TkVTUw==");
+        assert!(decode_base64("TkV!").is_err());
+    }
+
+    #[test]
+    fn fm2_files_are_read() {
+        let fm2 = "version 3\n\
+                   emuVersion 22020\n\
+                   rerecordCount 12\n\
+                   palFlag 1\n\
+                   romFilename game\n\
+                   romChecksum base64:kAFQmDzST7DWlj99KOF/cg==\n\
+                   guid 01234567-89AB-CDEF-0123-456789ABCDEF\n\
+                   fourscore 0\n\
+                   port0 1\n\
+                   port1 0\n\
+                   port2 0\n\
+                   |0|........|||\n\
+                   |1|R......A|||\n\
+                   |0|...UT. .|||\n";
+        let movie = Movie::from_fm2(fm2).unwrap();
+        assert_eq!(movie.region, Region::Pal);
+        assert_eq!(movie.rerecords, 12);
+        assert!(!movie.four_score);
+        assert_eq!(movie.savestate, None);
+        let game = movie.game.unwrap();
+        assert_eq!(game.name, "game");
+        assert_eq!(game.md5, crate::checksum::md5(b"abc"));
+        assert_eq!(
+            format_guid(&movie.guid),
+            "01234567-89AB-CDEF-0123-456789ABCDEF"
+        );
+        assert_eq!(
+            movie.frames,
+            [
+                frame(&[], false),
+                frame(&[(0, Button::Right), (0, Button::A)], true),
+                frame(&[(0, Button::Up), (0, Button::Start)], false),
+            ]
+        );
+    }
+
+    #[test]
+    fn fm2_round_trips() {
+        let mut movie = Movie::new(Region::Ntsc, true);
+        movie.game = Some(Game {
+            name: "Some Game (U)".to_string(),
+            md5: [0xa5; 16],
+        });
+        movie.savestate = Some(vec![1, 2, 3, 4, 5]);
+        movie.rerecords = 3;
+        movie.frames = vec![
+            frame(&[(0, Button::Left), (3, Button::Select)], false),
+            frame(&[(1, Button::B), (2, Button::Down)], true),
+        ];
+        let fm2 = movie.to_fm2().unwrap();
+        for line in [
+            "emuVersion 22020\n",
+            "romFilename Some Game (U)\n",
+            "romChecksum base64:paWlpaWlpaWlpaWlpaWlpQ==\n",
+            "|0|.L......|........|........|.....S..||\n",
+        ] {
+            assert!(fm2.contains(line), "{}", fm2);
+        }
+        assert!(fm2.contains(&format!("guid {}\n", format_guid(&movie.guid))));
+        assert_eq!(Movie::from_fm2(&fm2).unwrap(), movie);
+    }
+
+    #[test]
+    fn unsupported_fm2_files_are_rejected() {
+        let error = Movie::from_fm2("version 3\n|2|........|||\n").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: power cycles are not supported");
+        assert!(Movie::from_fm2("version 2\n").is_err());
+        assert!(Movie::from_fm2("port0 2\n").is_err());
+        assert!(Movie::from_fm2("|0|...|||\n").is_err());
+        assert!(Movie::new(Region::Dendy, false).to_fm2().is_err());
+        assert!(Movie::new(Region::Ntsc, false).to_fm2().is_err(), "no game");
+        assert!(Movie::from_fm2("guid 0123\n").is_err());
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn mmo_round_trips() {
+        let mut movie = Movie::new(Region::Dendy, false);
+        movie.frames = vec![
+            frame(&[(0, Button::Select), (1, Button::Right)], false),
+            frame(&[(0, Button::Start)], true),
+        ];
+        let mmo = movie.to_mmo().unwrap();
+        // Mesen movies have no GUID
+        let read = Movie::from_mmo(&mmo).unwrap();
+        assert_eq!(
+            Movie {
+                guid: movie.guid,
+                ..read
+            },
+            movie
+        );
+
+        movie.savestate = Some(Vec::new());
+        assert!(movie.to_mmo().is_err());
+    }
+}
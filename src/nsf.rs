@@ -0,0 +1,169 @@
+//! NSF music files: 6502 code and data, with a routine to start each song
+//! and one to call for every tick of it.
+
+use crate::rom::Rom;
+use crate::Result;
+use std::fs;
+use std::path::Path;
+
+/// Which video systems a tune was written for, which sets its tempo.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NsfRegion {
+    Ntsc,
+    Pal,
+    /// Either, told apart by X when INIT is called
+    Dual,
+}
+
+bitflags! {
+    /// Expansion sound chips a tune writes to.
+    pub struct ExpansionAudio: u8 {
+        const VRC6 = 0b0000_0001;
+        const VRC7 = 0b0000_0010;
+        const FDS = 0b0000_0100;
+        const MMC5 = 0b0000_1000;
+        const NAMCO_163 = 0b0001_0000;
+        const SUNSOFT_5B = 0b0010_0000;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Nsf {
+    pub version: u8,
+    /// Songs in the file, numbered from 1
+    pub songs: u8,
+    /// Song to play first, numbered from 1
+    pub starting_song: u8,
+    /// Where `data` is loaded, or the offset into its first bank when
+    /// bankswitched
+    pub load_address: u16,
+    /// Called with the song, counting from 0, in A
+    pub init_address: u16,
+    /// Called once per tick
+    pub play_address: u16,
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    /// Microseconds between PLAY calls on NTSC
+    pub ntsc_speed: u16,
+    /// Microseconds between PLAY calls on PAL
+    pub pal_speed: u16,
+    /// 4 kB banks for $8000-$FFFF at INIT, or `None` without bankswitching
+    pub bankswitch: Option<[u8; 8]>,
+    pub region: NsfRegion,
+    pub expansion_audio: ExpansionAudio,
+    /// The program and its data
+    pub data: Rom,
+}
+
+const HEADER_SIZE: usize = 0x80;
+
+impl Nsf {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Nsf> {
+        Self::from_bytes(fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: impl Into<Rom>) -> Result<Nsf> {
+        let bytes = bytes.into();
+        if bytes.len() < HEADER_SIZE {
+            return Err(format!("{} bytes is too short for an NSF header", bytes.len()).into());
+        }
+        if &bytes[0..5] != b"NESM\x1a" {
+            return Err("bad format".into());
+        }
+
+        let word = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        let text = |offset: usize| {
+            let field = &bytes[offset..offset + 32];
+            let end = field.iter().position(|&byte| byte == 0).unwrap_or(32);
+            String::from_utf8_lossy(&field[..end]).into_owned()
+        };
+
+        let mut banks = [0; 8];
+        banks.copy_from_slice(&bytes[0x70..0x78]);
+        let bankswitch = if banks.iter().any(|&bank| bank != 0) {
+            Some(banks)
+        } else {
+            None
+        };
+        let load_address = word(0x08);
+        if bankswitch.is_none() && load_address < 0x8000 {
+            return Err(format!("NSF loads at ${:04X}, below $8000", load_address).into());
+        }
+
+        let region = match bytes[0x7a] & 0b11 {
+            0b00 => NsfRegion::Ntsc,
+            0b01 => NsfRegion::Pal,
+            _ => NsfRegion::Dual,
+        };
+
+        // NSF2 may put metadata after the program, which it gives the
+        // length of
+        let length = u32::from_le_bytes([bytes[0x7d], bytes[0x7e], bytes[0x7f], 0]) as usize;
+        let data_end = if bytes[0x05] >= 2 && length != 0 {
+            (HEADER_SIZE + length).min(bytes.len())
+        } else {
+            bytes.len()
+        };
+
+        Ok(Nsf {
+            version: bytes[0x05],
+            songs: bytes[0x06],
+            starting_song: bytes[0x07].max(1),
+            load_address,
+            init_address: word(0x0a),
+            play_address: word(0x0c),
+            title: text(0x0e),
+            artist: text(0x2e),
+            copyright: text(0x4e),
+            ntsc_speed: word(0x6e),
+            pal_speed: word(0x78),
+            bankswitch,
+            region,
+            expansion_audio: ExpansionAudio::from_bits_truncate(bytes[0x7b]),
+            data: bytes.slice(HEADER_SIZE..data_end),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_header() {
+        let mut bytes = vec![0; HEADER_SIZE];
+        bytes[..8].copy_from_slice(b"NESM\x1a\x01\x05\x02");
+        bytes[0x08..0x0e].copy_from_slice(&[0x00, 0x80, 0x03, 0x80, 0x06, 0x80]);
+        bytes[0x0e..0x13].copy_from_slice(b"Title");
+        bytes[0x2e..0x34].copy_from_slice(b"Artist");
+        bytes[0x6e..0x70].copy_from_slice(&16639u16.to_le_bytes());
+        bytes[0x7a] = 0x02;
+        bytes[0x7b] = 0x11;
+        bytes.extend_from_slice(&[0xea; 16]);
+        let nsf = Nsf::from_bytes(bytes).unwrap();
+
+        assert_eq!(nsf.songs, 5);
+        assert_eq!(nsf.starting_song, 2);
+        assert_eq!(nsf.load_address, 0x8000);
+        assert_eq!(nsf.init_address, 0x8003);
+        assert_eq!(nsf.play_address, 0x8006);
+        assert_eq!(nsf.title, "Title");
+        assert_eq!(nsf.artist, "Artist");
+        assert_eq!(nsf.copyright, "");
+        assert_eq!(nsf.ntsc_speed, 16639);
+        assert_eq!(nsf.bankswitch, None);
+        assert_eq!(nsf.region, NsfRegion::Dual);
+        assert_eq!(
+            nsf.expansion_audio,
+            ExpansionAudio::VRC6 | ExpansionAudio::NAMCO_163
+        );
+        assert_eq!(nsf.data.len(), 16);
+    }
+
+    #[test]
+    fn rejects_short_files() {
+        assert!(Nsf::from_bytes(&b"NESM\x1a"[..]).is_err());
+    }
+}
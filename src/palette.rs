@@ -0,0 +1,162 @@
+use crate::Result;
+use std::fs;
+use std::path::Path;
+
+/// How much each set emphasis bit darkens the other two channels.
+const EMPHASIS_ATTENUATION: f32 = 0.816;
+
+/// The 2C02 colors without emphasis, as commonly used by emulators.
+#[rustfmt::skip]
+const NTSC: [[u8; 3]; 64] = [
+    [84, 84, 84], [0, 30, 116], [8, 16, 144], [48, 0, 136],
+    [68, 0, 100], [92, 0, 48], [84, 4, 0], [60, 24, 0],
+    [32, 42, 0], [8, 58, 0], [0, 64, 0], [0, 60, 0],
+    [0, 50, 60], [0, 0, 0], [0, 0, 0], [0, 0, 0],
+    [152, 150, 152], [8, 76, 196], [48, 50, 236], [92, 30, 228],
+    [136, 20, 176], [160, 20, 100], [152, 34, 32], [120, 60, 0],
+    [84, 90, 0], [40, 114, 0], [8, 124, 0], [0, 118, 40],
+    [0, 102, 120], [0, 0, 0], [0, 0, 0], [0, 0, 0],
+    [236, 238, 236], [76, 154, 236], [120, 124, 236], [176, 98, 236],
+    [228, 84, 236], [236, 88, 180], [236, 106, 100], [212, 136, 32],
+    [160, 170, 0], [116, 196, 0], [76, 208, 32], [56, 204, 108],
+    [56, 180, 204], [60, 60, 60], [0, 0, 0], [0, 0, 0],
+    [236, 238, 236], [168, 204, 236], [188, 188, 236], [212, 178, 236],
+    [236, 174, 236], [236, 174, 212], [236, 180, 176], [228, 196, 144],
+    [204, 210, 120], [180, 222, 120], [168, 226, 144], [152, 226, 180],
+    [160, 214, 228], [160, 162, 160], [0, 0, 0], [0, 0, 0],
+];
+
+/// RGB colors for the 512 values a PPU pixel can take: 64 colors times
+/// the eight combinations of the emphasis bits, see
+/// [`Ppu::frame`](crate::ppu::Ppu::frame).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    colors: Vec<[u8; 3]>,
+}
+
+impl Palette {
+    /// The built-in NTSC palette.
+    pub fn ntsc() -> Palette {
+        Palette::from_base(&NTSC)
+    }
+
+    /// The built-in PAL palette. The 2C07 swaps the red and green emphasis
+    /// bits.
+    pub fn pal() -> Palette {
+        let mut palette = Palette::ntsc();
+        let colors = palette.colors.clone();
+        for (pixel, color) in palette.colors.iter_mut().enumerate() {
+            let emphasis = pixel >> 6;
+            let swapped = (emphasis & 0b100) | ((emphasis & 1) << 1) | ((emphasis >> 1) & 1);
+            *color = colors[swapped << 6 | (pixel & 0x3f)];
+        }
+        palette
+    }
+
+    /// Extend 64 colors to 512 by darkening the channels that are not
+    /// emphasized.
+    pub fn from_base(base: &[[u8; 3]; 64]) -> Palette {
+        let colors = (0..8)
+            .flat_map(|emphasis| base.iter().map(move |&rgb| emphasize(rgb, emphasis)))
+            .collect();
+        Palette { colors }
+    }
+
+    /// Read a .pal file: 64 or 512 RGB triples. 64 colors are extended
+    /// with [`Palette::from_base`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Palette> {
+        let colors: Vec<[u8; 3]> = bytes
+            .chunks_exact(3)
+            .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+            .collect();
+        match (bytes.len() % 3, colors.len()) {
+            (0, 64) => {
+                let mut base = [[0; 3]; 64];
+                base.copy_from_slice(&colors);
+                Ok(Palette::from_base(&base))
+            }
+            (0, 512) => Ok(Palette { colors }),
+            _ => Err(format!("palette is {} bytes, expected 192 or 1536", bytes.len()).into()),
+        }
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Palette> {
+        Palette::from_bytes(&fs::read(path)?)
+    }
+
+    /// The 512 colors as a .pal file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.colors.concat()
+    }
+
+    /// The color of PPU pixel `pixel`. Bits above the emphasis bits are
+    /// ignored.
+    pub fn rgb(&self, pixel: u16) -> [u8; 3] {
+        self.colors[pixel as usize & 0x1ff]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::ntsc()
+    }
+}
+
+/// Apply the PPUMASK emphasis bits, red in bit 0, green in bit 1 and blue
+/// in bit 2. Each one darkens the other two channels.
+fn emphasize(rgb: [u8; 3], emphasis: u8) -> [u8; 3] {
+    let mut channels = rgb.map(f32::from);
+    for emphasized in 0..3 {
+        if emphasis & (1 << emphasized) == 0 {
+            continue;
+        }
+        for (channel, value) in channels.iter_mut().enumerate() {
+            if channel != emphasized {
+                *value *= EMPHASIS_ATTENUATION;
+            }
+        }
+    }
+    channels.map(|value| value.round() as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emphasis_darkens_the_other_channels() {
+        let palette = Palette::ntsc();
+        assert_eq!(palette.rgb(0x30), [236, 238, 236]);
+        // Red
+        assert_eq!(palette.rgb(0x070), [236, 194, 193]);
+        // Green and blue
+        assert_eq!(palette.rgb(0x1b0), [157, 194, 193]);
+        // All three
+        assert_eq!(palette.rgb(0x1f0), [157, 158, 157]);
+    }
+
+    #[test]
+    fn pal_swaps_red_and_green_emphasis() {
+        let (ntsc, pal) = (Palette::ntsc(), Palette::pal());
+        assert_eq!(pal.rgb(0x030), ntsc.rgb(0x030));
+        assert_eq!(pal.rgb(0x070), ntsc.rgb(0x0b0));
+        assert_eq!(pal.rgb(0x0b0), ntsc.rgb(0x070));
+        assert_eq!(pal.rgb(0x130), ntsc.rgb(0x130));
+        assert_eq!(pal.rgb(0x170), ntsc.rgb(0x1b0));
+    }
+
+    #[test]
+    fn pal_files() {
+        let base: Vec<u8> = (0..64 * 3).map(|byte| byte as u8).collect();
+        let palette = Palette::from_bytes(&base).unwrap();
+        assert_eq!(palette.rgb(0x01), [3, 4, 5]);
+        assert_eq!(palette.rgb(0x101), [2, 3, 5]);
+
+        let full = palette.to_bytes();
+        assert_eq!(full.len(), 512 * 3);
+        assert_eq!(Palette::from_bytes(&full).unwrap(), palette);
+
+        assert!(Palette::from_bytes(&base[..190]).is_err());
+        assert!(Palette::from_bytes(&[0; 100 * 3]).is_err());
+    }
+}
@@ -1,21 +1,1379 @@
 use crate::bus::Bus;
+use crate::capabilities::Capabilities;
+use crate::region::Region;
+#[cfg(feature = "serde")]
+use crate::state::serde_state;
+use crate::state::{State, StateReader, StateWriter};
+use crate::Result;
 
-#[derive(Debug, Clone, Copy)]
+/// Dots per scanline.
+const DOTS: u16 = 341;
+/// The first scanline of vblank on NTSC.
+const VBLANK_SCANLINE: u16 = 241;
+/// The scanline before the first visible one on NTSC.
+const PRE_RENDER_SCANLINE: u16 = 261;
+
+/// How long a bit of the I/O latch holds its value once it stops being
+/// driven, about 600 ms.
+const LATCH_DECAY_DOTS: u64 = 3_200_000;
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+
+bitflags! {
+    /// PPUCTRL ($2000)
+    #[cfg_attr(
+        feature = "serde",
+        derive(serde::Serialize, serde::Deserialize),
+        serde(transparent)
+    )]
+    pub struct Control: u8 {
+        const NAMETABLE = 0b0000_0011;
+        const INCREMENT_32 = 0b0000_0100;
+        const SPRITE_TABLE = 0b0000_1000;
+        const BACKGROUND_TABLE = 0b0001_0000;
+        const SPRITE_8X16 = 0b0010_0000;
+        const PPU_SLAVE = 0b0100_0000;
+        const NMI_ENABLE = 0b1000_0000;
+    }
+}
+
+bitflags! {
+    /// PPUMASK ($2001)
+    #[cfg_attr(
+        feature = "serde",
+        derive(serde::Serialize, serde::Deserialize),
+        serde(transparent)
+    )]
+    pub struct Mask: u8 {
+        const GRAYSCALE = 0b0000_0001;
+        const SHOW_BACKGROUND_LEFT = 0b0000_0010;
+        const SHOW_SPRITES_LEFT = 0b0000_0100;
+        const SHOW_BACKGROUND = 0b0000_1000;
+        const SHOW_SPRITES = 0b0001_0000;
+        const EMPHASIZE_RED = 0b0010_0000;
+        const EMPHASIZE_GREEN = 0b0100_0000;
+        const EMPHASIZE_BLUE = 0b1000_0000;
+    }
+}
+
+bitflags! {
+    /// PPUSTATUS ($2002). The low five bits are open bus.
+    #[cfg_attr(
+        feature = "serde",
+        derive(serde::Serialize, serde::Deserialize),
+        serde(transparent)
+    )]
+    pub struct PpuStatus: u8 {
+        const SPRITE_OVERFLOW = 0b0010_0000;
+        const SPRITE_0_HIT = 0b0100_0000;
+        const VBLANK = 0b1000_0000;
+    }
+}
+
+/// A sprite loaded for the current scanline.
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Sprite {
+    x: u8,
+    attributes: u8,
+    /// Pattern bits for the row, already flipped horizontally if needed
+    low: u8,
+    high: u8,
+}
+
+impl Sprite {
+    /// The two pattern bits at screen column `x`, 0 where transparent.
+    fn pattern_at(&self, x: usize) -> u8 {
+        match x.checked_sub(self.x as usize) {
+            Some(offset) if offset < 8 => {
+                let bit = 7 - offset;
+                ((self.low >> bit) & 1) | (((self.high >> bit) & 1) << 1)
+            }
+            _ => 0,
+        }
+    }
+}
+
+impl State for Sprite {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.x);
+        state.write(&self.attributes);
+        state.write(&self.low);
+        state.write(&self.high);
+    }
+
+    fn load(state: &mut StateReader) -> Result<Sprite> {
+        Ok(Sprite {
+            x: state.read()?,
+            attributes: state.read()?,
+            low: state.read()?,
+            high: state.read()?,
+        })
+    }
+}
+
+const SPRITE_PALETTE: u8 = 0b0000_0011;
+const SPRITE_BEHIND_BACKGROUND: u8 = 0b0010_0000;
+const SPRITE_FLIP_HORIZONTAL: u8 = 0b0100_0000;
+const SPRITE_FLIP_VERTICAL: u8 = 0b1000_0000;
+
+#[derive(Debug, Clone)]
 pub struct Ppu<B: Bus> {
-    pub(crate) bus: B,
+    bus: B,
+    /// Sprite attribute memory
+    oam: [u8; 256],
+    /// OAMADDR ($2003)
+    oam_address: u8,
+    control: Control,
+    mask: Mask,
+    status: PpuStatus,
+    /// Palette RAM, $3F00-$3F1F
+    palette: [u8; 32],
+    /// Current VRAM address
+    v: u16,
+    /// Temporary VRAM address, the top left of the screen
+    t: u16,
+    /// Fine X scroll
+    x: u8,
+    /// Write toggle shared by PPUSCROLL and PPUADDR, set after the first
+    /// write
+    w: bool,
+    /// PPUDATA read buffer
+    read_buffer: u8,
+    /// Last value on the data bus between the CPU and the PPU registers
+    io_latch: u8,
+    /// The dot each latch bit was last driven at
+    latch_refreshed: [u64; 8],
+    /// Whether undriven latch bits fade, see [`Ppu::set_latch_decay`]
+    latch_decay: bool,
+    /// Dots since power on
+    dots: u64,
+    scanline: u16,
+    dot: u16,
+    /// Frame timing, see [`Ppu::set_region`]
+    region: Region,
+    vblank_scanline: u16,
+    pre_render_scanline: u16,
+    /// Set when PPUSTATUS is read just before vblank starts, which keeps
+    /// the flag from being set that frame
+    suppress_vblank: bool,
+    odd_frame: bool,
+    /// Colors of the last complete frame, row by row, see [`Ppu::frame`]
+    frame: Vec<u16>,
+    frames: u64,
+    /// Latches for the tile being fetched
+    next_tile: u8,
+    next_attribute: u8,
+    next_low: u8,
+    next_high: u8,
+    /// Background shift registers, the high byte is the tile being drawn
+    pattern_low: u16,
+    pattern_high: u16,
+    attribute_low: u16,
+    attribute_high: u16,
+    /// Sprites found for the next scanline, as copied from OAM
+    secondary_oam: [u8; 32],
+    secondary_count: usize,
+    /// Whether secondary OAM starts with sprite 0
+    secondary_has_zero: bool,
+    sprites: [Sprite; 8],
+    sprite_count: usize,
+    /// Whether `sprites` starts with sprite 0
+    sprite_zero_loaded: bool,
 }
 
 impl<B: Bus> Ppu<B> {
     pub fn new(bus: B) -> Ppu<B> {
-        Ppu { bus }
+        Ppu {
+            bus,
+            oam: [0; 256],
+            oam_address: 0,
+            control: Control::empty(),
+            mask: Mask::empty(),
+            status: PpuStatus::empty(),
+            palette: [0; 32],
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            read_buffer: 0,
+            io_latch: 0,
+            latch_refreshed: [0; 8],
+            latch_decay: true,
+            dots: 0,
+            scanline: 0,
+            dot: 0,
+            region: Region::Ntsc,
+            vblank_scanline: VBLANK_SCANLINE,
+            pre_render_scanline: PRE_RENDER_SCANLINE,
+            suppress_vblank: false,
+            odd_frame: false,
+            frame: vec![0; WIDTH * HEIGHT],
+            frames: 0,
+            next_tile: 0,
+            next_attribute: 0,
+            next_low: 0,
+            next_high: 0,
+            pattern_low: 0,
+            pattern_high: 0,
+            attribute_low: 0,
+            attribute_high: 0,
+            secondary_oam: [0xff; 32],
+            secondary_count: 0,
+            secondary_has_zero: false,
+            sprites: [Sprite::default(); 8],
+            sprite_count: 0,
+            sprite_zero_loaded: false,
+        }
+    }
+
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    /// Whether the PPU is asserting NMI, which it does while the vblank
+    /// flag is set and NMI generation is enabled.
+    ///
+    /// Reading PPUSTATUS clears the flag and so drops the line, and enabling
+    /// NMI during vblank raises it again. The CPU triggers on the rising edge.
+    pub fn nmi(&self) -> bool {
+        self.status.contains(PpuStatus::VBLANK) && self.control.contains(Control::NMI_ENABLE)
+    }
+
+    pub fn control(&self) -> Control {
+        self.control
+    }
+
+    pub fn mask(&self) -> Mask {
+        self.mask
+    }
+
+    pub fn status(&self) -> PpuStatus {
+        self.status
+    }
+
+    /// The current VRAM address.
+    pub fn vram_address(&self) -> u16 {
+        self.v
+    }
+
+    /// The scanline and dot about to be run. Scanline 0 is the first
+    /// visible one and the last, 261 on NTSC, the pre-render line.
+    pub fn position(&self) -> (u16, u16) {
+        (self.scanline, self.dot)
+    }
+
+    /// The last complete frame, [`WIDTH`] by [`HEIGHT`], row by row.
+    ///
+    /// Each pixel is a color from palette RAM in bits 0-5, with grayscale
+    /// already applied, and the PPUMASK emphasis bits in bits 6-8. This is
+    /// the index into a 512-entry palette.
+    pub fn frame(&self) -> &[u16] {
+        &self.frame
+    }
+
+    /// Frames completed since power on. A frame completes as vblank starts.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Dots that can run before vblank next starts or ends, which is when
+    /// NMI and the frame count change without a register access. At
+    /// least this many: odd frames may be a dot short.
+    pub fn dots_until_vblank_edge(&self) -> u64 {
+        let dots_per_frame = u64::from(self.pre_render_scanline + 1) * DOTS as u64;
+        let now = u64::from(self.scanline) * DOTS as u64 + u64::from(self.dot);
+        let until = |scanline: u16| {
+            let edge = u64::from(scanline) * DOTS as u64 + 1;
+            (edge + dots_per_frame - now) % dots_per_frame
+        };
+        until(self.vblank_scanline)
+            .min(until(self.pre_render_scanline))
+            .saturating_sub(1)
     }
 
-    pub fn reset(&mut self) {}
-    pub fn step(&mut self) {}
+    /// Whether the background or sprites are enabled, which is what turns
+    /// on fetching and the scroll counters.
+    pub fn rendering_enabled(&self) -> bool {
+        self.mask
+            .intersects(Mask::SHOW_BACKGROUND | Mask::SHOW_SPRITES)
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
 
+    /// Switch to another console's frame timing: scanlines per frame, when
+    /// vblank starts and whether odd frames are a dot short.
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.vblank_scanline = region.vblank_scanline();
+        self.pre_render_scanline = region.scanlines() - 1;
+        if self.scanline > self.pre_render_scanline {
+            self.scanline = self.pre_render_scanline;
+        }
+    }
+
+    /// Write the registers, memory and rendering progress, for savestates.
+    /// The bus, the region and the last frame's pixels are left out.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write(&self.oam);
+        state.write(&self.oam_address);
+        state.write(&self.control.bits());
+        state.write(&self.mask.bits());
+        state.write(&self.status.bits());
+        state.write(&self.palette);
+        state.write(&self.v);
+        state.write(&self.t);
+        state.write(&self.x);
+        state.write(&self.w);
+        state.write(&self.read_buffer);
+        state.write(&self.io_latch);
+        state.write(&self.latch_refreshed);
+        state.write(&self.dots);
+        state.write(&self.scanline);
+        state.write(&self.dot);
+        state.write(&self.suppress_vblank);
+        state.write(&self.odd_frame);
+        state.write(&self.frames);
+        state.write(&self.next_tile);
+        state.write(&self.next_attribute);
+        state.write(&self.next_low);
+        state.write(&self.next_high);
+        state.write(&self.pattern_low);
+        state.write(&self.pattern_high);
+        state.write(&self.attribute_low);
+        state.write(&self.attribute_high);
+        state.write(&self.secondary_oam);
+        state.write(&self.secondary_count);
+        state.write(&self.secondary_has_zero);
+        state.write(&self.sprites);
+        state.write(&self.sprite_count);
+        state.write(&self.sprite_zero_loaded);
+    }
+
+    /// Restore what `save_state` wrote, into a PPU already set to the
+    /// region it was saved in.
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.oam = state.read()?;
+        self.oam_address = state.read()?;
+        self.control = Control::from_bits_truncate(state.read()?);
+        self.mask = Mask::from_bits_truncate(state.read()?);
+        self.status = PpuStatus::from_bits_truncate(state.read()?);
+        self.palette = state.read()?;
+        self.v = state.read()?;
+        self.t = state.read()?;
+        self.x = state.read()?;
+        self.w = state.read()?;
+        self.read_buffer = state.read()?;
+        self.io_latch = state.read()?;
+        self.latch_refreshed = state.read()?;
+        self.dots = state.read()?;
+        self.scanline = state.read()?;
+        self.dot = state.read()?;
+        if self.scanline > self.pre_render_scanline || self.dot >= DOTS {
+            return Err(format!("no dot {} on scanline {}", self.dot, self.scanline).into());
+        }
+        self.suppress_vblank = state.read()?;
+        self.odd_frame = state.read()?;
+        self.frames = state.read()?;
+        self.next_tile = state.read()?;
+        self.next_attribute = state.read()?;
+        self.next_low = state.read()?;
+        self.next_high = state.read()?;
+        self.pattern_low = state.read()?;
+        self.pattern_high = state.read()?;
+        self.attribute_low = state.read()?;
+        self.attribute_high = state.read()?;
+        self.secondary_oam = state.read()?;
+        self.secondary_count = state.read()?;
+        self.secondary_has_zero = state.read()?;
+        self.sprites = state.read()?;
+        self.sprite_count = state.read()?;
+        self.sprite_zero_loaded = state.read()?;
+        if self.secondary_count > 8 || self.sprite_count > 8 {
+            return Err("more than 8 sprites on a scanline".into());
+        }
+        Ok(())
+    }
+
+    /// Return to the state at power on. The bus, the region and the open
+    /// bus setting are kept.
+    pub fn power_on(&mut self)
+    where
+        B: Clone,
+    {
+        let mut ppu = Ppu::new(self.bus.clone());
+        ppu.set_region(self.region);
+        ppu.latch_decay = self.latch_decay;
+        *self = ppu;
+    }
+
+    /// The reset line clears PPUCTRL, PPUMASK, the scroll and the read
+    /// buffer. VRAM, OAM and the VRAM address are kept.
+    pub fn reset(&mut self) {
+        self.control = Control::empty();
+        self.mask = Mask::empty();
+        self.t = 0;
+        self.x = 0;
+        self.w = false;
+        self.read_buffer = 0;
+    }
+
+    /// Advance by one dot.
+    pub fn step(&mut self) {
+        self.dots += 1;
+        if self.scanline < HEIGHT as u16 || self.scanline == self.pre_render_scanline {
+            if self.rendering_enabled() {
+                self.render_dot();
+            }
+            if self.scanline < HEIGHT as u16 && (1..=WIDTH as u16).contains(&self.dot) {
+                self.output_pixel();
+            }
+        }
+        if self.dot == 1 {
+            if self.scanline == self.vblank_scanline {
+                if !self.suppress_vblank {
+                    self.status.insert(PpuStatus::VBLANK);
+                }
+                self.suppress_vblank = false;
+                self.frames += 1;
+            } else if self.scanline == self.pre_render_scanline {
+                self.status = PpuStatus::empty();
+            }
+        }
+        self.dot += 1;
+        // Odd frames skip the last dot of the pre-render line while
+        // rendering, so the frame is one dot shorter
+        let skip = self.scanline == self.pre_render_scanline
+            && self.dot == DOTS - 1
+            && self.odd_frame
+            && self.region.skips_odd_dot()
+            && self.rendering_enabled();
+        if self.dot == DOTS || skip {
+            self.dot = 0;
+            self.scanline = (self.scanline + 1) % (self.pre_render_scanline + 1);
+            if self.scanline == 0 {
+                self.odd_frame = !self.odd_frame;
+            }
+        }
+    }
+
+    /// Read a register. Bits the register does not drive come from the I/O
+    /// latch, which holds the last value on the bus until it decays.
     pub fn read(&mut self, address: u16) -> u8 {
-        0
+        self.decay_latch();
+        let (data, driven) = match address & 0x7 {
+            2 => {
+                let data = self.status.bits();
+                self.status.remove(PpuStatus::VBLANK);
+                self.w = false;
+                if (self.scanline, self.dot) == (self.vblank_scanline, 1) {
+                    self.suppress_vblank = true;
+                }
+                (data, 0xe0)
+            }
+            4 => self.oam_data(),
+            7 => self.read_data(),
+            // Write-only registers
+            _ => (0, 0x00),
+        };
+        self.refresh_latch(data, driven);
+        self.io_latch
+    }
+
+    /// What `read` would return, without its side effects: PPUSTATUS keeps
+    /// its vblank flag, PPUDATA its buffer and address, and the I/O latch
+    /// neither refreshes nor decays.
+    pub fn peek(&self, address: u16) -> u8 {
+        let (data, driven) = match address & 0x7 {
+            2 => (self.status.bits(), 0xe0),
+            4 => self.oam_data(),
+            7 => {
+                let address = self.v & 0x3fff;
+                if address >= 0x3f00 {
+                    (self.palette_color(address), 0x3f)
+                } else {
+                    (self.read_buffer, 0xff)
+                }
+            }
+            _ => (0, 0x00),
+        };
+        (self.io_latch & !driven) | (data & driven)
+    }
+
+    /// OAMDATA ($2004) and the bits it drives.
+    fn oam_data(&self) -> (u8, u8) {
+        let data = self.oam[self.oam_address as usize];
+        // Bits 2-4 of the attribute byte do not exist
+        if self.oam_address & 3 == 2 {
+            (data & 0xe3, 0xff)
+        } else {
+            (data, 0xff)
+        }
+    }
+
+    pub fn write(&mut self, address: u16, data: u8) {
+        self.refresh_latch(data, 0xff);
+        match address & 0x7 {
+            0 => {
+                self.control = Control::from_bits_truncate(data);
+                self.t = (self.t & !0x0c00) | ((data as u16 & 0x03) << 10);
+            }
+            1 => self.mask = Mask::from_bits_truncate(data),
+            3 => self.oam_address = data,
+            4 => self.write_oam(data),
+            5 => {
+                if !self.w {
+                    self.t = (self.t & !0x001f) | (data as u16 >> 3);
+                    self.x = data & 0x07;
+                } else {
+                    self.t = (self.t & !0x73e0)
+                        | ((data as u16 & 0x07) << 12)
+                        | ((data as u16 & 0xf8) << 2);
+                }
+                self.w = !self.w;
+            }
+            6 => {
+                if !self.w {
+                    self.t = (self.t & 0x00ff) | ((data as u16 & 0x3f) << 8);
+                } else {
+                    self.t = (self.t & 0xff00) | data as u16;
+                    self.v = self.t;
+                }
+                self.w = !self.w;
+            }
+            7 => self.write_data(data),
+            _ => {}
+        }
+    }
+
+    /// Write to OAMDATA ($2004), which is also where OAM DMA writes.
+    pub fn write_oam(&mut self, data: u8) {
+        self.oam[self.oam_address as usize] = data;
+        self.oam_address = self.oam_address.wrapping_add(1);
+    }
+
+    pub fn oam(&self) -> &[u8; 256] {
+        &self.oam
+    }
+
+    /// Set the `driven` bits of the I/O latch from `data`.
+    fn refresh_latch(&mut self, data: u8, driven: u8) {
+        self.io_latch = (self.io_latch & !driven) | (data & driven);
+        for (bit, refreshed) in self.latch_refreshed.iter_mut().enumerate() {
+            if driven & (1 << bit) != 0 {
+                *refreshed = self.dots;
+            }
+        }
+    }
+
+    /// Let undriven bits of the I/O latch fade, or have them hold their
+    /// value for good. How long they last on hardware varies from chip to
+    /// chip and with temperature.
+    pub fn set_latch_decay(&mut self, enabled: bool) {
+        self.latch_decay = enabled;
+    }
+
+    /// Clear the latch bits that have not been driven for a while.
+    fn decay_latch(&mut self) {
+        if !self.latch_decay {
+            return;
+        }
+        for (bit, &refreshed) in self.latch_refreshed.iter().enumerate() {
+            if self.dots - refreshed > LATCH_DECAY_DOTS {
+                self.io_latch &= !(1 << bit);
+            }
+        }
+    }
+
+    /// Read PPUDATA ($2007), returning the data and the bits it drives.
+    /// Palette reads come back at once with the top two bits open, everything
+    /// else goes through the read buffer.
+    fn read_data(&mut self) -> (u8, u8) {
+        let address = self.v & 0x3fff;
+        let data = if address >= 0x3f00 {
+            // The buffer is filled from the nametable under the palette
+            self.read_buffer = self.bus.read(address - 0x1000);
+            (self.palette_color(address), 0x3f)
+        } else {
+            let data = self.read_buffer;
+            self.read_buffer = self.bus.read(address);
+            (data, 0xff)
+        };
+        self.increment_address();
+        data
+    }
+
+    /// Write PPUDATA ($2007).
+    fn write_data(&mut self, data: u8) {
+        let address = self.v & 0x3fff;
+        if address >= 0x3f00 {
+            self.palette[palette_index(address)] = data & 0x3f;
+        } else {
+            self.bus.write(address, data);
+        }
+        self.increment_address();
+    }
+
+    /// The fetches and scroll updates of one dot on a rendering scanline.
+    fn render_dot(&mut self) {
+        let dot = self.dot;
+        if (2..=257).contains(&dot) || (322..=337).contains(&dot) {
+            self.shift_background();
+            match (dot - 1) % 8 {
+                0 => {
+                    self.load_background();
+                    self.next_tile = self.bus.read(0x2000 | (self.v & 0x0fff));
+                }
+                2 => {
+                    let v = self.v;
+                    let address = 0x23c0 | (v & 0x0c00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+                    let shift = ((v >> 4) & 0x04) | (v & 0x02);
+                    self.next_attribute = (self.bus.read(address) >> shift) & 0x03;
+                }
+                4 => self.next_low = self.bus.read(self.background_address()),
+                6 => self.next_high = self.bus.read(self.background_address() + 8),
+                7 => self.increment_x(),
+                _ => {}
+            }
+        }
+        match dot {
+            256 => self.increment_y(),
+            257 => {
+                self.load_background();
+                self.v = (self.v & !0x041f) | (self.t & 0x041f);
+                if self.scanline == self.pre_render_scanline {
+                    self.secondary_count = 0;
+                    self.secondary_has_zero = false;
+                } else {
+                    self.evaluate_sprites();
+                }
+                // The line is drawn, so its sprites can be replaced
+                self.sprite_count = self.secondary_count;
+                self.sprite_zero_loaded = self.secondary_has_zero;
+            }
+            280..=304 if self.scanline == self.pre_render_scanline => {
+                self.v = (self.v & !0x7be0) | (self.t & 0x7be0);
+            }
+            _ => {}
+        }
+        if (257..=320).contains(&dot) && (dot - 257) % 8 == 7 {
+            self.fetch_sprite((dot - 257) as usize / 8);
+        }
+    }
+
+    fn background_address(&self) -> u16 {
+        let table = if self.control.contains(Control::BACKGROUND_TABLE) {
+            0x1000
+        } else {
+            0x0000
+        };
+        table + self.next_tile as u16 * 16 + ((self.v >> 12) & 0x07)
+    }
+
+    fn shift_background(&mut self) {
+        self.pattern_low <<= 1;
+        self.pattern_high <<= 1;
+        self.attribute_low <<= 1;
+        self.attribute_high <<= 1;
+    }
+
+    /// Move the fetched tile into the low byte of the shift registers.
+    fn load_background(&mut self) {
+        let expand = |bit: u8| if bit != 0 { 0xff } else { 0x00 };
+        self.pattern_low = (self.pattern_low & 0xff00) | self.next_low as u16;
+        self.pattern_high = (self.pattern_high & 0xff00) | self.next_high as u16;
+        self.attribute_low = (self.attribute_low & 0xff00) | expand(self.next_attribute & 1);
+        self.attribute_high = (self.attribute_high & 0xff00) | expand(self.next_attribute & 2);
+    }
+
+    /// Move to the next tile, wrapping into the horizontally adjacent
+    /// nametable.
+    fn increment_x(&mut self) {
+        if self.v & 0x001f == 31 {
+            self.v &= !0x001f;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    /// Move to the next row of pixels, wrapping into the vertically adjacent
+    /// nametable after row 29.
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let mut coarse_y = (self.v & 0x03e0) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            self.v ^= 0x0800;
+        } else if coarse_y == 31 {
+            // Rows 30 and 31 hold attributes; scrolling into them wraps
+            // without switching nametables
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !0x03e0) | (coarse_y << 5);
+    }
+
+    fn sprite_height(&self) -> u16 {
+        if self.control.contains(Control::SPRITE_8X16) {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Copy the first eight sprites on the next scanline into secondary OAM,
+    /// then look for a ninth to set the overflow flag.
+    fn evaluate_sprites(&mut self) {
+        let height = self.sprite_height();
+        let scanline = self.scanline;
+        let in_range = |y: u8| scanline.wrapping_sub(y as u16) < height;
+        let mut secondary_oam = [0xff; 32];
+        let mut count = 0;
+        let mut n = 0;
+        while n < 64 && count < 8 {
+            let sprite = &self.oam[n * 4..n * 4 + 4];
+            if in_range(sprite[0]) {
+                secondary_oam[count * 4..count * 4 + 4].copy_from_slice(sprite);
+                count += 1;
+            }
+            n += 1;
+        }
+        self.secondary_has_zero = count > 0 && in_range(self.oam[0]);
+
+        // The hardware increments the byte offset along with the sprite
+        // index after a miss, so it compares tile numbers, attributes and X
+        // positions as if they were Y coordinates
+        let mut m = 0;
+        while n < 64 {
+            if in_range(self.oam[n * 4 + m]) {
+                self.status.insert(PpuStatus::SPRITE_OVERFLOW);
+                break;
+            }
+            n += 1;
+            m = (m + 1) & 3;
+        }
+        self.secondary_oam = secondary_oam;
+        self.secondary_count = count;
+    }
+
+    /// Fetch the pattern row for sprite `slot` of the next scanline. Empty
+    /// slots still fetch, from tile $FF.
+    fn fetch_sprite(&mut self, slot: usize) {
+        let entry = &self.secondary_oam[slot * 4..slot * 4 + 4];
+        let (y, tile, attributes, x) = (entry[0], entry[1], entry[2], entry[3]);
+        let height = self.sprite_height();
+        let mut row = self.scanline.wrapping_sub(y as u16) & (height - 1);
+        if attributes & SPRITE_FLIP_VERTICAL != 0 {
+            row = height - 1 - row;
+        }
+        let address = if height == 16 {
+            let table = (tile as u16 & 1) * 0x1000;
+            let tile = (tile & 0xfe) as u16 + row / 8;
+            table + tile * 16 + row % 8
+        } else {
+            let table = if self.control.contains(Control::SPRITE_TABLE) {
+                0x1000
+            } else {
+                0x0000
+            };
+            table + tile as u16 * 16 + row
+        };
+        let mut low = self.bus.read(address);
+        let mut high = self.bus.read(address + 8);
+        if slot >= self.secondary_count {
+            return;
+        }
+        if attributes & SPRITE_FLIP_HORIZONTAL != 0 {
+            low = low.reverse_bits();
+            high = high.reverse_bits();
+        }
+        self.sprites[slot] = Sprite {
+            x,
+            attributes,
+            low,
+            high,
+        };
+    }
+
+    /// The background pixel at the current dot as a palette RAM index.
+    fn background_pixel(&self, x: usize) -> u8 {
+        if !self.mask.contains(Mask::SHOW_BACKGROUND)
+            || (x < 8 && !self.mask.contains(Mask::SHOW_BACKGROUND_LEFT))
+        {
+            return 0;
+        }
+        let bit = 15 - self.x;
+        let pattern = ((self.pattern_low >> bit) & 1) | (((self.pattern_high >> bit) & 1) << 1);
+        if pattern == 0 {
+            return 0;
+        }
+        let attribute =
+            ((self.attribute_low >> bit) & 1) | (((self.attribute_high >> bit) & 1) << 1);
+        (attribute << 2 | pattern) as u8
+    }
+
+    /// The frontmost opaque sprite pixel at `x` as a palette RAM index, and
+    /// whether it is behind the background.
+    fn sprite_pixel(&self, x: usize) -> Option<(u8, bool)> {
+        if !self.mask.contains(Mask::SHOW_SPRITES)
+            || (x < 8 && !self.mask.contains(Mask::SHOW_SPRITES_LEFT))
+        {
+            return None;
+        }
+        self.sprites[..self.sprite_count].iter().find_map(|sprite| {
+            let pattern = sprite.pattern_at(x);
+            if pattern == 0 {
+                return None;
+            }
+            let palette = 0x10 | (sprite.attributes & SPRITE_PALETTE) << 2 | pattern;
+            Some((palette, sprite.attributes & SPRITE_BEHIND_BACKGROUND != 0))
+        })
+    }
+
+    /// Whether sprite 0 has an opaque pixel at `x` that can hit the
+    /// background. Other sprites in front of it do not matter, but the
+    /// left column clipping and column 255 do.
+    fn sprite_zero_at(&self, x: usize) -> bool {
+        self.sprite_zero_loaded
+            && x != 255
+            && self.mask.contains(Mask::SHOW_SPRITES)
+            && (x >= 8 || self.mask.contains(Mask::SHOW_SPRITES_LEFT))
+            && self.sprites[0].pattern_at(x) != 0
+    }
+
+    /// Combine the background and sprite pixels for the current dot.
+    fn output_pixel(&mut self) {
+        let x = self.dot as usize - 1;
+        let index = if self.rendering_enabled() {
+            let background = self.background_pixel(x);
+            if background != 0 && self.sprite_zero_at(x) {
+                self.status.insert(PpuStatus::SPRITE_0_HIT);
+            }
+            match self.sprite_pixel(x) {
+                Some((sprite, behind)) if background == 0 || !behind => sprite,
+                _ => background,
+            }
+        } else {
+            0
+        };
+        let emphasis = (self.mask.bits() as u16 >> 5) << 6;
+        self.frame[self.scanline as usize * WIDTH + x] =
+            self.palette_color(index as u16) as u16 | emphasis;
+    }
+
+    /// The color in palette RAM at `address`, as seen through the
+    /// grayscale bit.
+    fn palette_color(&self, address: u16) -> u8 {
+        let color = self.palette[palette_index(address)];
+        if self.mask.contains(Mask::GRAYSCALE) {
+            color & 0x30
+        } else {
+            color
+        }
+    }
+
+    fn increment_address(&mut self) {
+        let step = if self.control.contains(Control::INCREMENT_32) {
+            32
+        } else {
+            1
+        };
+        self.v = self.v.wrapping_add(step) & 0x7fff;
+    }
+}
+
+/// Index into palette RAM. The sprite backdrop entries $3F10, $3F14, $3F18
+/// and $3F1C mirror the background ones.
+fn palette_index(address: u16) -> usize {
+    let index = address as usize & 0x1f;
+    if index & 0x13 == 0x10 {
+        index & 0x0f
+    } else {
+        index
+    }
+}
+
+#[cfg(feature = "serde")]
+serde_state!(Ppu<B: Bus> {
+    oam as bytes,
+    oam_address,
+    control,
+    mask,
+    status,
+    palette,
+    v,
+    t,
+    x,
+    w,
+    read_buffer,
+    io_latch,
+    latch_refreshed,
+    dots,
+    scanline,
+    dot,
+    suppress_vblank,
+    odd_frame,
+    frames,
+    next_tile,
+    next_attribute,
+    next_low,
+    next_high,
+    pattern_low,
+    pattern_high,
+    attribute_low,
+    attribute_high,
+    secondary_oam,
+    secondary_count,
+    secondary_has_zero,
+    sprites,
+    sprite_count,
+    sprite_zero_loaded,
+});
+
+/// Add the PPU's accuracy settings, see [`Ppu::set_latch_decay`].
+pub(crate) fn register_capabilities(capabilities: &mut Capabilities) {
+    capabilities.accuracy.push("latch decay");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Vram(Vec<u8>);
+
+    impl Bus for Vram {
+        fn read(&mut self, address: u16) -> u8 {
+            self.peek(address)
+        }
+        fn write(&mut self, address: u16, data: u8) {
+            self.0[address as usize] = data;
+        }
+        fn peek(&self, address: u16) -> u8 {
+            self.0[address as usize]
+        }
+    }
+
+    fn ppu() -> Ppu<Vram> {
+        Ppu::new(Vram(vec![0; 0x4000]))
+    }
+
+    fn run_to(ppu: &mut Ppu<Vram>, scanline: u16, dot: u16) {
+        while ppu.position() != (scanline, dot) {
+            ppu.step();
+        }
+    }
+
+    /// A PPU with tile 1 at column 2, row 1 of the first nametable using
+    /// background palette 1, and a horizontally flipped sprite at (50, 101)
+    /// with one opaque pixel on its right edge.
+    fn scene() -> Ppu<Vram> {
+        let mut ppu = ppu();
+        let vram = &mut ppu.bus_mut().0;
+        vram[0x0010..0x0018].copy_from_slice(&[0xff; 8]);
+        vram[0x0028..0x0030].copy_from_slice(&[0x80; 8]);
+        vram[0x2000 + 32 + 2] = 0x01;
+        vram[0x23c0] = 0b0100;
+        ppu.palette[0x00] = 0x0f;
+        ppu.palette[0x05] = 0x21;
+        ppu.palette[0x16] = 0x2a;
+        ppu.oam[..4].copy_from_slice(&[100, 0x02, 0x41, 50]);
+        ppu.write(0x2001, 0x1e);
+        ppu
+    }
+
+    /// Run until a whole frame has been drawn with the current settings.
+    fn render(ppu: &mut Ppu<Vram>) {
+        let frames = ppu.frames();
+        while ppu.frames() < frames + 2 {
+            ppu.step();
+        }
+    }
+
+    fn pixel(ppu: &Ppu<Vram>, x: usize, y: usize) -> u16 {
+        ppu.frame()[y * WIDTH + x]
+    }
+
+    #[test]
+    fn renders_background_and_sprites() {
+        let mut ppu = scene();
+        render(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), 0x0f);
+        assert_eq!(pixel(&ppu, 15, 8), 0x0f);
+        assert_eq!(pixel(&ppu, 16, 8), 0x21);
+        assert_eq!(pixel(&ppu, 23, 15), 0x21);
+        assert_eq!(pixel(&ppu, 24, 15), 0x0f);
+        assert_eq!(pixel(&ppu, 57, 100), 0x0f);
+        assert_eq!(pixel(&ppu, 50, 101), 0x0f);
+        assert_eq!(pixel(&ppu, 57, 101), 0x2a);
+        assert_eq!(pixel(&ppu, 57, 108), 0x2a);
+        assert_eq!(pixel(&ppu, 57, 109), 0x0f);
+    }
+
+    #[test]
+    fn scrolls_by_fine_and_coarse_offsets() {
+        let mut ppu = scene();
+        ppu.read(0x2002);
+        ppu.write(0x2005, 11);
+        ppu.write(0x2005, 4);
+        render(&mut ppu);
+        assert_eq!(pixel(&ppu, 4, 4), 0x0f);
+        assert_eq!(pixel(&ppu, 5, 4), 0x21);
+        assert_eq!(pixel(&ppu, 12, 11), 0x21);
+        assert_eq!(pixel(&ppu, 13, 11), 0x0f);
+    }
+
+    #[test]
+    fn left_column_masking() {
+        let mut ppu = scene();
+        ppu.oam[..4].copy_from_slice(&[100, 0x02, 0x01, 0]);
+        ppu.write(0x2001, 0x18);
+        render(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 101), 0x0f);
+        ppu.write(0x2001, 0x1e);
+        render(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 101), 0x2a);
+    }
+
+    #[test]
+    fn sprite_zero_hits_opaque_background() {
+        let mut ppu = scene();
+        render(&mut ppu);
+        assert!(!ppu.status().contains(PpuStatus::SPRITE_0_HIT));
+
+        ppu.oam[..4].copy_from_slice(&[7, 0x02, 0x00, 23]);
+        render(&mut ppu);
+        assert!(ppu.status().contains(PpuStatus::SPRITE_0_HIT));
+
+        // Only sprite 0 counts
+        ppu.oam[..8].copy_from_slice(&[100, 0x02, 0x00, 0, 7, 0x02, 0x00, 23]);
+        render(&mut ppu);
+        assert!(!ppu.status().contains(PpuStatus::SPRITE_0_HIT));
     }
 
-    pub fn write(&mut self, address: u16, data: u8) {}
+    #[test]
+    fn sprite_zero_hit_is_cleared_before_the_next_frame() {
+        let mut ppu = scene();
+        ppu.oam[..4].copy_from_slice(&[7, 0x02, 0x00, 23]);
+        render(&mut ppu);
+        run_to(&mut ppu, PRE_RENDER_SCANLINE, 2);
+        assert_eq!(ppu.status(), PpuStatus::empty());
+    }
+
+    #[test]
+    fn nine_sprites_on_a_line_overflow() {
+        let mut ppu = scene();
+        ppu.oam = [0xff; 256];
+        for sprite in ppu.oam.chunks_exact_mut(4).take(8) {
+            sprite.copy_from_slice(&[50, 0x02, 0x00, 0]);
+        }
+        render(&mut ppu);
+        assert!(!ppu.status().contains(PpuStatus::SPRITE_OVERFLOW));
+
+        ppu.oam[32..36].copy_from_slice(&[50, 0x02, 0x00, 0]);
+        render(&mut ppu);
+        assert!(ppu.status().contains(PpuStatus::SPRITE_OVERFLOW));
+    }
+
+    #[test]
+    fn overflow_check_reads_the_wrong_bytes() {
+        let mut ppu = scene();
+        ppu.oam = [0xff; 256];
+        for sprite in ppu.oam.chunks_exact_mut(4).take(8) {
+            sprite.copy_from_slice(&[50, 0x02, 0x00, 0]);
+        }
+        // Sprite 9's tile number is compared as if it were a Y coordinate
+        ppu.oam[36..40].copy_from_slice(&[0xff, 50, 0xff, 0xff]);
+        render(&mut ppu);
+        assert!(ppu.status().contains(PpuStatus::SPRITE_OVERFLOW));
+
+        // and a real ninth sprite after a miss is skipped over
+        ppu.oam[36..40].copy_from_slice(&[0xff; 4]);
+        ppu.oam[40..44].copy_from_slice(&[50, 0xff, 0xff, 0xff]);
+        render(&mut ppu);
+        assert!(!ppu.status().contains(PpuStatus::SPRITE_OVERFLOW));
+    }
+
+    #[test]
+    fn nmi_follows_vblank_while_enabled() {
+        let mut ppu = ppu();
+        ppu.write(0x2000, Control::NMI_ENABLE.bits());
+        run_to(&mut ppu, VBLANK_SCANLINE, 1);
+        assert!(!ppu.nmi());
+        ppu.step();
+        assert!(ppu.nmi());
+
+        ppu.write(0x2000, 0x00);
+        assert!(!ppu.nmi());
+        ppu.write(0x2000, Control::NMI_ENABLE.bits());
+        assert!(ppu.nmi());
+
+        ppu.read(0x2002);
+        assert!(!ppu.nmi());
+        ppu.write(0x2000, Control::NMI_ENABLE.bits());
+        assert!(!ppu.nmi());
+    }
+
+    #[test]
+    fn nmi_ends_with_vblank() {
+        let mut ppu = ppu();
+        ppu.write(0x2000, Control::NMI_ENABLE.bits());
+        run_to(&mut ppu, VBLANK_SCANLINE, 2);
+        assert!(ppu.nmi());
+        run_to(&mut ppu, PRE_RENDER_SCANLINE, 2);
+        assert!(!ppu.nmi());
+    }
+
+    #[test]
+    fn reading_status_as_vblank_starts_suppresses_nmi() {
+        let mut ppu = ppu();
+        ppu.write(0x2000, Control::NMI_ENABLE.bits());
+        run_to(&mut ppu, VBLANK_SCANLINE, 1);
+        ppu.read(0x2002);
+        run_to(&mut ppu, VBLANK_SCANLINE + 1, 0);
+        assert!(!ppu.nmi());
+    }
+
+    #[test]
+    fn palette_mirrors() {
+        let mut ppu = ppu();
+        ppu.write(0x2006, 0x3f);
+        ppu.write(0x2006, 0x10);
+        for color in 0x20..0x30 {
+            ppu.write(0x2007, color);
+        }
+        // $3F10, $3F14, $3F18 and $3F1C land on the background entries
+        assert_eq!(ppu.palette[0x00], 0x20);
+        assert_eq!(ppu.palette[0x04], 0x24);
+        assert_eq!(ppu.palette[0x10], 0x00);
+        assert_eq!(ppu.palette[0x11], 0x21);
+        // and the 32 bytes repeat up to $3FFF
+        ppu.write(0x2006, 0x3f);
+        ppu.write(0x2006, 0xf1);
+        assert_eq!(ppu.read(0x2007) & 0x3f, 0x21);
+    }
+
+    #[test]
+    fn grayscale_and_emphasis() {
+        let mut ppu = scene();
+        ppu.write(
+            0x2001,
+            0x1e | Mask::GRAYSCALE.bits() | Mask::EMPHASIZE_BLUE.bits(),
+        );
+        render(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), 0x100);
+        assert_eq!(pixel(&ppu, 16, 8), 0x120);
+        ppu.write(0x2006, 0x3f);
+        ppu.write(0x2006, 0x05);
+        assert_eq!(ppu.read(0x2007), 0x20);
+    }
+
+    #[test]
+    fn write_only_registers_read_the_latch() {
+        let mut ppu = ppu();
+        ppu.write(0x2000, 0x00);
+        ppu.write(0x2005, 0x5a);
+        assert_eq!(ppu.read(0x2000), 0x5a);
+        assert_eq!(ppu.read(0x2006), 0x5a);
+        // PPUSTATUS drives only its top three bits
+        assert_eq!(ppu.read(0x2002), 0x1a);
+        assert_eq!(ppu.read(0x2001), 0x1a);
+    }
+
+    #[test]
+    fn palette_and_oam_reads_leave_bits_open() {
+        let mut ppu = ppu();
+        ppu.write(0x2006, 0x3f);
+        ppu.write(0x2006, 0x01);
+        ppu.write(0x2007, 0xff);
+        ppu.write(0x2006, 0x3f);
+        ppu.write(0x2006, 0xc1);
+        assert_eq!(ppu.read(0x2007), 0xff);
+        ppu.write(0x2003, 0x02);
+        ppu.write(0x2004, 0xff);
+        ppu.write(0x2003, 0x02);
+        assert_eq!(ppu.read(0x2004), 0xe3);
+    }
+
+    #[test]
+    fn latch_decays() {
+        let mut ppu = ppu();
+        ppu.palette[0] = 0x3f;
+        ppu.write(0x2003, 0xff);
+        for _ in 0..LATCH_DECAY_DOTS / 2 {
+            ppu.step();
+        }
+        // Refreshes the low six bits
+        ppu.v = 0x3f00;
+        ppu.read(0x2007);
+        for _ in 0..LATCH_DECAY_DOTS / 2 + 1 {
+            ppu.step();
+        }
+        assert_eq!(ppu.read(0x2001), 0x3f);
+        for _ in 0..LATCH_DECAY_DOTS / 2 + 1 {
+            ppu.step();
+        }
+        assert_eq!(ppu.read(0x2001), 0x00);
+
+        ppu.set_latch_decay(false);
+        ppu.write(0x2003, 0xff);
+        for _ in 0..LATCH_DECAY_DOTS * 2 {
+            ppu.step();
+        }
+        assert_eq!(ppu.read(0x2001), 0xff);
+    }
+
+    /// Dots from the start of the next frame to the start of the one after.
+    fn frame_length(ppu: &mut Ppu<Vram>) -> u64 {
+        run_to(ppu, 0, 0);
+        let start = ppu.dots;
+        ppu.step();
+        run_to(ppu, 0, 0);
+        ppu.dots - start
+    }
+
+    #[test]
+    fn pal_and_dendy_frames() {
+        for &(region, vblank, dots) in &[
+            (Region::Pal, 241, 312 * 341),
+            (Region::Dendy, 291, 312 * 341),
+        ] {
+            let mut ppu = ppu();
+            ppu.set_region(region);
+            ppu.mask = Mask::SHOW_BACKGROUND;
+            run_to(&mut ppu, vblank, 2);
+            assert!(ppu.status.contains(PpuStatus::VBLANK));
+            // No dot is skipped on odd frames
+            for _ in 0..2 {
+                let frames = ppu.frames();
+                let start = ppu.dots;
+                while ppu.frames() == frames {
+                    ppu.step();
+                }
+                assert_eq!(ppu.dots - start, dots, "{:?}", region);
+            }
+        }
+    }
+
+    #[test]
+    fn predicts_vblank_edges() {
+        let mut ppu = ppu();
+        ppu.mask = Mask::SHOW_BACKGROUND;
+        let mut edges = 0;
+        let mut safe = ppu.dots_until_vblank_edge();
+        for _ in 0..3 * 89342 {
+            let vblank = ppu.status.contains(PpuStatus::VBLANK);
+            ppu.step();
+            let next = ppu.dots_until_vblank_edge();
+            if safe > 0 {
+                assert_eq!(ppu.status.contains(PpuStatus::VBLANK), vblank);
+                assert!(next < safe);
+            } else if ppu.status.contains(PpuStatus::VBLANK) != vblank {
+                edges += 1;
+            }
+            safe = next;
+        }
+        assert_eq!(edges, 6);
+    }
+
+    #[test]
+    fn odd_frames_skip_a_dot_while_rendering() {
+        let mut ppu = ppu();
+        let lengths: Vec<_> = (0..4).map(|_| frame_length(&mut ppu)).collect();
+        assert_eq!(lengths, [89342; 4]);
+
+        ppu.write(0x2001, Mask::SHOW_BACKGROUND.bits());
+        let lengths: Vec<_> = (0..4).map(|_| frame_length(&mut ppu)).collect();
+        assert_eq!(lengths, [89342, 89341, 89342, 89341]);
+    }
+
+    #[test]
+    fn ppudata_reads_are_buffered() {
+        let mut ppu = ppu();
+        ppu.bus_mut().0[0x2000..0x2003].copy_from_slice(&[0x11, 0x22, 0x33]);
+        ppu.write(0x2006, 0x20);
+        ppu.write(0x2006, 0x00);
+        assert_eq!(ppu.read(0x2007), 0x00);
+        assert_eq!(ppu.read(0x2007), 0x11);
+        assert_eq!(ppu.read(0x2007), 0x22);
+        assert_eq!(ppu.vram_address(), 0x2003);
+    }
+
+    #[test]
+    fn ppudata_increments_by_32() {
+        let mut ppu = ppu();
+        ppu.write(0x2000, Control::INCREMENT_32.bits());
+        ppu.write(0x2006, 0x21);
+        ppu.write(0x2006, 0x00);
+        ppu.write(0x2007, 0xaa);
+        ppu.write(0x2007, 0xbb);
+        assert_eq!(ppu.bus().0[0x2100], 0xaa);
+        assert_eq!(ppu.bus().0[0x2120], 0xbb);
+        assert_eq!(ppu.vram_address(), 0x2140);
+    }
+
+    #[test]
+    fn palette_reads_skip_the_buffer() {
+        let mut ppu = ppu();
+        ppu.bus_mut().0[0x2f10] = 0x5a;
+        ppu.write(0x2006, 0x3f);
+        ppu.write(0x2006, 0x00);
+        ppu.write(0x2007, 0xff);
+        ppu.write(0x2006, 0x3f);
+        ppu.write(0x2006, 0x10);
+        assert_eq!(ppu.read(0x2007), 0x3f);
+        ppu.write(0x2006, 0x00);
+        ppu.write(0x2006, 0x00);
+        assert_eq!(ppu.read(0x2007), 0x5a);
+    }
+
+    #[test]
+    fn scroll_and_address_share_the_write_toggle() {
+        let mut ppu = ppu();
+        ppu.write(0x2000, 0x03);
+        ppu.write(0x2005, 0x7d);
+        assert_eq!((ppu.t, ppu.x), (0x0c0f, 0x05));
+        ppu.write(0x2005, 0x5e);
+        assert_eq!(ppu.t, 0x6d6f);
+        ppu.write(0x2006, 0x3d);
+        ppu.read(0x2002);
+        ppu.write(0x2006, 0x21);
+        ppu.write(0x2006, 0x08);
+        assert_eq!(ppu.vram_address(), 0x2108);
+    }
+
+    #[test]
+    fn vblank_flag_is_set_and_cleared() {
+        let mut ppu = ppu();
+        run_to(&mut ppu, VBLANK_SCANLINE, 2);
+        assert_eq!(ppu.read(0x2002) & 0x80, 0x80);
+        assert_eq!(ppu.read(0x2002) & 0x80, 0x00);
+
+        run_to(&mut ppu, VBLANK_SCANLINE + 1, 0);
+        ppu.status.insert(PpuStatus::SPRITE_0_HIT);
+        run_to(&mut ppu, PRE_RENDER_SCANLINE, 2);
+        assert_eq!(ppu.status(), PpuStatus::empty());
+    }
+
+    #[test]
+    fn reading_status_as_vblank_starts_suppresses_it() {
+        let mut ppu = ppu();
+        run_to(&mut ppu, VBLANK_SCANLINE, 1);
+        assert_eq!(ppu.read(0x2002) & 0x80, 0x00);
+        ppu.step();
+        assert_eq!(ppu.read(0x2002) & 0x80, 0x00);
+    }
 }
@@ -0,0 +1,19 @@
+//! The types most programs driving the emulator need.
+//!
+//! ```
+//! use nes::prelude::*;
+//! ```
+
+pub use crate::apu::{Apu, Channel};
+pub use crate::bus::Bus;
+pub use crate::cartridge::Cartridge;
+pub use crate::clock::Clock;
+pub use crate::console::{Console, ConsoleBuilder, Deterministic, RamFill};
+pub use crate::cpu::{Cpu, IrqSource, Registers, Status, Step, Vector};
+pub use crate::ines::{FileFormat, Header, Mirroring, Timing};
+pub use crate::input::{Button, Controller, FourScore, InputState, Joypad};
+pub use crate::mapper::Mapper;
+pub use crate::nsf::Nsf;
+pub use crate::palette::Palette;
+pub use crate::region::Region;
+pub use crate::Result;
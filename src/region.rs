@@ -0,0 +1,114 @@
+//! The console variants, which differ in clock speeds and frame timing.
+
+use crate::capabilities::Capabilities;
+use crate::clock::Clock;
+
+/// Which console a game runs on. PAL consoles run slower, with more
+/// scanlines per frame and a longer vblank; the Dendy, a Famiclone sold
+/// in Russia, pairs PAL's frame rate with NTSC's CPU to PPU ratio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl Region {
+    /// The variants, by name.
+    pub const NAMES: &'static [(&'static str, Region)] = &[
+        ("NTSC", Region::Ntsc),
+        ("PAL", Region::Pal),
+        ("Dendy", Region::Dendy),
+    ];
+
+    pub fn clock(self) -> Clock {
+        match self {
+            Region::Ntsc => Clock::NTSC,
+            Region::Pal => Clock::PAL,
+            Region::Dendy => Clock::DENDY,
+        }
+    }
+
+    /// Master clock cycles per second.
+    pub fn master_clock_hz(self) -> f64 {
+        match self {
+            Region::Ntsc => 236_250_000.0 / 11.0,
+            Region::Pal | Region::Dendy => 26_601_712.0,
+        }
+    }
+
+    /// CPU cycles per second.
+    pub fn cpu_hz(self) -> f64 {
+        self.master_clock_hz() / self.clock().cpu_divider() as f64
+    }
+
+    /// Scanlines per frame, including vblank and the pre-render line.
+    pub fn scanlines(self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    /// The scanline vblank starts on. The Dendy waits 50 lines after the
+    /// picture, so NTSC games' vblank code has time to run before it ends.
+    pub fn vblank_scanline(self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    /// Whether odd frames skip a dot while rendering, which only the NTSC
+    /// PPU does.
+    pub fn skips_odd_dot(self) -> bool {
+        self == Region::Ntsc
+    }
+
+    /// Whether the APU uses PAL's frame counter and noise periods. The
+    /// Dendy's APU keeps NTSC's.
+    pub fn pal_apu(self) -> bool {
+        self == Region::Pal
+    }
+
+    /// Frames per second. NTSC frames average half a dot short of 341 by
+    /// 262 because of the odd frame skip.
+    pub fn frame_rate(self) -> f64 {
+        let dots = self.scanlines() as f64 * 341.0;
+        let dots = if self.skips_odd_dot() {
+            dots - 0.5
+        } else {
+            dots
+        };
+        let dots_per_second = self.master_clock_hz() / self.clock().ppu_divider() as f64;
+        dots_per_second / dots
+    }
+}
+
+/// Add the consoles that can be emulated.
+pub(crate) fn register_capabilities(capabilities: &mut Capabilities) {
+    capabilities
+        .regions
+        .extend(Region::NAMES.iter().map(|(name, _)| *name));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_rates() {
+        assert!((Region::Ntsc.frame_rate() - 60.0988).abs() < 0.0001);
+        assert!((Region::Pal.frame_rate() - 50.0070).abs() < 0.0001);
+        assert!((Region::Dendy.frame_rate() - 50.0070).abs() < 0.0001);
+    }
+
+    #[test]
+    fn cpu_speeds() {
+        assert_eq!(Region::Ntsc.cpu_hz().round(), 1_789_773.0);
+        assert_eq!(Region::Pal.cpu_hz().round(), 1_662_607.0);
+        assert_eq!(Region::Dendy.cpu_hz().round(), 1_773_447.0);
+    }
+}
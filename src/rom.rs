@@ -0,0 +1,143 @@
+use std::fmt;
+use std::ops;
+use std::sync::Arc;
+
+/// Read-only ROM contents shared between the loaded file and the mappers
+/// that address into it.
+///
+/// Cloning or slicing a `Rom` never copies the bytes, so PRG and CHR banks
+/// can point into the original file buffer (or memory map) directly.
+#[derive(Clone)]
+pub struct Rom {
+    data: Arc<dyn AsRef<[u8]> + Send + Sync>,
+    start: usize,
+    end: usize,
+}
+
+impl Rom {
+    fn new(data: Arc<dyn AsRef<[u8]> + Send + Sync>) -> Rom {
+        let end = (*data).as_ref().len();
+        Rom {
+            data,
+            start: 0,
+            end,
+        }
+    }
+
+    /// Memory map the file at `path` instead of reading it into memory.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated while any `Rom` created
+    /// from it is alive.
+    #[cfg(feature = "mmap")]
+    pub unsafe fn map_file(path: impl AsRef<std::path::Path>) -> std::io::Result<Rom> {
+        let file = std::fs::File::open(path)?;
+        let mmap = memmap2::Mmap::map(&file)?;
+        Ok(Rom::new(Arc::new(mmap)))
+    }
+
+    /// A view of part of this ROM sharing the same storage.
+    pub fn slice<R: ops::RangeBounds<usize>>(&self, range: R) -> Rom {
+        let start = match range.start_bound() {
+            ops::Bound::Included(start) => *start,
+            ops::Bound::Excluded(start) => *start + 1,
+            ops::Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            ops::Bound::Included(end) => *end + 1,
+            ops::Bound::Excluded(end) => *end,
+            ops::Bound::Unbounded => self.len(),
+        };
+        assert!(
+            start <= end && end <= self.len(),
+            "range {}..{} out of bounds for ROM of {} bytes",
+            start,
+            end,
+            self.len()
+        );
+        Rom {
+            data: self.data.clone(),
+            start: self.start + start,
+            end: self.start + end,
+        }
+    }
+}
+
+impl ops::Deref for Rom {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &(*self.data).as_ref()[self.start..self.end]
+    }
+}
+
+impl AsRef<[u8]> for Rom {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl From<Vec<u8>> for Rom {
+    fn from(bytes: Vec<u8>) -> Rom {
+        Rom::new(Arc::new(bytes))
+    }
+}
+
+impl From<&[u8]> for Rom {
+    fn from(bytes: &[u8]) -> Rom {
+        Rom::from(bytes.to_vec())
+    }
+}
+
+impl From<Arc<[u8]>> for Rom {
+    fn from(bytes: Arc<[u8]>) -> Rom {
+        Rom::new(Arc::new(bytes))
+    }
+}
+
+impl fmt::Debug for Rom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Rom({} bytes)", self.len())
+    }
+}
+
+impl PartialEq for Rom {
+    fn eq(&self, other: &Rom) -> bool {
+        **self == **other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slices_share_storage() {
+        let bytes: Arc<[u8]> = (0..=255).collect::<Vec<u8>>().into();
+        let rom = Rom::from(bytes.clone());
+        let slice = rom.slice(16..=31);
+        assert_eq!(&*slice, &bytes[16..32]);
+        assert_eq!(slice.as_ptr(), bytes[16..].as_ptr());
+
+        let nested = slice.slice(4..);
+        assert_eq!(nested.len(), 12);
+        assert_eq!(nested.as_ptr(), bytes[20..].as_ptr());
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn slice_out_of_bounds() {
+        Rom::from(vec![0; 16]).slice(8..24);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn map_file_matches_read() {
+        let bytes: Vec<u8> = (0..=255).cycle().take(0x6010).collect();
+        let path = std::env::temp_dir().join("nes-map-file-test.nes");
+        std::fs::write(&path, &bytes).unwrap();
+        let rom = unsafe { Rom::map_file(&path).unwrap() };
+        assert_eq!(&*rom, &bytes[..]);
+    }
+}
@@ -0,0 +1,114 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+/// Events waiting for a master clock cycle, earliest first.
+///
+/// Events due at the same cycle come out in the order they were scheduled.
+#[derive(Debug, Clone)]
+pub struct Scheduler<E> {
+    queue: BinaryHeap<Reverse<Entry<E>>>,
+    /// Breaks ties between events due at the same cycle
+    sequence: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Entry<E> {
+    due: u64,
+    sequence: u64,
+    event: E,
+}
+
+impl<E> PartialEq for Entry<E> {
+    fn eq(&self, other: &Entry<E>) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<E> Eq for Entry<E> {}
+
+impl<E> PartialOrd for Entry<E> {
+    fn partial_cmp(&self, other: &Entry<E>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<E> Ord for Entry<E> {
+    fn cmp(&self, other: &Entry<E>) -> Ordering {
+        (self.due, self.sequence).cmp(&(other.due, other.sequence))
+    }
+}
+
+impl<E> Scheduler<E> {
+    pub fn new() -> Scheduler<E> {
+        Scheduler {
+            queue: BinaryHeap::new(),
+            sequence: 0,
+        }
+    }
+
+    /// Queue `event` for master cycle `due`.
+    pub fn schedule(&mut self, due: u64, event: E) {
+        self.queue.push(Reverse(Entry {
+            due,
+            sequence: self.sequence,
+            event,
+        }));
+        self.sequence += 1;
+    }
+
+    /// The cycle the next event is due at.
+    pub fn next_due(&self) -> Option<u64> {
+        self.queue.peek().map(|Reverse(entry)| entry.due)
+    }
+
+    /// Remove and return the next event if it is due by `now`.
+    pub fn pop_due(&mut self, now: u64) -> Option<E> {
+        if self.next_due()? > now {
+            return None;
+        }
+        self.queue.pop().map(|Reverse(entry)| entry.event)
+    }
+
+    /// Drop every event.
+    pub fn clear(&mut self) {
+        self.queue.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+impl<E> Default for Scheduler<E> {
+    fn default() -> Scheduler<E> {
+        Scheduler::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_come_out_in_due_order() {
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(30, "c");
+        scheduler.schedule(10, "a");
+        scheduler.schedule(20, "b1");
+        scheduler.schedule(20, "b2");
+        assert_eq!(scheduler.next_due(), Some(10));
+
+        assert_eq!(scheduler.pop_due(5), None);
+        assert_eq!(scheduler.pop_due(20), Some("a"));
+        assert_eq!(scheduler.pop_due(20), Some("b1"));
+        assert_eq!(scheduler.pop_due(20), Some("b2"));
+        assert_eq!(scheduler.pop_due(20), None);
+        assert_eq!(scheduler.len(), 1);
+        assert_eq!(scheduler.pop_due(100), Some("c"));
+        assert!(scheduler.is_empty());
+    }
+}
@@ -0,0 +1,646 @@
+//! Binary encoding of emulator state for savestates.
+//!
+//! Values are written in order with no field names, little-endian, so
+//! loading must read them back in the order they were saved.
+
+use crate::clock::Clock;
+use crate::ines::Mirroring;
+use crate::region::Region;
+use crate::Result;
+use std::convert::{TryFrom, TryInto};
+
+/// Starts every savestate from
+/// [`Console::save_state`](crate::console::Console::save_state).
+pub const MAGIC: &[u8; 4] = b"NESS";
+
+/// The layout `Console::save_state` writes, after [`MAGIC`]. Bump it when
+/// the layout changes, and keep loading the older ones.
+///
+/// Version 2 added CHR RAM to the cartridges that lacked it.
+pub const VERSION: u16 = 2;
+
+/// A value that can be written to and read back from a savestate.
+pub trait State: Sized {
+    fn save(&self, state: &mut StateWriter);
+    fn load(state: &mut StateReader) -> Result<Self>;
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter::default()
+    }
+
+    pub fn write<T: State>(&mut self, value: &T) {
+        value.save(self);
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+    version: u16,
+}
+
+impl<'a> StateReader<'a> {
+    /// Read a state in the current [`VERSION`]'s layout.
+    pub fn new(bytes: &'a [u8]) -> StateReader<'a> {
+        StateReader {
+            bytes,
+            version: VERSION,
+        }
+    }
+
+    /// The layout the state was written in, for components whose state
+    /// has changed since older versions.
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn set_version(&mut self, version: u16) {
+        self.version = version;
+    }
+
+    pub fn read<T: State>(&mut self) -> Result<T> {
+        T::load(self)
+    }
+
+    /// Read a buffer saved from one the same length as `buffer`, such as
+    /// RAM, in place.
+    pub fn read_into(&mut self, buffer: &mut [u8]) -> Result<()> {
+        let len = self.read::<u32>()? as usize;
+        if len != buffer.len() {
+            return Err(format!("saved {} bytes where {} were expected", len, buffer.len()).into());
+        }
+        buffer.copy_from_slice(self.read_bytes(len)?);
+        Ok(())
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err("savestate ends early".into());
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    /// Bytes not read yet.
+    pub fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+macro_rules! int_state {
+    ($($int:ty),*) => {
+        $(
+            impl State for $int {
+                fn save(&self, state: &mut StateWriter) {
+                    state.write_bytes(&self.to_le_bytes());
+                }
+
+                fn load(state: &mut StateReader) -> Result<$int> {
+                    let bytes = state.read_bytes(std::mem::size_of::<$int>())?;
+                    Ok(<$int>::from_le_bytes(bytes.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+int_state!(u8, u16, u32, u64, i16, i32);
+
+/// Saved as 64 bits so states load on any platform.
+impl State for usize {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&(*self as u64));
+    }
+
+    fn load(state: &mut StateReader) -> Result<usize> {
+        Ok(usize::try_from(state.read::<u64>()?)?)
+    }
+}
+
+impl State for bool {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&(*self as u8));
+    }
+
+    fn load(state: &mut StateReader) -> Result<bool> {
+        match state.read::<u8>()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            byte => Err(format!("{} is not a bool", byte).into()),
+        }
+    }
+}
+
+/// Length-prefixed, for buffers whose size can change. Use
+/// [`StateReader::read_into`] for fixed-size ones.
+impl State for Vec<u8> {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&(self.len() as u32));
+        state.write_bytes(self);
+    }
+
+    fn load(state: &mut StateReader) -> Result<Vec<u8>> {
+        let len = state.read::<u32>()? as usize;
+        Ok(state.read_bytes(len)?.to_vec())
+    }
+}
+
+impl<T: State> State for Option<T> {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.is_some());
+        if let Some(value) = self {
+            state.write(value);
+        }
+    }
+
+    fn load(state: &mut StateReader) -> Result<Option<T>> {
+        if state.read()? {
+            Ok(Some(state.read()?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<T: State + Copy + Default, const N: usize> State for [T; N] {
+    fn save(&self, state: &mut StateWriter) {
+        for value in self {
+            state.write(value);
+        }
+    }
+
+    fn load(state: &mut StateReader) -> Result<[T; N]> {
+        let mut values = [T::default(); N];
+        for value in &mut values {
+            *value = state.read()?;
+        }
+        Ok(values)
+    }
+}
+
+impl State for Mirroring {
+    fn save(&self, state: &mut StateWriter) {
+        let byte: u8 = match self {
+            Mirroring::Horizontal => 0,
+            Mirroring::Vertical => 1,
+            Mirroring::FourScreen => 2,
+            Mirroring::SingleScreenLower => 3,
+            Mirroring::SingleScreenUpper => 4,
+        };
+        state.write(&byte);
+    }
+
+    fn load(state: &mut StateReader) -> Result<Mirroring> {
+        Ok(match state.read::<u8>()? {
+            0 => Mirroring::Horizontal,
+            1 => Mirroring::Vertical,
+            2 => Mirroring::FourScreen,
+            3 => Mirroring::SingleScreenLower,
+            4 => Mirroring::SingleScreenUpper,
+            byte => return Err(format!("{} is not a mirroring", byte).into()),
+        })
+    }
+}
+
+impl State for Region {
+    fn save(&self, state: &mut StateWriter) {
+        let byte: u8 = match self {
+            Region::Ntsc => 0,
+            Region::Pal => 1,
+            Region::Dendy => 2,
+        };
+        state.write(&byte);
+    }
+
+    fn load(state: &mut StateReader) -> Result<Region> {
+        Ok(match state.read::<u8>()? {
+            0 => Region::Ntsc,
+            1 => Region::Pal,
+            2 => Region::Dendy,
+            byte => return Err(format!("{} is not a region", byte).into()),
+        })
+    }
+}
+
+impl State for Clock {
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.cpu_divider());
+        state.write(&self.ppu_divider());
+        state.write(&self.master_cycle());
+    }
+
+    fn load(state: &mut StateReader) -> Result<Clock> {
+        let cpu_divider = state.read()?;
+        let ppu_divider = state.read()?;
+        if cpu_divider == 0 || ppu_divider == 0 {
+            return Err("a clock divider is 0".into());
+        }
+        Ok(Clock::new(cpu_divider, ppu_divider).at(state.read()?))
+    }
+}
+
+/// A field serialized as bytes, for memory such as OAM: serde only
+/// implements arrays up to 32 elements, and most formats store bytes more
+/// compactly than a sequence.
+#[cfg(feature = "serde")]
+pub(crate) struct Bytes<'a>(pub &'a [u8]);
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Bytes<'_> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+/// `#[serde(with)]` for byte arrays, see [`Bytes`].
+#[cfg(feature = "serde")]
+pub(crate) mod byte_array {
+    use super::{Bytes, BytesPlace};
+    use serde::de::DeserializeSeed;
+    use serde::{Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        bytes: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        Bytes(bytes).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        let mut bytes = [0; N];
+        BytesPlace(&mut bytes).deserialize(deserializer)?;
+        Ok(bytes)
+    }
+}
+
+/// Deserializes a value over the one it points to.
+#[cfg(feature = "serde")]
+pub(crate) struct Place<'a, T>(pub &'a mut T);
+
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>> serde::de::DeserializeSeed<'de> for Place<'_, T> {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<(), D::Error> {
+        *self.0 = T::deserialize(deserializer)?;
+        Ok(())
+    }
+}
+
+/// Deserializes [`Bytes`] over memory of the same size.
+#[cfg(feature = "serde")]
+pub(crate) struct BytesPlace<'a>(pub &'a mut [u8]);
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::DeserializeSeed<'de> for BytesPlace<'_> {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<(), D::Error> {
+        deserializer.deserialize_bytes(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::Visitor<'de> for BytesPlace<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} bytes", self.0.len())
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, bytes: &[u8]) -> std::result::Result<(), E> {
+        if bytes.len() != self.0.len() {
+            return Err(E::invalid_length(bytes.len(), &self));
+        }
+        self.0.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> std::result::Result<(), A::Error> {
+        use serde::de::Error;
+        for i in 0..self.0.len() {
+            let byte = seq
+                .next_element()?
+                .ok_or_else(|| A::Error::invalid_length(i, &self))?;
+            self.0[i] = byte;
+        }
+        if seq.next_element::<u8>()?.is_some() {
+            return Err(A::Error::invalid_length(self.0.len() + 1, &self));
+        }
+        Ok(())
+    }
+}
+
+/// Deserializes a sequence over as many components, each in place. Arrays
+/// and `Option`s of components are serialized as slices to match.
+#[cfg(feature = "serde")]
+pub(crate) struct Seeds<'a, T>(pub &'a mut [T]);
+
+#[cfg(feature = "serde")]
+impl<'de, T> serde::de::DeserializeSeed<'de> for Seeds<'_, T>
+where
+    for<'a> &'a mut T: serde::de::DeserializeSeed<'de, Value = ()>,
+{
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T> serde::de::Visitor<'de> for Seeds<'_, T>
+where
+    for<'a> &'a mut T: serde::de::DeserializeSeed<'de, Value = ()>,
+{
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "a sequence of {}", self.0.len())
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> std::result::Result<(), A::Error> {
+        use serde::de::Error;
+        let len = self.0.len();
+        let expected = format!("a sequence of {}", len);
+        for (i, value) in self.0.iter_mut().enumerate() {
+            if seq.next_element_seed(value)?.is_none() {
+                return Err(A::Error::invalid_length(i, &expected.as_str()));
+            }
+        }
+        if seq.next_element::<serde::de::IgnoredAny>()?.is_some() {
+            return Err(A::Error::invalid_length(len + 1, &expected.as_str()));
+        }
+        Ok(())
+    }
+}
+
+/// Implement `Serialize` for a component from the fields its savestate
+/// covers, and `DeserializeSeed` for `&mut` the component to load them
+/// over one already set up, e.g. on its bus or with its ROM. Fields marked
+/// `as bytes` go through [`Bytes`], `as seeds` are arrays or options of
+/// other such components, and `as chr` is a [`Chr`](crate::mapper::Chr)'s
+/// RAM.
+///
+/// Once loaded, the component makes a round trip through its savestate
+/// so the same checks apply as for [`State`].
+#[cfg(feature = "serde")]
+macro_rules! serde_state {
+    (@ser $value:expr) => { &$value };
+    (@ser $value:expr, bytes) => { &$crate::state::Bytes(&$value[..]) };
+    (@ser $value:expr, seeds) => { &$value.as_slice() };
+    (@ser $value:expr, chr) => { &$crate::state::Bytes($value.ram()) };
+    (@de $value:expr) => { $crate::state::Place(&mut $value) };
+    (@de $value:expr, bytes) => { $crate::state::BytesPlace(&mut $value[..]) };
+    (@de $value:expr, seeds) => { $crate::state::Seeds($value.as_mut_slice()) };
+    (@de $value:expr, chr) => { $crate::state::BytesPlace($value.ram_mut()) };
+    (
+        $name:ident $(<$param:ident: $bound:path>)?
+        { $($field:ident $(as $kind:ident)?),* $(,)? }
+    ) => {
+        impl$(<$param: $bound>)? serde::Serialize for $name$(<$param>)? {
+            fn serialize<S: serde::Serializer>(
+                &self,
+                serializer: S,
+            ) -> std::result::Result<S::Ok, S::Error> {
+                use serde::ser::SerializeStruct;
+                const FIELDS: &[&str] = &[$(stringify!($field)),*];
+                let mut state = serializer.serialize_struct(stringify!($name), FIELDS.len())?;
+                $(
+                    state.serialize_field(
+                        stringify!($field),
+                        $crate::state::serde_state!(@ser self.$field $(, $kind)?),
+                    )?;
+                )*
+                state.end()
+            }
+        }
+
+        impl<'de, 'a $(, $param: $bound)?> serde::de::DeserializeSeed<'de>
+            for &'a mut $name$(<$param>)?
+        {
+            type Value = ();
+
+            fn deserialize<D: serde::Deserializer<'de>>(
+                self,
+                deserializer: D,
+            ) -> std::result::Result<(), D::Error> {
+                const FIELDS: &[&str] = &[$(stringify!($field)),*];
+
+                struct Visitor<'a, T>(&'a mut T);
+
+                impl<'de, 'a $(, $param: $bound)?> serde::de::Visitor<'de>
+                    for Visitor<'a, $name$(<$param>)?>
+                {
+                    type Value = ();
+
+                    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                        write!(f, "struct {}", stringify!($name))
+                    }
+
+                    fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                        self,
+                        mut seq: A,
+                    ) -> std::result::Result<(), A::Error> {
+                        use serde::de::Error;
+                        $(
+                            let place = $crate::state::serde_state!(@de self.0.$field $(, $kind)?);
+                            if seq.next_element_seed(place)?.is_none() {
+                                return Err(A::Error::missing_field(stringify!($field)));
+                            }
+                        )*
+                        self.0.reload_state().map_err(A::Error::custom)
+                    }
+
+                    fn visit_map<A: serde::de::MapAccess<'de>>(
+                        self,
+                        mut map: A,
+                    ) -> std::result::Result<(), A::Error> {
+                        use serde::de::Error;
+                        let mut seen = [false; FIELDS.len()];
+                        while let Some(key) = map.next_key::<String>()? {
+                            let i = FIELDS
+                                .iter()
+                                .position(|field| *field == key)
+                                .ok_or_else(|| A::Error::unknown_field(&key, FIELDS))?;
+                            if seen[i] {
+                                return Err(A::Error::duplicate_field(FIELDS[i]));
+                            }
+                            seen[i] = true;
+                            match key.as_str() {
+                                $(
+                                    stringify!($field) => map.next_value_seed(
+                                        $crate::state::serde_state!(@de self.0.$field $(, $kind)?),
+                                    )?,
+                                )*
+                                _ => unreachable!(),
+                            }
+                        }
+                        if let Some(i) = seen.iter().position(|seen| !seen) {
+                            return Err(A::Error::missing_field(FIELDS[i]));
+                        }
+                        self.0.reload_state().map_err(A::Error::custom)
+                    }
+                }
+
+                deserializer.deserialize_struct(stringify!($name), FIELDS, Visitor(self))
+            }
+        }
+
+        impl$(<$param: $bound>)? $name$(<$param>)? {
+            /// Put what serde loaded through the savestate's checks.
+            fn reload_state(&mut self) -> $crate::Result<()> {
+                let mut state = $crate::state::StateWriter::new();
+                self.save_state(&mut state);
+                let bytes = state.into_bytes();
+                self.load_state(&mut $crate::state::StateReader::new(&bytes))
+            }
+        }
+    };
+}
+
+#[cfg(feature = "serde")]
+pub(crate) use serde_state;
+
+/// The `serialize_state` and `deserialize_state` methods of
+/// [`Mapper`](crate::mapper::Mapper) and
+/// [`Controller`](crate::input::Controller), for a type with
+/// [`serde_state!`].
+#[cfg(feature = "serde")]
+macro_rules! serde_state_methods {
+    () => {
+        fn serialize_state(&self) -> &dyn erased_serde::Serialize {
+            self
+        }
+
+        fn deserialize_state<'de>(
+            &mut self,
+            deserializer: &mut dyn erased_serde::Deserializer<'de>,
+        ) -> std::result::Result<(), erased_serde::Error> {
+            serde::de::DeserializeSeed::deserialize(self, deserializer)
+        }
+    };
+}
+
+#[cfg(feature = "serde")]
+pub(crate) use serde_state_methods;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::{self, Mapper};
+    use crate::rom::Rom;
+
+    #[test]
+    fn values_round_trip() {
+        let mut state = StateWriter::new();
+        state.write(&0x12u8);
+        state.write(&0x3456u16);
+        state.write(&-2i16);
+        state.write(&usize::MAX);
+        state.write(&true);
+        state.write(&vec![1u8, 2, 3]);
+        state.write(&Some(Mirroring::SingleScreenUpper));
+        state.write(&[7u16; 3]);
+        state.write(&Region::Dendy);
+        state.write(&Clock::PAL.at(1234));
+        let bytes = state.into_bytes();
+
+        let mut state = StateReader::new(&bytes);
+        assert_eq!(state.read::<u8>().unwrap(), 0x12);
+        assert_eq!(state.read::<u16>().unwrap(), 0x3456);
+        assert_eq!(state.read::<i16>().unwrap(), -2);
+        assert_eq!(state.read::<usize>().unwrap(), usize::MAX);
+        assert!(state.read::<bool>().unwrap());
+        assert_eq!(state.read::<Vec<u8>>().unwrap(), [1, 2, 3]);
+        assert_eq!(
+            state.read::<Option<Mirroring>>().unwrap(),
+            Some(Mirroring::SingleScreenUpper)
+        );
+        assert_eq!(state.read::<[u16; 3]>().unwrap(), [7; 3]);
+        assert_eq!(state.read::<Region>().unwrap(), Region::Dendy);
+        assert_eq!(state.read::<Clock>().unwrap(), Clock::PAL.at(1234));
+        assert_eq!(state.remaining(), 0);
+        assert!(state.read::<u8>().is_err());
+    }
+
+    #[test]
+    fn read_into_checks_the_length() {
+        let mut state = StateWriter::new();
+        state.write(&vec![0xaau8; 4]);
+        let bytes = state.into_bytes();
+
+        let mut buffer = [0; 4];
+        StateReader::new(&bytes).read_into(&mut buffer).unwrap();
+        assert_eq!(buffer, [0xaa; 4]);
+        assert!(StateReader::new(&bytes).read_into(&mut [0; 8]).is_err());
+    }
+
+    fn save(mapper: &dyn Mapper) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        mapper.save_state(&mut state);
+        state.into_bytes()
+    }
+
+    #[test]
+    fn every_mapper_round_trips() {
+        for id in mapper::supported() {
+            let mut bytes = vec![0; 16 + 0x20000 + 0x8000];
+            bytes[..8].copy_from_slice(&[b'N', b'E', b'S', 0x1a, 8, 4, id << 4, id & 0xf0]);
+            let rom = Rom::from(bytes);
+            let mut mapper = <dyn Mapper>::from_bytes(rom.clone()).unwrap();
+            for (offset, address) in (0x6000..=0xffff).step_by(0x3ff).enumerate() {
+                mapper.cpu_write(address, offset as u8 | 0x41);
+            }
+            for address in (0..0x2000).step_by(0x1ff) {
+                mapper.ppu_write(address, 0x55);
+            }
+            for _ in 0..1000 {
+                mapper.cpu_clock();
+            }
+            let saved = save(mapper.as_ref());
+
+            let mut restored = <dyn Mapper>::from_bytes(rom).unwrap();
+            let mut state = StateReader::new(&saved);
+            restored.load_state(&mut state).unwrap();
+            assert_eq!(state.remaining(), 0, "mapper {}", id);
+            assert_eq!(save(restored.as_ref()), saved, "mapper {}", id);
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! Checks that stepping the emulator does not touch the heap.
+//!
+//! This lives in its own test binary because it installs a counting global
+//! allocator.
+
+mod support;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+#[test]
+fn step_without_trace_does_not_allocate() {
+    #[rustfmt::skip]
+    let program = [
+        0xe8,             // INX
+        0x8a,             // TXA
+        0x48,             // PHA
+        0x68,             // PLA
+        0x9d, 0x00, 0x60, // STA $6000,X
+        0x20, 0x0d, 0x80, // JSR $800D
+        0x4c, 0x00, 0x80, // JMP $8000
+        // $800D
+        0x60,             // RTS
+    ];
+    let mut console = support::run(&program, 0);
+
+    let before = ALLOCATIONS.load(Ordering::SeqCst);
+    for _ in 0..1000 {
+        console.step();
+    }
+    let after = ALLOCATIONS.load(Ordering::SeqCst);
+    assert_eq!(after - before, 0, "allocations during 1000 instructions");
+}
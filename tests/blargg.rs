@@ -0,0 +1,230 @@
+//! Runs blargg's test ROM suites from a directory outside the repository.
+//!
+//! The ROMs are not committed, so the harness is ignored by default:
+//!
+//! ```text
+//! TEST_ROM_DIR=~/nes-test-roms cargo test --test blargg -- --ignored --nocapture
+//! ```
+//!
+//! Every `.nes` file below `TEST_ROM_DIR` is run and grouped into a suite by
+//! its first directory component (`cpu`, `ppu`, `apu`, `mmc3`, ...). Results
+//! are printed as a table and written as CSV to `TEST_ROM_REPORT`, or
+//! `target/blargg.csv` when it is unset. The test fails unless every ROM
+//! passes, listing the others with the text they wrote at $6004.
+
+use nes::console::{Console, RamFill};
+use std::env;
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+
+/// Instructions to run before giving up on a ROM that never reports.
+const MAX_STEPS: usize = 20_000_000;
+
+/// Instructions to wait before pressing reset when a ROM asks for it.
+const RESET_DELAY_STEPS: usize = 100_000;
+
+/// Written to $6001-$6003 once the status byte at $6000 is valid.
+const SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_NEEDS_RESET: u8 = 0x81;
+
+#[derive(Debug)]
+enum Outcome {
+    Passed,
+    Failed(u8),
+    TimedOut,
+    Crashed,
+}
+
+impl Outcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Passed => "pass",
+            Outcome::Failed(_) => "fail",
+            Outcome::TimedOut => "timeout",
+            Outcome::Crashed => "crash",
+        }
+    }
+}
+
+struct TestResult {
+    suite: String,
+    rom: String,
+    outcome: Outcome,
+    message: String,
+}
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+    let mut entries: Vec<_> = fs::read_dir(dir)
+        .unwrap_or_else(|err| panic!("cannot read {}: {}", dir.display(), err))
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            find_roms(&path, roms);
+        } else if path.extension().is_some_and(|ext| ext == "nes") {
+            roms.push(path);
+        }
+    }
+}
+
+fn read_message(console: &mut Console) -> String {
+    let text = console.read_range(0x6004..=0x6fff);
+    let end = text
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(text.len());
+    String::from_utf8_lossy(&text[..end]).trim().to_string()
+}
+
+fn run_rom(path: &Path) -> (Outcome, String) {
+    let mut console = Console::from_file(PathBuf::from(path)).unwrap();
+    console.power_on(RamFill::Zeros);
+    let mut steps = 0;
+    while steps < MAX_STEPS {
+        console.step();
+        steps += 1;
+        let status = console.read_range(0x6000..=0x6003);
+        if status[1..] != SIGNATURE {
+            continue;
+        }
+        match status[0] {
+            STATUS_RUNNING => {}
+            STATUS_NEEDS_RESET => {
+                for _ in 0..RESET_DELAY_STEPS {
+                    console.step();
+                }
+                steps += RESET_DELAY_STEPS;
+                console.soft_reset();
+            }
+            0 => return (Outcome::Passed, read_message(&mut console)),
+            code => return (Outcome::Failed(code), read_message(&mut console)),
+        }
+    }
+    (Outcome::TimedOut, String::new())
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("panicked")
+    }
+}
+
+fn write_report(path: &Path, results: &[TestResult]) {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    let mut writer = csv::Writer::from_path(path).unwrap();
+    writer
+        .write_record(["suite", "rom", "outcome", "code", "message"])
+        .unwrap();
+    for result in results {
+        let code = match result.outcome {
+            Outcome::Failed(code) => code.to_string(),
+            _ => String::new(),
+        };
+        writer
+            .write_record([
+                result.suite.as_str(),
+                result.rom.as_str(),
+                result.outcome.as_str(),
+                code.as_str(),
+                result.message.as_str(),
+            ])
+            .unwrap();
+    }
+    writer.flush().unwrap();
+}
+
+#[test]
+#[ignore]
+fn blargg_suites() {
+    let dir = match env::var_os("TEST_ROM_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            println!("TEST_ROM_DIR is not set, skipping");
+            return;
+        }
+    };
+    let report = env::var_os("TEST_ROM_REPORT")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("target/blargg.csv"));
+
+    let mut roms = Vec::new();
+    find_roms(&dir, &mut roms);
+    assert!(!roms.is_empty(), "no .nes files in {}", dir.display());
+
+    // ROMs that hit unimplemented hardware panic; keep the output readable.
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let mut results = Vec::new();
+    for path in &roms {
+        let relative = path.strip_prefix(&dir).unwrap();
+        let suite = match relative.components().count() {
+            1 => String::from("."),
+            _ => relative
+                .components()
+                .next()
+                .unwrap()
+                .as_os_str()
+                .to_string_lossy()
+                .into_owned(),
+        };
+        let (outcome, message) = match panic::catch_unwind(|| run_rom(path)) {
+            Ok(result) => result,
+            Err(payload) => (Outcome::Crashed, panic_message(payload)),
+        };
+        results.push(TestResult {
+            suite,
+            rom: relative.display().to_string(),
+            outcome,
+            message,
+        });
+    }
+    panic::set_hook(default_hook);
+
+    for result in &results {
+        println!(
+            "{:8} {:7} {:50} {}",
+            result.suite,
+            result.outcome.as_str(),
+            result.rom,
+            result.message.replace('\n', " ")
+        );
+    }
+    let passed = results
+        .iter()
+        .filter(|result| matches!(result.outcome, Outcome::Passed))
+        .count();
+    println!("{}/{} passed", passed, results.len());
+
+    write_report(&report, &results);
+    println!("wrote {}", report.display());
+
+    // Passing means the ROM left 0 in its status byte at $6000
+    let failures: Vec<_> = results
+        .iter()
+        .filter(|result| !matches!(result.outcome, Outcome::Passed))
+        .map(|result| {
+            let outcome = match result.outcome {
+                Outcome::Failed(code) => format!("status ${:02X}", code),
+                _ => result.outcome.as_str().to_string(),
+            };
+            format!("{}: {}: {}", result.rom, outcome, result.message)
+        })
+        .collect();
+    assert!(
+        failures.is_empty(),
+        "{} of {} ROMs did not pass:\n{}",
+        failures.len(),
+        results.len(),
+        failures.join("\n")
+    );
+}
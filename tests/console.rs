@@ -0,0 +1,1247 @@
+//! The console's features, run on hand-assembled programs from
+//! `support`: loading, resets, savestates, movies, input, debugging aids
+//! and timing.
+
+mod support;
+
+use nes::cheats::Cheat;
+use nes::console::{Console, Deterministic, RamFill};
+use nes::cpu::{Status, Vector};
+use nes::debugger::{Access, AccessKind};
+use nes::input::Button;
+use nes::movie::Movie;
+use nes::region::Region;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn registers_can_be_set_and_inspected() {
+    #[rustfmt::skip]
+    let program = [
+        0xe8,             // INX
+        0xc8,             // INY
+        0x4c, 0x02, 0x80, // JMP $8002
+    ];
+    let mut console = support::run(&program, 0);
+    let mut registers = *console.registers();
+    assert_eq!(registers.pc, support::PROGRAM_START);
+    registers.x = 0x7f;
+    registers.y = 0xff;
+    console.set_registers(registers);
+
+    console.step();
+    console.step();
+    let registers = console.registers();
+    assert_eq!((registers.x, registers.y), (0x80, 0x00));
+    assert!(registers.ps.contains(Status::ZERO_RESULT));
+    assert!(!registers.ps.contains(Status::NEGATIVE_RESULT));
+    assert_eq!(registers.pc, support::PROGRAM_START + 2);
+}
+
+#[test]
+fn raw_program_in_work_ram() {
+    #[rustfmt::skip]
+    let program = [
+        0xa2, 0x05,       // LDX #$05
+        0x8a,             // TXA
+        0x9d, 0x00, 0x02, // STA $0200,X
+        0xca,             // DEX
+        0xd0, 0xf9,       // BNE $0602
+        0x4c, 0x09, 0x06, // JMP $0609
+    ];
+    let mut console = Console::load_raw_program(&program, 0x0600, 0x0600).unwrap();
+    console.set_trace(false);
+    console.soft_reset();
+    assert_eq!(console.registers().pc, 0x0600);
+    for _ in 0..1 + 4 * 5 {
+        console.step();
+    }
+    assert_eq!(console.read_range(0x0200..=0x0205), [0, 1, 2, 3, 4, 5]);
+    assert_eq!(console.registers().pc, 0x0609);
+}
+
+#[test]
+fn code_injected_into_work_ram_runs() {
+    // JMP $0300 from the cartridge into code loaded once powered on
+    let mut console = support::run(&[0x4c, 0x00, 0x03], 0);
+    #[rustfmt::skip]
+    console.load_slice_at(0x0300, &[
+        0xa9, 0x34,       // LDA #$34
+        0x85, 0x10,       // STA $10
+        0xa9, 0x12,       // LDA #$12
+        0x85, 0x11,       // STA $11
+        0x4c, 0x08, 0x03, // JMP $0308
+    ]);
+    console.write_range(0x0010..0x0012, &[0xff, 0xff]);
+    assert_eq!(console.read_u16_le(0x0010), 0xffff);
+    for _ in 0..5 {
+        console.step();
+    }
+    assert_eq!(console.read_u16_le(0x0010), 0x1234);
+    assert_eq!(console.read_u16_le(0xfffc), support::PROGRAM_START);
+    assert_eq!(console.registers().pc, 0x0308);
+}
+
+#[test]
+fn raw_program_outside_ram_is_rejected() {
+    assert!(Console::load_raw_program(&[0xea; 4], 0x1ffe, 0x1ffe).is_err());
+    assert!(Console::load_raw_program(&[0xea; 4], 0xfffe, 0xfffe).is_err());
+}
+
+#[test]
+fn raw_program_over_the_reset_vector_is_rejected() {
+    assert!(Console::load_raw_program(&[0xea; 4], 0xfffc, 0xfffc).is_err());
+    assert!(Console::load_raw_program(&[0xea; 4], 0xfff9, 0xfff9).is_err());
+    assert!(Console::load_raw_program(&[0xea; 4], 0xfff8, 0xfff8).is_ok());
+    assert!(Console::load_raw_program(&[0xea; 2], 0xfffe, 0xfffe).is_ok());
+}
+
+#[test]
+fn load_from_a_reader() {
+    #[rustfmt::skip]
+    let program = [
+        0xa9, 0x42,       // LDA #$42
+        0x8d, 0x00, 0x60, // STA $6000
+    ];
+    let image = support::nrom(&program);
+    let mut console = Console::from_reader(&image[..]).unwrap();
+    console.power_on(RamFill::Zeros);
+    console.step();
+    console.step();
+    assert_eq!(console.read_range(0x6000..=0x6000), [0x42]);
+}
+
+#[test]
+fn trainer_runs_from_prg_ram() {
+    #[rustfmt::skip]
+    let program = [
+        0x4c, 0x00, 0x70, // JMP $7000
+    ];
+    #[rustfmt::skip]
+    let trainer = [
+        0xa9, 0x42,       // LDA #$42
+        0x8d, 0x00, 0x60, // STA $6000
+        0x4c, 0x05, 0x70, // JMP $7005
+    ];
+    let mut image = support::nrom(&program);
+    image[6] |= 0x04;
+    let mut padded = [0; 512];
+    padded[..trainer.len()].copy_from_slice(&trainer);
+    image.splice(16..16, padded.iter().copied());
+
+    let mut console = Console::from_rom(image).unwrap();
+    console.power_on(RamFill::Zeros);
+    for _ in 0..4 {
+        console.step();
+    }
+    assert_eq!(console.read_range(0x6000..=0x6000), [0x42]);
+}
+
+#[test]
+fn reset_vector_override() {
+    #[rustfmt::skip]
+    let program = [
+        0x4c, 0x00, 0x80, // JMP $8000
+        0xa9, 0x42,       // LDA #$42
+        0x8d, 0x00, 0x60, // STA $6000
+    ];
+    let mut console = Console::from_rom(support::nrom(&program)).unwrap();
+    console.set_trace(false);
+    console.override_vector(Vector::Reset, Some(support::PROGRAM_START + 3));
+    console.soft_reset();
+    console.step();
+    console.step();
+    assert_eq!(console.read_range(0x6000..=0x6000), [0x42]);
+}
+
+#[test]
+fn soft_resets_keep_memory_and_power_cycles_start_over() {
+    #[rustfmt::skip]
+    let program = [
+        0xe6, 0x00,       // INC $00
+        0xee, 0x00, 0x60, // INC $6000
+        0x4c, 0x05, 0x80, // JMP $8005
+    ];
+    let mut console = support::run(&program, 2);
+    console.run_frame();
+    console.soft_reset();
+    console.step();
+    console.step();
+    assert_eq!(console.read_range(0x0000..=0x0000), [2]);
+    assert_eq!(console.read_range(0x6000..=0x6000), [2]);
+
+    console.power_cycle(RamFill::Ones);
+    assert_eq!(console.frames(), 0);
+    console.step();
+    console.step();
+    assert_eq!(console.read_range(0x0000..=0x0000), [0x00]);
+    assert_eq!(console.read_range(0x6000..=0x6000), [1]);
+
+    // Just like a console that was never switched off
+    console.run_frame();
+    console.power_cycle(RamFill::Zeros);
+    let mut fresh = support::run(&program, 0);
+    assert_eq!(console.frame_hash(), fresh.frame_hash());
+}
+
+#[test]
+fn soft_resets_keep_the_ppu() {
+    #[rustfmt::skip]
+    let program = [
+        0xa9, 0x80,       // LDA #$80
+        0x8d, 0x00, 0x20, // STA $2000
+        0x4c, 0x05, 0x80, // JMP $8005
+        0xe6, 0x00,       // INC $00
+        0x40,             // RTI
+    ];
+    let mut console = support::run(&program, 2);
+    console.override_vector(Vector::Reset, Some(support::PROGRAM_START + 5));
+    console.override_vector(Vector::Nmi, Some(support::PROGRAM_START + 8));
+    console.run_frame();
+    console.soft_reset();
+    let nmis = console.read_range(0x0000..=0x0000)[0];
+
+    // The reset skips the write to PPUCTRL, so NMIs only go on if the PPU
+    // kept it
+    console.run_frame();
+    console.run_frame();
+    assert!(
+        console.read_range(0x0000..=0x0000)[0] > nmis,
+        "no NMI after reset"
+    );
+}
+
+#[test]
+fn power_cycles_drop_scheduled_callbacks() {
+    #[rustfmt::skip]
+    let program = [
+        0x4c, 0x00, 0x80, // JMP $8000
+    ];
+    let mut console = support::run(&program, 0);
+    let fired = Arc::new(AtomicU32::new(0));
+    let counter = fired.clone();
+    console.schedule_in(1_000_000, move |_| {
+        counter.fetch_add(1, Ordering::Relaxed);
+    });
+    console.power_cycle(RamFill::Zeros);
+    let counter = fired.clone();
+    console.schedule_in(10, move |_| {
+        counter.fetch_add(10, Ordering::Relaxed);
+    });
+    for _ in 0..4 {
+        console.step();
+    }
+    assert_eq!(fired.load(Ordering::Relaxed), 10);
+    console.run_cycles(2_000_000);
+    assert_eq!(fired.load(Ordering::Relaxed), 10);
+}
+
+#[test]
+fn builder_configures_the_console() {
+    #[rustfmt::skip]
+    let program = [
+        0x4c, 0x00, 0x80, // JMP $8000
+    ];
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let sink = lines.clone();
+    let mut console = Console::builder()
+        .region(Region::Pal)
+        .ram_fill(RamFill::Ones)
+        .four_score()
+        .sample_rate(50_000)
+        .trace_sink(move |line: &str| sink.lock().unwrap().push(line.to_string()))
+        .build_bytes(support::nrom(&program))
+        .unwrap();
+    assert_eq!(console.region(), Region::Pal);
+    assert_eq!(console.read_range(0x0000..=0x0001), [0xff, 0xff]);
+    console.step();
+    assert_eq!(lines.lock().unwrap().len(), 1);
+
+    console.run_frame();
+    console.take_samples();
+    console.run_frame();
+    let samples = console.take_samples().len();
+    assert!((999..=1000).contains(&samples), "{} samples", samples);
+
+    let error = Console::builder()
+        .mapper(4000, 0)
+        .build_bytes(support::nrom(&program))
+        .unwrap_err();
+    assert!(error.to_string().contains("4000"), "{}", error);
+}
+
+#[test]
+fn scheduled_callbacks_run_once_due() {
+    #[rustfmt::skip]
+    let program = [
+        0x4c, 0x00, 0x80, // JMP $8000
+    ];
+    let mut console = support::run(&program, 0);
+    let fired = Arc::new(AtomicU32::new(0));
+    let counter = fired.clone();
+    console.schedule_in(10, move |_| {
+        counter.fetch_add(1, Ordering::Relaxed);
+    });
+
+    // Three cycles per JMP
+    for _ in 0..3 {
+        console.step();
+    }
+    assert_eq!(fired.load(Ordering::Relaxed), 0);
+    console.step();
+    assert_eq!(fired.load(Ordering::Relaxed), 1);
+    for _ in 0..10 {
+        console.step();
+    }
+    assert_eq!(fired.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn heatmap_counts_accesses() {
+    #[rustfmt::skip]
+    let program = [
+        0xee, 0x00, 0x03, // INC $0300
+        0x4c, 0x00, 0x80, // JMP $8000
+    ];
+    let mut console = support::run(&program, 0);
+    console.set_heatmap(true);
+    for _ in 0..10 {
+        console.step();
+    }
+    let heatmap = console.heatmap().unwrap();
+    // INC reads once and writes twice
+    assert_eq!(heatmap.reads()[0x0300], 5);
+    assert_eq!(heatmap.writes()[0x0300], 10);
+    assert_eq!(heatmap.reads()[0x8003], 5);
+    assert_eq!(heatmap.writes()[0x8003], 0);
+}
+
+#[test]
+fn trace_sink_receives_lines() {
+    #[rustfmt::skip]
+    let program = [
+        0xa9, 0x42,       // LDA #$42
+        0x4c, 0x02, 0x80, // JMP $8002
+    ];
+    let mut console = support::run(&program, 0);
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let sink = lines.clone();
+    console.set_trace_sink(move |line: &str| sink.lock().unwrap().push(line.to_string()));
+    console.step();
+    console.step();
+    console.clear_trace_sink();
+    console.step();
+
+    let lines = lines.lock().unwrap();
+    assert_eq!(lines.len(), 2);
+    assert!(
+        lines[0].starts_with("8000 A9 42      LDA #$42"),
+        "{}",
+        lines[0]
+    );
+    assert!(lines[1].contains("JMP $8002") && lines[1].contains("A:42"));
+}
+
+#[test]
+fn power_on_fills_work_ram() {
+    let mut console = Console::from_rom(support::nrom(&[])).unwrap();
+    console.power_on(RamFill::Ones);
+    assert!(console
+        .read_range(0x0000..0x0800)
+        .iter()
+        .all(|&byte| byte == 0xff));
+    assert_eq!(console.registers().sp, 0xfd);
+    assert_eq!(console.cycles(), 7);
+
+    console.power_on(RamFill::Random(1));
+    let first = console.read_range(0x0000..0x0800);
+    console.power_on(RamFill::Random(1));
+    assert_eq!(console.read_range(0x0000..0x0800), first);
+    assert!(first.iter().any(|&byte| byte != first[0]));
+}
+
+#[test]
+fn oam_dma_copies_a_page_and_stalls() {
+    #[rustfmt::skip]
+    let program = [
+        0xa2, 0x00,       // LDX #$00
+        0x8a,             // TXA
+        0x9d, 0x00, 0x02, // STA $0200,X
+        0xe8,             // INX
+        0xd0, 0xf9,       // BNE $8002
+        0xa9, 0x10,       // LDA #$10
+        0x8d, 0x03, 0x20, // STA $2003
+        0xa9, 0x02,       // LDA #$02
+        0x8d, 0x14, 0x40, // STA $4014
+        0x4c, 0x13, 0x80, // JMP $8013
+    ];
+    let mut console = support::run(&program, 1 + 4 * 256 + 3);
+    let cycles = console.cycles();
+    let step = console.step();
+    assert_eq!(step.cycles, 4 + 513 + (cycles + 4) % 2);
+    assert_eq!(console.cycles(), cycles + step.cycles);
+
+    // DMA starts at OAMADDR and wraps around
+    let oam = console.oam();
+    for (index, &data) in oam.iter().enumerate() {
+        assert_eq!(data, (index as u8).wrapping_sub(0x10), "OAM ${:02X}", index);
+    }
+}
+
+#[test]
+fn controller_reads() {
+    #[rustfmt::skip]
+    let program = [
+        0xa9, 0x01,       // LDA #$01
+        0x8d, 0x16, 0x40, // STA $4016
+        0xa9, 0x00,       // LDA #$00
+        0x8d, 0x16, 0x40, // STA $4016
+        0xa2, 0x00,       // LDX #$00
+        0xad, 0x16, 0x40, // LDA $4016
+        0x9d, 0x00, 0x60, // STA $6000,X
+        0xad, 0x17, 0x40, // LDA $4017
+        0x9d, 0x10, 0x60, // STA $6010,X
+        0xe8,             // INX
+        0xe0, 0x08,       // CPX #$08
+        0xd0, 0xef,       // BNE $800C
+        0x4c, 0x1d, 0x80, // JMP $801D
+    ];
+    let mut console = Console::from_rom(support::nrom(&program)).unwrap();
+    console.power_on(RamFill::Zeros);
+    console.set_button_state(0, Button::A, true);
+    console.set_button_state(0, Button::Down, true);
+    console.set_button_state(1, Button::Select, true);
+    for _ in 0..5 + 7 * 8 {
+        console.step();
+    }
+    assert_eq!(
+        console.read_range(0x6000..0x6008),
+        [0x41, 0x40, 0x40, 0x40, 0x40, 0x41, 0x40, 0x40]
+    );
+    assert_eq!(
+        console.read_range(0x6010..0x6018),
+        [0x40, 0x40, 0x41, 0x40, 0x40, 0x40, 0x40, 0x40]
+    );
+}
+
+#[test]
+fn four_score_players_three_and_four() {
+    #[rustfmt::skip]
+    let program = [
+        0xa9, 0x01,       // LDA #$01
+        0x8d, 0x16, 0x40, // STA $4016
+        0xa9, 0x00,       // LDA #$00
+        0x8d, 0x16, 0x40, // STA $4016
+        0xa2, 0x00,       // LDX #$00
+        0xad, 0x16, 0x40, // LDA $4016
+        0x9d, 0x00, 0x60, // STA $6000,X
+        0xad, 0x17, 0x40, // LDA $4017
+        0x9d, 0x20, 0x60, // STA $6020,X
+        0xe8,             // INX
+        0xe0, 0x18,       // CPX #$18
+        0xd0, 0xef,       // BNE $800C
+        0x4c, 0x1d, 0x80, // JMP $801D
+    ];
+    let mut console = Console::from_rom(support::nrom(&program)).unwrap();
+    console.power_on(RamFill::Zeros);
+    console.set_four_score(true);
+    console.set_button_state(2, Button::Start, true);
+    console.set_button_state(3, Button::A, true);
+    for _ in 0..5 + 7 * 24 {
+        console.step();
+    }
+    let mut bits = |range| -> Vec<u8> {
+        console
+            .read_range(range)
+            .iter()
+            .map(|data| data & 1)
+            .collect()
+    };
+    #[rustfmt::skip]
+    assert_eq!(bits(0x6000..0x6018), [
+        0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 1, 0, 0, 0, 0,
+        0, 0, 0, 1, 0, 0, 0, 0,
+    ]);
+    #[rustfmt::skip]
+    assert_eq!(bits(0x6020..0x6038), [
+        0, 0, 0, 0, 0, 0, 0, 0,
+        1, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 1, 0, 0, 0, 0, 0,
+    ]);
+}
+
+#[test]
+fn nmi_at_vblank() {
+    #[rustfmt::skip]
+    let program = [
+        0xa9, 0x80,       // LDA #$80
+        0x8d, 0x00, 0x20, // STA $2000
+        0x4c, 0x05, 0x80, // JMP $8005
+        0xee, 0x00, 0x60, // INC $6000
+        0x40,             // RTI
+    ];
+    let mut console = Console::from_rom(support::nrom(&program)).unwrap();
+    console.override_vector(Vector::Nmi, Some(support::PROGRAM_START + 8));
+    console.power_on(RamFill::Zeros);
+    // Vblank starts 241 * 341 dots in, then every 262 * 341 dots
+    while console.cycles() < 27_000 {
+        console.step();
+    }
+    assert_eq!(console.read_range(0x6000..=0x6000), [0]);
+    while console.cycles() < 28_000 {
+        console.step();
+    }
+    assert_eq!(console.read_range(0x6000..=0x6000), [1]);
+    while console.cycles() < 75_000 {
+        console.step();
+    }
+    assert_eq!(console.read_range(0x6000..=0x6000), [2]);
+}
+
+#[test]
+fn nmi_during_brk_hijacks_it() {
+    #[rustfmt::skip]
+    let program = [
+        0xa9, 0x80,       // LDA #$80
+        0x8d, 0x00, 0x20, // STA $2000
+        0x00, 0xea,       // BRK
+        0x4c, 0x05, 0x80, // JMP $8005
+        0x40,             // RTI
+        // NMI handler, counting NMIs at $11 and those that took over a
+        // BRK, whose pushed P has the break flag set, at $10
+        0xe6, 0x11,       // INC $11
+        0xba,             // TSX
+        0xbd, 0x01, 0x01, // LDA $0101,X
+        0x29, 0x10,       // AND #$10
+        0xf0, 0x02,       // BEQ +2
+        0xe6, 0x10,       // INC $10
+        0x40,             // RTI
+    ];
+    let mut console = Console::from_rom(support::nrom(&program)).unwrap();
+    console.override_vector(Vector::Irq, Some(support::PROGRAM_START + 10));
+    console.override_vector(Vector::Nmi, Some(support::PROGRAM_START + 11));
+    console.power_on(RamFill::Zeros);
+    // Ten vblanks, with the BRK loop at a different phase in each
+    while console.cycles() < 300_000 {
+        console.step();
+    }
+    let [hijacked, nmis] = [console.peek(0x0010), console.peek(0x0011)];
+    assert_eq!(nmis, 10);
+    assert!(hijacked > 0);
+}
+
+#[test]
+fn frame_irq_is_taken_after_the_instruction_it_arrives_in() {
+    #[rustfmt::skip]
+    let program = [
+        0xa9, 0x00,       // LDA #$00
+        0x8d, 0x17, 0x40, // STA $4017
+        0x58,             // CLI
+        0xea,             // NOP
+        0xee, 0x00, 0x02, // INC $0200
+        0x4c, 0x07, 0x80, // JMP $8007
+    ];
+    let mut console = support::run(&program, 0);
+    // The NOP puts the flag's rise mid-instruction rather than on a last
+    // cycle, which is after the poll. The first instruction to end with
+    // the flag set is then the last one before the handler.
+    let mut raised = false;
+    loop {
+        let step = console.step();
+        if step.interrupt == Some(Vector::Irq) {
+            break;
+        }
+        assert!(!raised, "IRQ taken late");
+        raised = console.peek(0x4015) & 0x40 != 0;
+        assert!(console.cycles() < 40_000, "no frame IRQ");
+    }
+    assert!(raised);
+}
+
+#[test]
+fn run_frame_produces_pixels() {
+    #[rustfmt::skip]
+    let program = [
+        0xa9, 0x3f,       // LDA #$3F
+        0x8d, 0x06, 0x20, // STA $2006
+        0xa9, 0x00,       // LDA #$00
+        0x8d, 0x06, 0x20, // STA $2006
+        0xa9, 0x21,       // LDA #$21
+        0x8d, 0x07, 0x20, // STA $2007
+        0x4c, 0x0f, 0x80, // JMP $800F
+    ];
+    let mut console = support::run(&program, 0);
+    console.run_frame();
+    console.run_frame();
+    assert_eq!(console.frames(), 2);
+    let frame = console.frame();
+    assert_eq!(frame.len(), 256 * 240 * 4);
+    assert_eq!(frame[..4], [76, 154, 236, 255]);
+    assert_eq!(frame[frame.len() - 4..], [76, 154, 236, 255]);
+    assert!(console.frame_pixels().iter().all(|&pixel| pixel == 0x21));
+}
+
+#[test]
+fn nametable_mirroring_follows_the_header() {
+    #[rustfmt::skip]
+    let program = [
+        0xa9, 0x20,       // LDA #$20
+        0x8d, 0x06, 0x20, // STA $2006
+        0xa9, 0x00,       // LDA #$00
+        0x8d, 0x06, 0x20, // STA $2006
+        0xa9, 0x11,       // LDA #$11
+        0x8d, 0x07, 0x20, // STA $2007
+        0xa9, 0x24,       // LDA #$24
+        0x8d, 0x06, 0x20, // STA $2006
+        0xa9, 0x00,       // LDA #$00
+        0x8d, 0x06, 0x20, // STA $2006
+        0xad, 0x07, 0x20, // LDA $2007
+        0xad, 0x07, 0x20, // LDA $2007
+        0x8d, 0x00, 0x60, // STA $6000
+        0xa9, 0x28,       // LDA #$28
+        0x8d, 0x06, 0x20, // STA $2006
+        0xa9, 0x00,       // LDA #$00
+        0x8d, 0x06, 0x20, // STA $2006
+        0xad, 0x07, 0x20, // LDA $2007
+        0xad, 0x07, 0x20, // LDA $2007
+        0x8d, 0x01, 0x60, // STA $6001
+    ];
+    for (flags_6, expected) in [(0x00, [0x11, 0x00]), (0x01, [0x00, 0x11])] {
+        let mut image = support::nrom(&program);
+        image[6] = flags_6;
+        let mut console = Console::from_rom(image).unwrap();
+        console.power_on(RamFill::Zeros);
+        for _ in 0..21 {
+            console.step();
+        }
+        assert_eq!(console.read_range(0x6000..=0x6001), expected);
+    }
+}
+
+#[test]
+fn nsf_calls_init_then_play() {
+    let mut nsf = vec![0; 0x80];
+    // Three songs starting at the second, loaded at $8000 with INIT at
+    // $8000 and PLAY at $8003, 60 Hz
+    nsf[..16].copy_from_slice(b"NESM\x1a\x01\x03\x02\x00\x80\x00\x80\x03\x80\x00\x00");
+    nsf[0x6e..0x70].copy_from_slice(&16639u16.to_le_bytes());
+    #[rustfmt::skip]
+    nsf.extend_from_slice(&[
+        0x85, 0x00, // STA $00
+        0x60,       // RTS
+        0xe6, 0x01, // INC $01
+        0x60,       // RTS
+    ]);
+    let mut console = Console::from_nsf_bytes(nsf).unwrap();
+    assert_eq!(console.nsf().unwrap().songs, 3);
+    assert_eq!(console.song(), Some(2));
+    for _ in 0..10 {
+        console.run_frame();
+    }
+    let ram = console.read_range(0x00..=0x01);
+    assert_eq!(ram[0], 1);
+    assert!((9..=11).contains(&ram[1]), "{} PLAY calls", ram[1]);
+
+    // One PLAY period is about 29780 cycles
+    console.play_song(3).unwrap();
+    let start = console.cycles();
+    while console.cycles() < start + 30_000 {
+        console.step();
+    }
+    assert_eq!(console.read_range(0x00..=0x01), [2, 1]);
+    assert!(console.play_song(4).is_err());
+}
+
+#[test]
+fn nes20_timing_selects_the_region() {
+    let mut image = support::nrom(&[0x4c, 0x00, 0x80]); // JMP $8000
+    image[7] |= 0b0000_1000; // NES 2.0
+    image[12] = 1; // PAL
+    let mut console = Console::from_rom(image).unwrap();
+    console.power_on(RamFill::Zeros);
+    assert_eq!(console.region(), Region::Pal);
+    assert!((console.frame_rate() - 50.007).abs() < 0.001);
+
+    // 312 scanlines of 341 dots, 3.2 dots per CPU cycle
+    console.run_frame();
+    let start = console.cycles();
+    console.run_frame();
+    let cycles = console.cycles() - start;
+    assert!((33_245..=33_250).contains(&cycles), "{}", cycles);
+
+    console.set_region(Region::Ntsc);
+    let start = console.cycles();
+    console.run_frame();
+    console.run_frame();
+    let cycles = console.cycles() - start;
+    assert!((59_559..=59_564).contains(&cycles), "{}", cycles);
+}
+
+#[test]
+fn run_cycles_and_run_until() {
+    #[rustfmt::skip]
+    let program = [
+        0xe6, 0x00,       // INC $00
+        0x4c, 0x00, 0x80, // JMP $8000
+    ];
+    let mut console = support::run(&program, 0);
+    let start = console.cycles();
+    // INC zero page is 5 cycles and JMP 3, so 100 cycles ends mid-loop
+    let cycles = console.run_cycles(100);
+    assert_eq!(cycles, 101);
+    assert_eq!(console.cycles() - start, 101);
+    assert_eq!(console.run_cycles(0), 0);
+
+    let instructions = console.run_until(|console| console.read_range(0x00..=0x00)[0] == 20);
+    assert_eq!(console.read_range(0x00..=0x00), [20]);
+    // INC $00 has run 13 times; 7 more, each after a JMP
+    assert_eq!(instructions, 14);
+}
+
+#[test]
+fn batched_runs_match_stepping() {
+    #[rustfmt::skip]
+    let mut program = vec![
+        0xa9, 0x00,       // LDA #$00
+        0x8d, 0x17, 0x40, // STA $4017, frame IRQ on
+        0xa9, 0x80,       // LDA #$80
+        0x8d, 0x00, 0x20, // STA $2000, NMI on
+        0x58,             // CLI
+        0xe6, 0x02,       // INC $02
+        0x4c, 0x0b, 0x80, // JMP $800B
+    ];
+    program.resize(0x20, 0xea);
+    #[rustfmt::skip]
+    // Each handler logs the main loop's count when it runs
+    #[rustfmt::skip]
+    program.extend_from_slice(&[
+        0xa6, 0x00,       // NMI: LDX $00
+        0xa5, 0x02,       // LDA $02
+        0x9d, 0x00, 0x02, // STA $0200,X
+        0xe6, 0x00,       // INC $00
+        0x40,             // RTI
+        0xa6, 0x01,       // IRQ: LDX $01
+        0xa5, 0x02,       // LDA $02
+        0x9d, 0x00, 0x03, // STA $0300,X
+        0xe6, 0x01,       // INC $01
+        0xad, 0x15, 0x40, // LDA $4015
+        0x40,             // RTI
+    ]);
+    let mut image = support::nrom(&program);
+    // NMI at $8020, IRQ at $802A
+    let vectors = 16 + 16 * 1024 - 6;
+    image[vectors..vectors + 2].copy_from_slice(&[0x20, 0x80]);
+    image[vectors + 4..vectors + 6].copy_from_slice(&[0x2a, 0x80]);
+
+    let power_on = || {
+        let mut console = Console::from_rom(image.clone()).unwrap();
+        console.power_on(RamFill::Zeros);
+        console
+    };
+    let mut stepped = power_on();
+    let mut batched = power_on();
+    for _ in 0..5 {
+        let frames = stepped.frames();
+        while stepped.frames() == frames {
+            stepped.step();
+        }
+        batched.run_frame();
+        assert_eq!(batched.cycles(), stepped.cycles());
+        assert_eq!(batched.registers(), stepped.registers());
+    }
+    let start = stepped.cycles();
+    while stepped.cycles() - start < 100_000 {
+        stepped.step();
+    }
+    batched.run_cycles(100_000);
+    assert_eq!(batched.cycles(), stepped.cycles());
+    assert_eq!(batched.registers(), stepped.registers());
+    assert_eq!(batched.clock(), stepped.clock());
+    assert_eq!(batched.frames(), stepped.frames());
+
+    let counters = batched.read_range(0x00..=0x01);
+    assert!(counters[0] >= 8 && counters[1] >= 8, "{:?}", counters);
+    assert_eq!(
+        batched.read_range(0x0200..=0x03ff),
+        stepped.read_range(0x0200..=0x03ff)
+    );
+}
+
+#[test]
+fn savestates_resume_where_they_left_off() {
+    #[rustfmt::skip]
+    let program = [
+        0xa9, 0x1e,       // LDA #$1E
+        0x8d, 0x01, 0x20, // STA $2001, rendering on
+        0xa9, 0x0f,       // LDA #$0F
+        0x8d, 0x15, 0x40, // STA $4015
+        0xa9, 0x05,       // LDA #$05
+        0x8d, 0x00, 0x40, // STA $4000, decaying
+        0xa9, 0x01,       // LDA #$01
+        0x8d, 0x03, 0x40, // STA $4003
+        0xe6, 0x00,       // INC $00
+        0xa5, 0x00,       // LDA $00
+        0x8d, 0x02, 0x40, // STA $4002
+        0x4c, 0x14, 0x80, // JMP $8014
+    ];
+    let mut console = support::run(&program, 0);
+    console.set_button_state(0, Button::A, true);
+    console.run_frame();
+    console.run_cycles(10_000);
+    let state = console.save_state();
+
+    let run = |console: &mut Console| {
+        let mut audio = Vec::new();
+        for _ in 0..1000 {
+            console.run_cycles(100);
+            audio.push(console.audio_output());
+        }
+        console.run_frame();
+        (
+            console.cycles(),
+            *console.registers(),
+            console.read_range(0x0000..=0x07ff),
+            console.frame_pixels(),
+            audio,
+        )
+    };
+    let first = run(&mut console);
+    console.set_button_state(0, Button::A, false);
+    console.load_state(&state).unwrap();
+    assert_eq!(run(&mut console), first);
+
+    // A fresh console running the same game picks up from the state too
+    let mut other = support::run(&program, 0);
+    other.load_state(&state).unwrap();
+    assert_eq!(run(&mut other), first);
+}
+
+#[test]
+fn bad_savestates_are_rejected() {
+    let mut console = support::run(&[0x4c, 0x00, 0x80], 10);
+    let state = console.save_state();
+    let registers = *console.registers();
+
+    assert!(console.load_state(b"not a state").is_err());
+    assert!(console.load_state(&state[..state.len() - 1]).is_err());
+    let mut newer = state.clone();
+    newer[4] = 0xff;
+    let error = console.load_state(&newer).unwrap_err();
+    assert!(error.to_string().contains("version"), "{}", error);
+
+    // Failed loads leave the console as it was
+    assert_eq!(*console.registers(), registers);
+    assert_eq!(console.save_state(), state);
+}
+
+#[test]
+fn latched_input_waits_for_the_next_frame() {
+    #[rustfmt::skip]
+    let program = [
+        0xa9, 0x01,       // LDA #$01
+        0x8d, 0x16, 0x40, // STA $4016
+        0xa9, 0x00,       // LDA #$00
+        0x8d, 0x16, 0x40, // STA $4016
+        0xad, 0x16, 0x40, // LDA $4016
+        0x85, 0x00,       // STA $00
+        0x4c, 0x00, 0x80, // JMP $8000
+    ];
+    let mut console = support::run(&program, 0);
+    console.set_input_latched(true);
+    console.set_button_state(0, Button::A, true);
+    console.run_cycles(1000);
+    assert_eq!(console.read_range(0x00..=0x00), [0x40]);
+    assert!(console.input().is_pressed(0, Button::A));
+
+    console.run_frame();
+    console.run_cycles(100);
+    assert_eq!(console.read_range(0x00..=0x00), [0x41]);
+
+    console.set_button_state(0, Button::A, false);
+    console.latch_input();
+    console.run_cycles(100);
+    assert_eq!(console.read_range(0x00..=0x00), [0x40]);
+}
+
+#[test]
+fn deterministic_consoles_keep_the_same_frame_hash() {
+    #[rustfmt::skip]
+    let program = [
+        0xa9, 0x01,       // LDA #$01
+        0x8d, 0x16, 0x40, // STA $4016
+        0xa9, 0x00,       // LDA #$00
+        0x8d, 0x16, 0x40, // STA $4016
+        0xad, 0x16, 0x40, // LDA $4016
+        0x65, 0x00,       // ADC $00
+        0x85, 0x00,       // STA $00
+        0x4c, 0x00, 0x80, // JMP $8000
+    ];
+    let config = Deterministic {
+        ram_fill: RamFill::Random(7),
+        ..Deterministic::default()
+    };
+    let mut consoles: Vec<_> = (0..2)
+        .map(|_| {
+            let mut console = Console::from_rom(support::nrom(&program)).unwrap();
+            console.power_on_deterministic(config);
+            console
+        })
+        .collect();
+    for frame in 0..10 {
+        for console in &mut consoles {
+            console.set_button_state(0, Button::B, frame % 3 == 0);
+        }
+        consoles[0].run_frame();
+        // Stepping gets to the same place as running in batches
+        let frames = consoles[1].frames();
+        while consoles[1].frames() == frames {
+            consoles[1].step();
+        }
+        let hashes: Vec<_> = consoles.iter_mut().map(Console::frame_hash).collect();
+        assert_eq!(hashes[0], hashes[1], "frame {}", frame);
+    }
+
+    consoles[1].set_button_state(0, Button::A, true);
+    for console in &mut consoles {
+        console.run_frame();
+    }
+    assert_ne!(consoles[0].frame_hash(), consoles[1].frame_hash());
+}
+
+#[test]
+fn movies_replay_exactly() {
+    #[rustfmt::skip]
+    let program = [
+        0xa9, 0x01,       // LDA #$01
+        0x8d, 0x16, 0x40, // STA $4016
+        0xa9, 0x00,       // LDA #$00
+        0x8d, 0x16, 0x40, // STA $4016
+        0xa2, 0x08,       // LDX #$08
+        0xad, 0x16, 0x40, // LDA $4016
+        0x4a,             // LSR A
+        0x26, 0x00,       // ROL $00
+        0xca,             // DEX
+        0xd0, 0xf7,       // BNE $800C
+        0xa5, 0x00,       // LDA $00, the buttons
+        0x18,             // CLC
+        0x65, 0x01,       // ADC $01
+        0x85, 0x01,       // STA $01, their sum
+        0x90, 0x02,       // BCC $8020
+        0xe6, 0x02,       // INC $02
+        0x4c, 0x00, 0x80, // JMP $8000
+    ];
+    let record = |movie: &mut Movie, console: &mut Console, frames| {
+        for frame in frames {
+            console.set_button_state(0, Button::A, frame % 3 == 0);
+            console.set_button_state(0, Button::Up, frame % 4 == 1);
+            movie.record_frame(console, frame == 12);
+        }
+    };
+    let result = |console: &mut Console| {
+        (
+            console.cycles(),
+            *console.registers(),
+            console.read_range(0x0000..=0x07ff),
+        )
+    };
+    let mut console = support::run(&program, 0);
+    let mut movie = Movie::new(console.region(), false);
+    movie.start(&mut console).unwrap();
+    record(&mut movie, &mut console, 0..20);
+    let recorded = result(&mut console);
+    assert_ne!(recorded.2[0x02], 0);
+
+    let mut other = support::run(&program, 0);
+    movie.play(&mut other).unwrap();
+    assert_eq!(result(&mut other), recorded);
+    assert!(movie.play(&mut other).is_err(), "the console has run");
+
+    // Recording again from partway through gives the same result
+    let mut again = support::run(&program, 0);
+    movie.rerecord_from(&mut again, 8).unwrap();
+    assert_eq!((movie.frames.len(), movie.rerecords), (8, 1));
+    record(&mut movie, &mut again, 8..20);
+    assert_eq!(result(&mut again), recorded);
+
+    // And so does a movie from a savestate, through an .fm2 file
+    let mut from_state = Movie::from_console(&console, false);
+    from_state.start(&mut console).unwrap();
+    record(&mut from_state, &mut console, 0..10);
+    let fm2 = from_state.to_fm2().unwrap();
+    let mut other = support::run(&program, 0);
+    Movie::from_fm2(&fm2).unwrap().play(&mut other).unwrap();
+    assert_eq!(result(&mut other), result(&mut console));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_states_match_savestates() {
+    use serde::de::DeserializeSeed;
+
+    #[rustfmt::skip]
+    let program = [
+        0xa9, 0x1e,       // LDA #$1E
+        0x8d, 0x01, 0x20, // STA $2001, rendering on
+        0xe6, 0x00,       // INC $00
+        0x4c, 0x05, 0x80, // JMP $8005
+    ];
+    let mut console = support::run(&program, 0);
+    console.run_frame();
+    let state = console.save_state();
+    let json = serde_json::to_string(&console).unwrap();
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(value["cpu"]["registers"]["pc"], console.registers().pc);
+    assert_eq!(value["wram"][0], console.read_range(0x0000..=0x0000)[0]);
+
+    console.run_frame();
+    console
+        .deserialize(&mut serde_json::Deserializer::from_str(&json))
+        .unwrap();
+    assert_eq!(console.save_state(), state);
+
+    // Failed loads leave the console as it was
+    console.run_frame();
+    let state = console.save_state();
+    let mut newer = value.clone();
+    newer["version"] = 0xffff.into();
+    let error = console.deserialize(newer).unwrap_err();
+    assert!(error.to_string().contains("version"), "{}", error);
+    let mut missing = value;
+    missing.as_object_mut().unwrap().remove("apu");
+    assert!(console.deserialize(missing).is_err());
+    assert_eq!(console.save_state(), state);
+}
+
+#[test]
+fn cheats_patch_cpu_reads() {
+    #[rustfmt::skip]
+    let mut program = vec![
+        0xad, 0x10, 0x80, // LDA $8010
+        0x8d, 0x00, 0x60, // STA $6000
+        0xa5, 0x00,       // LDA $00
+        0x8d, 0x01, 0x60, // STA $6001
+        0x4c, 0x00, 0x80, // JMP $8000
+    ];
+    program.resize(0x10, 0xea);
+    program.push(0x5a);
+    let mut console = support::run(&program, 0);
+    let results = |console: &mut Console| {
+        for _ in 0..5 {
+            console.step();
+        }
+        console.read_range(0x6000..0x6002)
+    };
+    assert_eq!(results(&mut console), [0x5a, 0x00]);
+
+    let game_genie = Cheat {
+        address: 0x8010,
+        value: 0xa5,
+        compare: Some(0x5a),
+    };
+    let rom = console
+        .add_cheat(&game_genie.to_game_genie().unwrap())
+        .unwrap();
+    assert_eq!(rom, game_genie);
+    let ram = console.add_cheat("0000:42").unwrap();
+    console.add_cheat("8010:00:00").unwrap();
+    assert_eq!(results(&mut console), [0xa5, 0x42]);
+    assert_eq!(console.read_range(0x8010..=0x8010), [0xa5]);
+
+    assert!(console.set_cheat_enabled(&rom, false));
+    assert!(console.remove_cheat(&ram));
+    assert_eq!(results(&mut console), [0x5a, 0x00]);
+    assert_eq!(console.cheats().len(), 2);
+    assert!(console.add_cheat("SXIOPB").is_err());
+}
+
+#[test]
+fn peeks_and_pokes_have_no_side_effects() {
+    #[rustfmt::skip]
+    let program = [
+        0xa9, 0x01,       // LDA #$01
+        0x8d, 0x16, 0x40, // STA $4016
+        0xa9, 0x00,       // LDA #$00
+        0x8d, 0x16, 0x40, // STA $4016
+        0x4c, 0x0a, 0x80, // JMP $800A
+    ];
+    let mut console = support::run(&program, 0);
+    console.set_button_state(0, Button::A, true);
+    console.set_button_state(0, Button::B, true);
+    for _ in 0..4 {
+        console.step();
+    }
+    // Open bus holds the $00 last written, not the $40 an LDA $4016 leaves
+    assert_eq!(console.peek(0x4016), 0x01);
+    assert_eq!(console.peek(0x4016), 0x01);
+    assert_eq!(console.read_range(0x4016..=0x4016), [0x01]);
+    assert_eq!(console.peek_range(0x4016..=0x4016), [0x01]);
+    assert_eq!(console.read_range(0x4016..=0x4016), [0x01]);
+    assert_eq!(console.peek(0x4016), 0x00);
+
+    while console.peek(0x2002) & 0x80 == 0 {
+        console.step();
+    }
+    assert_eq!(console.peek(0x2002) & 0x80, 0x80);
+    assert_eq!(console.read_range(0x2002..=0x2002)[0] & 0x80, 0x80);
+    assert_eq!(console.peek(0x2002) & 0x80, 0x00);
+
+    assert!(console.poke(0x0810, 0x99));
+    assert!(console.poke(0x6000, 0x07));
+    assert_eq!(console.peek_range(0x0010..0x0012), [0x99, 0x00]);
+    assert_eq!(console.read_range(0x6000..=0x6000), [0x07]);
+    assert!(!console.poke(0x8000, 0x00));
+    assert!(!console.poke(0x2000, 0x80));
+    assert_eq!(console.peek_range(0xfffe..), [0x00, 0x80]);
+    assert!(console.peek_range(0x0010..0x0010).is_empty());
+}
+
+#[test]
+fn undo_history_does_not_read_registers() {
+    #[rustfmt::skip]
+    let program = [
+        0x8d, 0x02, 0x20, // STA $2002
+        0x4c, 0x00, 0x80, // JMP $8000
+    ];
+    let mut console = support::run(&program, 0);
+    console.set_undo_depth(4);
+    console.set_trace_sink(|_: &str| {});
+    while console.peek(0x2002) & 0x80 == 0 {
+        console.step();
+    }
+    console.step();
+    console.step();
+    assert_eq!(console.peek(0x2002) & 0x80, 0x80);
+}
+
+#[test]
+fn unmapped_reads_see_open_bus() {
+    #[rustfmt::skip]
+    let program = [
+        0xad, 0x00, 0x50, // LDA $5000
+        0x8d, 0x00, 0x60, // STA $6000
+        0xad, 0x18, 0x40, // LDA $4018
+        0x8d, 0x01, 0x60, // STA $6001
+        0xa2, 0xff,       // LDX #$FF
+        0xbd, 0x01, 0x4f, // LDA $4F01,X
+        0x8d, 0x02, 0x60, // STA $6002
+        0x4c, 0x13, 0x80, // JMP $8013
+    ];
+    let mut console = support::run(&program, 10);
+    // The last byte fetched was the high byte of the jump target
+    assert_eq!(console.peek(0x5000), 0x80);
+    // The page-crossing read of $5000 follows a dummy read of $4F00
+    assert_eq!(console.read_range(0x6000..0x6003), [0x50, 0x40, 0x4f]);
+}
+
+#[test]
+fn watches_see_reads_and_writes() {
+    #[rustfmt::skip]
+    let program = [
+        0xa5, 0x10,       // LDA $10
+        0x8d, 0x00, 0x02, // STA $0200
+        0xe6, 0x10,       // INC $10
+        0x4c, 0x00, 0x80, // JMP $8000
+    ];
+    let mut console = support::run(&program, 0);
+    let accesses = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&accesses);
+    let reads = console.watch(AccessKind::Read, 0x0010..=0x0010, move |access| {
+        seen.lock().unwrap().push(access)
+    });
+    let seen = Arc::clone(&accesses);
+    console.watch(AccessKind::Write, 0x0200.., move |access| {
+        seen.lock().unwrap().push(access)
+    });
+    for _ in 0..3 {
+        console.step();
+    }
+    let access = |kind, address, data| Access {
+        kind,
+        address,
+        data,
+    };
+    assert_eq!(
+        *accesses.lock().unwrap(),
+        [
+            access(AccessKind::Read, 0x0010, 0x00),
+            access(AccessKind::Write, 0x0200, 0x00),
+            // INC $10 reads it again, and its writes are not watched
+            access(AccessKind::Read, 0x0010, 0x00),
+        ]
+    );
+
+    accesses.lock().unwrap().clear();
+    assert!(console.unwatch(reads));
+    assert!(!console.unwatch(reads));
+    console.read_range(0x0010..=0x0010);
+    for _ in 0..3 {
+        console.step();
+    }
+    assert_eq!(
+        *accesses.lock().unwrap(),
+        [access(AccessKind::Write, 0x0200, 0x01)]
+    );
+    console.clear_watches();
+    console.step();
+    console.step();
+    assert_eq!(accesses.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn clones_run_on_other_threads_independently() {
+    #[rustfmt::skip]
+    let program = [
+        0xa9, 0x01,       // LDA #$01
+        0x8d, 0x00, 0x60, // STA $6000
+        0x4c, 0x05, 0x80, // JMP $8005
+    ];
+    let mut console = support::run(&program, 0);
+    let mut clone = console.clone();
+    let mut clone = std::thread::spawn(move || {
+        clone.step();
+        clone.step();
+        clone
+    })
+    .join()
+    .unwrap();
+    assert_eq!(clone.peek(0x6000), 0x01);
+    assert_eq!(console.peek(0x6000), 0x00);
+}
+
+#[test]
+fn uxrom_without_chr_rom_renders_from_chr_ram() {
+    #[rustfmt::skip]
+    let program = [
+        // Wait out the PPU's warm-up, which ignores writes
+        0x2c, 0x02, 0x20, // BIT $2002
+        0x10, 0xfb,       // BPL $8000
+        0x2c, 0x02, 0x20, // BIT $2002
+        0x10, 0xfb,       // BPL $8005
+        0xa9, 0x00,       // LDA #$00
+        0x8d, 0x06, 0x20, // STA $2006
+        0x8d, 0x06, 0x20, // STA $2006
+        0xa9, 0x5a,       // LDA #$5A
+        0x8d, 0x07, 0x20, // STA $2007
+        0xa9, 0x00,       // LDA #$00
+        0x8d, 0x06, 0x20, // STA $2006
+        0x8d, 0x06, 0x20, // STA $2006
+        0xad, 0x07, 0x20, // LDA $2007
+        0xad, 0x07, 0x20, // LDA $2007
+        0x85, 0x10,       // STA $10
+        0xa9, 0x18,       // LDA #$18
+        0x8d, 0x01, 0x20, // STA $2001
+        0x4c, 0x2c, 0x80, // JMP $802C
+    ];
+    // Mapper 2 with no CHR ROM, which means 8 kB of CHR RAM
+    let mut image = support::nrom(&program);
+    image[5] = 0;
+    image[6] = 0x20;
+    image.truncate(16 + 16 * 1024);
+    let mut console = Console::from_rom(image).unwrap();
+    console.power_on(RamFill::Zeros);
+    for _ in 0..4 {
+        console.run_frame();
+    }
+    assert_eq!(console.peek(0x0010), 0x5a);
+}
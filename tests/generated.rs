@@ -0,0 +1,103 @@
+//! CPU exercises assembled by hand into generated ROMs. Each program stores
+//! its results in PRG RAM at $6000 and then spins on `JMP *`.
+
+mod support;
+
+#[test]
+fn implied_transfers_and_counters() {
+    #[rustfmt::skip]
+    let program = [
+        0xa9, 0x7f,       // LDA #$7F
+        0xaa,             // TAX
+        0xe8,             // INX         X = $80
+        0x8e, 0x00, 0x60, // STX $6000
+        0xa8,             // TAY
+        0x88,             // DEY         Y = $7E
+        0x8c, 0x01, 0x60, // STY $6001
+        0xa2, 0x00,       // LDX #$00
+        0xca,             // DEX         X = $FF
+        0x8a,             // TXA
+        0x8d, 0x02, 0x60, // STA $6002
+        0xa0, 0xff,       // LDY #$FF
+        0xc8,             // INY         Y = $00
+        0x98,             // TYA
+        0x8d, 0x03, 0x60, // STA $6003
+        0xba,             // TSX
+        0x8e, 0x04, 0x60, // STX $6004
+        0x4c, 0x1e, 0x80, // JMP $801E
+    ];
+    let mut console = support::run(&program, 32);
+    assert_eq!(
+        console.read_range(0x6000..=0x6004),
+        [0x80, 0x7e, 0xff, 0x00, 0xfd]
+    );
+}
+
+#[test]
+fn transfers_set_zero_and_negative() {
+    #[rustfmt::skip]
+    let program = [
+        0xa2, 0x01,       // LDX #$01
+        0xa9, 0x80,       // LDA #$80
+        0xaa,             // TAX         N set, Z clear
+        0x08,             // PHP
+        0xa0, 0x00,       // LDY #$00
+        0x98,             // TYA         Z set, N clear
+        0x08,             // PHP
+        0x68,             // PLA
+        0x8d, 0x01, 0x60, // STA $6001
+        0x68,             // PLA
+        0x8d, 0x00, 0x60, // STA $6000
+        0x4c, 0x12, 0x80, // JMP $8012
+    ];
+    let mut console = support::run(&program, 32);
+    assert_eq!(console.read_range(0x6000..=0x6001), [0xb4, 0x36]);
+}
+
+#[test]
+fn implied_flag_instructions() {
+    #[rustfmt::skip]
+    let program = [
+        0x38,             // SEC
+        0x78,             // SEI
+        0xf8,             // SED
+        0x08,             // PHP
+        0x68,             // PLA
+        0x8d, 0x00, 0x60, // STA $6000   NV1BDIZC = 0011_1101
+        0x18,             // CLC
+        0x58,             // CLI
+        0xd8,             // CLD
+        0xa9, 0x40,       // LDA #$40
+        0x69, 0x40,       // ADC #$40    sets V and N
+        0xb8,             // CLV
+        0x08,             // PHP
+        0x68,             // PLA
+        0x8d, 0x01, 0x60, // STA $6001   NV1BDIZC = 1011_0000
+        0x4c, 0x15, 0x80, // JMP $8015
+    ];
+    let mut console = support::run(&program, 32);
+    assert_eq!(console.read_range(0x6000..=0x6001), [0x3d, 0xb0]);
+}
+
+#[test]
+fn stack_and_subroutines() {
+    #[rustfmt::skip]
+    let program = [
+        0xa9, 0x11,       // LDA #$11
+        0x48,             // PHA
+        0xa9, 0x22,       // LDA #$22
+        0x48,             // PHA
+        0x20, 0x14, 0x80, // JSR $8014
+        0x68,             // PLA
+        0x8d, 0x01, 0x60, // STA $6001
+        0x68,             // PLA
+        0x8d, 0x02, 0x60, // STA $6002
+        0x4c, 0x11, 0x80, // JMP $8011
+        // $8014
+        0xba,             // TSX
+        0x8e, 0x00, 0x60, // STX $6000
+        0x60,             // RTS
+    ];
+    let mut console = support::run(&program, 32);
+    assert_eq!(console.read_range(0x6000..=0x6002), [0xf9, 0x22, 0x11]);
+}
@@ -0,0 +1,41 @@
+//! Builds small iNES images for integration tests so that basic CPU coverage
+//! does not depend on third-party ROM binaries.
+
+use nes::console::{Console, RamFill};
+
+/// Where generated programs start executing.
+pub const PROGRAM_START: u16 = 0x8000;
+
+/// Wrap `program` in a 16 kB NROM image with all vectors pointing at its
+/// first byte, which is mapped at [`PROGRAM_START`].
+pub fn nrom(program: &[u8]) -> Vec<u8> {
+    const PRG_ROM_SIZE: usize = 16 * 1024;
+    const CHR_ROM_SIZE: usize = 8 * 1024;
+    assert!(program.len() <= PRG_ROM_SIZE - 6, "program too large");
+
+    let mut image = Vec::with_capacity(16 + PRG_ROM_SIZE + CHR_ROM_SIZE);
+    // NES<EOF>, one 16 kB PRG bank, one 8 kB CHR bank, mapper 0
+    image.extend_from_slice(b"NES\x1a\x01\x01\x00\x00");
+    image.extend_from_slice(&[0; 8]);
+
+    let mut prg_rom = vec![0xea; PRG_ROM_SIZE];
+    prg_rom[..program.len()].copy_from_slice(program);
+    let [low, high] = PROGRAM_START.to_le_bytes();
+    // NMI, reset and IRQ/BRK vectors
+    prg_rom[PRG_ROM_SIZE - 6..].copy_from_slice(&[low, high, low, high, low, high]);
+    image.extend_from_slice(&prg_rom);
+
+    image.extend_from_slice(&[0; CHR_ROM_SIZE]);
+    image
+}
+
+/// Load `program` and run `instructions` instructions from power on.
+pub fn run(program: &[u8], instructions: usize) -> Console {
+    let mut console = Console::from_rom(nrom(program)).unwrap();
+    console.set_trace(false);
+    console.power_on(RamFill::Zeros);
+    for _ in 0..instructions {
+        console.step();
+    }
+    console
+}